@@ -0,0 +1,118 @@
+use std::io::{Read, Write};
+
+use rs_rdb2kv::bucket::Bucket;
+use rs_rdb2kv::dump::{DumpReader, DumpWriter};
+use rs_rdb2kv::evt::Event;
+use rs_rdb2kv::item::RawItem;
+use rs_rdb2kv::list::PageRequest;
+
+use postgres::Client;
+use rusqlite::Connection;
+
+use crate::{pg, sqlite};
+
+/// A connection to a SQLite file or a PostgreSQL server.
+pub enum Backend {
+    Sqlite(Connection),
+    Postgres(Client),
+}
+
+impl Backend {
+    /// Opens a PostgreSQL connection for `postgres://`, `postgresql://` or key/value DSNs;
+    /// otherwise opens a SQLite file(an optional `sqlite://` prefix is removed).
+    pub fn open(url: &str) -> Result<Self, Event> {
+        let pg_url: bool = url.starts_with("postgres://")
+            || url.starts_with("postgresql://")
+            || url.contains("host=")
+            || url.contains("dbname=");
+        match pg_url {
+            true => pg::open(url).map(Backend::Postgres),
+            false => sqlite::open(url.trim_start_matches("sqlite://")).map(Backend::Sqlite),
+        }
+    }
+
+    pub fn get(&mut self, b: &Bucket, key: &[u8]) -> Result<Option<Vec<u8>>, Event> {
+        match self {
+            Backend::Sqlite(c) => sqlite::get(c, b, key),
+            Backend::Postgres(c) => pg::get(c, b, key),
+        }
+    }
+
+    pub fn put(&mut self, b: &Bucket, items: Vec<RawItem>) -> Result<u64, Event> {
+        match self {
+            Backend::Sqlite(c) => sqlite::put(c, b, items),
+            Backend::Postgres(c) => pg::put(c, b, items),
+        }
+    }
+
+    /// Inserts an item unless the key exists(atomically); returns 1 if inserted.
+    pub fn insert(&mut self, b: &Bucket, item: RawItem) -> Result<u64, Event> {
+        match self {
+            Backend::Sqlite(c) => sqlite::insert(c, b, item),
+            Backend::Postgres(c) => pg::insert(c, b, item),
+        }
+    }
+
+    /// Updates the value of an existing key(atomically); returns 0 if the key does not exist.
+    pub fn update(&mut self, b: &Bucket, key: &[u8], val: &[u8]) -> Result<u64, Event> {
+        match self {
+            Backend::Sqlite(c) => sqlite::update(c, b, key, val),
+            Backend::Postgres(c) => pg::update(c, b, key, val),
+        }
+    }
+
+    pub fn del(&mut self, b: &Bucket, key: &[u8]) -> Result<u64, Event> {
+        match self {
+            Backend::Sqlite(c) => sqlite::del(c, b, key),
+            Backend::Postgres(c) => pg::del(c, b, key),
+        }
+    }
+
+    pub fn keys(&mut self, b: &Bucket) -> Result<Vec<Vec<u8>>, Event> {
+        match self {
+            Backend::Sqlite(c) => sqlite::keys(c, b),
+            Backend::Postgres(c) => pg::keys(c, b),
+        }
+    }
+
+    pub fn page(&mut self, req: &PageRequest) -> Result<Vec<RawItem>, Event> {
+        match self {
+            Backend::Sqlite(c) => sqlite::page(c, req),
+            Backend::Postgres(c) => pg::page(c, req),
+        }
+    }
+
+    pub fn buckets(&mut self) -> Result<Vec<Bucket>, Event> {
+        match self {
+            Backend::Sqlite(c) => sqlite::buckets(c),
+            Backend::Postgres(c) => pg::buckets(c),
+        }
+    }
+
+    pub fn drop_bucket(&mut self, b: &Bucket) -> Result<(), Event> {
+        match self {
+            Backend::Sqlite(c) => sqlite::drop(c, b),
+            Backend::Postgres(c) => pg::drop(c, b),
+        }
+    }
+
+    pub fn export<W>(&mut self, b: &Bucket, w: &mut DumpWriter<W>) -> Result<u64, Event>
+    where
+        W: Write,
+    {
+        match self {
+            Backend::Sqlite(c) => sqlite::export(c, b, w),
+            Backend::Postgres(c) => pg::export(c, b, w),
+        }
+    }
+
+    pub fn import<R>(&mut self, b: &Bucket, r: &mut DumpReader<R>) -> Result<u64, Event>
+    where
+        R: Read,
+    {
+        match self {
+            Backend::Sqlite(c) => sqlite::import(c, b, r),
+            Backend::Postgres(c) => pg::import(c, b, r),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use std::str::FromStr;
+
+use rs_rdb2kv::dump::{base64_decode, base64_encode};
+use rs_rdb2kv::evt::Event;
+
+/// Renders/parses keys and values on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Hex,
+    Base64,
+}
+
+impl FromStr for Encoding {
+    type Err = Event;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utf8" => Ok(Encoding::Utf8),
+            "hex" => Ok(Encoding::Hex),
+            "base64" => Ok(Encoding::Base64),
+            _ => Err(Event::UnexpectedError(format!("Unknown encoding: {}", s))),
+        }
+    }
+}
+
+fn hex_decode(s: &str) -> Result<Vec<u8>, Event> {
+    let invalid = || Event::UnexpectedError(format!("Invalid hex string: {}", s));
+    if !s.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i: usize| {
+            s.get(i..i + 2)
+                .and_then(|h: &str| u8::from_str_radix(h, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+impl Encoding {
+    /// Converts a command line argument into bytes.
+    pub fn decode(&self, s: &str) -> Result<Vec<u8>, Event> {
+        match self {
+            Encoding::Utf8 => Ok(s.as_bytes().to_vec()),
+            Encoding::Hex => hex_decode(s),
+            Encoding::Base64 => base64_decode(s),
+        }
+    }
+
+    /// Renders bytes(invalid UTF-8 sequences are replaced when rendered as utf8).
+    pub fn encode(&self, b: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(b).into_owned(),
+            Encoding::Hex => b.iter().map(|x: &u8| format!("{:02x}", x)).collect(),
+            Encoding::Base64 => base64_encode(b),
+        }
+    }
+}
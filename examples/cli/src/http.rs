@@ -0,0 +1,389 @@
+//! A minimal HTTP/1.1 gateway.
+//!
+//! | Method | Path                                              | Response                  |
+//! |--------|---------------------------------------------------|---------------------------|
+//! | GET    | /buckets/{bucket}/keys/{key}                      | 200 value / 404           |
+//! | PUT    | /buckets/{bucket}/keys/{key}                      | 204(the body is the value)|
+//! | DELETE | /buckets/{bucket}/keys/{key}                      | 204 / 404                 |
+//! | GET    | /buckets/{bucket}/keys?prefix=&after=&limit=      | 200 JSON                  |
+//! | DELETE | /buckets/{bucket}                                 | 204                       |
+//!
+//! Keys are percent-encoded raw bytes in paths, query strings and listings; `+` is a space only
+//! in query strings.
+//! A listing looks like `{"keys":["k1","k2"],"next":"k2"}`; `next` is null on the last page
+//! and can be passed as `after` to get the next page.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use rs_rdb2kv::bucket::Bucket;
+use rs_rdb2kv::evt::Event;
+use rs_rdb2kv::item::{Item, RawItem};
+use rs_rdb2kv::list::PageRequest;
+
+use crate::backend::Backend;
+
+const LIMIT_DEFAULT: u64 = 100;
+const LIMIT_MAX: u64 = 10000;
+const BODY_MAX: usize = 64 * 1024 * 1024;
+const LINE_MAX: u64 = 8 * 1024;
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    fn text(status: u16, msg: &str) -> Self {
+        Self::new(
+            status,
+            "text/plain; charset=utf-8",
+            format!("{}\n", msg).into_bytes(),
+        )
+    }
+
+    fn empty(status: u16) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", vec![])
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            204 => "No Content",
+            400 => "Bad Request",
+            404 => "Not Found",
+            409 => "Conflict",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        }
+    }
+
+    fn write_to<W>(&self, w: &mut W) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        write!(
+            w,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.body.len(),
+        )?;
+        w.write_all(&self.body)?;
+        w.flush()
+    }
+}
+
+/// Maps an event to a status code.
+fn status(e: &Event) -> u16 {
+    match e {
+        Event::InvalidBucket(_) => 400,
+        Event::BucketNotFound(_) => 404,
+        Event::Conflict(_) => 409,
+        Event::ConnectionError(_) => 503,
+        Event::PoolTimeout(_) => 503,
+        _ => 500,
+    }
+}
+
+impl From<Event> for Response {
+    fn from(e: Event) -> Self {
+        Self::text(status(&e), &format!("{:?}", e))
+    }
+}
+
+fn unhex(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decodes a percent-encoded path segment or query value.
+///
+/// `+` means a space only in query strings(form encoding); it is kept as is in paths.
+fn percent_decode(s: &str, form: bool) -> Result<Vec<u8>, Response> {
+    let b: &[u8] = s.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(b.len());
+    let mut i: usize = 0;
+    while i < b.len() {
+        match b[i] {
+            b'%' => {
+                let hi = b.get(i + 1).copied().and_then(unhex);
+                let lo = b.get(i + 2).copied().and_then(unhex);
+                match hi.zip(lo) {
+                    None => return Err(Response::text(400, "Invalid percent encoding")),
+                    Some((h, l)) => out.push((h << 4) | l),
+                }
+                i += 3;
+            }
+            b'+' if form => {
+                out.push(b' ');
+                i += 1;
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn percent_encode(b: &[u8]) -> String {
+    b.iter().fold(String::with_capacity(b.len()), |mut s, &c| {
+        match c {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                s.push(c as char)
+            }
+            _ => s.push_str(&format!("%{:02X}", c)),
+        }
+        s
+    })
+}
+
+fn bucket(segment: &str) -> Result<Bucket, Response> {
+    let name: Vec<u8> = percent_decode(segment, false)?;
+    String::from_utf8(name)
+        .map(Bucket::from)
+        .map_err(|_| Response::text(400, "Invalid bucket name"))
+}
+
+struct Query {
+    prefix: Vec<u8>,
+    after: Option<Vec<u8>>,
+    limit: u64,
+}
+
+impl Query {
+    fn parse(q: &str) -> Result<Self, Response> {
+        let mut query = Self {
+            prefix: vec![],
+            after: None,
+            limit: LIMIT_DEFAULT,
+        };
+        for pair in q.split('&').filter(|p| !p.is_empty()) {
+            let (name, val) = pair.split_once('=').unwrap_or((pair, ""));
+            match name {
+                "prefix" => query.prefix = percent_decode(val, true)?,
+                "after" => query.after = Some(percent_decode(val, true)?),
+                "limit" => {
+                    query.limit = val
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|l| (1..=LIMIT_MAX).contains(l))
+                        .ok_or_else(|| Response::text(400, "Invalid limit"))?
+                }
+                _ => {}
+            }
+        }
+        Ok(query)
+    }
+}
+
+/// Lists keys which start with the prefix.
+///
+/// Paging starts right after the prefix itself(checked by a point lookup) unless a later key
+/// is given, and stops at the first key without the prefix.
+fn list(kv: &mut Backend, b: Bucket, q: Query) -> Result<Response, Event> {
+    let mut keys: Vec<Vec<u8>> = vec![];
+    let after: Option<Vec<u8>> = match q.after {
+        Some(a) if a >= q.prefix => Some(a),
+        _ if q.prefix.is_empty() => None,
+        _ => {
+            if kv.get(&b, &q.prefix)?.is_some() {
+                keys.push(q.prefix.clone());
+            }
+            Some(q.prefix.clone())
+        }
+    };
+    let rest: u64 = q.limit - keys.len() as u64;
+    let items: Vec<RawItem> = match rest {
+        0 => vec![],
+        _ => kv.page(&PageRequest::new(b, after, rest))?,
+    };
+    keys.extend(
+        items
+            .into_iter()
+            .map(|i: RawItem| i.into_pair().0)
+            .take_while(|k: &Vec<u8>| k.starts_with(&q.prefix)),
+    );
+    let next: String = match keys.len() as u64 == q.limit {
+        true => keys
+            .last()
+            .map(|k| format!(r#""{}""#, percent_encode(k)))
+            .unwrap_or_else(|| String::from("null")),
+        false => String::from("null"),
+    };
+    let body: String = format!(
+        r#"{{"keys":[{}],"next":{}}}"#,
+        keys.iter()
+            .map(|k| format!(r#""{}""#, percent_encode(k)))
+            .collect::<Vec<_>>()
+            .join(","),
+        next,
+    );
+    Ok(Response::new(200, "application/json", body.into_bytes()))
+}
+
+fn route(
+    kv: &mut Backend,
+    method: &str,
+    target: &str,
+    body: Vec<u8>,
+) -> Result<Response, Response> {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("GET", ["buckets", b, "keys", k]) => {
+            match kv.get(&bucket(b)?, &percent_decode(k, false)?)? {
+                None => Ok(Response::text(404, "Key not found")),
+                Some(v) => Ok(Response::new(200, "application/octet-stream", v)),
+            }
+        }
+        ("PUT", ["buckets", b, "keys", k]) => {
+            kv.put(
+                &bucket(b)?,
+                vec![Item::new(percent_decode(k, false)?, body)],
+            )?;
+            Ok(Response::empty(204))
+        }
+        ("DELETE", ["buckets", b, "keys", k]) => {
+            match kv.del(&bucket(b)?, &percent_decode(k, false)?)? {
+                0 => Ok(Response::text(404, "Key not found")),
+                _ => Ok(Response::empty(204)),
+            }
+        }
+        ("GET", ["buckets", b, "keys"]) => Ok(list(kv, bucket(b)?, Query::parse(query)?)?),
+        ("DELETE", ["buckets", b]) => {
+            kv.drop_bucket(&bucket(b)?)?;
+            Ok(Response::empty(204))
+        }
+        (_, ["buckets", _, "keys", _]) | (_, ["buckets", _, "keys"]) | (_, ["buckets", _]) => {
+            Err(Response::text(405, "Method not allowed"))
+        }
+        _ => Err(Response::text(404, "No such resource")),
+    }
+}
+
+/// Reads from the stream until the deadline; every read waits at most the remaining time.
+struct DeadlineReader {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left: Duration = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "Request deadline exceeded",
+            ));
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+/// Reads a line of at most `LINE_MAX` bytes.
+fn read_line_bounded<R>(r: &mut R) -> Result<String, Response>
+where
+    R: BufRead,
+{
+    let mut line: String = String::new();
+    r.take(LINE_MAX)
+        .read_line(&mut line)
+        .map_err(|_| Response::text(400, "Unable to read the request"))?;
+    match line.ends_with('\n') || (line.len() as u64) < LINE_MAX {
+        true => Ok(line),
+        false => Err(Response::text(400, "Line too long")),
+    }
+}
+
+fn read_request<R>(r: &mut R) -> Result<(String, String, Vec<u8>), Response>
+where
+    R: BufRead,
+{
+    let bad = |_| Response::text(400, "Unable to read the request");
+    let line: String = read_line_bounded(r)?;
+    let mut parts = line.split_whitespace();
+    let method: String = parts.next().map(String::from).unwrap_or_default();
+    let target: String = parts.next().map(String::from).unwrap_or_default();
+    if target.is_empty() {
+        return Err(Response::text(400, "Invalid request line"));
+    }
+    let mut len: usize = 0;
+    loop {
+        let header: String = read_line_bounded(r)?;
+        let header: &str = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, val)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = val
+                    .trim()
+                    .parse()
+                    .map_err(|_| Response::text(400, "Invalid content length"))?;
+            }
+        }
+    }
+    if len > BODY_MAX {
+        return Err(Response::text(413, "Body too large"));
+    }
+    let mut body: Vec<u8> = vec![0; len];
+    r.read_exact(&mut body).map_err(bad)?;
+    Ok((method, target, body))
+}
+
+fn handle(kv: &mut Backend, stream: TcpStream) -> std::io::Result<()> {
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut r = BufReader::new(DeadlineReader {
+        stream: stream.try_clone()?,
+        deadline: Instant::now() + REQUEST_TIMEOUT,
+    });
+    let res: Response = match read_request(&mut r) {
+        Err(res) => res,
+        Ok((method, target, body)) => {
+            route(kv, &method, &target, body).unwrap_or_else(|res: Response| res)
+        }
+    };
+    let mut w = stream;
+    res.write_to(&mut w)
+}
+
+/// Serves requests one by one using the backend.
+///
+/// A request must arrive within `REQUEST_TIMEOUT` in total(lines are capped at `LINE_MAX` bytes),
+/// and a response which cannot be written within `IO_TIMEOUT` is dropped, so that a slow client
+/// cannot block the others for longer than that.
+pub fn serve(kv: &mut Backend, addr: &str) -> Result<(), Event> {
+    let l: TcpListener = TcpListener::bind(addr)
+        .map_err(|e| Event::ConnectionError(format!("Unable to listen: {}", e)))?;
+    eprintln!("listening: {}", addr);
+    for stream in l.incoming() {
+        let handled = stream.and_then(|s| handle(kv, s));
+        if let Err(e) = handled {
+            eprintln!("Unable to handle a request: {}", e);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::process;
+
+use rs_rdb2kv::bucket::Bucket;
+use rs_rdb2kv::dump::{DumpFormat, DumpReader, DumpWriter};
+use rs_rdb2kv::evt::Event;
+use rs_rdb2kv::item::{Item, RawItem};
+use rs_rdb2kv::list::PageRequest;
+
+mod backend;
+mod encoding;
+mod http;
+mod pg;
+mod resp;
+mod sqlite;
+
+use backend::Backend;
+use encoding::Encoding;
+
+const USAGE: &str = r#"Usage: rdb2kv <url> [options] <command> [args]
+
+url: a SQLite file path(optionally prefixed with sqlite://) or a PostgreSQL DSN
+
+commands:
+  get <bucket> <key>          prints the value(exits with 1 if missing)
+  put <bucket> <key> [val]    upserts the value(reads stdin if val is missing)
+  del <bucket> <key>          deletes the key
+  list <bucket>               prints keys in the key order
+  buckets                     prints bucket names
+  drop <bucket>               drops the bucket
+  export <bucket>             writes a dump of the bucket
+  import <bucket>             upserts items from a dump
+  serve-http <addr>           serves buckets over HTTP(e.g. 127.0.0.1:8080)
+  serve-resp <addr>           serves buckets over the Redis protocol(e.g. 127.0.0.1:6379)
+
+options:
+  --key-encoding utf8|hex|base64   keys on the command line(default: utf8)
+  --val-encoding utf8|hex|base64   values on the command line(default: utf8)
+  --after <key>                    list: keys greater than this key
+  --limit <n>                      list: the maximum number of keys
+  --format ndjson|binary           export/import: the dump format(default: ndjson)
+  --file <path>                    export/import: the dump file(default: stdout/stdin)
+"#;
+
+struct Args {
+    positional: Vec<String>,
+    options: BTreeMap<String, String>,
+}
+
+impl Args {
+    fn parse<I>(args: I) -> Result<Self, Event>
+    where
+        I: Iterator<Item = String>,
+    {
+        let mut positional: Vec<String> = vec![];
+        let mut options: BTreeMap<String, String> = BTreeMap::new();
+        let mut it = args.peekable();
+        while let Some(a) = it.next() {
+            match a.strip_prefix("--") {
+                None => positional.push(a),
+                Some(name) => {
+                    let val: String = it.next().ok_or_else(|| {
+                        Event::UnexpectedError(format!("Missing value for --{}", name))
+                    })?;
+                    options.insert(String::from(name), val);
+                }
+            }
+        }
+        Ok(Self {
+            positional,
+            options,
+        })
+    }
+
+    fn arg(&self, i: usize, name: &str) -> Result<&str, Event> {
+        self.positional
+            .get(i)
+            .map(|s| s.as_str())
+            .ok_or_else(|| Event::UnexpectedError(format!("Missing argument: {}", name)))
+    }
+
+    fn opt(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|s| s.as_str())
+    }
+
+    fn encoding(&self, name: &str) -> Result<Encoding, Event> {
+        self.opt(name).unwrap_or("utf8").parse()
+    }
+
+    fn format(&self) -> Result<DumpFormat, Event> {
+        match self.opt("format").unwrap_or("ndjson") {
+            "ndjson" => Ok(DumpFormat::Ndjson),
+            "binary" => Ok(DumpFormat::Binary),
+            f => Err(Event::UnexpectedError(format!("Unknown format: {}", f))),
+        }
+    }
+
+    fn bucket(&self) -> Result<Bucket, Event> {
+        self.arg(2, "bucket").map(|b| Bucket::from(String::from(b)))
+    }
+}
+
+fn read_stdin() -> Result<Vec<u8>, Event> {
+    let mut buf: Vec<u8> = vec![];
+    io::stdin()
+        .read_to_end(&mut buf)
+        .map_err(|e| Event::UnexpectedError(format!("Unable to read stdin: {}", e)))?;
+    Ok(buf)
+}
+
+fn list(kv: &mut Backend, a: &Args, kenc: Encoding) -> Result<Vec<Vec<u8>>, Event> {
+    let b: Bucket = a.bucket()?;
+    match (a.opt("after"), a.opt("limit")) {
+        (None, None) => kv.keys(&b),
+        (after, limit) => {
+            let after: Option<Vec<u8>> = after.map(|k| kenc.decode(k)).transpose()?;
+            let limit: u64 = limit
+                .map(|l| l.parse::<u64>())
+                .transpose()
+                .map_err(|e| Event::UnexpectedError(format!("Invalid limit: {}", e)))?
+                .unwrap_or(u64::MAX >> 1);
+            let items: Vec<RawItem> = kv.page(&PageRequest::new(b, after, limit))?;
+            Ok(items.into_iter().map(|i| i.into_pair().0).collect())
+        }
+    }
+}
+
+fn export(kv: &mut Backend, a: &Args) -> Result<u64, Event> {
+    let b: Bucket = a.bucket()?;
+    let out: Box<dyn Write> = match a.opt("file") {
+        None => Box::new(io::stdout()),
+        Some(path) => Box::new(
+            File::create(path)
+                .map_err(|e| Event::UnexpectedError(format!("Unable to create: {}", e)))?,
+        ),
+    };
+    let mut w = DumpWriter::new(a.format()?, BufWriter::new(out), &b)?;
+    let cnt: u64 = kv.export(&b, &mut w)?;
+    w.finish()?;
+    Ok(cnt)
+}
+
+fn import(kv: &mut Backend, a: &Args) -> Result<u64, Event> {
+    let b: Bucket = a.bucket()?;
+    let input: Box<dyn Read> = match a.opt("file") {
+        None => Box::new(io::stdin()),
+        Some(path) => Box::new(
+            File::open(path)
+                .map_err(|e| Event::UnexpectedError(format!("Unable to open: {}", e)))?,
+        ),
+    };
+    let mut r = DumpReader::new(a.format()?, input)?;
+    kv.import(&b, &mut r)
+}
+
+fn sub(a: &Args) -> Result<i32, Event> {
+    let kenc: Encoding = a.encoding("key-encoding")?;
+    let venc: Encoding = a.encoding("val-encoding")?;
+    let cmd: &str = a.arg(1, "command")?;
+    if cmd == "serve-resp" {
+        resp::serve(a.arg(0, "url")?, a.arg(2, "addr")?)?;
+        return Ok(0);
+    }
+    let mut kv: Backend = Backend::open(a.arg(0, "url")?)?;
+    let key = |i: usize| a.arg(i, "key").and_then(|k| kenc.decode(k));
+    match cmd {
+        "get" => match kv.get(&a.bucket()?, &key(3)?)? {
+            None => return Ok(1),
+            Some(v) => println!("{}", venc.encode(&v)),
+        },
+        "put" => {
+            let val: Vec<u8> = match a.positional.get(4) {
+                None => read_stdin()?,
+                Some(v) => venc.decode(v)?,
+            };
+            let cnt: u64 = kv.put(&a.bucket()?, vec![Item::new(key(3)?, val)])?;
+            eprintln!("upserted: {}", cnt);
+        }
+        "del" => {
+            let cnt: u64 = kv.del(&a.bucket()?, &key(3)?)?;
+            eprintln!("deleted: {}", cnt);
+        }
+        "list" => {
+            for k in list(&mut kv, a, kenc)? {
+                println!("{}", kenc.encode(&k));
+            }
+        }
+        "buckets" => {
+            for b in kv.buckets()? {
+                println!("{}", b.as_str());
+            }
+        }
+        "drop" => kv.drop_bucket(&a.bucket()?)?,
+        "export" => {
+            let cnt: u64 = export(&mut kv, a)?;
+            eprintln!("exported: {}", cnt);
+        }
+        "import" => {
+            let cnt: u64 = import(&mut kv, a)?;
+            eprintln!("imported: {}", cnt);
+        }
+        "serve-http" => http::serve(&mut kv, a.arg(2, "addr")?)?,
+        _ => return Err(Event::UnexpectedError(format!("Unknown command: {}", cmd))),
+    }
+    Ok(0)
+}
+
+fn main() {
+    let parsed: Result<Args, Event> = Args::parse(env::args().skip(1));
+    if parsed
+        .as_ref()
+        .map(|a| a.positional.len() < 2)
+        .unwrap_or(true)
+    {
+        eprint!("{}", USAGE);
+        process::exit(2)
+    }
+    let code: i32 = match parsed.and_then(|a| sub(&a)) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            2
+        }
+    };
+    process::exit(code)
+}
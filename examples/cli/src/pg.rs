@@ -0,0 +1,305 @@
+use std::io::{Read, Write};
+
+use rs_rdb2kv::bucket::{bucket_checker_new_identifier, Bucket};
+use rs_rdb2kv::del::{delete_key_bytes_mut, drop_bucket_mut, drop_builder_default_checked};
+use rs_rdb2kv::dump::{export_bucket_new_mut, import_bucket_new_mut, DumpReader, DumpWriter};
+use rs_rdb2kv::evt::Event;
+use rs_rdb2kv::get::{select_bytes_new_mut, GetRequest};
+use rs_rdb2kv::item::{Item, RawItem};
+use rs_rdb2kv::list::{
+    list_buckets_new_mut, list_items_page_new_mut, list_keys_bytes_new_mut,
+    list_query_builder_checked, page_builder_new, PageBuilder, PageRequest,
+};
+use rs_rdb2kv::retry::{retry_mut, retryable_default, RetryPolicy};
+use rs_rdb2kv::upsert::{
+    upsert_builder_new, upsert_bytes_all_new_mut, BulkRequest, Requests, UpsertBuilder,
+};
+
+use postgres::error::SqlState;
+use postgres::{Client, NoTls, Row, Transaction};
+
+fn unexpected(msg: &'static str) -> impl Fn(postgres::Error) -> Event {
+    move |e: postgres::Error| match e.code() {
+        Some(&SqlState::UNDEFINED_TABLE) => Event::BucketNotFound(format!("{}: {}", msg, e)),
+        Some(&SqlState::T_R_SERIALIZATION_FAILURE) | Some(&SqlState::T_R_DEADLOCK_DETECTED) => {
+            Event::Conflict(format!("{}: {}", msg, e))
+        }
+        _ => Event::UnexpectedError(format!("{}: {}", msg, e)),
+    }
+}
+
+fn checked<F>(build: F) -> impl Fn(&Bucket) -> Result<String, Event>
+where
+    F: Fn(&str) -> String,
+{
+    let checker = bucket_checker_new_identifier();
+    move |b: &Bucket| {
+        checker(b)?;
+        Ok(build(b.as_str()))
+    }
+}
+
+fn create_query(b: &str) -> String {
+    format!(
+        r#"
+            CREATE TABLE IF NOT EXISTS {} (
+                key BYTEA,
+                val BYTEA,
+                CONSTRAINT {}_pkc PRIMARY KEY(key)
+            )
+        "#,
+        b, b,
+    )
+}
+
+fn upsert_builder() -> impl UpsertBuilder {
+    upsert_builder_new(
+        checked(create_query),
+        checked(|b: &str| {
+            format!(
+                r#"
+                    INSERT INTO {} AS tgt
+                    VALUES($1::BYTEA, $2::BYTEA)
+                    ON CONFLICT ON CONSTRAINT {}_pkc
+                    DO UPDATE
+                    SET val = EXCLUDED.val
+                    WHERE tgt.val <> EXCLUDED.val
+                "#,
+                b, b,
+            )
+        }),
+    )
+}
+
+/// Inserts an item only if the key is absent(the primary key decides atomically).
+fn insert_builder() -> impl UpsertBuilder {
+    upsert_builder_new(
+        checked(create_query),
+        checked(|b: &str| {
+            format!(
+                r#"
+                    INSERT INTO {}
+                    VALUES($1::BYTEA, $2::BYTEA)
+                    ON CONFLICT ON CONSTRAINT {}_pkc
+                    DO NOTHING
+                "#,
+                b, b,
+            )
+        }),
+    )
+}
+
+/// Replaces the value of an item only if the key exists(no row is inserted for absent keys).
+fn update_builder() -> impl UpsertBuilder {
+    upsert_builder_new(
+        checked(create_query),
+        checked(|b: &str| format!("UPDATE {} SET val = $2::BYTEA WHERE key = $1::BYTEA", b)),
+    )
+}
+
+fn page_builder() -> impl PageBuilder {
+    page_builder_new(
+        checked(|b: &str| format!("SELECT key, val FROM {} ORDER BY key LIMIT $1::BIGINT", b)),
+        checked(|b: &str| {
+            format!(
+                "SELECT key, val FROM {} WHERE key > $1::BYTEA ORDER BY key LIMIT $2::BIGINT",
+                b
+            )
+        }),
+    )
+}
+
+fn upsert_handler<'a, B>(
+    builder: B,
+) -> impl Fn(Requests, &mut Transaction<'a>) -> Result<u64, Event>
+where
+    B: UpsertBuilder,
+{
+    upsert_bytes_all_new_mut(
+        |t: &mut Transaction, query: &str| {
+            t.execute(query, &[])
+                .map_err(unexpected("Unable to create bucket"))
+        },
+        |t: &mut Transaction, query: &str, key: &[u8], val: &[u8]| {
+            t.execute(query, &[&key, &val])
+                .map_err(unexpected("Unable to upsert"))
+        },
+        builder,
+    )
+}
+
+fn row2item(r: &Row) -> Result<RawItem, Event> {
+    let conv = unexpected("Unable to get an item");
+    Ok(Item::new(
+        r.try_get(0).map_err(&conv)?,
+        r.try_get(1).map_err(&conv)?,
+    ))
+}
+
+fn page_items(
+    c: &mut Client,
+    query: &str,
+    after: Option<&[u8]>,
+    limit: u64,
+) -> Result<Vec<RawItem>, Event> {
+    let limit: i64 = limit as i64;
+    let rows: Vec<Row> = match after {
+        None => c.query(query, &[&limit]),
+        Some(a) => c.query(query, &[&a, &limit]),
+    }
+    .map_err(unexpected("Unable to get items"))?;
+    rows.iter().map(row2item).collect()
+}
+
+fn first_column<T>(rows: Vec<Row>) -> Result<Vec<T>, Event>
+where
+    T: for<'a> postgres::types::FromSql<'a>,
+{
+    rows.iter()
+        .map(|r: &Row| r.try_get(0).map_err(unexpected("Unable to get a column")))
+        .collect()
+}
+
+pub fn open(url: &str) -> Result<Client, Event> {
+    Client::connect(url, NoTls)
+        .map_err(|e| Event::ConnectionError(format!("Unable to connect: {}", e)))
+}
+
+pub fn get(c: &mut Client, b: &Bucket, key: &[u8]) -> Result<Option<Vec<u8>>, Event> {
+    let f = select_bytes_new_mut(
+        |c: &mut Client, query: &str, key: &[u8]| {
+            let o: Option<Row> = c
+                .query_opt(query, &[&key])
+                .map_err(unexpected("Unable to get a value"))?;
+            o.map(|r: Row| r.try_get(0))
+                .transpose()
+                .map_err(unexpected("Unable to get a value"))
+        },
+        checked(|b: &str| format!("SELECT val FROM {} WHERE key = $1::BYTEA", b)),
+    );
+    let req = GetRequest::new(Bucket::from(String::from(b.as_str())), key.to_vec());
+    f(&req, c)
+}
+
+/// Upserts items in a single transaction which is replayed on serialization failures/deadlocks.
+pub fn put(c: &mut Client, b: &Bucket, items: Vec<RawItem>) -> Result<u64, Event> {
+    put_with(c, b, items, upsert_builder)
+}
+
+/// Inserts an item unless the key exists; returns 1 if inserted.
+pub fn insert(c: &mut Client, b: &Bucket, item: RawItem) -> Result<u64, Event> {
+    put_with(c, b, vec![item], insert_builder)
+}
+
+/// Updates the value of an existing key; returns 0 if the key does not exist.
+pub fn update(c: &mut Client, b: &Bucket, key: &[u8], val: &[u8]) -> Result<u64, Event> {
+    put_with(
+        c,
+        b,
+        vec![Item::new(key.to_vec(), val.to_vec())],
+        update_builder,
+    )
+}
+
+fn put_with<F, B>(c: &mut Client, b: &Bucket, items: Vec<RawItem>, builder: F) -> Result<u64, Event>
+where
+    F: Fn() -> B,
+    B: UpsertBuilder,
+{
+    retry_mut(
+        &RetryPolicy::default(),
+        &retryable_default,
+        &std::thread::sleep,
+        || {
+            let mut t: Transaction = c
+                .transaction()
+                .map_err(unexpected("Unable to start transaction"))?;
+            let copied: Vec<RawItem> = items
+                .iter()
+                .map(|i| Item::new(i.as_key().clone(), i.as_val().clone()))
+                .collect();
+            let req = BulkRequest::new(Bucket::from(String::from(b.as_str())), copied);
+            let cnt: u64 = upsert_handler(builder())(vec![req].into_iter(), &mut t)?;
+            t.commit().map_err(unexpected("Unable to commit changes"))?;
+            Ok(cnt)
+        },
+    )
+}
+
+pub fn del(c: &mut Client, b: &Bucket, key: &[u8]) -> Result<u64, Event> {
+    let f = delete_key_bytes_mut(
+        |c: &mut Client, query: &str, key: &[u8]| {
+            c.execute(query, &[&key])
+                .map_err(unexpected("Unable to delete an item"))
+        },
+        checked(|b: &str| format!("DELETE FROM {} WHERE key = $1::BYTEA", b)),
+    );
+    f(b, key, c)
+}
+
+pub fn keys(c: &mut Client, b: &Bucket) -> Result<Vec<Vec<u8>>, Event> {
+    let f = list_keys_bytes_new_mut(
+        |c: &mut Client, query: &str| {
+            let rows: Vec<Row> = c
+                .query(query, &[])
+                .map_err(unexpected("Unable to list keys"))?;
+            first_column(rows)
+        },
+        list_query_builder_checked(bucket_checker_new_identifier()),
+    );
+    f(b, c)
+}
+
+pub fn page(c: &mut Client, req: &PageRequest) -> Result<Vec<RawItem>, Event> {
+    list_items_page_new_mut(page_items, page_builder())(req, c)
+}
+
+pub fn buckets(c: &mut Client) -> Result<Vec<Bucket>, Event> {
+    let f = list_buckets_new_mut(
+        |c: &mut Client, query: &str| {
+            let rows: Vec<Row> = c
+                .query(query, &[])
+                .map_err(unexpected("Unable to list buckets"))?;
+            first_column(rows)
+        },
+        String::from(
+            r#"
+                SELECT tablename::TEXT FROM pg_tables
+                WHERE schemaname = current_schema()
+                ORDER BY tablename
+            "#,
+        ),
+    );
+    f(c)
+}
+
+pub fn drop(c: &mut Client, b: &Bucket) -> Result<(), Event> {
+    let f = drop_bucket_mut(
+        |c: &mut Client, query: &str| {
+            c.batch_execute(query)
+                .map_err(unexpected("Unable to drop the bucket"))
+        },
+        drop_builder_default_checked(bucket_checker_new_identifier()),
+    );
+    f(b, c)
+}
+
+pub fn export<W>(c: &mut Client, b: &Bucket, w: &mut DumpWriter<W>) -> Result<u64, Event>
+where
+    W: Write,
+{
+    let f = export_bucket_new_mut(list_items_page_new_mut(page_items, page_builder()), 1000);
+    f(b, c, w)
+}
+
+pub fn import<R>(c: &mut Client, b: &Bucket, r: &mut DumpReader<R>) -> Result<u64, Event>
+where
+    R: Read,
+{
+    let mut t: Transaction = c
+        .transaction()
+        .map_err(unexpected("Unable to start transaction"))?;
+    let cnt: u64 = import_bucket_new_mut(upsert_handler(upsert_builder()), 1000)(b, r, &mut t)?;
+    t.commit().map_err(unexpected("Unable to commit changes"))?;
+    Ok(cnt)
+}
@@ -0,0 +1,523 @@
+//! A Redis(RESP2/RESP3) front-end.
+//!
+//! Each client gets its own thread and its own backend connection.
+//! A Redis database is a bucket: `SELECT 3` switches to the bucket `db3`, and `SELECT name`
+//! switches to the bucket `name`(the default bucket is `db0`).
+//! A missing bucket behaves like an empty database.
+//!
+//! Supported commands: GET, SET(NX/XX), DEL, EXISTS, MGET, MSET, KEYS, SCAN(MATCH/COUNT),
+//! SELECT, FLUSHDB, PING, ECHO, HELLO, COMMAND, CLIENT and QUIT.
+//! SCAN cursors are per-connection ids of the last returned key; only the latest
+//! `SCAN_CURSORS_MAX` cursors are kept.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use rs_rdb2kv::bucket::Bucket;
+use rs_rdb2kv::evt::Event;
+use rs_rdb2kv::item::{Item, RawItem};
+use rs_rdb2kv::list::PageRequest;
+
+use crate::backend::Backend;
+
+const SCAN_COUNT_DEFAULT: u64 = 10;
+const SCAN_CURSORS_MAX: usize = 1024;
+
+/// The max length of a bulk string(same as `proto-max-bulk-len` of Redis).
+const BULK_MAX: u64 = 512 * 1024 * 1024;
+
+/// The max number of bulk strings in a command.
+const MULTIBULK_MAX: u64 = 1024 * 1024;
+
+/// The max length of an inline command or a length line.
+const LINE_MAX: u64 = 64 * 1024;
+
+enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Int(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+    Map(Vec<(Reply, Reply)>),
+}
+
+impl Reply {
+    fn bulk(b: &[u8]) -> Self {
+        Self::Bulk(Some(b.to_vec()))
+    }
+
+    fn error(msg: &str) -> Self {
+        Self::Error(format!("ERR {}", msg))
+    }
+
+    fn write_to<W>(&self, w: &mut W, proto: u8) -> io::Result<()>
+    where
+        W: Write,
+    {
+        match self {
+            Reply::Simple(s) => write!(w, "+{}\r\n", s),
+            Reply::Error(s) => write!(w, "-{}\r\n", s.replace(['\r', '\n'], " ")),
+            Reply::Int(i) => write!(w, ":{}\r\n", i),
+            Reply::Bulk(None) => match proto {
+                3 => w.write_all(b"_\r\n"),
+                _ => w.write_all(b"$-1\r\n"),
+            },
+            Reply::Bulk(Some(b)) => {
+                write!(w, "${}\r\n", b.len())?;
+                w.write_all(b)?;
+                w.write_all(b"\r\n")
+            }
+            Reply::Array(a) => {
+                write!(w, "*{}\r\n", a.len())?;
+                a.iter().try_for_each(|r| r.write_to(w, proto))
+            }
+            Reply::Map(m) => {
+                match proto {
+                    3 => write!(w, "%{}\r\n", m.len())?,
+                    _ => write!(w, "*{}\r\n", m.len() * 2)?,
+                }
+                m.iter().try_for_each(|(k, v)| {
+                    k.write_to(w, proto)?;
+                    v.write_to(w, proto)
+                })
+            }
+        }
+    }
+}
+
+impl From<Event> for Reply {
+    fn from(e: Event) -> Self {
+        match e {
+            Event::InvalidBucket(b) => Reply::error(&format!("invalid bucket: {}", b)),
+            Event::BucketNotFound(b) => Reply::error(&format!("no such bucket: {}", b)),
+            Event::Conflict(m) => Reply::error(&format!("conflict: {}", m)),
+            Event::PoolTimeout(m) => Reply::error(&format!("pool timeout: {}", m)),
+            Event::ConnectionError(m) => Reply::error(&format!("connection error: {}", m)),
+            Event::ChecksumMismatch(m) => Reply::error(&format!("checksum mismatch: {}", m)),
+            Event::UnexpectedError(m) => Reply::error(&m),
+            e => Reply::error(&format!("{:?}", e)),
+        }
+    }
+}
+
+/// Treats a missing bucket as an empty one.
+fn or_empty<T>(r: Result<T, Event>, empty: T) -> Result<T, Event> {
+    match r {
+        Err(Event::BucketNotFound(_)) => Ok(empty),
+        r => r,
+    }
+}
+
+/// Matches a key against a Redis glob pattern(`*`, `?`, `[a-z]`, `[^abc]` and `\x`).
+///
+/// Every other token matches exactly one byte, so only the last `*` needs to be retried:
+/// O(pattern × key) whatever the number of `*`.
+fn glob(p: &[u8], s: &[u8]) -> bool {
+    let (mut pi, mut si): (usize, usize) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while si < s.len() {
+        if p.get(pi) == Some(&b'*') {
+            pi += 1;
+            star = Some((pi, si));
+            continue;
+        }
+        if let Some(next) = token(p, pi, s[si]) {
+            pi = next;
+            si += 1;
+            continue;
+        }
+        match star {
+            None => return false,
+            Some((sp, ss)) => {
+                star = Some((sp, ss + 1));
+                pi = sp;
+                si = ss + 1;
+            }
+        }
+    }
+    p[pi..].iter().all(|c| *c == b'*')
+}
+
+/// Matches a byte against the token at `pi`(not `*`) and returns the index of the next token.
+fn token(p: &[u8], pi: usize, c: u8) -> Option<usize> {
+    let matched = |hit: bool, next: usize| Some(next).filter(|_| hit);
+    match p.get(pi)? {
+        b'?' => Some(pi + 1),
+        b'[' => match class(&p[pi + 1..], c) {
+            Some((hit, rest)) => matched(hit, p.len() - rest.len()),
+            None => matched(c == b'[', pi + 1),
+        },
+        b'\\' if pi + 1 < p.len() => matched(c == p[pi + 1], pi + 2),
+        t => matched(c == *t, pi + 1),
+    }
+}
+
+/// Matches a byte against a class body(after `[`) and returns the rest of the pattern.
+///
+/// Returns None if the class is not closed.
+fn class(p: &[u8], c: u8) -> Option<(bool, &[u8])> {
+    let (negate, body) = match p.first() {
+        Some(b'^') => (true, &p[1..]),
+        _ => (false, p),
+    };
+    let end: usize = body.iter().position(|&b| b == b']')?;
+    let (set, rest) = (&body[..end], &body[end + 1..]);
+    let mut hit: bool = false;
+    let mut i: usize = 0;
+    while i < set.len() {
+        match (set.get(i + 1), set.get(i + 2)) {
+            (Some(b'-'), Some(&hi)) => {
+                hit |= (set[i]..=hi).contains(&c);
+                i += 3;
+            }
+            _ => {
+                hit |= set[i] == c;
+                i += 1;
+            }
+        }
+    }
+    Some((hit != negate, rest))
+}
+
+struct Session {
+    kv: Backend,
+    bucket: Bucket,
+    proto: u8,
+    cursors: BTreeMap<u64, Vec<u8>>,
+    last_cursor: u64,
+}
+
+impl Session {
+    fn new(kv: Backend) -> Self {
+        Self {
+            kv,
+            bucket: Bucket::from(String::from("db0")),
+            proto: 2,
+            cursors: BTreeMap::new(),
+            last_cursor: 0,
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, Event> {
+        or_empty(self.kv.get(&self.bucket, key), None)
+    }
+
+    fn set(&mut self, args: &[Vec<u8>]) -> Result<Reply, Event> {
+        let (key, val, opts) = match args {
+            [key, val, opts @ ..] => (key, val, opts),
+            _ => return Ok(Reply::error("wrong number of arguments for 'set' command")),
+        };
+        let mut nx: bool = false;
+        let mut xx: bool = false;
+        for o in opts {
+            match o.to_ascii_uppercase().as_slice() {
+                b"NX" => nx = true,
+                b"XX" => xx = true,
+                _ => return Ok(Reply::error("only NX and XX options are supported")),
+            }
+        }
+        let set: u64 = match (nx, xx) {
+            (true, true) => return Ok(Reply::error("syntax error")),
+            (true, false) => self
+                .kv
+                .insert(&self.bucket, Item::new(key.clone(), val.clone()))?,
+            (false, true) => or_empty(self.kv.update(&self.bucket, key, val), 0)?,
+            (false, false) => self
+                .kv
+                .put(&self.bucket, vec![Item::new(key.clone(), val.clone())])
+                .map(|_| 1)?,
+        };
+        match set {
+            0 => Ok(Reply::Bulk(None)),
+            _ => Ok(Reply::Simple("OK")),
+        }
+    }
+
+    fn scan(&mut self, args: &[Vec<u8>]) -> Result<Reply, Event> {
+        let cursor: u64 = match args.first().and_then(|c| parse_u64(c)) {
+            None => return Ok(Reply::error("invalid cursor")),
+            Some(c) => c,
+        };
+        let after: Option<Vec<u8>> = match cursor {
+            0 => None,
+            c => match self.cursors.remove(&c) {
+                None => return Ok(Reply::error("invalid cursor")),
+                Some(k) => Some(k),
+            },
+        };
+        let mut pattern: Vec<u8> = b"*".to_vec();
+        let mut count: u64 = SCAN_COUNT_DEFAULT;
+        for pair in args[1..].chunks(2) {
+            match (pair[0].to_ascii_uppercase().as_slice(), pair.get(1)) {
+                (b"MATCH", Some(p)) => pattern = p.clone(),
+                (b"COUNT", Some(c)) => match parse_u64(c).filter(|c| 0 < *c) {
+                    None => return Ok(Reply::error("value is not an integer or out of range")),
+                    Some(c) => count = c,
+                },
+                _ => return Ok(Reply::error("syntax error")),
+            }
+        }
+        let req = PageRequest::new(
+            Bucket::from(String::from(self.bucket.as_str())),
+            after,
+            count,
+        );
+        let items: Vec<RawItem> = or_empty(self.kv.page(&req), vec![])?;
+        let next: u64 = match (items.len() as u64) < count {
+            true => 0,
+            false => {
+                self.last_cursor += 1;
+                let last: Vec<u8> = items.last().map(|i| i.as_key().clone()).unwrap_or_default();
+                self.cursors.insert(self.last_cursor, last);
+                while SCAN_CURSORS_MAX < self.cursors.len() {
+                    self.cursors.pop_first();
+                }
+                self.last_cursor
+            }
+        };
+        let keys: Vec<Reply> = items
+            .into_iter()
+            .map(|i: RawItem| i.into_pair().0)
+            .filter(|k| glob(&pattern, k))
+            .map(|k| Reply::Bulk(Some(k)))
+            .collect();
+        Ok(Reply::Array(vec![
+            Reply::bulk(next.to_string().as_bytes()),
+            Reply::Array(keys),
+        ]))
+    }
+
+    fn hello(&mut self, args: &[Vec<u8>]) -> Reply {
+        if let Some(v) = args.first() {
+            match parse_u64(v) {
+                Some(v @ 2..=3) => self.proto = v as u8,
+                _ => return Reply::Error(String::from("NOPROTO unsupported protocol version")),
+            }
+        }
+        Reply::Map(vec![
+            (Reply::bulk(b"server"), Reply::bulk(b"rdb2kv")),
+            (
+                Reply::bulk(b"version"),
+                Reply::bulk(env!("CARGO_PKG_VERSION").as_bytes()),
+            ),
+            (Reply::bulk(b"proto"), Reply::Int(self.proto as i64)),
+            (Reply::bulk(b"mode"), Reply::bulk(b"standalone")),
+            (Reply::bulk(b"role"), Reply::bulk(b"master")),
+            (Reply::bulk(b"modules"), Reply::Array(vec![])),
+        ])
+    }
+
+    fn exec(&mut self, cmd: &[u8], args: &[Vec<u8>]) -> Result<Reply, Event> {
+        let arity = |ok: bool| match ok {
+            true => Ok(()),
+            false => Err(Reply::error(&format!(
+                "wrong number of arguments for '{}' command",
+                String::from_utf8_lossy(cmd).to_lowercase()
+            ))),
+        };
+        let checked = match cmd {
+            b"GET" | b"ECHO" | b"SELECT" | b"KEYS" => arity(args.len() == 1),
+            b"DEL" | b"EXISTS" | b"MGET" | b"SCAN" => arity(!args.is_empty()),
+            b"MSET" => arity(!args.is_empty() && args.len().is_multiple_of(2)),
+            _ => Ok(()),
+        };
+        if let Err(r) = checked {
+            return Ok(r);
+        }
+        match cmd {
+            b"PING" => Ok(match args.first() {
+                None => Reply::Simple("PONG"),
+                Some(m) => Reply::bulk(m),
+            }),
+            b"ECHO" => Ok(Reply::bulk(&args[0])),
+            b"HELLO" => Ok(self.hello(args)),
+            b"COMMAND" => Ok(Reply::Array(vec![])),
+            b"CLIENT" | b"QUIT" => Ok(Reply::Simple("OK")),
+            b"SELECT" => {
+                let name: String = String::from_utf8_lossy(&args[0]).into_owned();
+                self.bucket = match name.parse::<u64>() {
+                    Ok(i) => Bucket::from(format!("db{}", i)),
+                    Err(_) => Bucket::from(name),
+                };
+                self.cursors.clear();
+                Ok(Reply::Simple("OK"))
+            }
+            b"GET" => self.get(&args[0]).map(Reply::Bulk),
+            b"SET" => self.set(args),
+            b"MGET" => args
+                .iter()
+                .map(|k| self.get(k).map(Reply::Bulk))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Reply::Array),
+            b"MSET" => {
+                let items: Vec<RawItem> = args
+                    .chunks(2)
+                    .map(|kv| Item::new(kv[0].clone(), kv[1].clone()))
+                    .collect();
+                self.kv.put(&self.bucket, items)?;
+                Ok(Reply::Simple("OK"))
+            }
+            b"DEL" => args
+                .iter()
+                .try_fold(0, |tot: u64, k| {
+                    or_empty(self.kv.del(&self.bucket, k), 0).map(|cnt| tot + cnt)
+                })
+                .map(|tot| Reply::Int(tot as i64)),
+            b"EXISTS" => args
+                .iter()
+                .try_fold(0, |tot: u64, k| {
+                    self.get(k).map(|o| tot + o.map(|_| 1).unwrap_or(0))
+                })
+                .map(|tot| Reply::Int(tot as i64)),
+            b"KEYS" => {
+                let keys: Vec<Vec<u8>> = or_empty(self.kv.keys(&self.bucket), vec![])?;
+                Ok(Reply::Array(
+                    keys.into_iter()
+                        .filter(|k| glob(&args[0], k))
+                        .map(|k| Reply::Bulk(Some(k)))
+                        .collect(),
+                ))
+            }
+            b"SCAN" => self.scan(args),
+            b"FLUSHDB" => {
+                self.kv.drop_bucket(&self.bucket)?;
+                Ok(Reply::Simple("OK"))
+            }
+            _ => Ok(Reply::error(&format!(
+                "unknown command '{}'",
+                String::from_utf8_lossy(cmd)
+            ))),
+        }
+    }
+}
+
+fn parse_u64(b: &[u8]) -> Option<u64> {
+    std::str::from_utf8(b).ok().and_then(|s| s.parse().ok())
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads a line up to `LINE_MAX` bytes.
+fn read_line<R>(r: &mut R) -> io::Result<Option<Vec<u8>>>
+where
+    R: BufRead,
+{
+    let mut line: Vec<u8> = vec![];
+    match r.by_ref().take(LINE_MAX).read_until(b'\n', &mut line)? {
+        0 => Ok(None),
+        n if n as u64 == LINE_MAX && line.last() != Some(&b'\n') => Err(invalid("too long line")),
+        _ => {
+            while line
+                .last()
+                .map(|c| *c == b'\n' || *c == b'\r')
+                .unwrap_or(false)
+            {
+                line.pop();
+            }
+            Ok(Some(line))
+        }
+    }
+}
+
+/// Reads a bulk string; the buffer grows as bytes arrive(the length is untrusted).
+fn read_bulk<R>(r: &mut R) -> io::Result<Vec<u8>>
+where
+    R: BufRead,
+{
+    let head: Vec<u8> = read_line(r)?.ok_or_else(|| invalid("unexpected end of stream"))?;
+    let len: u64 = match head.split_first() {
+        Some((b'$', len)) => parse_u64(len),
+        _ => None,
+    }
+    .ok_or_else(|| invalid("expected a bulk string"))?;
+    if BULK_MAX < len {
+        return Err(invalid("invalid bulk length"));
+    }
+    let mut buf: Vec<u8> = vec![];
+    r.by_ref().take(len + 2).read_to_end(&mut buf)?;
+    match buf.len() as u64 == len + 2 && buf.ends_with(b"\r\n") {
+        true => {
+            buf.truncate(len as usize);
+            Ok(buf)
+        }
+        false => Err(invalid("unexpected end of bulk string")),
+    }
+}
+
+/// Reads a command as an array of bulk strings or as an inline command.
+fn read_command<R>(r: &mut R) -> io::Result<Option<Vec<Vec<u8>>>>
+where
+    R: BufRead,
+{
+    let line: Vec<u8> = match read_line(r)? {
+        None => return Ok(None),
+        Some(l) => l,
+    };
+    match line.split_first() {
+        Some((b'*', n)) => {
+            let n: u64 = parse_u64(n)
+                .filter(|n| *n <= MULTIBULK_MAX)
+                .ok_or_else(|| invalid("invalid multibulk length"))?;
+            (0..n)
+                .map(|_| read_bulk(r))
+                .collect::<io::Result<Vec<_>>>()
+                .map(Some)
+        }
+        _ => Ok(Some(
+            line.split(|c| c.is_ascii_whitespace())
+                .filter(|w| !w.is_empty())
+                .map(|w| w.to_vec())
+                .collect(),
+        )),
+    }
+}
+
+fn handle(url: &str, stream: TcpStream) -> io::Result<()> {
+    let mut r = BufReader::new(stream.try_clone()?);
+    let mut w = BufWriter::new(stream);
+    let mut s: Session = match Backend::open(url) {
+        Ok(kv) => Session::new(kv),
+        Err(e) => {
+            Reply::from(e).write_to(&mut w, 2)?;
+            return w.flush();
+        }
+    };
+    while let Some(c) = read_command(&mut r)? {
+        let (cmd, args) = match c.split_first() {
+            None => continue,
+            Some((cmd, args)) => (cmd.to_ascii_uppercase(), args),
+        };
+        let reply: Reply = s.exec(&cmd, args).unwrap_or_else(Reply::from);
+        reply.write_to(&mut w, s.proto)?;
+        w.flush()?;
+        if cmd == b"QUIT" {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Serves Redis clients; each client uses its own connection to the url.
+pub fn serve(url: &str, addr: &str) -> Result<(), Event> {
+    let l: TcpListener = TcpListener::bind(addr)
+        .map_err(|e| Event::ConnectionError(format!("Unable to listen: {}", e)))?;
+    eprintln!("listening: {}", addr);
+    for stream in l.incoming() {
+        match stream {
+            Err(e) => eprintln!("Unable to accept: {}", e),
+            Ok(s) => {
+                let url: String = String::from(url);
+                thread::spawn(move || {
+                    if let Err(e) = handle(&url, s) {
+                        eprintln!("Unable to handle a client: {}", e);
+                    }
+                });
+            }
+        }
+    }
+    Ok(())
+}
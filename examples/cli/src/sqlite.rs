@@ -0,0 +1,284 @@
+use std::io::{Read, Write};
+
+use rs_rdb2kv::bucket::{bucket_checker_new_identifier, Bucket};
+use rs_rdb2kv::del::{delete_key_bytes_mut, drop_bucket_mut, drop_builder_default_checked};
+use rs_rdb2kv::dump::{export_bucket_new_mut, import_bucket_new_mut, DumpReader, DumpWriter};
+use rs_rdb2kv::evt::Event;
+use rs_rdb2kv::get::{select_bytes_new_mut, GetRequest};
+use rs_rdb2kv::item::{Item, RawItem};
+use rs_rdb2kv::list::{
+    list_buckets_new_mut, list_items_page_new_mut, list_keys_bytes_new_mut,
+    list_query_builder_checked, page_builder_new, PageBuilder, PageRequest,
+};
+use rs_rdb2kv::upsert::{
+    upsert_builder_new, upsert_bytes_all_new_mut, BulkRequest, Requests, UpsertBuilder,
+};
+
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
+
+fn unexpected(msg: &'static str) -> impl Fn(rusqlite::Error) -> Event {
+    move |e: rusqlite::Error| match &e {
+        rusqlite::Error::SqliteFailure(_, Some(m)) if m.starts_with("no such table") => {
+            Event::BucketNotFound(format!("{}: {}", msg, e))
+        }
+        _ => Event::UnexpectedError(format!("{}: {}", msg, e)),
+    }
+}
+
+fn checked<F>(build: F) -> impl Fn(&Bucket) -> Result<String, Event>
+where
+    F: Fn(&str) -> String,
+{
+    let checker = bucket_checker_new_identifier();
+    move |b: &Bucket| {
+        checker(b)?;
+        Ok(build(b.as_str()))
+    }
+}
+
+fn create_query(b: &str) -> String {
+    format!(
+        r#"
+            CREATE TABLE IF NOT EXISTS {} (
+                key BLOB,
+                val BLOB,
+                CONSTRAINT {}_pkc PRIMARY KEY (key)
+            )
+        "#,
+        b, b,
+    )
+}
+
+fn upsert_builder() -> impl UpsertBuilder {
+    upsert_builder_new(
+        checked(create_query),
+        checked(|b: &str| {
+            format!(
+                r#"
+                    INSERT INTO {}
+                    VALUES (?1, ?2)
+                    ON CONFLICT (key)
+                    DO UPDATE
+                    SET val = excluded.val
+                    WHERE {}.val <> excluded.val
+                "#,
+                b, b,
+            )
+        }),
+    )
+}
+
+/// Inserts an item only if the key is absent(the primary key decides atomically).
+fn insert_builder() -> impl UpsertBuilder {
+    upsert_builder_new(
+        checked(create_query),
+        checked(|b: &str| {
+            format!(
+                "INSERT INTO {} VALUES (?1, ?2) ON CONFLICT (key) DO NOTHING",
+                b
+            )
+        }),
+    )
+}
+
+/// Replaces the value of an item only if the key exists(no row is inserted for absent keys).
+fn update_builder() -> impl UpsertBuilder {
+    upsert_builder_new(
+        checked(create_query),
+        checked(|b: &str| format!("UPDATE {} SET val = ?2 WHERE key = ?1", b)),
+    )
+}
+
+fn page_builder() -> impl PageBuilder {
+    page_builder_new(
+        checked(|b: &str| format!("SELECT key, val FROM {} ORDER BY key LIMIT ?1", b)),
+        checked(|b: &str| {
+            format!(
+                "SELECT key, val FROM {} WHERE key > ?1 ORDER BY key LIMIT ?2",
+                b
+            )
+        }),
+    )
+}
+
+fn upsert_handler<'a, B>(
+    builder: B,
+) -> impl Fn(Requests, &mut Transaction<'a>) -> Result<u64, Event>
+where
+    B: UpsertBuilder,
+{
+    upsert_bytes_all_new_mut(
+        |t: &mut Transaction, query: &str| {
+            t.execute_batch(query)
+                .map(|_| 0)
+                .map_err(unexpected("Unable to create bucket"))
+        },
+        |t: &mut Transaction, query: &str, key: &[u8], val: &[u8]| {
+            t.prepare_cached(query)
+                .and_then(|mut s| s.execute(params![key, val]))
+                .map(|cnt: usize| cnt as u64)
+                .map_err(unexpected("Unable to upsert"))
+        },
+        builder,
+    )
+}
+
+fn row2item(r: &Row) -> Result<RawItem, rusqlite::Error> {
+    Ok(Item::new(r.get(0)?, r.get(1)?))
+}
+
+fn page_items(
+    c: &mut Connection,
+    query: &str,
+    after: Option<&[u8]>,
+    limit: u64,
+) -> Result<Vec<RawItem>, Event> {
+    let mut s = c
+        .prepare_cached(query)
+        .map_err(unexpected("Unable to prepare"))?;
+    let rows = match after {
+        None => s.query_map(params![limit as i64], row2item),
+        Some(a) => s.query_map(params![a, limit as i64], row2item),
+    }
+    .map_err(unexpected("Unable to get items"))?;
+    rows.map(|r| r.map_err(unexpected("Unable to get an item")))
+        .collect()
+}
+
+pub fn open(path: &str) -> Result<Connection, Event> {
+    Connection::open(path).map_err(|e| Event::ConnectionError(format!("Unable to open: {}", e)))
+}
+
+pub fn get(c: &mut Connection, b: &Bucket, key: &[u8]) -> Result<Option<Vec<u8>>, Event> {
+    let f = select_bytes_new_mut(
+        |c: &mut Connection, query: &str, key: &[u8]| {
+            c.prepare_cached(query)
+                .and_then(|mut s| s.query_row(params![key], |r| r.get(0)).optional())
+                .map_err(unexpected("Unable to get a value"))
+        },
+        checked(|b: &str| format!("SELECT val FROM {} WHERE key = ?1", b)),
+    );
+    let req = GetRequest::new(Bucket::from(String::from(b.as_str())), key.to_vec());
+    f(&req, c)
+}
+
+pub fn put(c: &mut Connection, b: &Bucket, items: Vec<RawItem>) -> Result<u64, Event> {
+    put_with(c, b, items, upsert_builder())
+}
+
+/// Inserts an item unless the key exists; returns 1 if inserted.
+pub fn insert(c: &mut Connection, b: &Bucket, item: RawItem) -> Result<u64, Event> {
+    put_with(c, b, vec![item], insert_builder())
+}
+
+/// Updates the value of an existing key; returns 0 if the key does not exist.
+pub fn update(c: &mut Connection, b: &Bucket, key: &[u8], val: &[u8]) -> Result<u64, Event> {
+    put_with(
+        c,
+        b,
+        vec![Item::new(key.to_vec(), val.to_vec())],
+        update_builder(),
+    )
+}
+
+fn put_with<B>(
+    c: &mut Connection,
+    b: &Bucket,
+    items: Vec<RawItem>,
+    builder: B,
+) -> Result<u64, Event>
+where
+    B: UpsertBuilder,
+{
+    let mut t: Transaction = c
+        .transaction()
+        .map_err(unexpected("Unable to start transaction"))?;
+    let req = BulkRequest::new(Bucket::from(String::from(b.as_str())), items);
+    let cnt: u64 = upsert_handler(builder)(vec![req].into_iter(), &mut t)?;
+    t.commit().map_err(unexpected("Unable to commit changes"))?;
+    Ok(cnt)
+}
+
+pub fn del(c: &mut Connection, b: &Bucket, key: &[u8]) -> Result<u64, Event> {
+    let f = delete_key_bytes_mut(
+        |c: &mut Connection, query: &str, key: &[u8]| {
+            c.prepare_cached(query)
+                .and_then(|mut s| s.execute(params![key]))
+                .map(|cnt: usize| cnt as u64)
+                .map_err(unexpected("Unable to delete an item"))
+        },
+        checked(|b: &str| format!("DELETE FROM {} WHERE key = ?1", b)),
+    );
+    f(b, key, c)
+}
+
+pub fn keys(c: &mut Connection, b: &Bucket) -> Result<Vec<Vec<u8>>, Event> {
+    let f = list_keys_bytes_new_mut(
+        |c: &mut Connection, query: &str| {
+            let mut s = c.prepare(query).map_err(unexpected("Unable to prepare"))?;
+            let rows = s
+                .query_map(params![], |r| r.get(0))
+                .map_err(unexpected("Unable to list keys"))?;
+            rows.map(|r| r.map_err(unexpected("Unable to get a key")))
+                .collect()
+        },
+        list_query_builder_checked(bucket_checker_new_identifier()),
+    );
+    f(b, c)
+}
+
+pub fn page(c: &mut Connection, req: &PageRequest) -> Result<Vec<RawItem>, Event> {
+    list_items_page_new_mut(page_items, page_builder())(req, c)
+}
+
+pub fn buckets(c: &mut Connection) -> Result<Vec<Bucket>, Event> {
+    let f = list_buckets_new_mut(
+        |c: &mut Connection, query: &str| {
+            let mut s = c.prepare(query).map_err(unexpected("Unable to prepare"))?;
+            let rows = s
+                .query_map(params![], |r| r.get(0))
+                .map_err(unexpected("Unable to list buckets"))?;
+            rows.map(|r| r.map_err(unexpected("Unable to get a bucket name")))
+                .collect()
+        },
+        String::from(
+            r#"
+                SELECT name FROM sqlite_master
+                WHERE type = 'table' AND name NOT LIKE 'sqlite\_%' ESCAPE '\'
+                ORDER BY name
+            "#,
+        ),
+    );
+    f(c)
+}
+
+pub fn drop(c: &mut Connection, b: &Bucket) -> Result<(), Event> {
+    let f = drop_bucket_mut(
+        |c: &mut Connection, query: &str| {
+            c.execute_batch(query)
+                .map_err(unexpected("Unable to drop the bucket"))
+        },
+        drop_builder_default_checked(bucket_checker_new_identifier()),
+    );
+    f(b, c)
+}
+
+pub fn export<W>(c: &mut Connection, b: &Bucket, w: &mut DumpWriter<W>) -> Result<u64, Event>
+where
+    W: Write,
+{
+    let f = export_bucket_new_mut(list_items_page_new_mut(page_items, page_builder()), 1000);
+    f(b, c, w)
+}
+
+pub fn import<R>(c: &mut Connection, b: &Bucket, r: &mut DumpReader<R>) -> Result<u64, Event>
+where
+    R: Read,
+{
+    let mut t: Transaction = c
+        .transaction()
+        .map_err(unexpected("Unable to start transaction"))?;
+    let cnt: u64 = import_bucket_new_mut(upsert_handler(upsert_builder()), 1000)(b, r, &mut t)?;
+    t.commit().map_err(unexpected("Unable to commit changes"))?;
+    Ok(cnt)
+}
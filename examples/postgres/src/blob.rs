@@ -0,0 +1,269 @@
+use std::env;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use rs_rdb2kv::blob::{read_range, BlobIo};
+use rs_rdb2kv::del::{delete_key_bytes_mut, drop_bucket_mut};
+use rs_rdb2kv::{bucket::Bucket, evt::Event};
+
+use postgres::{Client, Config, NoTls, Row, Transaction};
+
+const INV_READ: i32 = 0x40000;
+const INV_WRITE: i32 = 0x20000;
+
+/// Large objects referenced by `{bucket}_lo(key BYTEA, oid OID)`.
+///
+/// Values are stored outside the table(`pg_largeobject`): deleting a row of `{bucket}_lo` or
+/// dropping the table does not remove the large object. Delete keys with `pg_lo_delete` and
+/// drop buckets with `pg_lo_drop`, which unlink large objects; large objects already leaked by
+/// plain `DELETE`/`DROP TABLE` can be removed with `vacuumlo`.
+///
+/// Descriptors are valid until the end of the transaction.
+struct LargeObjects<'c> {
+    tx: Transaction<'c>,
+}
+
+struct LargeObject<'a, 'c> {
+    tx: &'a mut Transaction<'c>,
+    fd: i32,
+    pos: i64,
+    len: i64,
+}
+
+fn to_io(e: postgres::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+impl<'a, 'c> Read for LargeObject<'a, 'c> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n: i32 = buf.len().min(1 << 20) as i32;
+        let got: Vec<u8> = self
+            .tx
+            .query_one("SELECT loread($1, $2)", &[&self.fd, &n])
+            .and_then(|row| row.try_get(0))
+            .map_err(to_io)?;
+        buf[..got.len()].copy_from_slice(&got);
+        self.pos += got.len() as i64;
+        Ok(got.len())
+    }
+}
+
+impl<'a, 'c> Write for LargeObject<'a, 'c> {
+    /// Writes up to the length of the value(large objects would grow otherwise).
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room: usize = (self.len - self.pos).max(0) as usize;
+        let data: &[u8] = &buf[..buf.len().min(room).min(1 << 20)];
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let n: i32 = self
+            .tx
+            .query_one("SELECT lowrite($1, $2)", &[&self.fd, &data])
+            .and_then(|row| row.try_get(0))
+            .map_err(to_io)?;
+        self.pos += i64::from(n);
+        Ok(n as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, 'c> Seek for LargeObject<'a, 'c> {
+    fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+        let (offset, whence): (i64, i32) = match from {
+            SeekFrom::Start(p) => (p as i64, 0),
+            SeekFrom::Current(o) => (o, 1),
+            SeekFrom::End(o) => (o, 2),
+        };
+        let pos: i64 = self
+            .tx
+            .query_one(
+                "SELECT lo_lseek64($1, $2, $3)",
+                &[&self.fd, &offset, &whence],
+            )
+            .and_then(|row| row.try_get(0))
+            .map_err(to_io)?;
+        self.pos = pos;
+        Ok(pos as u64)
+    }
+}
+
+impl<'c> LargeObjects<'c> {
+    fn oid(&mut self, b: &Bucket, key: &[u8]) -> Result<Option<u32>, Event> {
+        let query: String = format!("SELECT oid FROM {}_lo WHERE key = $1", b.as_str());
+        self.tx
+            .query_opt(query.as_str(), &[&key])
+            .and_then(|o| o.map(|row| row.try_get(0)).transpose())
+            .map_err(|e| Event::UnexpectedError(format!("Unable to get an oid: {}", e)))
+    }
+
+    fn open(&mut self, oid: u32, mode: i32) -> Result<i32, Event> {
+        self.tx
+            .query_one("SELECT lo_open($1, $2)", &[&oid, &mode])
+            .and_then(|row| row.try_get(0))
+            .map_err(|e| Event::UnexpectedError(format!("Unable to open a large object: {}", e)))
+    }
+}
+
+impl<'c> BlobIo for LargeObjects<'c> {
+    type Reader<'a>
+        = LargeObject<'a, 'c>
+    where
+        Self: 'a;
+    type Writer<'a>
+        = LargeObject<'a, 'c>
+    where
+        Self: 'a;
+
+    fn open_value_reader<'a>(
+        &'a mut self,
+        b: &Bucket,
+        key: &[u8],
+    ) -> Result<Option<Self::Reader<'a>>, Event> {
+        let oid: Option<u32> = self.oid(b, key)?;
+        match oid {
+            None => Ok(None),
+            Some(oid) => {
+                let fd: i32 = self.open(oid, INV_READ)?;
+                Ok(Some(LargeObject {
+                    tx: &mut self.tx,
+                    fd,
+                    pos: 0,
+                    len: i64::MAX,
+                }))
+            }
+        }
+    }
+
+    fn open_value_writer<'a>(
+        &'a mut self,
+        b: &Bucket,
+        key: &[u8],
+        len: u64,
+    ) -> Result<Self::Writer<'a>, Event> {
+        let len: i64 = len
+            .try_into()
+            .map_err(|_| Event::UnexpectedError(format!("Too large value: {}", len)))?;
+        if let Some(prev) = self.oid(b, key)? {
+            self.tx
+                .execute("SELECT lo_unlink($1)", &[&prev])
+                .map_err(|e| Event::UnexpectedError(format!("Unable to unlink: {}", e)))?;
+        }
+        let oid: u32 = self
+            .tx
+            .query_one("SELECT lo_create(0)", &[])
+            .and_then(|row| row.try_get(0))
+            .map_err(|e| Event::UnexpectedError(format!("Unable to create: {}", e)))?;
+        let query: String = format!(
+            r#"
+                INSERT INTO {}_lo VALUES($1, $2)
+                ON CONFLICT(key) DO UPDATE SET oid = EXCLUDED.oid
+            "#,
+            b.as_str(),
+        );
+        self.tx
+            .execute(query.as_str(), &[&key, &oid])
+            .map_err(|e| Event::UnexpectedError(format!("Unable to save an oid: {}", e)))?;
+        let fd: i32 = self.open(oid, INV_READ | INV_WRITE)?;
+        self.tx
+            .execute("SELECT lo_truncate64($1, $2)", &[&fd, &len])
+            .map_err(|e| Event::UnexpectedError(format!("Unable to allocate: {}", e)))?;
+        Ok(LargeObject {
+            tx: &mut self.tx,
+            fd,
+            pos: 0,
+            len,
+        })
+    }
+}
+
+/// Deletes the key and unlinks its large object; returns the number of deleted keys.
+fn pg_lo_delete(t: &mut Transaction, query: &str, key: &[u8]) -> Result<u64, Event> {
+    let rows: Vec<Row> = t
+        .query(query, &[&key])
+        .map_err(|e| Event::UnexpectedError(format!("Unable to delete: {}", e)))?;
+    for row in &rows {
+        let oid: u32 = row
+            .try_get(0)
+            .map_err(|e| Event::UnexpectedError(format!("Unable to get an oid: {}", e)))?;
+        t.execute("SELECT lo_unlink($1)", &[&oid])
+            .map_err(|e| Event::UnexpectedError(format!("Unable to unlink: {}", e)))?;
+    }
+    Ok(rows.len() as u64)
+}
+
+/// Unlinks all large objects of the bucket and drops the bucket.
+fn pg_lo_drop(t: &mut Transaction, query: &str) -> Result<(), Event> {
+    t.batch_execute(query)
+        .map_err(|e| Event::UnexpectedError(format!("Unable to drop: {}", e)))
+}
+
+fn pg_lo_delete_query(b: &Bucket) -> Result<String, Event> {
+    Ok(format!(
+        "DELETE FROM {}_lo WHERE key = $1 RETURNING oid",
+        b.as_str()
+    ))
+}
+
+fn pg_lo_drop_query(b: &Bucket) -> Result<String, Event> {
+    Ok(format!(
+        r#"
+            SELECT lo_unlink(oid) FROM {b}_lo;
+            DROP TABLE {b}_lo;
+        "#,
+        b = b.as_str(),
+    ))
+}
+
+pub fn ranges() -> Result<(), Event> {
+    let mut c: Client = Config::new()
+        .host(env::var("PGHOST").unwrap().as_str())
+        .dbname(env::var("PGDATABASE").unwrap().as_str())
+        .user(env::var("PGUSER").unwrap().as_str())
+        .password(env::var("PGPASSWORD").unwrap_or_default())
+        .connect(NoTls)
+        .map_err(|e| Event::ConnectionError(format!("Unable to connect: {}", e)))?;
+    c.execute(
+        r#"
+            CREATE TABLE IF NOT EXISTS artifacts_lo (
+                key BYTEA,
+                oid OID,
+                CONSTRAINT artifacts_lo_pkc PRIMARY KEY(key)
+            )
+        "#,
+        &[],
+    )
+    .map_err(|e| Event::UnexpectedError(format!("Unable to create a bucket: {}", e)))?;
+
+    let tx: Transaction = c
+        .transaction()
+        .map_err(|e| Event::ConnectionError(format!("Unable to begin: {}", e)))?;
+    let mut objects = LargeObjects { tx };
+    let b: Bucket = Bucket::from(String::from("artifacts"));
+
+    let mut w = objects.open_value_writer(&b, b"build.tar", 1 << 20)?;
+    w.write_all(b"ustar")
+        .and_then(|_| w.seek(SeekFrom::End(-4)))
+        .and_then(|_| w.write_all(b"tail"))
+        .map_err(|e| Event::UnexpectedError(format!("Unable to write: {}", e)))?;
+
+    let mut r = objects
+        .open_value_reader(&b, b"build.tar")?
+        .ok_or_else(|| Event::UnexpectedError(String::from("Unable to get a large object")))?;
+    let mut head: Vec<u8> = vec![];
+    read_range(&mut r, 0, 5, &mut head)?;
+    println!("large object head: {:?}", String::from_utf8(head));
+
+    objects.open_value_writer(&b, b"build.log", 16)?;
+    let delete = delete_key_bytes_mut(pg_lo_delete, pg_lo_delete_query);
+    let deleted: u64 = delete(&b, b"build.log", &mut objects.tx)?;
+    println!("large objects deleted: {}", deleted);
+    let remove = drop_bucket_mut(pg_lo_drop, pg_lo_drop_query);
+    remove(&b, &mut objects.tx)?;
+
+    objects
+        .tx
+        .commit()
+        .map_err(|e| Event::UnexpectedError(format!("Unable to commit: {}", e)))
+}
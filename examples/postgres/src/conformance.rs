@@ -0,0 +1,130 @@
+use std::env;
+
+use rs_rdb2kv::conformance::{check_all, kv_handlers_new, KvHandlers, Requests};
+use rs_rdb2kv::del::{delete_key_bytes_mut, drop_bucket_mut, drop_builder_default_unchecked};
+use rs_rdb2kv::get::select_bytes_new_mut;
+use rs_rdb2kv::list::{list_keys_bytes_new_mut, list_query_builder_unchecked};
+use rs_rdb2kv::upsert::{upsert_builder_new, upsert_bytes_all_new_mut, UpsertBuilder};
+use rs_rdb2kv::{bucket::Bucket, evt::Event};
+
+use postgres::error::SqlState;
+use postgres::{Client, Config, NoTls, Row, Transaction};
+
+fn unexpected(msg: &'static str) -> impl Fn(postgres::Error) -> Event {
+    move |e: postgres::Error| match e.code() {
+        Some(&SqlState::UNDEFINED_TABLE) => Event::BucketNotFound(format!("{}: {}", msg, e)),
+        _ => Event::UnexpectedError(format!("{}: {}", msg, e)),
+    }
+}
+
+fn upsert_builder_pg() -> impl UpsertBuilder {
+    upsert_builder_new(
+        |b: &Bucket| {
+            Ok(format!(
+                r#"
+                    CREATE TABLE IF NOT EXISTS {} (
+                        key BYTEA,
+                        val BYTEA,
+                        CONSTRAINT {}_pkc PRIMARY KEY(key)
+                    )
+                "#,
+                b.as_str(),
+                b.as_str(),
+            ))
+        },
+        |b: &Bucket| {
+            Ok(format!(
+                r#"
+                    INSERT INTO {} AS tgt
+                    VALUES($1::BYTEA, $2::BYTEA)
+                    ON CONFLICT ON CONSTRAINT {}_pkc
+                    DO UPDATE
+                    SET val = EXCLUDED.val
+                    WHERE tgt.val <> EXCLUDED.val
+                "#,
+                b.as_str(),
+                b.as_str(),
+            ))
+        },
+    )
+}
+
+fn upsert_handler<'a>() -> impl Fn(Requests, &mut Transaction<'a>) -> Result<u64, Event> {
+    upsert_bytes_all_new_mut(
+        |t: &mut Transaction, query: &str| {
+            t.execute(query, &[])
+                .map_err(unexpected("Unable to create"))
+        },
+        |t: &mut Transaction, query: &str, key: &[u8], val: &[u8]| {
+            t.execute(query, &[&key, &val])
+                .map_err(unexpected("Unable to upsert"))
+        },
+        upsert_builder_pg(),
+    )
+}
+
+fn handlers() -> impl KvHandlers<Client> {
+    kv_handlers_new(
+        select_bytes_new_mut(
+            |c: &mut Client, query: &str, key: &[u8]| {
+                let o: Option<Row> = c
+                    .query_opt(query, &[&key])
+                    .map_err(unexpected("Unable to select"))?;
+                o.map(|r: Row| r.try_get(0))
+                    .transpose()
+                    .map_err(unexpected("Unable to get a value"))
+            },
+            |b: &Bucket| {
+                Ok(format!(
+                    "SELECT val FROM {} WHERE key = $1::BYTEA",
+                    b.as_str()
+                ))
+            },
+        ),
+        |requests: Requests, c: &mut Client| {
+            let mut t: Transaction = c
+                .transaction()
+                .map_err(unexpected("Unable to start transaction"))?;
+            let cnt: u64 = upsert_handler()(requests, &mut t)?;
+            t.commit().map_err(unexpected("Unable to commit"))?;
+            Ok(cnt)
+        },
+        delete_key_bytes_mut(
+            |c: &mut Client, query: &str, key: &[u8]| {
+                c.execute(query, &[&key])
+                    .map_err(unexpected("Unable to delete"))
+            },
+            |b: &Bucket| Ok(format!("DELETE FROM {} WHERE key = $1::BYTEA", b.as_str())),
+        ),
+        list_keys_bytes_new_mut(
+            |c: &mut Client, query: &str| {
+                let rows: Vec<Row> = c.query(query, &[]).map_err(unexpected("Unable to list"))?;
+                rows.iter()
+                    .map(|r: &Row| r.try_get(0).map_err(unexpected("Unable to get a key")))
+                    .collect()
+            },
+            list_query_builder_unchecked(),
+        ),
+        drop_bucket_mut(
+            |c: &mut Client, query: &str| {
+                c.batch_execute(query).map_err(unexpected("Unable to drop"))
+            },
+            drop_builder_default_unchecked(),
+        ),
+    )
+}
+
+pub fn conformance() -> Result<(), Event> {
+    let mut c: Client = Config::new()
+        .host(env::var("PGHOST").unwrap().as_str())
+        .dbname(env::var("PGDATABASE").unwrap().as_str())
+        .user(env::var("PGUSER").unwrap().as_str())
+        .password(env::var("PGPASSWORD").unwrap_or_default())
+        .connect(NoTls)
+        .map_err(|e| Event::ConnectionError(format!("Unable to connect: {}", e)))?;
+
+    let b = Bucket::from(String::from("conformance_2022_11_01"));
+    check_all(&handlers(), &mut c, &b)?;
+    println!("conformance: ok");
+    Ok(())
+}
@@ -0,0 +1,237 @@
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use rs_rdb2kv::feed::{
+    changelog_builder_new, changelog_enable_mut, changes_follow_new_mut, changes_since_new_mut,
+    Change, ChangeOp, ChangelogBuilder,
+};
+use rs_rdb2kv::{bucket::Bucket, evt::Event};
+
+use postgres::fallible_iterator::FallibleIterator;
+use postgres::{Client, Config, NoTls, Row};
+
+/// Builds changelog queries whose revisions follow the commit order.
+///
+/// A `BIGSERIAL` is assigned before commit: a transaction committing later with a lower number
+/// would be skipped by readers resumed from a higher revision. Changes are recorded with the
+/// transaction id(`xid`) instead, and `{b}_changes_since` assigns revisions(under a lock) only to
+/// changes of transactions older than any in-flight transaction(`pg_snapshot_xmin`), ordered by
+/// `(xid, seq)`; no change can be committed before an assigned revision later.
+fn pg_changelog_builder() -> impl ChangelogBuilder {
+    changelog_builder_new(
+        |b: &Bucket| {
+            Ok(format!(
+                r#"
+                    CREATE TABLE IF NOT EXISTS {b}_changes (
+                        seq BIGSERIAL PRIMARY KEY,
+                        xid XID8 NOT NULL DEFAULT pg_current_xact_id(),
+                        revision BIGINT UNIQUE,
+                        key BYTEA NOT NULL,
+                        op TEXT NOT NULL,
+                        ts BIGINT NOT NULL
+                    );
+                    CREATE OR REPLACE FUNCTION {b}_changes_fn() RETURNS TRIGGER AS $$
+                    BEGIN
+                        IF TG_OP = 'DELETE' THEN
+                            INSERT INTO {b}_changes(key, op, ts)
+                            VALUES(OLD.key, 'D', EXTRACT(EPOCH FROM NOW())::BIGINT);
+                        ELSE
+                            INSERT INTO {b}_changes(key, op, ts)
+                            VALUES(NEW.key, LEFT(TG_OP, 1), EXTRACT(EPOCH FROM NOW())::BIGINT);
+                        END IF;
+                        PERFORM pg_notify('{b}_changes', '');
+                        RETURN NULL;
+                    END
+                    $$ LANGUAGE plpgsql;
+                    CREATE OR REPLACE FUNCTION {b}_changes_since(after BIGINT, lim BIGINT)
+                    RETURNS TABLE(key BYTEA, op TEXT, revision BIGINT, ts BIGINT) AS $$
+                    #variable_conflict use_column
+                    BEGIN
+                        PERFORM pg_advisory_xact_lock(hashtext('{b}_changes'));
+                        UPDATE {b}_changes AS c
+                        SET revision = a.revision
+                        FROM (
+                            SELECT
+                                p.seq,
+                                (SELECT COALESCE(MAX(m.revision), 0) FROM {b}_changes AS m)
+                                + ROW_NUMBER() OVER (ORDER BY p.xid, p.seq) AS revision
+                            FROM {b}_changes AS p
+                            WHERE p.revision IS NULL
+                            AND p.xid < pg_snapshot_xmin(pg_current_snapshot())
+                        ) AS a
+                        WHERE c.seq = a.seq;
+                        RETURN QUERY
+                            SELECT c.key, c.op, c.revision, c.ts FROM {b}_changes AS c
+                            WHERE c.revision > after
+                            ORDER BY c.revision
+                            LIMIT lim;
+                    END
+                    $$ LANGUAGE plpgsql;
+                    DROP TRIGGER IF EXISTS {b}_changes_trg ON {b};
+                    CREATE TRIGGER {b}_changes_trg
+                    AFTER INSERT OR UPDATE OR DELETE ON {b}
+                    FOR EACH ROW EXECUTE FUNCTION {b}_changes_fn();
+                "#,
+                b = b.as_str(),
+            ))
+        },
+        |b: &Bucket| {
+            Ok(format!(
+                r#"
+                    DROP TRIGGER IF EXISTS {b}_changes_trg ON {b};
+                    DROP FUNCTION IF EXISTS {b}_changes_fn();
+                    DROP FUNCTION IF EXISTS {b}_changes_since(BIGINT, BIGINT);
+                    DROP TABLE IF EXISTS {b}_changes;
+                "#,
+                b = b.as_str(),
+            ))
+        },
+        |b: &Bucket| {
+            Ok(format!(
+                r#"
+                    SELECT key, op, revision, ts FROM {}_changes_since($1::BIGINT, $2::BIGINT)
+                "#,
+                b.as_str(),
+            ))
+        },
+    )
+}
+
+fn row2change(r: &Row) -> Result<Change, Event> {
+    let conv = |e| Event::UnexpectedError(format!("Unable to get a change: {}", e));
+    let op: &str = r.try_get(1).map_err(conv)?;
+    Ok(Change::new(
+        r.try_get(0).map_err(conv)?,
+        ChangeOp::try_from(op)?,
+        r.try_get::<_, i64>(2).map_err(conv)? as u64,
+        r.try_get(3).map_err(conv)?,
+    ))
+}
+
+fn pg_since(c: &mut Client, query: &str, rev: u64, limit: u64) -> Result<Vec<Change>, Event> {
+    let rows: Vec<Row> = c
+        .query(query, &[&(rev as i64), &(limit as i64)])
+        .map_err(|e| Event::UnexpectedError(format!("Unable to get changes: {}", e)))?;
+    rows.iter().map(row2change).collect()
+}
+
+fn pg_wait(c: &mut Client, b: &Bucket) -> Result<bool, Event> {
+    let channel: String = format!("{}_changes", b.as_str());
+    let mut n = c.notifications();
+    let mut it = n.timeout_iter(Duration::from_secs(5));
+    let first = it
+        .next()
+        .map_err(|e| Event::ConnectionError(format!("Unable to wait notification: {}", e)))?;
+    Ok(first.map(|n| n.channel() == channel).unwrap_or(false))
+}
+
+fn connect() -> Result<Client, Event> {
+    Config::new()
+        .host(env::var("PGHOST").unwrap().as_str())
+        .dbname(env::var("PGDATABASE").unwrap().as_str())
+        .user(env::var("PGUSER").unwrap().as_str())
+        .password(env::var("PGPASSWORD").unwrap_or_default())
+        .connect(NoTls)
+        .map_err(|e| Event::ConnectionError(format!("Unable to connect: {}", e)))
+}
+
+pub fn follow() -> Result<(), Event> {
+    let mut c: Client = connect()?;
+    c.batch_execute(
+        r#"
+            DROP TABLE IF EXISTS devices_feed_changes;
+            DROP TABLE IF EXISTS devices_feed;
+            CREATE TABLE devices_feed (
+                key BYTEA,
+                val BYTEA,
+                CONSTRAINT devices_feed_pkc PRIMARY KEY(key)
+            );
+            LISTEN devices_feed_changes;
+        "#,
+    )
+    .map_err(|e| Event::UnexpectedError(format!("Unable to create a bucket: {}", e)))?;
+
+    let b: Bucket = Bucket::from(String::from("devices_feed"));
+    let enable = changelog_enable_mut(
+        |c: &mut Client, query: &str| {
+            c.batch_execute(query)
+                .map_err(|e| Event::UnexpectedError(format!("Unable to enable changelog: {}", e)))
+        },
+        pg_changelog_builder(),
+    );
+    enable(&b, &mut c)?;
+
+    let writer = thread::spawn(|| -> Result<(), Event> {
+        let mut w: Client = connect()?;
+        let writes = [
+            "INSERT INTO devices_feed VALUES ('\\xcafef00d', '\\x42')",
+            "UPDATE devices_feed SET val = '\\x43'",
+            "DELETE FROM devices_feed",
+        ];
+        for q in writes {
+            thread::sleep(Duration::from_millis(100));
+            w.execute(q, &[])
+                .map_err(|e| Event::UnexpectedError(format!("Unable to write: {}", e)))?;
+        }
+        Ok(())
+    });
+
+    let since = changes_since_new_mut(pg_since, pg_changelog_builder());
+    let follow = changes_follow_new_mut(pg_wait, since);
+    let mut revision: u64 = 0;
+    while revision < 3 {
+        let changes: Vec<Change> = follow(&b, revision, 100, &mut c)?;
+        for ch in &changes {
+            println!(
+                "change: rev={} op={:?} key={:x?}",
+                ch.revision(),
+                ch.op(),
+                ch.as_key()
+            );
+        }
+        revision = changes.last().map(|ch| ch.revision()).unwrap_or(revision);
+    }
+    writer
+        .join()
+        .map_err(|_| Event::UnexpectedError(String::from("Writer panicked")))??;
+    resume(&b, revision, &mut c)
+}
+
+/// Resumes from a revision while a transaction which started writing earlier commits later.
+fn resume(b: &Bucket, revision: u64, c: &mut Client) -> Result<(), Event> {
+    let since = changes_since_new_mut(pg_since, pg_changelog_builder());
+    let write = |c: &mut Client, q: &str| {
+        c.batch_execute(q)
+            .map_err(|e| Event::UnexpectedError(format!("Unable to write: {}", e)))
+    };
+    let mut early: Client = connect()?;
+    let mut late: Client = connect()?;
+    write(
+        &mut early,
+        "BEGIN; INSERT INTO devices_feed VALUES ('\\xaa', '\\x01')",
+    )?;
+    write(
+        &mut late,
+        "INSERT INTO devices_feed VALUES ('\\xbb', '\\x02')",
+    )?;
+
+    let hidden: Vec<Change> = since(b, revision, 100, c)?;
+    if !hidden.is_empty() {
+        return Err(Event::UnexpectedError(String::from(
+            "Changes after an in-flight transaction must not be visible",
+        )));
+    }
+
+    write(&mut early, "COMMIT")?;
+    let changes: Vec<Change> = since(b, revision, 100, c)?;
+    let keys: Vec<&[u8]> = changes.iter().map(|ch| ch.as_key()).collect();
+    println!("resumed: {:x?}", keys);
+    match keys.as_slice() {
+        [first, second] if *first == [0xaa] && *second == [0xbb] => Ok(()),
+        _ => Err(Event::UnexpectedError(format!(
+            "Unexpected changes after resume: {:x?}",
+            keys
+        ))),
+    }
+}
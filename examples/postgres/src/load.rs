@@ -0,0 +1,136 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::hash::Hasher;
+
+use rs_rdb2kv::load::{load_builder_new, load_bytes_all_new_mut, LoadBuilder};
+use rs_rdb2kv::upsert::BulkRequest;
+use rs_rdb2kv::{bucket::Bucket, evt::Event, item::Item};
+
+use postgres::binary_copy::BinaryCopyInWriter;
+use postgres::types::Type;
+use postgres::{Client, Config, CopyInWriter, NoTls, Transaction};
+
+fn pg_load_builder_unchecked() -> impl LoadBuilder {
+    load_builder_new(
+        |b: &Bucket| {
+            Ok(format!(
+                r#"
+                    CREATE TABLE IF NOT EXISTS {} (
+                        key BYTEA,
+                        val BYTEA,
+                        CONSTRAINT {}_pkc PRIMARY KEY(key)
+                    )
+                "#,
+                b.as_str(),
+                b.as_str(),
+            ))
+        },
+        |b: &Bucket| {
+            Ok(format!(
+                r#"
+                    CREATE TEMP TABLE IF NOT EXISTS {} (
+                        seq BIGINT GENERATED ALWAYS AS IDENTITY,
+                        key BYTEA,
+                        val BYTEA
+                    ) ON COMMIT DROP
+                "#,
+                pg_stage_name(b),
+            ))
+        },
+        |b: &Bucket| {
+            Ok(format!(
+                r#"
+                    COPY {} (key, val) FROM STDIN (FORMAT BINARY)
+                "#,
+                pg_stage_name(b),
+            ))
+        },
+        |b: &Bucket| {
+            Ok(format!(
+                r#"
+                    INSERT INTO {} AS tgt
+                    SELECT DISTINCT ON (key) key, val FROM {}
+                    ORDER BY key, seq DESC
+                    ON CONFLICT ON CONSTRAINT {}_pkc
+                    DO UPDATE
+                    SET val = EXCLUDED.val
+                    WHERE tgt.val <> EXCLUDED.val
+                "#,
+                b.as_str(),
+                pg_stage_name(b),
+                b.as_str(),
+            ))
+        },
+        |b: &Bucket| Ok(format!("TRUNCATE {}", pg_stage_name(b))),
+    )
+}
+
+/// Gets the staging table name: a quoted name with a dot never matches a bucket(identifier).
+///
+/// The name is a fixed prefix and a hash of the bucket, so it stays within the identifier limit
+/// (63 bytes; longer names would be truncated and long buckets could share a staging table).
+/// Staged rows are numbered by `seq` in the copy order; the last row of a key wins on merge.
+fn pg_stage_name(b: &Bucket) -> String {
+    let mut h = DefaultHasher::new();
+    h.write(b.as_str().as_bytes());
+    format!(r#"pg_temp."stage.{:016x}""#, h.finish())
+}
+
+fn pg_copy(
+    t: &mut Transaction,
+    query: &str,
+    items: &[Item<Vec<u8>, Vec<u8>>],
+) -> Result<u64, Event> {
+    let w: CopyInWriter = t
+        .copy_in(query)
+        .map_err(|e| Event::UnexpectedError(format!("Unable to start copy: {}", e)))?;
+    let mut bw = BinaryCopyInWriter::new(w, &[Type::BYTEA, Type::BYTEA]);
+    for item in items {
+        let key: &[u8] = item.as_key();
+        let val: &[u8] = item.as_val();
+        bw.write(&[&key, &val])
+            .map_err(|e| Event::UnexpectedError(format!("Unable to copy an item: {}", e)))?;
+    }
+    bw.finish()
+        .map_err(|e| Event::UnexpectedError(format!("Unable to finish copy: {}", e)))
+}
+
+fn pg_load_all<I>(requests: I, mut t: Transaction) -> Result<u64, Event>
+where
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    let exec = |t: &mut Transaction, query: &str| {
+        t.execute(query, &[])
+            .map_err(|e| Event::UnexpectedError(format!("Unable to execute: {}", e)))
+    };
+    let f = load_bytes_all_new_mut(exec, pg_copy, pg_load_builder_unchecked());
+    let cnt: u64 = f(requests, &mut t)?;
+    t.commit()
+        .map_err(|e| Event::UnexpectedError(format!("Unable to commit changes: {}", e)))?;
+    Ok(cnt)
+}
+
+pub fn load() -> Result<(), Event> {
+    let mut c: Client = Config::new()
+        .host(env::var("PGHOST").unwrap().as_str())
+        .dbname(env::var("PGDATABASE").unwrap().as_str())
+        .user(env::var("PGUSER").unwrap().as_str())
+        .password(env::var("PGPASSWORD").unwrap_or_default())
+        .connect(NoTls)
+        .map_err(|e| Event::ConnectionError(format!("Unable to connect: {}", e)))?;
+    let t: Transaction = c
+        .transaction()
+        .map_err(|e| Event::UnexpectedError(format!("Unable to start transaction: {}", e)))?;
+    let items: Vec<Item<Vec<u8>, Vec<u8>>> = (0..100_000)
+        .map(|i: u32| Item::new(format!("{:08}", i).into_bytes(), i.to_be_bytes().to_vec()))
+        .collect();
+    let req = vec![BulkRequest::new(
+        Bucket::from(String::from(
+            "data_2022_11_03_cafef00ddeadbeafface864299792458",
+        )),
+        items,
+    )];
+    let cnt: u64 = pg_load_all(req.into_iter(), t)?;
+    println!("loaded: {}", cnt);
+    Ok(())
+}
@@ -1,16 +1,26 @@
 use rs_rdb2kv::evt::Event;
 
+mod blob;
+mod conformance;
 mod del;
+mod feed;
 mod list;
+mod load;
+mod migrate;
 mod select;
 mod upsert;
 
 fn sub() -> Result<(), Event> {
     upsert::upsert()?;
+    load::load()?;
     select::select()?;
     del::remove()?;
     del::delete()?;
     list::list()?;
+    feed::follow()?;
+    migrate::migrate()?;
+    conformance::conformance()?;
+    blob::ranges()?;
     Ok(())
 }
 
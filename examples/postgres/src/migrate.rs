@@ -0,0 +1,228 @@
+use std::env;
+
+use rs_rdb2kv::item::{Item, RawItem};
+use rs_rdb2kv::list::{
+    list_buckets_new_mut, list_items_page_new_mut, page_builder_new, PageRequest,
+};
+use rs_rdb2kv::migrate::{
+    digest_bucket_new_mut, migrate_all_new_mut, migrate_bucket_new_mut, verify_bucket_new_mut,
+    BucketDigest, Progress,
+};
+use rs_rdb2kv::upsert::{upsert_builder_new, upsert_bytes_all_new_mut, BulkRequest, Requests};
+use rs_rdb2kv::{bucket::Bucket, evt::Event};
+
+use postgres::{Client, Config, NoTls, Row};
+use rusqlite::{params, Connection};
+
+fn sqlite_page() -> impl Fn(&PageRequest, &mut Connection) -> Result<Vec<RawItem>, Event> {
+    let list = |c: &mut Connection, query: &str, after: Option<&[u8]>, limit: u64| {
+        let mut s = c
+            .prepare_cached(query)
+            .map_err(|e| Event::UnexpectedError(format!("Unable to prepare: {}", e)))?;
+        let to_item = |row: &rusqlite::Row| Ok(Item::new(row.get(0)?, row.get(1)?));
+        let rows = match after {
+            None => s.query_map(params![limit as i64], to_item),
+            Some(a) => s.query_map(params![a, limit as i64], to_item),
+        }
+        .map_err(|e| Event::UnexpectedError(format!("Unable to get items: {}", e)))?;
+        rows.map(|r| r.map_err(|e| Event::UnexpectedError(format!("Unable to get an item: {}", e))))
+            .collect()
+    };
+    let builder = page_builder_new(
+        |b: &Bucket| {
+            Ok(format!(
+                "SELECT key, val FROM {} ORDER BY key LIMIT ?1",
+                b.as_str()
+            ))
+        },
+        |b: &Bucket| {
+            Ok(format!(
+                "SELECT key, val FROM {} WHERE key > ?1 ORDER BY key LIMIT ?2",
+                b.as_str()
+            ))
+        },
+    );
+    list_items_page_new_mut(list, builder)
+}
+
+fn sqlite_upsert() -> impl Fn(Requests, &mut Connection) -> Result<u64, Event> {
+    upsert_bytes_all_new_mut(
+        |c: &mut Connection, query: &str| {
+            c.execute(query, params![])
+                .map(|cnt: usize| cnt as u64)
+                .map_err(|e| Event::UnexpectedError(format!("Unable to create bucket: {}", e)))
+        },
+        |c: &mut Connection, query: &str, key: &[u8], val: &[u8]| {
+            c.execute(query, params![key, val])
+                .map(|cnt: usize| cnt as u64)
+                .map_err(|e| Event::UnexpectedError(format!("Unable to upsert: {}", e)))
+        },
+        upsert_builder_new(
+            |b: &Bucket| {
+                Ok(format!(
+                    "CREATE TABLE IF NOT EXISTS {} (key BLOB PRIMARY KEY, val BLOB)",
+                    b.as_str()
+                ))
+            },
+            |b: &Bucket| {
+                Ok(format!(
+                    "INSERT INTO {} VALUES (?1, ?2) ON CONFLICT (key) DO UPDATE SET val = excluded.val",
+                    b.as_str()
+                ))
+            },
+        ),
+    )
+}
+
+fn sqlite_buckets() -> impl Fn(&mut Connection) -> Result<Vec<Bucket>, Event> {
+    let list = |c: &mut Connection, query: &str| {
+        let mut s = c
+            .prepare(query)
+            .map_err(|e| Event::UnexpectedError(format!("Unable to prepare: {}", e)))?;
+        let rows = s
+            .query_map(params![], |row| row.get::<_, String>(0))
+            .map_err(|e| Event::UnexpectedError(format!("Unable to list buckets: {}", e)))?;
+        rows.map(|r| r.map_err(|e| Event::UnexpectedError(format!("Unable to get a name: {}", e))))
+            .collect()
+    };
+    let query = String::from(
+        r#"
+            SELECT name FROM sqlite_master
+            WHERE type = 'table' AND name LIKE 'migrate\_%' ESCAPE '\'
+            ORDER BY name
+        "#,
+    );
+    list_buckets_new_mut(list, query)
+}
+
+fn pg_page() -> impl Fn(&PageRequest, &mut Client) -> Result<Vec<RawItem>, Event> {
+    let list = |c: &mut Client, query: &str, after: Option<&[u8]>, limit: u64| {
+        let limit: i64 = limit as i64;
+        let rows: Vec<Row> = match after {
+            None => c.query(query, &[&limit]),
+            Some(a) => c.query(query, &[&a, &limit]),
+        }
+        .map_err(|e| Event::UnexpectedError(format!("Unable to get items: {}", e)))?;
+        let conv =
+            |e: postgres::Error| Event::UnexpectedError(format!("Unable to get an item: {}", e));
+        rows.iter()
+            .map(|r: &Row| {
+                Ok(Item::new(
+                    r.try_get(0).map_err(conv)?,
+                    r.try_get(1).map_err(conv)?,
+                ))
+            })
+            .collect()
+    };
+    let builder = page_builder_new(
+        |b: &Bucket| {
+            Ok(format!(
+                "SELECT key, val FROM {} ORDER BY key LIMIT $1::BIGINT",
+                b.as_str()
+            ))
+        },
+        |b: &Bucket| {
+            Ok(format!(
+                "SELECT key, val FROM {} WHERE key > $1::BYTEA ORDER BY key LIMIT $2::BIGINT",
+                b.as_str()
+            ))
+        },
+    );
+    list_items_page_new_mut(list, builder)
+}
+
+fn pg_upsert() -> impl Fn(Requests, &mut Client) -> Result<u64, Event> {
+    upsert_bytes_all_new_mut(
+        |c: &mut Client, query: &str| {
+            c.execute(query, &[])
+                .map_err(|e| Event::UnexpectedError(format!("Unable to create bucket: {}", e)))
+        },
+        |c: &mut Client, query: &str, key: &[u8], val: &[u8]| {
+            c.execute(query, &[&key, &val])
+                .map_err(|e| Event::UnexpectedError(format!("Unable to upsert: {}", e)))
+        },
+        upsert_builder_new(
+            |b: &Bucket| {
+                Ok(format!(
+                    "CREATE TABLE IF NOT EXISTS {} (key BYTEA PRIMARY KEY, val BYTEA)",
+                    b.as_str()
+                ))
+            },
+            |b: &Bucket| {
+                Ok(format!(
+                    r#"
+                        INSERT INTO {} VALUES ($1::BYTEA, $2::BYTEA)
+                        ON CONFLICT (key) DO UPDATE SET val = EXCLUDED.val
+                    "#,
+                    b.as_str()
+                ))
+            },
+        ),
+    )
+}
+
+/// Both clients autocommit each upsert; nothing is left to commit after a page.
+fn autocommit<C>(_: &mut C) -> Result<(), Event> {
+    Ok(())
+}
+
+fn print_progress(p: &Progress) {
+    println!("migrating {}: {} items", p.as_bucket(), p.copied());
+}
+
+pub fn migrate() -> Result<(), Event> {
+    let mut sqlite: Connection = Connection::open_in_memory()
+        .map_err(|e| Event::ConnectionError(format!("Unable to open: {}", e)))?;
+    let mut pg: Client = Config::new()
+        .host(env::var("PGHOST").unwrap().as_str())
+        .dbname(env::var("PGDATABASE").unwrap().as_str())
+        .user(env::var("PGUSER").unwrap().as_str())
+        .password(env::var("PGPASSWORD").unwrap_or_default())
+        .connect(NoTls)
+        .map_err(|e| Event::ConnectionError(format!("Unable to connect: {}", e)))?;
+
+    let upsert = sqlite_upsert();
+    for name in ["migrate_devices", "migrate_dates"] {
+        let items: Vec<RawItem> = (0..2500u32)
+            .map(|i: u32| Item::new(i.to_be_bytes().to_vec(), format!("{}", i).into_bytes()))
+            .collect();
+        let req = BulkRequest::new(Bucket::from(String::from(name)), items);
+        upsert(vec![req].into_iter(), &mut sqlite)?;
+    }
+
+    let to_pg = migrate_all_new_mut(
+        sqlite_buckets(),
+        migrate_bucket_new_mut(sqlite_page(), pg_upsert(), autocommit, 1000, print_progress),
+    );
+    let cnt: u64 = to_pg(&mut sqlite, &mut pg)?;
+    println!("migrated to postgres: {}", cnt);
+
+    let verify = verify_bucket_new_mut(
+        digest_bucket_new_mut(sqlite_page(), 1000),
+        digest_bucket_new_mut(pg_page(), 1000),
+    );
+    for b in sqlite_buckets()(&mut sqlite)? {
+        let d: BucketDigest = verify(&b, &mut sqlite, &mut pg)?;
+        println!(
+            "verified {}: {} items, hash {:016x}",
+            b.as_str(),
+            d.count(),
+            d.hash()
+        );
+    }
+
+    let mut back: Connection = Connection::open_in_memory()
+        .map_err(|e| Event::ConnectionError(format!("Unable to open: {}", e)))?;
+    let to_sqlite =
+        migrate_bucket_new_mut(pg_page(), sqlite_upsert(), autocommit, 1000, print_progress);
+    let b: Bucket = Bucket::from(String::from("migrate_devices"));
+    to_sqlite(&b, Some(1000u32.to_be_bytes().to_vec()), &mut pg, &mut back)?;
+    to_sqlite(&b, None, &mut pg, &mut back)?;
+    let verify = verify_bucket_new_mut(
+        digest_bucket_new_mut(pg_page(), 1000),
+        digest_bucket_new_mut(sqlite_page(), 1000),
+    );
+    let d: BucketDigest = verify(&b, &mut pg, &mut back)?;
+    println!("verified {}(back): {} items", b.as_str(), d.count());
+    Ok(())
+}
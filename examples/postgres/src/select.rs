@@ -3,8 +3,9 @@ use std::env;
 use rs_rdb2kv::{bucket::Bucket, evt::Event};
 
 use rs_rdb2kv::get::{select_bytes_new_mut, GetRequest};
+use rs_rdb2kv::memo::{Operation, StatementCache};
 
-use postgres::{Client, Config, NoTls, Row};
+use postgres::{Client, Config, NoTls, Row, Statement};
 
 fn pg_sel_builder() -> impl Fn(&Bucket) -> Result<String, Event> {
     move |b: &Bucket| {
@@ -91,5 +92,41 @@ pub fn select() -> Result<(), Event> {
         .map_err(|e| Event::UnexpectedError(format!("Unable to convert to string: {}", e)))?;
     println!("selected: {}", s);
 
+    let mut cc = CachedClient {
+        client: c,
+        cache: StatementCache::new(1024),
+    };
+    for _ in 0..3 {
+        let ov: Option<Vec<u8>> = sel_cached(&q, &mut cc)?;
+        println!("selected(cached): {:?}", ov.map(String::from_utf8));
+    }
+    println!("cached statements: {}", cc.cache.len());
+
     Ok(())
 }
+
+struct CachedClient {
+    client: Client,
+    cache: StatementCache<Statement>,
+}
+
+/// Selects a value using the statement cached per bucket(the query is built only once).
+fn sel_cached(q: &GetRequest<Vec<u8>>, c: &mut CachedClient) -> Result<Option<Vec<u8>>, Event> {
+    let builder = pg_sel_builder();
+    let client: &mut Client = &mut c.client;
+    let s: &Statement =
+        c.cache
+            .get_or_prepare(Operation::Select, q.as_bucket(), builder, |query: &str| {
+                client
+                    .prepare(query)
+                    .map_err(|e| Event::UnexpectedError(format!("Unable to prepare: {}", e)))
+            })?;
+    let key: &[u8] = q.as_key();
+    let o: Option<Row> = client
+        .query_opt(s, &[&key])
+        .map_err(|e| Event::UnexpectedError(format!("Unable to try to get a row: {}", e)))?;
+    match o {
+        None => Ok(None),
+        Some(row) => row2bytes(&row).map(Some),
+    }
+}
@@ -1,12 +1,19 @@
 use std::env;
 
-use rs_rdb2kv::upsert::{upsert_builder_new, upsert_bytes_all_new_mut, BulkRequest, UpsertBuilder};
+use rs_rdb2kv::upsert::{
+    upsert_builder_multi_new, upsert_builder_new, upsert_bytes_all_new_chunked_mut,
+    upsert_bytes_all_new_mut, upsert_bytes_all_new_report_mut, upsert_bytes_all_new_returning_mut,
+    BulkRequest, PrevValues, UpsertBuilder, UpsertBuilderMulti, UpsertOutcome, UpsertReport,
+};
 
 use rs_rdb2kv::bucket::Bucket;
 use rs_rdb2kv::evt::Event;
 use rs_rdb2kv::item::Item;
 
-use postgres::{Client, Config, NoTls, Transaction};
+use rs_rdb2kv::retry::{retry_mut, retryable_default, RetryPolicy};
+
+use postgres::error::SqlState;
+use postgres::{Client, Config, IsolationLevel, NoTls, Row, SimpleQueryMessage, Transaction};
 
 fn pg_upsert_unchecked_new() -> impl UpsertBuilder {
     upsert_builder_new(
@@ -40,6 +47,187 @@ fn pg_upsert_unchecked_new() -> impl UpsertBuilder {
     )
 }
 
+fn pg_upsert_unnest_new() -> impl UpsertBuilderMulti {
+    upsert_builder_multi_new(
+        |b: &Bucket| pg_upsert_unchecked_new().build_create(b),
+        |b: &Bucket| pg_upsert_unchecked_new().build_upsert(b),
+        |b: &Bucket, _rows: usize| {
+            Ok(format!(
+                r#"
+                    INSERT INTO {} AS tgt
+                    SELECT * FROM UNNEST($1::BYTEA[], $2::BYTEA[])
+                    ON CONFLICT ON CONSTRAINT {}_pkc
+                    DO UPDATE
+                    SET val = EXCLUDED.val
+                    WHERE tgt.val <> EXCLUDED.val
+                "#,
+                b.as_str(),
+                b.as_str(),
+            ))
+        },
+    )
+}
+
+fn pg_upsert_all_unnest<I>(requests: I, mut t: Transaction) -> Result<u64, Event>
+where
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    let c = |t: &mut Transaction, query: &str| {
+        t.execute(query, &[])
+            .map_err(|e| Event::UnexpectedError(format!("Unable to create bucket: {}", e)))
+    };
+    let u = |t: &mut Transaction, query: &str, items: &[Item<Vec<u8>, Vec<u8>>]| {
+        let keys: Vec<&[u8]> = items.iter().map(|i| i.as_key().as_slice()).collect();
+        let vals: Vec<&[u8]> = items.iter().map(|i| i.as_val().as_slice()).collect();
+        t.execute(query, &[&keys, &vals])
+            .map_err(|e| Event::UnexpectedError(format!("Unable to upsert: {}", e)))
+    };
+    let f = upsert_bytes_all_new_chunked_mut(c, u, pg_upsert_unnest_new(), 10_000);
+    let cnt: u64 = f(requests, &mut t)?;
+    t.commit()
+        .map_err(|e| Event::UnexpectedError(format!("Unable to commit changes: {}", e)))?;
+    Ok(cnt)
+}
+
+/// Builds create query which first reports whether the bucket is missing(`t` or `f`).
+///
+/// The statements are executed using the simple query protocol.
+fn pg_create_report_new() -> impl Fn(&Bucket) -> Result<String, Event> {
+    |b: &Bucket| {
+        let create: String = pg_upsert_unchecked_new().build_create(b)?;
+        Ok(format!(
+            "SELECT to_regclass('{}') IS NULL; {}",
+            b.as_str(),
+            create
+        ))
+    }
+}
+
+fn pg_upsert_returning_new() -> impl UpsertBuilder {
+    upsert_builder_new(pg_create_report_new(), |b: &Bucket| {
+        pg_upsert_unchecked_new()
+            .build_upsert(b)
+            .map(|q: String| format!("{} RETURNING (xmax = 0) AS inserted", q))
+    })
+}
+
+fn pg_upsert_all_report<I>(requests: I, mut t: Transaction) -> Result<UpsertReport, Event>
+where
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    let c = |t: &mut Transaction, query: &str| {
+        let msgs: Vec<SimpleQueryMessage> = t
+            .simple_query(query)
+            .map_err(|e| Event::UnexpectedError(format!("Unable to create bucket: {}", e)))?;
+        let missing: Option<&str> = msgs.iter().find_map(|m| match m {
+            SimpleQueryMessage::Row(row) => row.get(0),
+            _ => None,
+        });
+        Ok(u64::from(missing == Some("t")))
+    };
+    let u = |t: &mut Transaction, query: &str, key: &[u8], val: &[u8]| {
+        let o: Option<Row> = t
+            .query_opt(query, &[&key, &val])
+            .map_err(|e| Event::UnexpectedError(format!("Unable to upsert: {}", e)))?;
+        match o {
+            None => Ok(UpsertOutcome::Unchanged),
+            Some(row) => row
+                .try_get(0)
+                .map(|inserted: bool| match inserted {
+                    true => UpsertOutcome::Inserted,
+                    false => UpsertOutcome::Updated,
+                })
+                .map_err(|e| Event::UnexpectedError(format!("Unable to get outcome: {}", e))),
+        }
+    };
+    let f = upsert_bytes_all_new_report_mut(c, u, pg_upsert_returning_new());
+    let report: UpsertReport = f(requests, &mut t)?;
+    t.commit()
+        .map_err(|e| Event::UnexpectedError(format!("Unable to commit changes: {}", e)))?;
+    Ok(report)
+}
+
+/// Builds upsert query which returns the previous value.
+///
+/// `FOR UPDATE` locks nothing if the key is missing, so two transactions inserting the same new
+/// key could both get `None`; run it in a SERIALIZABLE transaction(see `pg_upsert_all_prev`):
+/// the conflicting upsert fails with a serialization failure and the transaction is replayed.
+fn pg_upsert_prev_new() -> impl UpsertBuilder {
+    upsert_builder_new(
+        |b: &Bucket| pg_upsert_unchecked_new().build_create(b),
+        |b: &Bucket| {
+            Ok(format!(
+                r#"
+                    WITH prev AS (
+                        SELECT val FROM {} WHERE key = $1::BYTEA FOR UPDATE
+                    ), upst AS (
+                        INSERT INTO {} AS tgt
+                        VALUES($1::BYTEA, $2::BYTEA)
+                        ON CONFLICT ON CONSTRAINT {}_pkc
+                        DO UPDATE
+                        SET val = EXCLUDED.val
+                        WHERE tgt.val <> EXCLUDED.val
+                    )
+                    SELECT val FROM prev
+                "#,
+                b.as_str(),
+                b.as_str(),
+                b.as_str(),
+            ))
+        },
+    )
+}
+
+fn pg_event(msg: &'static str) -> impl Fn(postgres::Error) -> Event {
+    move |e: postgres::Error| match e.code() {
+        Some(&SqlState::T_R_SERIALIZATION_FAILURE) | Some(&SqlState::T_R_DEADLOCK_DETECTED) => {
+            Event::Conflict(format!("{}: {}", msg, e))
+        }
+        _ => Event::UnexpectedError(format!("{}: {}", msg, e)),
+    }
+}
+
+/// Upserts items and gets previous values in a SERIALIZABLE transaction(replayed on conflicts).
+fn pg_upsert_all_prev(
+    c: &mut Client,
+    b: &Bucket,
+    items: &[Item<Vec<u8>, Vec<u8>>],
+) -> Result<Vec<PrevValues>, Event> {
+    retry_mut(
+        &RetryPolicy::default(),
+        &retryable_default,
+        &std::thread::sleep,
+        || {
+            let mut t: Transaction = c
+                .build_transaction()
+                .isolation_level(IsolationLevel::Serializable)
+                .start()
+                .map_err(pg_event("Unable to start transaction"))?;
+            let copied: Vec<Item<Vec<u8>, Vec<u8>>> = items
+                .iter()
+                .map(|i| Item::new(i.as_key().clone(), i.as_val().clone()))
+                .collect();
+            let req = BulkRequest::new(Bucket::from(String::from(b.as_str())), copied);
+            let create = |t: &mut Transaction, query: &str| {
+                t.execute(query, &[])
+                    .map_err(pg_event("Unable to create bucket"))
+            };
+            let u = |t: &mut Transaction, query: &str, key: &[u8], val: &[u8]| {
+                let o: Option<Row> = t
+                    .query_opt(query, &[&key, &val])
+                    .map_err(pg_event("Unable to upsert"))?;
+                o.map(|row: Row| row.try_get(0))
+                    .transpose()
+                    .map_err(pg_event("Unable to get previous value"))
+            };
+            let f = upsert_bytes_all_new_returning_mut(create, u, pg_upsert_prev_new());
+            let prev: Vec<PrevValues> = f(vec![req].into_iter(), &mut t)?;
+            t.commit().map_err(pg_event("Unable to commit changes"))?;
+            Ok(prev)
+        },
+    )
+}
+
 fn pg_upsert_all<I>(requests: I, mut t: Transaction) -> Result<u64, Event>
 where
     I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
@@ -89,5 +277,50 @@ pub fn upsert() -> Result<(), Event> {
     )];
     let upst_cnt: u64 = pg_upsert_all(req.into_iter(), t)?;
     println!("upserted: {}", upst_cnt);
+
+    let t: Transaction = c
+        .transaction()
+        .map_err(|e| Event::UnexpectedError(format!("Unable to start transaction: {}", e)))?;
+    let items: Vec<Item<Vec<u8>, Vec<u8>>> = (0..100_000)
+        .map(|i: u32| Item::new(format!("{:08}", i).into_bytes(), i.to_be_bytes().to_vec()))
+        .collect();
+    let req = vec![BulkRequest::new(
+        Bucket::from(String::from(
+            "data_2022_11_02_cafef00ddeadbeafface864299792458",
+        )),
+        items,
+    )];
+    let upst_cnt: u64 = pg_upsert_all_unnest(req.into_iter(), t)?;
+    println!("upserted(unnest): {}", upst_cnt);
+
+    let t: Transaction = c
+        .transaction()
+        .map_err(|e| Event::UnexpectedError(format!("Unable to start transaction: {}", e)))?;
+    let req = vec![BulkRequest::new(
+        Bucket::from(String::from("devices_2022_11_04")),
+        vec![
+            Item::new(b"cafef00d".to_vec(), b"42".to_vec()),
+            Item::new(b"dafef00d".to_vec(), b"42".to_vec()),
+        ],
+    )];
+    let report: UpsertReport = pg_upsert_all_report(req.into_iter(), t)?;
+    for (bucket, r) in report.iter() {
+        println!(
+            "{}: created={} inserted={} updated={} unchanged={}",
+            bucket,
+            r.created(),
+            r.inserted(),
+            r.updated(),
+            r.unchanged(),
+        );
+    }
+
+    let items = vec![
+        Item::new(b"cafef00d".to_vec(), b"43".to_vec()),
+        Item::new(b"eafef00d".to_vec(), b"43".to_vec()),
+    ];
+    let b = Bucket::from(String::from("devices_2022_11_04"));
+    let prev: Vec<PrevValues> = pg_upsert_all_prev(&mut c, &b, &items)?;
+    println!("previous values: {:?}", prev);
     Ok(())
 }
@@ -0,0 +1,105 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use rs_rdb2kv::blob::{read_range, BlobIo};
+use rs_rdb2kv::{bucket::Bucket, evt::Event};
+
+use rusqlite::blob::Blob;
+use rusqlite::{params, Connection, DatabaseName, OptionalExtension};
+
+struct SqliteBlobs {
+    con: Connection,
+}
+
+impl SqliteBlobs {
+    fn rowid(&self, b: &Bucket, key: &[u8]) -> Result<Option<i64>, Event> {
+        let query: String = format!("SELECT rowid FROM {} WHERE key = ?1", b.as_str());
+        self.con
+            .query_row(query.as_str(), params![key], |row| row.get(0))
+            .optional()
+            .map_err(|e| Event::UnexpectedError(format!("Unable to get a rowid: {}", e)))
+    }
+
+    fn open(&self, b: &Bucket, rowid: i64, read_only: bool) -> Result<Blob<'_>, Event> {
+        self.con
+            .blob_open(DatabaseName::Main, b.as_str(), "val", rowid, read_only)
+            .map_err(|e| Event::UnexpectedError(format!("Unable to open a blob: {}", e)))
+    }
+}
+
+impl BlobIo for SqliteBlobs {
+    type Reader<'a> = Blob<'a>;
+    type Writer<'a> = Blob<'a>;
+
+    fn open_value_reader<'a>(
+        &'a mut self,
+        b: &Bucket,
+        key: &[u8],
+    ) -> Result<Option<Self::Reader<'a>>, Event> {
+        match self.rowid(b, key)? {
+            None => Ok(None),
+            Some(rowid) => self.open(b, rowid, true).map(Some),
+        }
+    }
+
+    fn open_value_writer<'a>(
+        &'a mut self,
+        b: &Bucket,
+        key: &[u8],
+        len: u64,
+    ) -> Result<Self::Writer<'a>, Event> {
+        let len: i64 = len
+            .try_into()
+            .map_err(|_| Event::UnexpectedError(format!("Too large value: {}", len)))?;
+        let query: String = format!(
+            r#"
+                INSERT INTO {} VALUES(?1, zeroblob(?2))
+                ON CONFLICT(key) DO UPDATE SET val = excluded.val
+            "#,
+            b.as_str(),
+        );
+        self.con
+            .execute(query.as_str(), params![key, len])
+            .map_err(|e| Event::UnexpectedError(format!("Unable to allocate a blob: {}", e)))?;
+        let rowid: i64 = self
+            .rowid(b, key)?
+            .ok_or_else(|| Event::UnexpectedError(String::from("Missing allocated blob")))?;
+        self.open(b, rowid, false)
+    }
+}
+
+pub fn ranges() -> Result<(), Event> {
+    let con: Connection = Connection::open_in_memory()
+        .map_err(|e| Event::ConnectionError(format!("Unable to open: {}", e)))?;
+    con.execute(
+        r#"
+            CREATE TABLE IF NOT EXISTS artifacts(
+                key BLOB,
+                val BLOB,
+                CONSTRAINT artifacts_pkc PRIMARY KEY(key)
+            )
+        "#,
+        params![],
+    )
+    .map_err(|e| Event::UnexpectedError(format!("Unable to create artifacts bucket: {}", e)))?;
+    let mut blobs = SqliteBlobs { con };
+    let b: Bucket = Bucket::from(String::from("artifacts"));
+
+    let mut w = blobs.open_value_writer(&b, b"build.tar", 1 << 20)?;
+    w.write_all(b"ustar")
+        .and_then(|_| w.seek(SeekFrom::End(-4)))
+        .and_then(|_| w.write_all(b"tail"))
+        .map_err(|e| Event::UnexpectedError(format!("Unable to write a blob: {}", e)))?;
+    drop(w);
+
+    let mut r = blobs
+        .open_value_reader(&b, b"build.tar")?
+        .ok_or_else(|| Event::UnexpectedError(String::from("Unable to get a blob")))?;
+    let mut head: Vec<u8> = vec![];
+    read_range(&mut r, 0, 5, &mut head)?;
+    let mut tail: String = String::new();
+    r.seek(SeekFrom::End(-4))
+        .and_then(|_| r.read_to_string(&mut tail))
+        .map_err(|e| Event::UnexpectedError(format!("Unable to read a blob: {}", e)))?;
+    println!("blob head: {:?}, tail: {}", String::from_utf8(head), tail);
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use rs_rdb2kv::chunk::ChunkStore;
+use rs_rdb2kv::del::delete_key_bytes_mut;
+use rs_rdb2kv::get::{select_bytes_new_mut, GetRequest};
+use rs_rdb2kv::item::{Item, RawItem};
+use rs_rdb2kv::list::{list_items_page_new_mut, page_builder_new, PageRequest};
+use rs_rdb2kv::upsert::{upsert_builder_new, upsert_bytes_all_new_mut, Requests};
+use rs_rdb2kv::{bucket::Bucket, evt::Event};
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+/// Maps a missing table to `BucketNotFound`(required by `ChunkStore`).
+fn unexpected(msg: &'static str) -> impl Fn(rusqlite::Error) -> Event {
+    move |e: rusqlite::Error| match &e {
+        rusqlite::Error::SqliteFailure(_, Some(m)) if m.starts_with("no such table") => {
+            Event::BucketNotFound(format!("{}: {}", msg, e))
+        }
+        _ => Event::UnexpectedError(format!("{}: {}", msg, e)),
+    }
+}
+
+fn row2item(r: &Row) -> Result<RawItem, rusqlite::Error> {
+    Ok(Item::new(r.get(0)?, r.get(1)?))
+}
+
+#[allow(clippy::type_complexity)]
+fn chunk_store() -> ChunkStore<
+    impl Fn(&GetRequest<Vec<u8>>, &mut Connection) -> Result<Option<Vec<u8>>, Event>,
+    impl Fn(Requests, &mut Connection) -> Result<u64, Event>,
+    impl Fn(&Bucket, &[u8], &mut Connection) -> Result<u64, Event>,
+    impl Fn(&PageRequest, &mut Connection) -> Result<Vec<RawItem>, Event>,
+> {
+    ChunkStore::new(
+        select_bytes_new_mut(
+            |t: &mut Connection, query: &str, key: &[u8]| {
+                t.query_row(query, params![key], |r| r.get(0))
+                    .optional()
+                    .map_err(unexpected("Unable to select"))
+            },
+            |b: &Bucket| Ok(format!("SELECT val FROM {} WHERE key = ?1", b.as_str())),
+        ),
+        upsert_bytes_all_new_mut(
+            |t: &mut Connection, query: &str| {
+                t.execute_batch(query)
+                    .map(|_| 0)
+                    .map_err(unexpected("Unable to create"))
+            },
+            |t: &mut Connection, query: &str, key: &[u8], val: &[u8]| {
+                t.execute(query, params![key, val])
+                    .map(|cnt: usize| cnt as u64)
+                    .map_err(unexpected("Unable to upsert"))
+            },
+            upsert_builder_new(
+                |b: &Bucket| {
+                    Ok(format!(
+                        "CREATE TABLE IF NOT EXISTS {} (key BLOB PRIMARY KEY, val BLOB)",
+                        b.as_str()
+                    ))
+                },
+                |b: &Bucket| {
+                    Ok(format!(
+                        r#"
+                            INSERT INTO {} VALUES (?1, ?2)
+                            ON CONFLICT (key) DO UPDATE SET val = excluded.val
+                        "#,
+                        b.as_str()
+                    ))
+                },
+            ),
+        ),
+        delete_key_bytes_mut(
+            |t: &mut Connection, query: &str, key: &[u8]| {
+                t.execute(query, params![key])
+                    .map(|cnt: usize| cnt as u64)
+                    .map_err(unexpected("Unable to delete"))
+            },
+            |b: &Bucket| Ok(format!("DELETE FROM {} WHERE key = ?1", b.as_str())),
+        ),
+        list_items_page_new_mut(
+            |t: &mut Connection, query: &str, after: Option<&[u8]>, limit: u64| {
+                let mut s = t.prepare(query).map_err(unexpected("Unable to prepare"))?;
+                let rows = match after {
+                    None => s.query_map(params![limit as i64], row2item),
+                    Some(a) => s.query_map(params![a, limit as i64], row2item),
+                }
+                .map_err(unexpected("Unable to get chunks"))?;
+                rows.map(|r| r.map_err(unexpected("Unable to get a chunk")))
+                    .collect()
+            },
+            page_builder_new(
+                |b: &Bucket| {
+                    Ok(format!(
+                        "SELECT key, val FROM {} ORDER BY key LIMIT ?1",
+                        b.as_str()
+                    ))
+                },
+                |b: &Bucket| {
+                    Ok(format!(
+                        "SELECT key, val FROM {} WHERE key > ?1 ORDER BY key LIMIT ?2",
+                        b.as_str()
+                    ))
+                },
+            ),
+        ),
+        4096,
+    )
+}
+
+/// Runs the closure in a transaction; commits it on success and rolls it back on failure.
+fn in_transaction<F, T>(c: &mut Connection, f: F) -> Result<T, Event>
+where
+    F: FnOnce(&mut Connection) -> Result<T, Event>,
+{
+    c.execute_batch("BEGIN")
+        .map_err(unexpected("Unable to start transaction"))?;
+    match f(c) {
+        Ok(r) => {
+            c.execute_batch("COMMIT")
+                .map_err(unexpected("Unable to commit"))?;
+            Ok(r)
+        }
+        Err(e) => {
+            c.execute_batch("ROLLBACK")
+                .map_err(unexpected("Unable to roll back"))?;
+            Err(e)
+        }
+    }
+}
+
+pub fn chunks() -> Result<(), Event> {
+    let mut c: Connection = Connection::open_in_memory()
+        .map_err(|e| Event::ConnectionError(format!("Unable to open: {}", e)))?;
+    let store = chunk_store();
+    let b = Bucket::from(String::from("artifacts"));
+
+    let missing = in_transaction(&mut c, |t| store.get(&b, b"empty", t))?;
+    println!("chunks: get from a missing bucket: {:?}", missing);
+    let deleted: u64 = in_transaction(&mut c, |t| store.delete(&b, b"empty", t))?;
+    println!("chunks: deleted from a missing bucket: {}", deleted);
+
+    in_transaction(&mut c, |t| store.put(&b, b"empty", &mut [].as_slice(), t))?;
+    let empty = in_transaction(&mut c, |t| store.get(&b, b"empty", t))?;
+    println!("chunks: empty value: {:?}", empty);
+
+    let large: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+    let len: u64 = in_transaction(&mut c, |t| {
+        store.put(&b, b"large", &mut large.as_slice(), t)
+    })?;
+    let got = in_transaction(&mut c, |t| store.get(&b, b"large", t))?;
+    println!(
+        "chunks: wrote {} bytes, read back: {}",
+        len,
+        got == Some(large)
+    );
+
+    let deleted: u64 = in_transaction(&mut c, |t| store.delete(&b, b"large", t))?;
+    println!("chunks: deleted rows(manifest + chunks): {}", deleted);
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use rs_rdb2kv::conformance::{check_all, kv_handlers_new, KvHandlers, Requests};
+use rs_rdb2kv::del::{delete_key_bytes_mut, drop_bucket_mut, drop_builder_default_unchecked};
+use rs_rdb2kv::get::select_bytes_new_mut;
+use rs_rdb2kv::list::{list_keys_bytes_new_mut, list_query_builder_unchecked};
+use rs_rdb2kv::upsert::{upsert_builder_new, upsert_bytes_all_new_mut, UpsertBuilder};
+use rs_rdb2kv::{bucket::Bucket, evt::Event};
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+fn unexpected(msg: &'static str) -> impl Fn(rusqlite::Error) -> Event {
+    move |e: rusqlite::Error| match &e {
+        rusqlite::Error::SqliteFailure(_, Some(m)) if m.starts_with("no such table") => {
+            Event::BucketNotFound(format!("{}: {}", msg, e))
+        }
+        _ => Event::UnexpectedError(format!("{}: {}", msg, e)),
+    }
+}
+
+fn upsert_builder_sqlite() -> impl UpsertBuilder {
+    upsert_builder_new(
+        |b: &Bucket| {
+            Ok(format!(
+                "CREATE TABLE IF NOT EXISTS {} (key BLOB PRIMARY KEY, val BLOB)",
+                b.as_str()
+            ))
+        },
+        |b: &Bucket| {
+            Ok(format!(
+                r#"
+                    INSERT INTO {} VALUES (?1, ?2)
+                    ON CONFLICT (key) DO UPDATE SET val = excluded.val
+                "#,
+                b.as_str()
+            ))
+        },
+    )
+}
+
+fn upsert_handler<'a>() -> impl Fn(Requests, &mut Transaction<'a>) -> Result<u64, Event> {
+    upsert_bytes_all_new_mut(
+        |t: &mut Transaction, query: &str| {
+            t.execute_batch(query)
+                .map(|_| 0)
+                .map_err(unexpected("Unable to create"))
+        },
+        |t: &mut Transaction, query: &str, key: &[u8], val: &[u8]| {
+            t.execute(query, params![key, val])
+                .map(|cnt: usize| cnt as u64)
+                .map_err(unexpected("Unable to upsert"))
+        },
+        upsert_builder_sqlite(),
+    )
+}
+
+fn handlers() -> impl KvHandlers<Connection> {
+    kv_handlers_new(
+        select_bytes_new_mut(
+            |c: &mut Connection, query: &str, key: &[u8]| {
+                c.query_row(query, params![key], |r| r.get(0))
+                    .optional()
+                    .map_err(unexpected("Unable to select"))
+            },
+            |b: &Bucket| Ok(format!("SELECT val FROM {} WHERE key = ?1", b.as_str())),
+        ),
+        |requests: Requests, c: &mut Connection| {
+            let mut t: Transaction = c
+                .transaction()
+                .map_err(unexpected("Unable to start transaction"))?;
+            let cnt: u64 = upsert_handler()(requests, &mut t)?;
+            t.commit().map_err(unexpected("Unable to commit"))?;
+            Ok(cnt)
+        },
+        delete_key_bytes_mut(
+            |c: &mut Connection, query: &str, key: &[u8]| {
+                c.execute(query, params![key])
+                    .map(|cnt: usize| cnt as u64)
+                    .map_err(unexpected("Unable to delete"))
+            },
+            |b: &Bucket| Ok(format!("DELETE FROM {} WHERE key = ?1", b.as_str())),
+        ),
+        list_keys_bytes_new_mut(
+            |c: &mut Connection, query: &str| {
+                let mut s = c.prepare(query).map_err(unexpected("Unable to prepare"))?;
+                let rows = s
+                    .query_map(params![], |r| r.get(0))
+                    .map_err(unexpected("Unable to list"))?;
+                rows.map(|r| r.map_err(unexpected("Unable to get a key")))
+                    .collect()
+            },
+            list_query_builder_unchecked(),
+        ),
+        drop_bucket_mut(
+            |c: &mut Connection, query: &str| {
+                c.execute_batch(query).map_err(unexpected("Unable to drop"))
+            },
+            drop_builder_default_unchecked(),
+        ),
+    )
+}
+
+pub fn conformance() -> Result<(), Event> {
+    let mut c: Connection = Connection::open_in_memory()
+        .map_err(|e| Event::ConnectionError(format!("Unable to open: {}", e)))?;
+    let b = Bucket::from(String::from("conformance_2022_11_01"));
+    check_all(&handlers(), &mut c, &b)?;
+    println!("conformance: ok");
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use std::io::{self, Stdout};
+
+use rs_rdb2kv::dump::{
+    export_bucket_new_mut, import_bucket_new_mut, DumpFormat, DumpReader, DumpWriter,
+};
+use rs_rdb2kv::item::{Item, RawItem};
+use rs_rdb2kv::list::{list_items_page_new_mut, page_builder_new, PageBuilder, PageRequest};
+use rs_rdb2kv::upsert::{upsert_builder_new, upsert_bytes_all_new_mut, BulkRequest, UpsertBuilder};
+use rs_rdb2kv::{bucket::Bucket, evt::Event};
+
+use rusqlite::{params, Connection};
+
+fn page_builder_sqlite() -> impl PageBuilder {
+    page_builder_new(
+        |b: &Bucket| {
+            Ok(format!(
+                "SELECT key, val FROM {} ORDER BY key LIMIT ?1",
+                b.as_str()
+            ))
+        },
+        |b: &Bucket| {
+            Ok(format!(
+                "SELECT key, val FROM {} WHERE key > ?1 ORDER BY key LIMIT ?2",
+                b.as_str()
+            ))
+        },
+    )
+}
+
+pub fn sqlite_page(
+    c: &mut Connection,
+    query: &str,
+    after: Option<&[u8]>,
+    limit: u64,
+) -> Result<Vec<RawItem>, Event> {
+    let mut s = c
+        .prepare_cached(query)
+        .map_err(|e| Event::UnexpectedError(format!("Unable to prepare: {}", e)))?;
+    let to_item = |row: &rusqlite::Row| Ok(Item::new(row.get(0)?, row.get(1)?));
+    let rows = match after {
+        None => s.query_map(params![limit as i64], to_item),
+        Some(a) => s.query_map(params![a, limit as i64], to_item),
+    }
+    .map_err(|e| Event::UnexpectedError(format!("Unable to get items: {}", e)))?;
+    rows.map(|r| r.map_err(|e| Event::UnexpectedError(format!("Unable to get an item: {}", e))))
+        .collect()
+}
+
+pub fn sqlite_page_getter() -> impl Fn(&PageRequest, &mut Connection) -> Result<Vec<RawItem>, Event>
+{
+    list_items_page_new_mut(sqlite_page, page_builder_sqlite())
+}
+
+fn upsert_builder_sqlite() -> impl UpsertBuilder {
+    upsert_builder_new(
+        |b: &Bucket| {
+            Ok(format!(
+                "CREATE TABLE IF NOT EXISTS {} (key BLOB PRIMARY KEY, val BLOB)",
+                b.as_str()
+            ))
+        },
+        |b: &Bucket| {
+            Ok(format!(
+                r#"
+                    INSERT INTO {} VALUES (?1, ?2)
+                    ON CONFLICT (key) DO UPDATE SET val = excluded.val
+                "#,
+                b.as_str()
+            ))
+        },
+    )
+}
+
+pub fn export_import() -> Result<(), Event> {
+    let mut c: Connection = Connection::open_in_memory()
+        .map_err(|e| Event::ConnectionError(format!("Unable to open: {}", e)))?;
+    let upsert = upsert_bytes_all_new_mut(
+        |c: &mut Connection, query: &str| {
+            c.execute(query, params![])
+                .map(|cnt: usize| cnt as u64)
+                .map_err(|e| Event::UnexpectedError(format!("Unable to create bucket: {}", e)))
+        },
+        |c: &mut Connection, query: &str, key: &[u8], val: &[u8]| {
+            c.execute(query, params![key, val])
+                .map(|cnt: usize| cnt as u64)
+                .map_err(|e| Event::UnexpectedError(format!("Unable to upsert: {}", e)))
+        },
+        upsert_builder_sqlite(),
+    );
+    let src: Bucket = Bucket::from(String::from("devices"));
+    let items: Vec<RawItem> = (0..5u8)
+        .map(|i: u8| Item::new(format!("device-{}", i).into_bytes(), vec![i; 3]))
+        .collect();
+    upsert(
+        vec![BulkRequest::new(
+            Bucket::from(String::from("devices")),
+            items,
+        )]
+        .into_iter(),
+        &mut c,
+    )?;
+
+    let mut nd: DumpWriter<Stdout> = DumpWriter::new(DumpFormat::Ndjson, io::stdout(), &src)?;
+    let export = export_bucket_new_mut(sqlite_page_getter(), 2);
+    export(&src, &mut c, &mut nd)?;
+    nd.finish()?;
+
+    let mut bin: DumpWriter<Vec<u8>> = DumpWriter::new(DumpFormat::Binary, vec![], &src)?;
+    let export = export_bucket_new_mut(sqlite_page_getter(), 1000);
+    let cnt: u64 = export(&src, &mut c, &mut bin)?;
+    let dumped: Vec<u8> = bin.finish()?;
+    println!("exported: {} items, {} bytes", cnt, dumped.len());
+
+    let import = import_bucket_new_mut(upsert, 1000);
+    let mut r = DumpReader::new(DumpFormat::Binary, dumped.as_slice())?;
+    let dst: Bucket = Bucket::from(format!("{}_copy", r.bucket()));
+    let cnt: u64 = import(&dst, &mut r, &mut c)?;
+    println!("imported: {}", cnt);
+    Ok(())
+}
@@ -0,0 +1,191 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+use rs_rdb2kv::feed::{
+    changelog_builder_new, changelog_enable_mut, changes_follow_new_mut, changes_since_new_mut,
+    Change, ChangeOp, ChangelogBuilder,
+};
+use rs_rdb2kv::{bucket::Bucket, evt::Event};
+
+use rusqlite::hooks::Action;
+use rusqlite::{params, Connection, Row};
+
+/// Builds changelog queries; revisions follow the commit order because SQLite has one writer.
+fn changelog_builder_sqlite() -> impl ChangelogBuilder {
+    changelog_builder_new(
+        |b: &Bucket| {
+            let trigger = |suffix: &str, when: &str, rec: &str, op: &str| {
+                format!(
+                    r#"
+                        CREATE TRIGGER IF NOT EXISTS {b}_changes_{suffix}
+                        AFTER {when} ON {b}
+                        BEGIN
+                            INSERT INTO {b}_changes(key, op, ts)
+                            VALUES({rec}.key, '{op}', CAST(strftime('%s', 'now') AS INTEGER));
+                        END;
+                    "#,
+                    b = b.as_str(),
+                )
+            };
+            Ok(format!(
+                r#"
+                    CREATE TABLE IF NOT EXISTS {b}_changes (
+                        revision INTEGER PRIMARY KEY AUTOINCREMENT,
+                        key BLOB NOT NULL,
+                        op TEXT NOT NULL,
+                        ts INTEGER NOT NULL
+                    );
+                    {}
+                    {}
+                    {}
+                "#,
+                trigger("i", "INSERT", "NEW", ChangeOp::Insert.as_str()),
+                trigger("u", "UPDATE", "NEW", ChangeOp::Update.as_str()),
+                trigger("d", "DELETE", "OLD", ChangeOp::Delete.as_str()),
+                b = b.as_str(),
+            ))
+        },
+        |b: &Bucket| {
+            Ok(format!(
+                r#"
+                    DROP TRIGGER IF EXISTS {b}_changes_i;
+                    DROP TRIGGER IF EXISTS {b}_changes_u;
+                    DROP TRIGGER IF EXISTS {b}_changes_d;
+                    DROP TABLE IF EXISTS {b}_changes;
+                "#,
+                b = b.as_str(),
+            ))
+        },
+        |b: &Bucket| {
+            Ok(format!(
+                r#"
+                    SELECT key, op, revision, ts FROM {}_changes
+                    WHERE revision > ?1
+                    ORDER BY revision
+                    LIMIT ?2
+                "#,
+                b.as_str(),
+            ))
+        },
+    )
+}
+
+/// A connection which receives names of written tables from `update_hook`.
+///
+/// `update_hook` only sees writes done through this connection.
+struct FeedConnection {
+    conn: Connection,
+    written: Receiver<String>,
+}
+
+impl FeedConnection {
+    fn new(conn: Connection) -> Self {
+        let (tx, rx): (Sender<String>, Receiver<String>) = channel();
+        conn.update_hook(Some(
+            move |_: Action, _db: &str, table: &str, _rowid: i64| {
+                let _ = tx.send(String::from(table));
+            },
+        ));
+        Self { conn, written: rx }
+    }
+}
+
+fn row2change(r: &Row) -> Result<Change, rusqlite::Error> {
+    let op: String = r.get(1)?;
+    let op: ChangeOp = ChangeOp::try_from(op.as_str()).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(
+            1,
+            rusqlite::types::Type::Text,
+            format!("{:?}", e).into(),
+        )
+    })?;
+    Ok(Change::new(
+        r.get(0)?,
+        op,
+        r.get::<_, i64>(2)? as u64,
+        r.get(3)?,
+    ))
+}
+
+fn sqlite_since(
+    c: &mut FeedConnection,
+    query: &str,
+    rev: u64,
+    limit: u64,
+) -> Result<Vec<Change>, Event> {
+    let mut s = c
+        .conn
+        .prepare_cached(query)
+        .map_err(|e| Event::UnexpectedError(format!("Unable to prepare: {}", e)))?;
+    let rows = s
+        .query_map(params![rev as i64, limit as i64], row2change)
+        .map_err(|e| Event::UnexpectedError(format!("Unable to get changes: {}", e)))?;
+    rows.map(|r| r.map_err(|e| Event::UnexpectedError(format!("Unable to get a change: {}", e))))
+        .collect()
+}
+
+fn sqlite_wait(c: &mut FeedConnection, b: &Bucket) -> Result<bool, Event> {
+    let changelog: String = format!("{}_changes", b.as_str());
+    let first = c.written.recv_timeout(Duration::from_millis(100)).ok();
+    let rest = c.written.try_iter();
+    Ok(first
+        .into_iter()
+        .chain(rest)
+        .any(|t: String| t == changelog))
+}
+
+pub fn follow() -> Result<(), Event> {
+    let conn: Connection = Connection::open_in_memory()
+        .map_err(|e| Event::ConnectionError(format!("Unable to open: {}", e)))?;
+    let mut c = FeedConnection::new(conn);
+
+    c.conn
+        .execute(
+            r#"
+                CREATE TABLE IF NOT EXISTS devices (
+                    key BLOB,
+                    val BLOB,
+                    CONSTRAINT devices_pkc PRIMARY KEY(key)
+                )
+            "#,
+            params![],
+        )
+        .map_err(|e| Event::UnexpectedError(format!("Unable to create devices bucket: {}", e)))?;
+
+    let b: Bucket = Bucket::from(String::from("devices"));
+    let enable = changelog_enable_mut(
+        |c: &mut FeedConnection, query: &str| {
+            c.conn
+                .execute_batch(query)
+                .map_err(|e| Event::UnexpectedError(format!("Unable to enable changelog: {}", e)))
+        },
+        changelog_builder_sqlite(),
+    );
+    enable(&b, &mut c)?;
+
+    let since = changes_since_new_mut(sqlite_since, changelog_builder_sqlite());
+    let follow = changes_follow_new_mut(sqlite_wait, since);
+
+    let mut revision: u64 = 0;
+    let writes = [
+        "INSERT INTO devices VALUES (x'cafef00d', x'42')",
+        "UPDATE devices SET val = x'43' WHERE key = x'cafef00d'",
+        "DELETE FROM devices WHERE key = x'cafef00d'",
+    ];
+    for w in writes {
+        c.conn
+            .execute(w, params![])
+            .map_err(|e| Event::UnexpectedError(format!("Unable to write: {}", e)))?;
+        let changes: Vec<Change> = follow(&b, revision, 100, &mut c)?;
+        for ch in &changes {
+            println!(
+                "change: rev={} op={:?} key={:x?}",
+                ch.revision(),
+                ch.op(),
+                ch.as_key()
+            );
+        }
+        revision = changes.last().map(|ch| ch.revision()).unwrap_or(revision);
+    }
+    Ok(())
+}
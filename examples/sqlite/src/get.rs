@@ -1,4 +1,5 @@
 use rs_rdb2kv::get::{select_bytes_new_mut, GetRequest};
+use rs_rdb2kv::memo::builder_memo_new;
 use rs_rdb2kv::{bucket::Bucket, evt::Event};
 
 use rusqlite::{params, Connection, OptionalExtension};
@@ -16,17 +17,21 @@ fn select_builder_sqlite() -> impl Fn(&Bucket) -> Result<String, Event> {
     }
 }
 
-fn select_row(q: &GetRequest<Vec<u8>>, c: &mut Connection) -> Result<Option<Vec<u8>>, Event> {
-    let builder = select_builder_sqlite();
-    let f = select_bytes_new_mut(
+/// Creates the select handler once; query strings are memoized across lookups.
+fn select_handler(
+) -> impl Fn(&GetRequest<Vec<u8>>, &mut Connection) -> Result<Option<Vec<u8>>, Event> {
+    let builder = builder_memo_new(select_builder_sqlite());
+    select_bytes_new_mut(
         |con: &mut Connection, query: &str, key: &[u8]| {
-            let r = con.query_row(query, params![key], |row| row.get(0));
+            let mut s = con
+                .prepare_cached(query)
+                .map_err(|e| Event::UnexpectedError(format!("Unable to prepare: {}", e)))?;
+            let r = s.query_row(params![key], |row| row.get(0));
             r.optional()
                 .map_err(|e| Event::UnexpectedError(format!("Error getting a value: {}", e)))
         },
         builder,
-    );
-    f(q, c)
+    )
 }
 
 pub fn select() -> Result<(), Event> {
@@ -60,11 +65,19 @@ pub fn select() -> Result<(), Event> {
         Bucket::from(String::from("devices")),
         String::from("cafef00d-dead-beaf-face-864299792458").into_bytes(),
     );
+    let select_row = select_handler();
     let got: Option<Vec<u8>> = select_row(&q, &mut c)?;
     let v: Vec<u8> =
         got.ok_or_else(|| Event::UnexpectedError(String::from("Unable to get a value")))?;
     let s: String = String::from_utf8(v)
         .map_err(|e| Event::UnexpectedError(format!("Unable to convert to string: {}", e)))?;
     println!("got: {}", s);
+
+    let missing: GetRequest<Vec<u8>> = GetRequest::new(
+        Bucket::from(String::from("devices")),
+        String::from("missing").into_bytes(),
+    );
+    let got: Option<Vec<u8>> = select_row(&missing, &mut c)?;
+    println!("got(memoized query): {:?}", got);
     Ok(())
 }
@@ -1,16 +1,28 @@
 use rs_rdb2kv::evt::Event;
 
+mod blob;
+mod chunk;
+mod conformance;
 mod del;
+mod dump;
+mod feed;
 mod get;
 mod list;
 mod upsert;
 
 fn sub() -> Result<(), Event> {
     upsert::upsert()?;
+    upsert::upsert_multi()?;
+    upsert::upsert_prev()?;
     get::select()?;
     del::remove()?;
     del::delete()?;
     list::list()?;
+    feed::follow()?;
+    dump::export_import()?;
+    conformance::conformance()?;
+    blob::ranges()?;
+    chunk::chunks()?;
     Ok(())
 }
 
@@ -1,10 +1,15 @@
 use rs_rdb2kv::upsert::{
-    upsert_builder_new, upsert_bytes_all_new_immutable, BulkRequest, UpsertBuilder,
+    chunk_size_from_params, upsert_builder_multi_new, upsert_builder_new,
+    upsert_bytes_all_new_chunked_mut, upsert_bytes_all_new_immutable,
+    upsert_bytes_all_new_select_first_mut, values_placeholders_sqlite, BulkRequest, PrevValues,
+    UpsertBuilder, UpsertBuilderMulti, SQLITE_MAX_PARAMS,
 };
 
 use rs_rdb2kv::{bucket::Bucket, evt::Event, item::Item};
 
-use rusqlite::{params, Connection, Transaction};
+use rusqlite::{
+    params, params_from_iter, Connection, OptionalExtension, Transaction, TransactionBehavior,
+};
 fn upsert_builder_sqlite() -> impl UpsertBuilder {
     upsert_builder_new(
         |b: &Bucket| {
@@ -80,3 +85,123 @@ pub fn upsert() -> Result<(), Event> {
     println!("upst cnt: {}", cnt);
     Ok(())
 }
+
+fn upsert_builder_sqlite_multi() -> impl UpsertBuilderMulti {
+    upsert_builder_multi_new(
+        |b: &Bucket| upsert_builder_sqlite().build_create(b),
+        |b: &Bucket| upsert_builder_sqlite().build_upsert(b),
+        |b: &Bucket, rows: usize| {
+            Ok(format!(
+                r#"
+                    INSERT INTO {}
+                    VALUES {}
+                    ON CONFLICT (key)
+                    DO UPDATE
+                    SET val = excluded.val
+                    WHERE {}.val <> excluded.val
+                "#,
+                b.as_str(),
+                values_placeholders_sqlite(rows),
+                b.as_str(),
+            ))
+        },
+    )
+}
+
+fn upsert_all_multi<I>(requests: I, mut tx: Transaction) -> Result<u64, Event>
+where
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    let f = upsert_bytes_all_new_chunked_mut(
+        |t: &mut Transaction, query: &str| {
+            t.execute(query, params![])
+                .map_err(|e| Event::UnexpectedError(format!("Unable to create bucket: {}", e)))
+                .map(|cnt: usize| cnt as u64)
+        },
+        |t: &mut Transaction, query: &str, items: &[Item<Vec<u8>, Vec<u8>>]| {
+            let p = items
+                .iter()
+                .flat_map(|i: &Item<_, _>| [i.as_key(), i.as_val()]);
+            t.execute(query, params_from_iter(p))
+                .map_err(|e| Event::UnexpectedError(format!("Unable to upsert: {}", e)))
+                .map(|cnt: usize| cnt as u64)
+        },
+        upsert_builder_sqlite_multi(),
+        chunk_size_from_params(SQLITE_MAX_PARAMS, 2),
+    );
+    let cnt: u64 = f(requests, &mut tx)?;
+    tx.commit()
+        .map_err(|e| Event::UnexpectedError(format!("Unable to commit changes: {}", e)))?;
+    Ok(cnt)
+}
+
+pub fn upsert_multi() -> Result<(), Event> {
+    let mut c: Connection = Connection::open_in_memory()
+        .map_err(|e| Event::ConnectionError(format!("Unable to open: {}", e)))?;
+    let tx: Transaction = c
+        .transaction()
+        .map_err(|e| Event::UnexpectedError(format!("Unable to start transaction: {}", e)))?;
+    let items: Vec<Item<Vec<u8>, Vec<u8>>> = (0..100_000)
+        .map(|i: u32| Item::new(format!("{:08}", i).into_bytes(), i.to_be_bytes().to_vec()))
+        .collect();
+    let cnt: u64 = upsert_all_multi(
+        vec![BulkRequest::new(
+            Bucket::from(String::from("devices_2022_11_02")),
+            items,
+        )]
+        .into_iter(),
+        tx,
+    )?;
+    println!("upst cnt(multi): {}", cnt);
+    Ok(())
+}
+
+fn upsert_all_prev<I>(requests: I, mut tx: Transaction) -> Result<Vec<PrevValues>, Event>
+where
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    let f = upsert_bytes_all_new_select_first_mut(
+        |t: &mut Transaction, query: &str| {
+            t.execute(query, params![])
+                .map_err(|e| Event::UnexpectedError(format!("Unable to create bucket: {}", e)))
+                .map(|cnt: usize| cnt as u64)
+        },
+        |t: &mut Transaction, query: &str, key: &[u8]| {
+            t.query_row(query, params![key], |row| row.get(0))
+                .optional()
+                .map_err(|e| Event::UnexpectedError(format!("Unable to select: {}", e)))
+        },
+        |t: &mut Transaction, query: &str, key: &[u8], val: &[u8]| {
+            t.execute(query, params![key, val])
+                .map_err(|e| Event::UnexpectedError(format!("Unable to upsert: {}", e)))
+                .map(|cnt: usize| cnt as u64)
+        },
+        upsert_builder_sqlite(),
+        |b: &Bucket| Ok(format!("SELECT val FROM {} WHERE key = ?1", b.as_str())),
+    );
+    let prev: Vec<PrevValues> = f(requests, &mut tx)?;
+    tx.commit()
+        .map_err(|e| Event::UnexpectedError(format!("Unable to commit changes: {}", e)))?;
+    Ok(prev)
+}
+
+pub fn upsert_prev() -> Result<(), Event> {
+    let mut c: Connection = Connection::open_in_memory()
+        .map_err(|e| Event::ConnectionError(format!("Unable to open: {}", e)))?;
+    let item = |k: &str, v: &str| Item::new(k.as_bytes().to_vec(), v.as_bytes().to_vec());
+    for val in ["42", "43"] {
+        let tx: Transaction = c
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(|e| Event::UnexpectedError(format!("Unable to start transaction: {}", e)))?;
+        let prev: Vec<PrevValues> = upsert_all_prev(
+            vec![BulkRequest::new(
+                Bucket::from(String::from("devices_2022_11_04")),
+                vec![item("cafef00d", val)],
+            )]
+            .into_iter(),
+            tx,
+        )?;
+        println!("previous values: {:?}", prev);
+    }
+    Ok(())
+}
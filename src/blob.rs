@@ -0,0 +1,107 @@
+//! Incremental value I/O.
+//!
+//! Backends which can access a stored value in place implement `BlobIo`, so that byte ranges of
+//! huge values can be read(or written) without materializing the whole value:
+//!
+//! | Backend    | Reader/Writer                                   |
+//! |------------|-------------------------------------------------|
+//! | memory     | `MemoryKv`(cursors over the stored value)       |
+//! | SQLite     | `blob_open`(see examples/sqlite)                |
+//! | PostgreSQL | large objects(`lo_open`, see examples/postgres) |
+//!
+//! Values stored by `chunk::ChunkStore` can be read by ranges using `ChunkReader`(`Read + Seek`).
+//!
+//! A writer works like an SQLite blob: `open_value_writer` replaces the value with `len` zero
+//! bytes and the writer overwrites bytes in place; it can not change the length of the value.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::bucket::Bucket;
+use crate::evt::Event;
+
+/// Opens readers/writers over stored values.
+pub trait BlobIo {
+    type Reader<'a>: Read + Seek
+    where
+        Self: 'a;
+
+    type Writer<'a>: Write + Seek
+    where
+        Self: 'a;
+
+    /// Opens a reader of the value if exists.
+    fn open_value_reader<'a>(
+        &'a mut self,
+        b: &Bucket,
+        key: &[u8],
+    ) -> Result<Option<Self::Reader<'a>>, Event>;
+
+    /// Replaces the value with `len` zero bytes and opens a writer of the value.
+    ///
+    /// The previous content is always discarded(even if the length is unchanged), so the writer
+    /// can not update a part of an existing value: read the value first and write back all
+    /// bytes to change a part of it.
+    fn open_value_writer<'a>(
+        &'a mut self,
+        b: &Bucket,
+        key: &[u8],
+        len: u64,
+    ) -> Result<Self::Writer<'a>, Event>;
+}
+
+/// Copies up to `len` bytes starting at `offset` from the reader; returns the number of bytes.
+pub fn read_range<R, W>(r: &mut R, offset: u64, len: u64, w: &mut W) -> Result<u64, Event>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    r.seek(SeekFrom::Start(offset))
+        .map_err(|e| Event::UnexpectedError(format!("Unable to seek: {}", e)))?;
+    io::copy(&mut r.take(len), w)
+        .map_err(|e| Event::UnexpectedError(format!("Unable to copy a range: {}", e)))
+}
+
+#[cfg(test)]
+mod test_blob {
+
+    mod memory {
+
+        use std::io::{Seek, SeekFrom, Write};
+
+        use crate::blob::{self, BlobIo};
+        use crate::bucket::Bucket;
+        use crate::evt::Event;
+        use crate::mem::{self, MemoryKv};
+
+        fn bucket() -> Bucket {
+            Bucket::from(String::from("artifacts"))
+        }
+
+        #[test]
+        fn test_range() {
+            let mut kv = MemoryKv::new();
+            assert!(matches!(
+                kv.open_value_writer(&bucket(), b"a", 8),
+                Err(Event::BucketNotFound(_))
+            ));
+            mem::create(&mut kv, "artifacts").unwrap();
+            assert!(kv.open_value_reader(&bucket(), b"a").unwrap().is_none());
+
+            let mut w = kv.open_value_writer(&bucket(), b"a", 8).unwrap();
+            w.write_all(b"head").unwrap();
+            w.seek(SeekFrom::End(-2)).unwrap();
+            w.write_all(b"tl").unwrap();
+            assert!(w.write_all(b"overflow").is_err());
+            let stored: Option<Vec<u8>> = mem::select(&mut kv, "artifacts", b"a").unwrap();
+            assert_eq!(stored, Some(b"head\0\0tl".to_vec()));
+
+            let mut r = kv.open_value_reader(&bucket(), b"a").unwrap().unwrap();
+            let mut out: Vec<u8> = vec![];
+            assert_eq!(blob::read_range(&mut r, 2, 3, &mut out).unwrap(), 3);
+            assert_eq!(out, b"ad\0".to_vec());
+            out.clear();
+            assert_eq!(blob::read_range(&mut r, 6, 100, &mut out).unwrap(), 2);
+            assert_eq!(out, b"tl".to_vec());
+        }
+    }
+}
@@ -22,3 +22,47 @@ impl From<String> for Bucket {
 pub fn bucket_checker_new_unchecked() -> impl Fn(&Bucket) -> Result<(), Event> {
     move |_: &Bucket| Ok(())
 }
+
+/// Creates new bucket checker which accepts simple SQL identifiers.
+///
+/// A valid name starts with an ASCII letter or `_`, followed by up to 62 ASCII letters,
+/// digits or `_`.
+pub fn bucket_checker_new_identifier() -> impl Fn(&Bucket) -> Result<(), Event> {
+    move |b: &Bucket| {
+        let name: &[u8] = b.as_str().as_bytes();
+        let head_ok: bool = name
+            .first()
+            .map(|c| c.is_ascii_alphabetic() || *c == b'_')
+            .unwrap_or(false);
+        let tail_ok: bool = name.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_');
+        match head_ok && tail_ok && name.len() < 64 {
+            true => Ok(()),
+            false => Err(Event::InvalidBucket(format!(
+                "Invalid bucket name: {}",
+                b.as_str()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_bucket {
+
+    mod bucket_checker_new_identifier {
+
+        use crate::bucket::{bucket_checker_new_identifier, Bucket};
+
+        #[test]
+        fn test_names() {
+            let f = bucket_checker_new_identifier();
+            let check = |s: &str| f(&Bucket::from(String::from(s))).is_ok();
+            assert!(check("devices_2022_11_01"));
+            assert!(check("_dates"));
+            assert!(!check(""));
+            assert!(!check("2022_11_01"));
+            assert!(!check("devices; DROP TABLE dates"));
+            assert!(!check("devices-01"));
+            assert!(!check(&"d".repeat(64)));
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! A write-behind buffer of upserts.
+//!
+//! Items are accumulated per bucket and flushed as `BulkRequest`s when the number of buffered
+//! items or the age of the oldest one reaches the threshold.
+//! Repeated keys are coalesced(the last write wins).
+//! Requests are flushed ordered by the bucket name and the key.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::bucket::Bucket;
+use crate::evt::Event;
+use crate::item::{Item, RawItem};
+use crate::upsert::{BulkRequest, Requests};
+
+/// Buffers upserts and flushes them using a closure.
+///
+/// A failed flush keeps the buffered items and the error is returned to the caller;
+/// while the buffer is full and cannot be flushed, new items are rejected(backpressure).
+pub struct UpsertBuffer<F>
+where
+    F: FnMut(Requests) -> Result<u64, Event>,
+{
+    flush: F,
+    max_items: usize,
+    max_age: Duration,
+    pending: BTreeMap<String, BTreeMap<Vec<u8>, Vec<u8>>>,
+    items: usize,
+    oldest: Option<Instant>,
+}
+
+impl<F> UpsertBuffer<F>
+where
+    F: FnMut(Requests) -> Result<u64, Event>,
+{
+    /// Creates new empty buffer.
+    ///
+    /// # Arguments
+    /// - flush: Upserts requests(e.g, calls `upsert_bytes_all_new_mut` in a transaction).
+    /// - max_items: Flushes when this number of distinct keys are buffered.
+    /// - max_age: Flushes when the oldest buffered item gets older than this.
+    pub fn new(flush: F, max_items: usize, max_age: Duration) -> Self {
+        Self {
+            flush,
+            max_items,
+            max_age,
+            pending: BTreeMap::new(),
+            items: 0,
+            oldest: None,
+        }
+    }
+
+    /// Gets the number of buffered(distinct) keys.
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    /// Checks if no item is buffered.
+    pub fn is_empty(&self) -> bool {
+        0 == self.items
+    }
+
+    fn full(&self) -> bool {
+        self.max_items <= self.items
+    }
+
+    fn due(&self) -> bool {
+        self.oldest
+            .map(|o| self.max_age <= o.elapsed())
+            .unwrap_or(false)
+    }
+
+    /// Buffers the item and flushes if a threshold is reached.
+    ///
+    /// Returns the count from the flush(0 if not flushed).
+    ///
+    /// An error does not always mean the item was rejected:
+    /// - If the buffer is already full and cannot be flushed, the item is not buffered.
+    /// - If the flush after buffering the item fails, the item WAS buffered and is flushed later;
+    ///   do not push it again(compare `len` before and after the push to tell these apart).
+    pub fn push(&mut self, bucket: &Bucket, item: RawItem) -> Result<u64, Event> {
+        let flushed: u64 = match self.full() {
+            true => self.flush()?,
+            false => 0,
+        };
+        let (key, val) = item.into_pair();
+        let b: &mut BTreeMap<Vec<u8>, Vec<u8>> = self
+            .pending
+            .entry(String::from(bucket.as_str()))
+            .or_default();
+        if b.insert(key, val).is_none() {
+            self.items += 1;
+        }
+        self.oldest.get_or_insert_with(Instant::now);
+        match self.full() || self.due() {
+            true => self.flush().map(|cnt| cnt + flushed),
+            false => Ok(flushed),
+        }
+    }
+
+    /// Flushes if the oldest buffered item is older than the max age.
+    ///
+    /// Call this periodically when items may arrive slowly.
+    pub fn flush_if_due(&mut self) -> Result<u64, Event> {
+        match self.due() {
+            true => self.flush(),
+            false => Ok(0),
+        }
+    }
+
+    /// Flushes all buffered items; they are kept if the flush fails.
+    pub fn flush(&mut self) -> Result<u64, Event> {
+        if self.is_empty() {
+            return Ok(0);
+        }
+        let requests: Vec<BulkRequest<Vec<u8>, Vec<u8>>> = self
+            .pending
+            .iter()
+            .map(|(b, items)| {
+                let items: Vec<RawItem> = items
+                    .iter()
+                    .map(|(k, v)| Item::new(k.clone(), v.clone()))
+                    .collect();
+                BulkRequest::new(Bucket::from(b.clone()), items)
+            })
+            .collect();
+        let cnt: u64 = (self.flush)(requests.into_iter())?;
+        self.clear();
+        Ok(cnt)
+    }
+
+    fn clear(&mut self) {
+        self.pending.clear();
+        self.items = 0;
+        self.oldest = None;
+    }
+
+    /// Flushes buffered items and drops the buffer.
+    ///
+    /// Buffered items are discarded even if the flush fails(the flush is not retried on drop).
+    pub fn finish(mut self) -> Result<u64, Event> {
+        let r: Result<u64, Event> = self.flush();
+        self.clear();
+        r
+    }
+}
+
+impl<F> Drop for UpsertBuffer<F>
+where
+    F: FnMut(Requests) -> Result<u64, Event>,
+{
+    /// Flushes remaining items; use `finish` to get the flush failure.
+    ///
+    /// A failure is logged as an error event(with the `tracing` feature) and the items are lost.
+    fn drop(&mut self) {
+        #[cfg(feature = "tracing")]
+        let items: usize = self.items;
+        if let Err(_e) = self.flush() {
+            #[cfg(feature = "tracing")]
+            tracing::error!(event = ?_e, items, "buffered upserts discarded");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_buffer {
+
+    mod upsert_buffer {
+
+        use std::cell::RefCell;
+        use std::time::Duration;
+
+        use crate::bucket::Bucket;
+        use crate::buffer::UpsertBuffer;
+        use crate::evt::Event;
+        use crate::item::Item;
+        use crate::mem::{self, MemoryKv};
+        use crate::upsert::{upsert_bytes_all_new_mut, Requests};
+
+        fn bucket() -> Bucket {
+            Bucket::from(String::from("devices"))
+        }
+
+        fn item(k: &str, v: &str) -> Item<Vec<u8>, Vec<u8>> {
+            Item::new(k.as_bytes().to_vec(), v.as_bytes().to_vec())
+        }
+
+        #[test]
+        fn test_coalesce() {
+            let kv: RefCell<MemoryKv> = RefCell::new(MemoryKv::new());
+            let f = upsert_bytes_all_new_mut(mem::create, mem::upsert, mem::upsert_builder());
+            let mut buf = UpsertBuffer::new(
+                |reqs: Requests| f(reqs, &mut kv.borrow_mut()),
+                2,
+                Duration::from_secs(3600),
+            );
+            assert_eq!(buf.push(&bucket(), item("a", "1")).unwrap(), 0);
+            assert_eq!(buf.push(&bucket(), item("a", "2")).unwrap(), 0);
+            assert_eq!(buf.len(), 1);
+            assert_eq!(buf.push(&bucket(), item("b", "3")).unwrap(), 3);
+            assert!(buf.is_empty());
+
+            buf.push(&bucket(), item("c", "4")).unwrap();
+            buf.finish().unwrap();
+
+            let kv: MemoryKv = kv.into_inner();
+            let b = kv.bucket("devices").unwrap();
+            assert_eq!(b.get(&b"a".to_vec()), Some(&b"2".to_vec()));
+            assert_eq!(b.len(), 3);
+        }
+
+        #[test]
+        fn test_failure() {
+            let up: RefCell<bool> = RefCell::new(false);
+            let mut buf = UpsertBuffer::new(
+                |reqs: Requests| match *up.borrow() {
+                    true => Ok(reqs.map(|r| r.as_items().len() as u64).sum()),
+                    false => Err(Event::ConnectionError(String::from("down"))),
+                },
+                1,
+                Duration::from_secs(3600),
+            );
+            assert!(buf.push(&bucket(), item("a", "1")).is_err());
+            assert_eq!(buf.len(), 1);
+            assert!(buf.push(&bucket(), item("b", "2")).is_err());
+            assert_eq!(buf.len(), 1);
+
+            *up.borrow_mut() = true;
+            assert_eq!(buf.push(&bucket(), item("b", "2")).unwrap(), 2);
+            assert!(buf.is_empty());
+        }
+
+        #[test]
+        fn test_finish_failure() {
+            let calls: RefCell<u64> = RefCell::new(0);
+            let mut buf = UpsertBuffer::new(
+                |_: Requests| {
+                    *calls.borrow_mut() += 1;
+                    Err(Event::ConnectionError(String::from("down")))
+                },
+                100,
+                Duration::from_secs(3600),
+            );
+            buf.push(&bucket(), item("a", "1")).unwrap();
+            assert!(buf.finish().is_err());
+            assert_eq!(*calls.borrow(), 1);
+        }
+
+        #[test]
+        fn test_age() {
+            let mut buf = UpsertBuffer::new(
+                |reqs: Requests| Ok(reqs.count() as u64),
+                100,
+                Duration::ZERO,
+            );
+            assert_eq!(buf.push(&bucket(), item("a", "1")).unwrap(), 1);
+            assert_eq!(buf.flush_if_due().unwrap(), 0);
+        }
+    }
+}
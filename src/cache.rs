@@ -0,0 +1,527 @@
+//! A read-through cache in front of the select handler.
+//!
+//! Entries are keyed by (bucket, key), evicted in LRU order and may expire after a TTL.
+//! Missing keys are cached too if negative caching is enabled.
+//! Wrap the upsert/delete/drop handlers with the `*_invalidate_new` functions below so that
+//! writes through them invalidate the affected entries once they are committed.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::bucket::Bucket;
+use crate::evt::Event;
+use crate::get::GetRequest;
+use crate::upsert::{BulkRequest, Requests};
+
+type CacheKey = (String, Vec<u8>);
+
+struct Entry {
+    val: Option<Vec<u8>>,
+    stored: Instant,
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<CacheKey, Entry>,
+    order: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    generation: u64,
+}
+
+impl Entries {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> bool {
+        match self.map.remove(key) {
+            Some(e) => {
+                self.order.remove(&e.tick);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Hit/miss counters of the cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub invalidations: u64,
+}
+
+/// A size-bounded LRU cache of values keyed by (bucket, key).
+pub struct Cache {
+    capacity: usize,
+    ttl: Option<Duration>,
+    negative: bool,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+impl Cache {
+    /// Creates new cache without TTL and negative caching.
+    ///
+    /// # Arguments
+    /// - capacity: The maximum number of entries.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ttl: None,
+            negative: false,
+            entries: Mutex::new(Entries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the time to live of entries.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Caches missing keys(`None`) as well.
+    pub fn with_negative(mut self) -> Self {
+        self.negative = true;
+        self
+    }
+
+    /// Gets the hit/miss counters.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Gets the number of cached entries(including expired ones not yet removed).
+    pub fn len(&self) -> usize {
+        self.lock().map(|g| g.map.len()).unwrap_or(0)
+    }
+
+    /// Checks if no entry is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Entries>, Event> {
+        self.entries
+            .lock()
+            .map_err(|e| Event::UnexpectedError(format!("Unable to lock cache: {}", e)))
+    }
+
+    fn expired(&self, e: &Entry) -> bool {
+        self.ttl.map(|t| t <= e.stored.elapsed()).unwrap_or(false)
+    }
+
+    /// Looks up the entry; returns the generation to be passed to `put` on a miss.
+    fn lookup(&self, key: &CacheKey) -> Result<Result<Option<Vec<u8>>, u64>, Event> {
+        let mut g = self.lock()?;
+        let tick: u64 = g.next_tick();
+        let entries: &mut Entries = &mut g;
+        match entries.map.get_mut(key) {
+            Some(e) if !self.expired(e) => {
+                entries.order.remove(&e.tick);
+                e.tick = tick;
+                entries.order.insert(tick, key.clone());
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Ok(Ok(e.val.clone()))
+            }
+            Some(_) => {
+                entries.remove(key);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Ok(Err(entries.generation))
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Ok(Err(entries.generation))
+            }
+        }
+    }
+
+    /// Stores the value unless invalidated since the lookup.
+    fn put(&self, key: CacheKey, val: Option<Vec<u8>>, generation: u64) -> Result<(), Event> {
+        let skip: bool = self.capacity == 0 || (val.is_none() && !self.negative);
+        let mut g = self.lock()?;
+        if skip || g.generation != generation {
+            return Ok(());
+        }
+        g.remove(&key);
+        let tick: u64 = g.next_tick();
+        g.order.insert(tick, key.clone());
+        g.map.insert(
+            key,
+            Entry {
+                val,
+                stored: Instant::now(),
+                tick,
+            },
+        );
+        while self.capacity < g.map.len() {
+            let oldest: Option<CacheKey> = g.order.pop_first().map(|(_, k)| k);
+            if let Some(k) = oldest {
+                g.map.remove(&k);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes entries of the keys in the bucket.
+    ///
+    /// Values selected before the invalidation are not cached afterwards.
+    pub fn invalidate<'a, I>(&self, bucket: &str, keys: I) -> Result<(), Event>
+    where
+        I: Iterator<Item = &'a [u8]>,
+    {
+        let mut g = self.lock()?;
+        g.generation += 1;
+        for key in keys {
+            if g.remove(&(String::from(bucket), key.to_vec())) {
+                self.invalidations.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes all entries of the bucket.
+    pub fn invalidate_bucket(&self, bucket: &str) -> Result<(), Event> {
+        let mut g = self.lock()?;
+        g.generation += 1;
+        let keys: Vec<CacheKey> = g.map.keys().filter(|(b, _)| b == bucket).cloned().collect();
+        for key in keys {
+            g.remove(&key);
+            self.invalidations.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+/// Creates select request handler which reads through the cache.
+///
+/// # Arguments
+/// - cache: The cache shared with the invalidating handlers.
+/// - select: The handler(e.g, `select_bytes_new_mut`).
+pub fn select_cached_new<S, C>(
+    cache: Arc<Cache>,
+    select: S,
+) -> impl Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>
+where
+    S: Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>,
+{
+    move |req: &GetRequest<Vec<u8>>, client: &mut C| {
+        let key: CacheKey = (String::from(req.as_bucket().as_str()), req.as_key().clone());
+        match cache.lookup(&key)? {
+            Ok(cached) => Ok(cached),
+            Err(generation) => {
+                let val: Option<Vec<u8>> = select(req, client)?;
+                cache.put(key, val.clone(), generation)?;
+                Ok(val)
+            }
+        }
+    }
+}
+
+/// Creates upsert requests handler which upserts, commits and then invalidates upserted keys.
+///
+/// Keys are invalidated after the commit(even if the upsert or the commit failed): a select
+/// which read the previous value before the commit does not cache it(see `Cache::invalidate`).
+/// Invalidating before the commit would let such a select repopulate the stale value.
+///
+/// # Arguments
+/// - cache: The cache shared with the select handler.
+/// - upsert: The handler(e.g, `upsert_bytes_all_new_mut`).
+/// - commit: Commits the upserted keys(a no-op for autocommit clients).
+pub fn upsert_invalidate_new<U, K, I, T>(
+    cache: Arc<Cache>,
+    upsert: U,
+    commit: K,
+) -> impl Fn(I, &mut T) -> Result<u64, Event>
+where
+    U: Fn(Requests, &mut T) -> Result<u64, Event>,
+    K: Fn(&mut T) -> Result<(), Event>,
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    move |requests: I, transaction: &mut T| {
+        let requests: Vec<BulkRequest<Vec<u8>, Vec<u8>>> = requests.collect();
+        let keys: Vec<(String, Vec<Vec<u8>>)> = requests
+            .iter()
+            .map(|req| {
+                let keys = req.as_items().iter().map(|i| i.as_key().clone());
+                (String::from(req.as_bucket().as_str()), keys.collect())
+            })
+            .collect();
+        let r: Result<u64, Event> = upsert(requests.into_iter(), transaction)
+            .and_then(|cnt| commit(transaction).map(|_| cnt));
+        keys.iter().try_for_each(|(b, keys)| {
+            cache.invalidate(b.as_str(), keys.iter().map(|k| k.as_slice()))
+        })?;
+        r
+    }
+}
+
+/// Creates remover which deletes, commits and then invalidates the deleted key.
+///
+/// The key is invalidated after the commit(even if the delete or the commit failed) for the
+/// same reason as `upsert_invalidate_new`.
+///
+/// # Arguments
+/// - cache: The cache shared with the select handler.
+/// - delete: The handler(e.g, `delete_key_bytes_mut`).
+/// - commit: Commits the deletion(a no-op for autocommit clients).
+pub fn delete_invalidate_new<D, K, C>(
+    cache: Arc<Cache>,
+    delete: D,
+    commit: K,
+) -> impl Fn(&Bucket, &[u8], &mut C) -> Result<u64, Event>
+where
+    D: Fn(&Bucket, &[u8], &mut C) -> Result<u64, Event>,
+    K: Fn(&mut C) -> Result<(), Event>,
+{
+    move |b: &Bucket, key: &[u8], client: &mut C| {
+        let r: Result<u64, Event> =
+            delete(b, key, client).and_then(|cnt| commit(client).map(|_| cnt));
+        cache.invalidate(b.as_str(), std::iter::once(key))?;
+        r
+    }
+}
+
+/// Creates bucket dropper which drops, commits and then invalidates all entries of the bucket.
+///
+/// The entries are invalidated after the commit(even if the drop or the commit failed) for the
+/// same reason as `upsert_invalidate_new`.
+///
+/// # Arguments
+/// - cache: The cache shared with the select handler.
+/// - remove: The handler(e.g, `drop_bucket_mut`).
+/// - commit: Commits the drop(a no-op for autocommit clients).
+pub fn drop_invalidate_new<D, K, C>(
+    cache: Arc<Cache>,
+    remove: D,
+    commit: K,
+) -> impl Fn(&Bucket, &mut C) -> Result<(), Event>
+where
+    D: Fn(&Bucket, &mut C) -> Result<(), Event>,
+    K: Fn(&mut C) -> Result<(), Event>,
+{
+    move |b: &Bucket, client: &mut C| {
+        let r: Result<(), Event> = remove(b, client).and_then(|_| commit(client));
+        cache.invalidate_bucket(b.as_str())?;
+        r
+    }
+}
+
+#[cfg(test)]
+mod test_cache {
+
+    mod select_cached_new {
+
+        use std::cell::RefCell;
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        use crate::bucket::Bucket;
+        use crate::cache::{self, Cache, CacheStats};
+        use crate::del::{delete_key_bytes_mut, drop_bucket_mut};
+        use crate::get::{select_bytes_new_mut, GetRequest};
+        use crate::item::Item;
+        use crate::mem::{self, MemoryKv};
+        use crate::upsert::{upsert_bytes_all_new_mut, BulkRequest};
+
+        fn bucket() -> Bucket {
+            Bucket::from(String::from("devices"))
+        }
+
+        fn req(key: &str) -> GetRequest<Vec<u8>> {
+            GetRequest::new(bucket(), key.as_bytes().to_vec())
+        }
+
+        fn put(c: &Arc<Cache>, kv: &mut MemoryKv, key: &str, val: &str) {
+            let f = cache::upsert_invalidate_new(
+                c.clone(),
+                upsert_bytes_all_new_mut(mem::create, mem::upsert, mem::upsert_builder()),
+                |_: &mut MemoryKv| Ok(()),
+            );
+            let items = vec![Item::new(key.as_bytes().to_vec(), val.as_bytes().to_vec())];
+            f(vec![BulkRequest::new(bucket(), items)].into_iter(), kv).unwrap();
+        }
+
+        #[test]
+        fn test_read_through() {
+            let c: Arc<Cache> = Arc::new(Cache::new(8));
+            let mut kv = MemoryKv::new();
+            put(&c, &mut kv, "a", "1");
+
+            let get = cache::select_cached_new(
+                c.clone(),
+                select_bytes_new_mut(mem::select, mem::builder()),
+            );
+            assert_eq!(get(&req("a"), &mut kv).unwrap(), Some(b"1".to_vec()));
+            assert_eq!(get(&req("a"), &mut kv).unwrap(), Some(b"1".to_vec()));
+            assert_eq!(get(&req("b"), &mut kv).unwrap(), None);
+            assert_eq!(get(&req("b"), &mut kv).unwrap(), None);
+            assert_eq!(c.len(), 1);
+
+            put(&c, &mut kv, "a", "2");
+            assert_eq!(get(&req("a"), &mut kv).unwrap(), Some(b"2".to_vec()));
+
+            let del = cache::delete_invalidate_new(
+                c.clone(),
+                delete_key_bytes_mut(mem::delete, mem::builder()),
+                |_: &mut MemoryKv| Ok(()),
+            );
+            del(&bucket(), b"a", &mut kv).unwrap();
+            assert_eq!(get(&req("a"), &mut kv).unwrap(), None);
+
+            let s: CacheStats = c.stats();
+            assert_eq!(s.hits, 1);
+            assert_eq!(s.misses, 5);
+            assert_eq!(s.invalidations, 2);
+        }
+
+        #[test]
+        fn test_invalidate_after_commit() {
+            let c: Arc<Cache> = Arc::new(Cache::new(8));
+            let mut kv = MemoryKv::new();
+            put(&c, &mut kv, "a", "1");
+
+            let get = cache::select_cached_new(
+                c.clone(),
+                select_bytes_new_mut(mem::select, mem::builder()),
+            );
+            let before_commit: RefCell<MemoryKv> = RefCell::new(MemoryKv::new());
+            put(&c, &mut before_commit.borrow_mut(), "a", "1");
+
+            let f = cache::upsert_invalidate_new(
+                c.clone(),
+                upsert_bytes_all_new_mut(mem::create, mem::upsert, mem::upsert_builder()),
+                |_: &mut MemoryKv| {
+                    let stale = get(&req("a"), &mut before_commit.borrow_mut())?;
+                    assert_eq!(stale, Some(b"1".to_vec()));
+                    Ok(())
+                },
+            );
+            let items = vec![Item::new(b"a".to_vec(), b"2".to_vec())];
+            f(vec![BulkRequest::new(bucket(), items)].into_iter(), &mut kv).unwrap();
+
+            assert_eq!(get(&req("a"), &mut kv).unwrap(), Some(b"2".to_vec()));
+        }
+
+        #[test]
+        fn test_delete_invalidate_after_commit() {
+            let c: Arc<Cache> = Arc::new(Cache::new(8));
+            let mut kv = MemoryKv::new();
+            put(&c, &mut kv, "a", "1");
+
+            let get = cache::select_cached_new(
+                c.clone(),
+                select_bytes_new_mut(mem::select, mem::builder()),
+            );
+            let before_commit: RefCell<MemoryKv> = RefCell::new(MemoryKv::new());
+            put(&c, &mut before_commit.borrow_mut(), "a", "1");
+            let stale = |_: &mut MemoryKv| {
+                let stale = get(&req("a"), &mut before_commit.borrow_mut())?;
+                assert_eq!(stale, Some(b"1".to_vec()));
+                Ok(())
+            };
+
+            let del = cache::delete_invalidate_new(
+                c.clone(),
+                delete_key_bytes_mut(mem::delete, mem::builder()),
+                stale,
+            );
+            assert_eq!(del(&bucket(), b"a", &mut kv).unwrap(), 1);
+            assert_eq!(get(&req("a"), &mut kv).unwrap(), None);
+
+            put(&c, &mut kv, "a", "1");
+            let remove = cache::drop_invalidate_new(
+                c.clone(),
+                drop_bucket_mut(mem::drop, mem::builder()),
+                stale,
+            );
+            remove(&bucket(), &mut kv).unwrap();
+            assert!(get(&req("a"), &mut kv).is_err());
+        }
+
+        #[test]
+        fn test_negative() {
+            let c: Arc<Cache> = Arc::new(Cache::new(8).with_negative());
+            let mut kv = MemoryKv::new();
+            put(&c, &mut kv, "a", "1");
+
+            let get = cache::select_cached_new(
+                c.clone(),
+                select_bytes_new_mut(mem::select, mem::builder()),
+            );
+            assert_eq!(get(&req("b"), &mut kv).unwrap(), None);
+            assert_eq!(get(&req("b"), &mut kv).unwrap(), None);
+            assert_eq!(c.stats().hits, 1);
+
+            put(&c, &mut kv, "b", "2");
+            assert_eq!(get(&req("b"), &mut kv).unwrap(), Some(b"2".to_vec()));
+
+            let remove = cache::drop_invalidate_new(
+                c.clone(),
+                drop_bucket_mut(mem::drop, mem::builder()),
+                |_: &mut MemoryKv| Ok(()),
+            );
+            remove(&bucket(), &mut kv).unwrap();
+            assert!(c.is_empty());
+        }
+
+        #[test]
+        fn test_lru() {
+            let c: Arc<Cache> = Arc::new(Cache::new(2));
+            let mut kv = MemoryKv::new();
+            put(&c, &mut kv, "a", "1");
+            put(&c, &mut kv, "b", "2");
+            put(&c, &mut kv, "c", "3");
+
+            let get = cache::select_cached_new(
+                c.clone(),
+                select_bytes_new_mut(mem::select, mem::builder()),
+            );
+            get(&req("a"), &mut kv).unwrap();
+            get(&req("b"), &mut kv).unwrap();
+            get(&req("a"), &mut kv).unwrap();
+            get(&req("c"), &mut kv).unwrap();
+            assert_eq!(c.stats().evictions, 1);
+
+            get(&req("a"), &mut kv).unwrap();
+            assert_eq!(c.stats().hits, 2);
+            get(&req("b"), &mut kv).unwrap();
+            assert_eq!(c.stats().hits, 2);
+        }
+
+        #[test]
+        fn test_ttl() {
+            let c: Arc<Cache> = Arc::new(Cache::new(2).with_ttl(Duration::ZERO));
+            let mut kv = MemoryKv::new();
+            put(&c, &mut kv, "a", "1");
+
+            let get = cache::select_cached_new(
+                c.clone(),
+                select_bytes_new_mut(mem::select, mem::builder()),
+            );
+            get(&req("a"), &mut kv).unwrap();
+            get(&req("a"), &mut kv).unwrap();
+            assert_eq!(c.stats().hits, 0);
+            assert_eq!(c.stats().misses, 2);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Value integrity checksums.
+//!
+//! A checksummed value(envelope) is `MAGIC`, a version byte(0x01), CRC32C of the key and the
+//! value(big endian) and the value.
+//! Values are verified on get(`Event::ChecksumMismatch` on mismatch) and `scrub_bucket_new_mut`
+//! scans a bucket to find corrupted values.
+//!
+//! Values without `MAGIC`(e.g, written before checksums were enabled) are returned unverified,
+//! so checksums can be enabled on an existing bucket; a scrub counts them as unsealed. They are
+//! sealed when rewritten. `MAGIC` starts with bytes which never appear in UTF-8, so legacy text
+//! values are never mistaken for envelopes.
+
+use crate::bucket::Bucket;
+use crate::crc::Crc32c;
+use crate::evt::Event;
+use crate::get::GetRequest;
+use crate::item::{Item, RawItem};
+use crate::list::{visit_items_mut, PageRequest};
+use crate::upsert::{BulkRequest, Requests};
+
+/// The prefix of checksummed values.
+pub const MAGIC: [u8; 3] = [0xff, 0xc5, 0x3c];
+
+/// The version byte of the envelope.
+pub const ENVELOPE_VERSION: u8 = 0x01;
+
+const HEADER_SIZE: usize = 5;
+
+fn checksum(key: &[u8], val: &[u8]) -> u32 {
+    let mut c = Crc32c::new();
+    c.update(key);
+    c.update(val);
+    c.value()
+}
+
+/// Wraps the value of the key with its checksum.
+pub fn seal(key: &[u8], val: &[u8]) -> Vec<u8> {
+    let mut sealed: Vec<u8> = Vec::with_capacity(MAGIC.len() + HEADER_SIZE + val.len());
+    sealed.extend_from_slice(&MAGIC);
+    sealed.push(ENVELOPE_VERSION);
+    sealed.extend_from_slice(&checksum(key, val).to_be_bytes());
+    sealed.extend_from_slice(val);
+    sealed
+}
+
+/// Verifies the envelope of the key and gets the value.
+///
+/// Values without `MAGIC` are returned as is.
+pub fn open<'a>(b: &Bucket, key: &[u8], sealed: &'a [u8]) -> Result<&'a [u8], Event> {
+    let Some(sealed) = sealed.strip_prefix(&MAGIC) else {
+        return Ok(sealed);
+    };
+    let mismatch = || Event::ChecksumMismatch(format!("{}: {}", b.as_str(), key.escape_ascii()));
+    let header: &[u8] = sealed.get(..HEADER_SIZE).ok_or_else(mismatch)?;
+    let val: &[u8] = &sealed[HEADER_SIZE..];
+    let expected: u32 = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+    match header[0] == ENVELOPE_VERSION && expected == checksum(key, val) {
+        true => Ok(val),
+        false => Err(mismatch()),
+    }
+}
+
+/// Creates upsert requests handler which writes checksummed values.
+///
+/// # Arguments
+/// - upsert: The handler(e.g, `upsert_bytes_all_new_mut`).
+pub fn upsert_checksummed_new<U, I, T>(upsert: U) -> impl Fn(I, &mut T) -> Result<u64, Event>
+where
+    U: Fn(Requests, &mut T) -> Result<u64, Event>,
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    move |requests: I, transaction: &mut T| {
+        let sealed: Vec<BulkRequest<Vec<u8>, Vec<u8>>> = requests
+            .map(|req| {
+                let items: Vec<RawItem> = req
+                    .as_items()
+                    .iter()
+                    .map(|i| Item::new(i.as_key().clone(), seal(i.as_key(), i.as_val())))
+                    .collect();
+                BulkRequest::new(Bucket::from(String::from(req.as_bucket().as_str())), items)
+            })
+            .collect();
+        upsert(sealed.into_iter(), transaction)
+    }
+}
+
+/// Creates select request handler which verifies the selected value.
+///
+/// # Arguments
+/// - select: The handler(e.g, `select_bytes_new_mut`).
+pub fn select_verified_new<S, C>(
+    select: S,
+) -> impl Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>
+where
+    S: Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>,
+{
+    move |req: &GetRequest<Vec<u8>>, client: &mut C| {
+        let sealed: Option<Vec<u8>> = select(req, client)?;
+        sealed
+            .map(|s| open(req.as_bucket(), req.as_key(), &s).map(|v| v.to_vec()))
+            .transpose()
+    }
+}
+
+/// A result of a scrub.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ScrubReport {
+    scanned: u64,
+    unsealed: u64,
+    mismatched: Vec<Vec<u8>>,
+}
+
+impl ScrubReport {
+    /// Gets the number of scanned items.
+    pub fn scanned(&self) -> u64 {
+        self.scanned
+    }
+
+    /// Gets the number of values without checksums(written before checksums were enabled).
+    pub fn unsealed(&self) -> u64 {
+        self.unsealed
+    }
+
+    /// Gets keys of corrupted values ordered by the key.
+    pub fn mismatched(&self) -> &[Vec<u8>] {
+        &self.mismatched
+    }
+
+    /// Checks if no corrupted value found(unsealed values are not corrupted).
+    pub fn is_clean(&self) -> bool {
+        self.mismatched.is_empty()
+    }
+}
+
+/// Creates new scrubber which verifies all values of the bucket.
+///
+/// # Arguments
+/// - page: Gets a page(see `list_items_page_new_mut`).
+/// - page_size: The number of items per page.
+pub fn scrub_bucket_new_mut<P, C>(
+    page: P,
+    page_size: u64,
+) -> impl Fn(&Bucket, &mut C) -> Result<ScrubReport, Event>
+where
+    P: Fn(&PageRequest, &mut C) -> Result<Vec<RawItem>, Event>,
+{
+    move |b: &Bucket, client: &mut C| {
+        let mut unsealed: u64 = 0;
+        let mut mismatched: Vec<Vec<u8>> = vec![];
+        let scanned: u64 = visit_items_mut(&page, b, None, page_size, client, |item: RawItem| {
+            if !item.as_val().starts_with(&MAGIC) {
+                unsealed += 1;
+            } else if open(b, item.as_key(), item.as_val()).is_err() {
+                mismatched.push(item.into_pair().0);
+            }
+            Ok(())
+        })?;
+        Ok(ScrubReport {
+            scanned,
+            unsealed,
+            mismatched,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_checksum {
+
+    mod seal {
+
+        use crate::bucket::Bucket;
+        use crate::checksum;
+        use crate::evt::Event;
+
+        #[test]
+        fn test_roundtrip() {
+            let b: Bucket = Bucket::from(String::from("devices"));
+            let sealed: Vec<u8> = checksum::seal(b"k", b"v");
+            assert_eq!(checksum::open(&b, b"k", &sealed).unwrap(), b"v");
+            assert!(matches!(
+                checksum::open(&b, b"x", &sealed),
+                Err(Event::ChecksumMismatch(_))
+            ));
+            let mut broken: Vec<u8> = sealed.clone();
+            broken[8] ^= 1;
+            assert!(checksum::open(&b, b"k", &broken).is_err());
+            assert!(checksum::open(&b, b"k", &sealed[..6]).is_err());
+        }
+
+        #[test]
+        fn test_legacy() {
+            let b: Bucket = Bucket::from(String::from("devices"));
+            assert_eq!(checksum::open(&b, b"k", b"v").unwrap(), b"v");
+            assert_eq!(checksum::open(&b, b"k", b"").unwrap(), b"");
+        }
+    }
+
+    mod scrub_bucket_new_mut {
+
+        use crate::bucket::Bucket;
+        use crate::checksum::{self, ScrubReport};
+        use crate::evt::Event;
+        use crate::get::{select_bytes_new_mut, GetRequest};
+        use crate::item::Item;
+        use crate::list::list_items_page_new_mut;
+        use crate::mem::{self, MemoryKv};
+        use crate::upsert::{upsert_bytes_all_new_mut, BulkRequest};
+
+        fn bucket() -> Bucket {
+            Bucket::from(String::from("devices"))
+        }
+
+        #[test]
+        fn test_corruption() {
+            let mut kv = MemoryKv::new();
+            let put = checksum::upsert_checksummed_new(upsert_bytes_all_new_mut(
+                mem::create,
+                mem::upsert,
+                mem::upsert_builder(),
+            ));
+            let items = (0..5).map(|i| Item::new(vec![i], vec![i; 8])).collect();
+            put(vec![BulkRequest::new(bucket(), items)].into_iter(), &mut kv).unwrap();
+
+            let raw = upsert_bytes_all_new_mut(mem::create, mem::upsert, mem::upsert_builder());
+            let mut broken: Vec<u8> = checksum::seal(&[3], &[3; 8]);
+            broken[10] ^= 1;
+            let raw_items = vec![Item::new(vec![3], broken), Item::new(vec![5], vec![5; 8])];
+            raw(
+                vec![BulkRequest::new(bucket(), raw_items)].into_iter(),
+                &mut kv,
+            )
+            .unwrap();
+
+            let get =
+                checksum::select_verified_new(select_bytes_new_mut(mem::select, mem::builder()));
+            let req = GetRequest::new(bucket(), vec![2]);
+            assert_eq!(get(&req, &mut kv).unwrap(), Some(vec![2; 8]));
+            let req = GetRequest::new(bucket(), vec![3]);
+            assert!(matches!(
+                get(&req, &mut kv),
+                Err(Event::ChecksumMismatch(_))
+            ));
+            let req = GetRequest::new(bucket(), vec![5]);
+            assert_eq!(get(&req, &mut kv).unwrap(), Some(vec![5; 8]));
+
+            let scrub = checksum::scrub_bucket_new_mut(
+                list_items_page_new_mut(mem::page, mem::page_builder()),
+                2,
+            );
+            let r: ScrubReport = scrub(&bucket(), &mut kv).unwrap();
+            assert_eq!(r.scanned(), 6);
+            assert_eq!(r.unsealed(), 1);
+            assert_eq!(r.mismatched(), &[vec![3]]);
+            assert!(!r.is_clean());
+        }
+    }
+}
@@ -0,0 +1,661 @@
+//! Chunked storage for large values.
+//!
+//! A large value is split into chunks stored in a side bucket(`{bucket}_chunks`); the bucket
+//! itself stores a small manifest of the value:
+//!
+//! | Offset | Length | Content                          |
+//! |--------|--------|----------------------------------|
+//! | 0      | 1      | magic(0xcb)                      |
+//! | 1      | 8      | generation(big endian)           |
+//! | 9      | 8      | value length(big endian)         |
+//! | 17     | 4      | chunk size(big endian)           |
+//!
+//! The key of a chunk is the length of the key(u32), the key, the generation(u64) and the index
+//! of the chunk(u32), all big endian; chunks of a value are ordered by the index and chunks of
+//! a key share the prefix(length, key), so they are listed by a page(range) request.
+//!
+//! Each writer uses a new random generation. An overwrite writes chunks of its generation,
+//! replaces the manifest and then deletes chunks of all other generations of the key, so readers
+//! never see a partially written value. Orphan chunks left by an unfinished writer are deleted
+//! by the next finished write(or delete) of the key.
+//!
+//! Run each write(`put`, `writer` until `finish`) and `delete` in a transaction: chunks of
+//! other generations which are visible when a writer finishes are deleted, including chunks of
+//! a concurrent writer which is still writing.
+//!
+//! A finished write creates both buckets(even for an empty value). Reads and deletes of a
+//! missing bucket return nothing, so the select/delete/page handlers must fail with
+//! `Event::BucketNotFound` for a missing bucket(e.g, map "no such table" of SQLite or
+//! `UNDEFINED_TABLE` of PostgreSQL); other errors are returned as is.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bucket::Bucket;
+use crate::evt::Event;
+use crate::get::GetRequest;
+use crate::item::{Item, RawItem};
+use crate::list::PageRequest;
+use crate::upsert::{BulkRequest, Requests};
+
+/// The magic byte of manifests.
+pub const MANIFEST_MAGIC: u8 = 0xcb;
+
+const MANIFEST_SIZE: usize = 21;
+
+/// The number of chunk keys listed per page when chunks are deleted.
+const CHUNK_PAGE: u64 = 64;
+
+/// A description of a chunked value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Manifest {
+    generation: u64,
+    len: u64,
+    chunk_size: u32,
+}
+
+impl Manifest {
+    /// Gets the length of the value.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Checks if the value is empty.
+    pub fn is_empty(&self) -> bool {
+        0 == self.len
+    }
+
+    /// Gets the size of chunks(the last chunk may be smaller).
+    pub fn chunk_size(&self) -> u32 {
+        self.chunk_size
+    }
+
+    /// Gets the number of chunks(at most `u32::MAX`).
+    pub fn chunks(&self) -> u64 {
+        self.len.div_ceil(u64::from(self.chunk_size))
+    }
+
+    fn chunk_len(&self, index: u32) -> usize {
+        let start: u64 = u64::from(index) * u64::from(self.chunk_size);
+        (self.len - start).min(u64::from(self.chunk_size)) as usize
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut v: Vec<u8> = Vec::with_capacity(MANIFEST_SIZE);
+        v.push(MANIFEST_MAGIC);
+        v.extend_from_slice(&self.generation.to_be_bytes());
+        v.extend_from_slice(&self.len.to_be_bytes());
+        v.extend_from_slice(&self.chunk_size.to_be_bytes());
+        v
+    }
+
+    fn decode(v: &[u8]) -> Result<Self, Event> {
+        let invalid = || Event::UnexpectedError(String::from("Invalid chunk manifest"));
+        match (v.len(), v.first()) {
+            (MANIFEST_SIZE, Some(&MANIFEST_MAGIC)) => Ok(()),
+            _ => Err(invalid()),
+        }?;
+        let u64_at = |i: usize| v[i..i + 8].try_into().map(u64::from_be_bytes);
+        let generation: u64 = u64_at(1).map_err(|_| invalid())?;
+        let len: u64 = u64_at(9).map_err(|_| invalid())?;
+        let chunk_size: u32 = v[17..21]
+            .try_into()
+            .map(u32::from_be_bytes)
+            .map_err(|_| invalid())?;
+        let m = Self {
+            generation,
+            len,
+            chunk_size,
+        };
+        match chunk_size == 0 || u64::from(u32::MAX) < m.chunks() {
+            true => Err(invalid()),
+            false => Ok(m),
+        }
+    }
+}
+
+/// Gets the side bucket which stores chunks of the bucket.
+pub fn chunk_bucket(b: &Bucket) -> Bucket {
+    Bucket::from(format!("{}_chunks", b.as_str()))
+}
+
+/// Gets the prefix shared by chunk keys of the key.
+fn chunk_prefix(key: &[u8]) -> Vec<u8> {
+    let mut v: Vec<u8> = Vec::with_capacity(4 + key.len() + 8 + 4);
+    v.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    v.extend_from_slice(key);
+    v
+}
+
+fn chunk_key(key: &[u8], generation: u64, index: u32) -> Vec<u8> {
+    let mut v: Vec<u8> = chunk_prefix(key);
+    v.extend_from_slice(&generation.to_be_bytes());
+    v.extend_from_slice(&index.to_be_bytes());
+    v
+}
+
+/// Gets the generation of the chunk key which starts with the prefix.
+fn chunk_generation(prefix: &[u8], chunk_key: &[u8]) -> Option<u64> {
+    chunk_key
+        .get(prefix.len()..prefix.len() + 8)
+        .and_then(|g| g.try_into().ok())
+        .map(u64::from_be_bytes)
+}
+
+/// Gets a random generation(unique per writer, not cryptographically secure).
+fn new_generation() -> u64 {
+    static WRITERS: AtomicU64 = AtomicU64::new(0);
+    let mut h = RandomState::new().build_hasher();
+    h.write_u64(WRITERS.fetch_add(1, Ordering::Relaxed));
+    if let Ok(d) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        h.write_u128(d.as_nanos());
+    }
+    h.finish()
+}
+
+fn to_io(e: Event) -> io::Error {
+    io::Error::other(format!("{:?}", e))
+}
+
+/// Stores large values as chunks using the select/upsert/delete/page handlers.
+pub struct ChunkStore<S, U, D, P> {
+    select: S,
+    upsert: U,
+    delete: D,
+    page: P,
+    chunk_size: u32,
+}
+
+impl<S, U, D, P> ChunkStore<S, U, D, P> {
+    /// Creates new chunk store.
+    ///
+    /// # Arguments
+    /// - select: Selects a value(e.g, `select_bytes_new_mut`).
+    /// - upsert: Upserts requests(e.g, `upsert_bytes_all_new_mut`).
+    /// - delete: Deletes a key(e.g, `delete_key_bytes_mut`).
+    /// - page: Gets a page of chunks(e.g, `list_items_page_new_mut`).
+    /// - chunk_size: The size of chunks in bytes(at least 1).
+    pub fn new(select: S, upsert: U, delete: D, page: P, chunk_size: u32) -> Self {
+        Self {
+            select,
+            upsert,
+            delete,
+            page,
+            chunk_size: chunk_size.max(1),
+        }
+    }
+
+    /// Gets the manifest of the key if exists.
+    pub fn manifest<C>(
+        &self,
+        b: &Bucket,
+        key: &[u8],
+        client: &mut C,
+    ) -> Result<Option<Manifest>, Event>
+    where
+        S: Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>,
+    {
+        let req = GetRequest::new(Bucket::from(String::from(b.as_str())), key.to_vec());
+        match (self.select)(&req, client) {
+            Ok(o) => o.map(|v| Manifest::decode(&v)).transpose(),
+            Err(Event::BucketNotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Deletes chunks of the key(all generations but the kept one) listed page by page.
+    fn delete_chunks<C>(
+        &self,
+        b: &Bucket,
+        key: &[u8],
+        keep: Option<u64>,
+        client: &mut C,
+    ) -> Result<u64, Event>
+    where
+        D: Fn(&Bucket, &[u8], &mut C) -> Result<u64, Event>,
+        P: Fn(&PageRequest, &mut C) -> Result<Vec<RawItem>, Event>,
+    {
+        let chunks: Bucket = chunk_bucket(b);
+        let prefix: Vec<u8> = chunk_prefix(key);
+        let mut after: Vec<u8> = prefix.clone();
+        let mut cnt: u64 = 0;
+        loop {
+            let req = PageRequest::new(chunk_bucket(b), Some(after), CHUNK_PAGE);
+            let items: Vec<RawItem> = match (self.page)(&req, client) {
+                Ok(items) => items,
+                Err(Event::BucketNotFound(_)) => vec![],
+                Err(e) => return Err(e),
+            };
+            let full: bool = items.len() as u64 == CHUNK_PAGE;
+            let keys: Vec<Vec<u8>> = items
+                .into_iter()
+                .map(|i| i.into_pair().0)
+                .take_while(|k| k.starts_with(&prefix))
+                .collect();
+            let more: bool = full && keys.len() as u64 == CHUNK_PAGE;
+            for k in keys.iter() {
+                if chunk_generation(&prefix, k) != keep {
+                    cnt += (self.delete)(&chunks, k, client)?;
+                }
+            }
+            match keys.into_iter().last() {
+                Some(last) if more => after = last,
+                _ => return Ok(cnt),
+            }
+        }
+    }
+
+    /// Opens a writer which replaces the value of the key when finished.
+    ///
+    /// The writer uses a new random generation; run it in a transaction(see the module doc).
+    pub fn writer<'a, C>(
+        &'a self,
+        b: &Bucket,
+        key: &[u8],
+        client: &'a mut C,
+    ) -> ChunkWriter<'a, S, U, D, P, C>
+    where
+        U: Fn(Requests, &mut C) -> Result<u64, Event>,
+        D: Fn(&Bucket, &[u8], &mut C) -> Result<u64, Event>,
+        P: Fn(&PageRequest, &mut C) -> Result<Vec<RawItem>, Event>,
+    {
+        ChunkWriter {
+            store: self,
+            client,
+            bucket: String::from(b.as_str()),
+            key: key.to_vec(),
+            generation: new_generation(),
+            buf: Vec::with_capacity(self.chunk_size as usize),
+            index: 0,
+            len: 0,
+        }
+    }
+
+    /// Opens a reader of the value if exists.
+    pub fn reader<'a, C>(
+        &'a self,
+        b: &Bucket,
+        key: &[u8],
+        client: &'a mut C,
+    ) -> Result<Option<ChunkReader<'a, S, C>>, Event>
+    where
+        S: Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>,
+    {
+        let manifest: Option<Manifest> = self.manifest(b, key, client)?;
+        Ok(manifest.map(|manifest| ChunkReader {
+            select: &self.select,
+            client,
+            bucket: String::from(b.as_str()),
+            key: key.to_vec(),
+            manifest,
+            pos: 0,
+            buf: vec![],
+            buf_index: None,
+        }))
+    }
+
+    /// Stores the value read from the reader; returns the length of the value.
+    pub fn put<C, R>(&self, b: &Bucket, key: &[u8], r: &mut R, client: &mut C) -> Result<u64, Event>
+    where
+        U: Fn(Requests, &mut C) -> Result<u64, Event>,
+        D: Fn(&Bucket, &[u8], &mut C) -> Result<u64, Event>,
+        P: Fn(&PageRequest, &mut C) -> Result<Vec<RawItem>, Event>,
+        R: Read,
+    {
+        let mut w = self.writer(b, key, client);
+        io::copy(r, &mut w)
+            .map_err(|e| Event::UnexpectedError(format!("Unable to write chunks: {}", e)))?;
+        w.finish()
+    }
+
+    /// Gets the whole value.
+    pub fn get<C>(&self, b: &Bucket, key: &[u8], client: &mut C) -> Result<Option<Vec<u8>>, Event>
+    where
+        S: Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>,
+    {
+        match self.reader(b, key, client)? {
+            None => Ok(None),
+            Some(mut r) => {
+                let mut v: Vec<u8> = Vec::with_capacity(r.manifest().len() as usize);
+                r.read_to_end(&mut v)
+                    .map_err(|e| Event::UnexpectedError(format!("Unable to read chunks: {}", e)))?;
+                Ok(Some(v))
+            }
+        }
+    }
+
+    /// Deletes the value and all chunks of the key(including orphans); returns the number of
+    /// deleted rows.
+    pub fn delete<C>(&self, b: &Bucket, key: &[u8], client: &mut C) -> Result<u64, Event>
+    where
+        D: Fn(&Bucket, &[u8], &mut C) -> Result<u64, Event>,
+        P: Fn(&PageRequest, &mut C) -> Result<Vec<RawItem>, Event>,
+    {
+        let cnt: u64 = match (self.delete)(b, key, client) {
+            Ok(cnt) => cnt,
+            Err(Event::BucketNotFound(_)) => 0,
+            Err(e) => return Err(e),
+        };
+        self.delete_chunks(b, key, None, client).map(|c| c + cnt)
+    }
+}
+
+/// Writes a value as chunks; call `finish` to replace the value.
+pub struct ChunkWriter<'a, S, U, D, P, C> {
+    store: &'a ChunkStore<S, U, D, P>,
+    client: &'a mut C,
+    bucket: String,
+    key: Vec<u8>,
+    generation: u64,
+    buf: Vec<u8>,
+    index: u32,
+    len: u64,
+}
+
+impl<'a, S, U, D, P, C> ChunkWriter<'a, S, U, D, P, C>
+where
+    U: Fn(Requests, &mut C) -> Result<u64, Event>,
+    D: Fn(&Bucket, &[u8], &mut C) -> Result<u64, Event>,
+    P: Fn(&PageRequest, &mut C) -> Result<Vec<RawItem>, Event>,
+{
+    fn upsert(&mut self, b: Bucket, key: Vec<u8>, val: Vec<u8>) -> Result<u64, Event> {
+        let req = BulkRequest::new(b, vec![Item::new(key, val)]);
+        (self.store.upsert)(vec![req].into_iter(), self.client)
+    }
+
+    fn write_chunk(&mut self) -> Result<(), Event> {
+        let next: u32 = self.index.checked_add(1).ok_or_else(|| {
+            Event::UnexpectedError(String::from("Too many chunks(use a larger chunk size)"))
+        })?;
+        let b: Bucket = chunk_bucket(&Bucket::from(self.bucket.clone()));
+        let key: Vec<u8> = chunk_key(&self.key, self.generation, self.index);
+        let val: Vec<u8> = std::mem::take(&mut self.buf);
+        self.len += val.len() as u64;
+        self.upsert(b, key, val)?;
+        self.index = next;
+        Ok(())
+    }
+
+    /// Writes the last chunk and the manifest, then deletes chunks of other generations.
+    ///
+    /// The chunk bucket is created even if no chunk was written(an empty value).
+    ///
+    /// Returns the length of the value.
+    pub fn finish(mut self) -> Result<u64, Event> {
+        if !self.buf.is_empty() {
+            self.write_chunk()?;
+        }
+        let m = Manifest {
+            generation: self.generation,
+            len: self.len,
+            chunk_size: self.store.chunk_size,
+        };
+        let b: Bucket = Bucket::from(self.bucket.clone());
+        let requests = vec![
+            BulkRequest::new(chunk_bucket(&b), vec![]),
+            BulkRequest::new(
+                Bucket::from(self.bucket.clone()),
+                vec![Item::new(self.key.clone(), m.encode())],
+            ),
+        ];
+        (self.store.upsert)(requests.into_iter(), self.client)?;
+        self.store
+            .delete_chunks(&b, &self.key, Some(self.generation), self.client)?;
+        Ok(m.len)
+    }
+}
+
+impl<'a, S, U, D, P, C> Write for ChunkWriter<'a, S, U, D, P, C>
+where
+    U: Fn(Requests, &mut C) -> Result<u64, Event>,
+    D: Fn(&Bucket, &[u8], &mut C) -> Result<u64, Event>,
+    P: Fn(&PageRequest, &mut C) -> Result<Vec<RawItem>, Event>,
+{
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let room: usize = self.store.chunk_size as usize - self.buf.len();
+        let n: usize = room.min(data.len());
+        self.buf.extend_from_slice(&data[..n]);
+        if self.buf.len() == self.store.chunk_size as usize {
+            self.write_chunk().map_err(to_io)?;
+        }
+        Ok(n)
+    }
+
+    /// Does nothing; partial chunks are written by `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads a chunked value; chunks are selected one by one.
+pub struct ChunkReader<'a, S, C> {
+    select: &'a S,
+    client: &'a mut C,
+    bucket: String,
+    key: Vec<u8>,
+    manifest: Manifest,
+    pos: u64,
+    buf: Vec<u8>,
+    buf_index: Option<u32>,
+}
+
+impl<'a, S, C> ChunkReader<'a, S, C>
+where
+    S: Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>,
+{
+    /// Gets the manifest of the value.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    fn load(&mut self, index: u32) -> Result<(), Event> {
+        let b: Bucket = chunk_bucket(&Bucket::from(self.bucket.clone()));
+        let key: Vec<u8> = chunk_key(&self.key, self.manifest.generation, index);
+        let chunk: Vec<u8> = (self.select)(&GetRequest::new(b, key), self.client)?
+            .filter(|c| c.len() == self.manifest.chunk_len(index))
+            .ok_or_else(|| Event::UnexpectedError(format!("Missing or broken chunk: {}", index)))?;
+        self.buf = chunk;
+        self.buf_index = Some(index);
+        Ok(())
+    }
+}
+
+impl<'a, S, C> Read for ChunkReader<'a, S, C>
+where
+    S: Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>,
+{
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.manifest.len <= self.pos || out.is_empty() {
+            return Ok(0);
+        }
+        let cs: u64 = u64::from(self.manifest.chunk_size);
+        let index: u32 = (self.pos / cs) as u32;
+        if self.buf_index != Some(index) {
+            self.load(index).map_err(to_io)?;
+        }
+        let offset: usize = (self.pos - u64::from(index) * cs) as usize;
+        let n: usize = (self.buf.len() - offset).min(out.len());
+        out[..n].copy_from_slice(&self.buf[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a, S, C> Seek for ChunkReader<'a, S, C> {
+    /// Moves the position; chunks are selected lazily by the next read.
+    fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+        let (base, offset): (u64, i64) = match from {
+            SeekFrom::Start(p) => (p, 0),
+            SeekFrom::End(o) => (self.manifest.len, o),
+            SeekFrom::Current(o) => (self.pos, o),
+        };
+        self.pos = base
+            .checked_add_signed(offset)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid seek position"))?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod test_chunk {
+
+    mod chunk_store {
+
+        use std::io::{Read, Write};
+
+        use crate::blob;
+        use crate::bucket::Bucket;
+        use crate::chunk::{ChunkStore, Manifest};
+        use crate::del::delete_key_bytes_mut;
+        use crate::evt::Event;
+        use crate::get::{select_bytes_new_mut, GetRequest};
+        use crate::item::RawItem;
+        use crate::list::{list_items_page_new_mut, PageRequest};
+        use crate::mem::{self, MemoryKv};
+        use crate::upsert::{upsert_bytes_all_new_mut, Requests};
+
+        fn bucket() -> Bucket {
+            Bucket::from(String::from("artifacts"))
+        }
+
+        #[allow(clippy::type_complexity)]
+        fn store(
+            chunk_size: u32,
+        ) -> ChunkStore<
+            impl Fn(&GetRequest<Vec<u8>>, &mut MemoryKv) -> Result<Option<Vec<u8>>, Event>,
+            impl Fn(Requests, &mut MemoryKv) -> Result<u64, Event>,
+            impl Fn(&Bucket, &[u8], &mut MemoryKv) -> Result<u64, Event>,
+            impl Fn(&PageRequest, &mut MemoryKv) -> Result<Vec<RawItem>, Event>,
+        > {
+            ChunkStore::new(
+                select_bytes_new_mut(mem::select, mem::builder()),
+                upsert_bytes_all_new_mut(mem::create, mem::upsert, mem::upsert_builder()),
+                delete_key_bytes_mut(mem::delete, mem::builder()),
+                list_items_page_new_mut(mem::page, mem::page_builder()),
+                chunk_size,
+            )
+        }
+
+        fn value(len: usize) -> Vec<u8> {
+            (0..len).map(|i| (i % 251) as u8).collect()
+        }
+
+        #[test]
+        fn test_roundtrip() {
+            let mut kv = MemoryKv::new();
+            let store = store(4);
+            assert_eq!(store.get(&bucket(), b"a", &mut kv).unwrap(), None);
+
+            let v: Vec<u8> = value(10);
+            assert_eq!(
+                store
+                    .put(&bucket(), b"a", &mut v.as_slice(), &mut kv)
+                    .unwrap(),
+                10
+            );
+            assert_eq!(kv.bucket("artifacts_chunks").unwrap().len(), 3);
+            assert_eq!(store.get(&bucket(), b"a", &mut kv).unwrap(), Some(v));
+
+            let v: Vec<u8> = value(5);
+            store
+                .put(&bucket(), b"a", &mut v.as_slice(), &mut kv)
+                .unwrap();
+            assert_eq!(kv.bucket("artifacts_chunks").unwrap().len(), 2);
+            let mut r = store.reader(&bucket(), b"a", &mut kv).unwrap().unwrap();
+            let mut head = [0u8; 3];
+            r.read_exact(&mut head).unwrap();
+            assert_eq!(head.to_vec(), v[..3].to_vec());
+            let mut tail: Vec<u8> = vec![];
+            assert_eq!(blob::read_range(&mut r, 3, 10, &mut tail).unwrap(), 2);
+            assert_eq!(tail, v[3..].to_vec());
+
+            store
+                .put(&bucket(), b"e", &mut [].as_slice(), &mut kv)
+                .unwrap();
+            assert_eq!(store.get(&bucket(), b"e", &mut kv).unwrap(), Some(vec![]));
+
+            assert_eq!(store.delete(&bucket(), b"a", &mut kv).unwrap(), 3);
+            assert!(kv.bucket("artifacts_chunks").unwrap().is_empty());
+            assert_eq!(store.get(&bucket(), b"a", &mut kv).unwrap(), None);
+        }
+
+        #[test]
+        fn test_empty_first() {
+            let mut kv = MemoryKv::new();
+            let store = store(4);
+            assert_eq!(store.delete(&bucket(), b"e", &mut kv).unwrap(), 0);
+            store
+                .put(&bucket(), b"e", &mut [].as_slice(), &mut kv)
+                .unwrap();
+            assert!(kv.bucket("artifacts_chunks").unwrap().is_empty());
+            assert_eq!(store.get(&bucket(), b"e", &mut kv).unwrap(), Some(vec![]));
+            assert_eq!(store.delete(&bucket(), b"e", &mut kv).unwrap(), 1);
+        }
+
+        #[test]
+        fn test_orphans() {
+            let mut kv = MemoryKv::new();
+            let store = store(1);
+            let v: Vec<u8> = value(3);
+            store
+                .put(&bucket(), b"ab", &mut v.as_slice(), &mut kv)
+                .unwrap();
+            store
+                .put(&bucket(), b"a", &mut v.as_slice(), &mut kv)
+                .unwrap();
+
+            let mut w = store.writer(&bucket(), b"a", &mut kv);
+            w.write_all(&value(200)).unwrap();
+            drop(w);
+            let orphans: usize = kv.bucket("artifacts_chunks").unwrap().len();
+            assert_eq!(orphans, 3 + 3 + 200);
+
+            store
+                .put(&bucket(), b"a", &mut v.as_slice(), &mut kv)
+                .unwrap();
+            assert_eq!(kv.bucket("artifacts_chunks").unwrap().len(), 3 + 3);
+            assert_eq!(
+                store.get(&bucket(), b"a", &mut kv).unwrap(),
+                Some(v.clone())
+            );
+
+            let mut w = store.writer(&bucket(), b"a", &mut kv);
+            w.write_all(&value(100)).unwrap();
+            drop(w);
+            assert_eq!(store.delete(&bucket(), b"a", &mut kv).unwrap(), 1 + 3 + 100);
+            assert_eq!(kv.bucket("artifacts_chunks").unwrap().len(), 3);
+            assert_eq!(store.get(&bucket(), b"ab", &mut kv).unwrap(), Some(v));
+        }
+
+        #[test]
+        fn test_generation() {
+            let mut kv = MemoryKv::new();
+            let store = store(4);
+            let g1: u64 = store.writer(&bucket(), b"a", &mut kv).generation;
+            let g2: u64 = store.writer(&bucket(), b"a", &mut kv).generation;
+            assert_ne!(g1, g2);
+        }
+
+        #[test]
+        fn test_too_many_chunks() {
+            let mut kv = MemoryKv::new();
+            let store = store(1);
+            let mut w = store.writer(&bucket(), b"a", &mut kv);
+            w.index = u32::MAX;
+            assert!(w.write(b"x").is_err());
+
+            let m = Manifest {
+                generation: 0,
+                len: u64::from(u32::MAX) + 1,
+                chunk_size: 1,
+            };
+            assert!(Manifest::decode(&m.encode()).is_err());
+            let mut larger: Vec<u8> = m.encode();
+            larger[17..21].copy_from_slice(&2u32.to_be_bytes());
+            assert_eq!(Manifest::decode(&larger).unwrap().chunks(), 1 << 31);
+        }
+    }
+}
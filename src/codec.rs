@@ -0,0 +1,270 @@
+//! Transparent value compression.
+//!
+//! Encoded values start with `MAGIC` followed by a header byte which identifies the codec:
+//!
+//! | Header | Codec              | Feature |
+//! |--------|--------------------|---------|
+//! | 0x00   | none(raw)          |         |
+//! | 0x01   | zstd               | `zstd`  |
+//! | 0x02   | lz4(size prepended)| `lz4`   |
+//!
+//! Values without `MAGIC`(e.g, written before the encoder was introduced) are decoded as is,
+//! so a bucket may hold both legacy and encoded values. `MAGIC` starts with bytes which never
+//! appear in UTF-8, so legacy text(e.g, JSON) values are never mistaken for encoded ones.
+//!
+//! Decoded values are limited in size(`DECODED_MAX` by default) so that a small corrupted or
+//! malicious value can not expand into a huge allocation.
+
+#[cfg(feature = "zstd")]
+use std::io::Read;
+
+use crate::evt::Event;
+use crate::get::GetRequest;
+
+/// The prefix of encoded values.
+pub const MAGIC: [u8; 3] = [0xff, 0xc0, 0xde];
+
+/// The default maximum size of a decoded value(64 MiB).
+pub const DECODED_MAX: usize = 64 * 1024 * 1024;
+
+/// The header of uncompressed values.
+pub const HEADER_RAW: u8 = 0x00;
+
+/// The header of zstd compressed values.
+pub const HEADER_ZSTD: u8 = 0x01;
+
+/// The header of lz4 compressed values.
+pub const HEADER_LZ4: u8 = 0x02;
+
+/// A compression algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Raw,
+    /// zstd with the compression level.
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+impl Codec {
+    /// Compresses the value; returns `None` if the codec could not compress it.
+    #[cfg_attr(not(any(feature = "zstd", feature = "lz4")), allow(unused_variables))]
+    fn compress(&self, val: &[u8]) -> Option<(u8, Vec<u8>)> {
+        match self {
+            Codec::Raw => None,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(level) => zstd::bulk::compress(val, *level)
+                .ok()
+                .map(|c| (HEADER_ZSTD, c)),
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => Some((HEADER_LZ4, lz4_flex::compress_prepend_size(val))),
+        }
+    }
+}
+
+/// Compresses values larger than the threshold.
+#[derive(Debug, Clone, Copy)]
+pub struct Encoder {
+    codec: Codec,
+    threshold: usize,
+}
+
+impl Encoder {
+    /// Creates new encoder.
+    ///
+    /// # Arguments
+    /// - codec: The compression algorithm.
+    /// - threshold: Values smaller than this are stored uncompressed.
+    pub fn new(codec: Codec, threshold: usize) -> Self {
+        Self { codec, threshold }
+    }
+
+    /// Encodes the value; it is stored uncompressed if compression does not make it smaller.
+    pub fn encode(&self, val: &[u8]) -> Vec<u8> {
+        let compressed: Option<(u8, Vec<u8>)> = match val.len() < self.threshold {
+            true => None,
+            false => self.codec.compress(val),
+        };
+        let (header, body): (u8, &[u8]) = match &compressed {
+            Some((h, c)) if c.len() < val.len() => (*h, c.as_slice()),
+            _ => (HEADER_RAW, val),
+        };
+        let mut encoded: Vec<u8> = Vec::with_capacity(MAGIC.len() + 1 + body.len());
+        encoded.extend_from_slice(&MAGIC);
+        encoded.push(header);
+        encoded.extend_from_slice(body);
+        encoded
+    }
+}
+
+/// Decodes the value encoded by `Encoder`(up to `DECODED_MAX` bytes).
+///
+/// Values without `MAGIC` are returned as is.
+pub fn decode(encoded: &[u8]) -> Result<Vec<u8>, Event> {
+    decode_limited(encoded, DECODED_MAX)
+}
+
+/// Decodes the value encoded by `Encoder`; fails if the decoded value exceeds the limit.
+///
+/// # Arguments
+/// - encoded: The stored value.
+/// - limit: The maximum size of the decoded value.
+#[cfg_attr(not(any(feature = "zstd", feature = "lz4")), allow(unused_variables))]
+pub fn decode_limited(encoded: &[u8], limit: usize) -> Result<Vec<u8>, Event> {
+    let Some(rest) = encoded.strip_prefix(&MAGIC) else {
+        return Ok(encoded.to_vec());
+    };
+    let (header, body) = rest
+        .split_first()
+        .ok_or_else(|| Event::UnexpectedError(String::from("Missing codec header")))?;
+    let too_large = || Event::UnexpectedError(format!("Decoded value exceeds {} bytes", limit));
+    match *header {
+        HEADER_RAW => Ok(body.to_vec()),
+        #[cfg(feature = "zstd")]
+        HEADER_ZSTD => {
+            let conv = |e| Event::UnexpectedError(format!("Unable to decompress(zstd): {}", e));
+            let d = zstd::stream::read::Decoder::new(body).map_err(conv)?;
+            let mut decoded: Vec<u8> = vec![];
+            d.take(limit as u64 + 1)
+                .read_to_end(&mut decoded)
+                .map_err(conv)?;
+            match decoded.len() <= limit {
+                true => Ok(decoded),
+                false => Err(too_large()),
+            }
+        }
+        #[cfg(feature = "lz4")]
+        HEADER_LZ4 => {
+            let size: usize = body
+                .first_chunk::<4>()
+                .map(|s| u32::from_le_bytes(*s) as usize)
+                .ok_or_else(|| Event::UnexpectedError(String::from("Missing size(lz4)")))?;
+            if limit < size {
+                return Err(too_large());
+            }
+            lz4_flex::decompress_size_prepended(body)
+                .map_err(|e| Event::UnexpectedError(format!("Unable to decompress(lz4): {}", e)))
+        }
+        h => Err(Event::UnexpectedError(format!(
+            "Unsupported codec header: {:#04x}",
+            h
+        ))),
+    }
+}
+
+/// Creates value converter for `convert_request` which encodes converted values.
+///
+/// # Arguments
+/// - encoder: Compresses values.
+/// - val2bytes: Converts values into bytes.
+pub fn val2bytes_encoded_new<V, G>(encoder: Encoder, val2bytes: G) -> impl Fn(V) -> Vec<u8>
+where
+    G: Fn(V) -> Vec<u8>,
+{
+    move |val: V| encoder.encode(&val2bytes(val))
+}
+
+/// Creates select request handler which decodes the selected value(see `decode`).
+///
+/// # Arguments
+/// - select: The handler(e.g, `select_bytes_new_mut`).
+pub fn select_decoded_new<S, C>(
+    select: S,
+) -> impl Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>
+where
+    S: Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>,
+{
+    move |req: &GetRequest<Vec<u8>>, client: &mut C| {
+        let encoded: Option<Vec<u8>> = select(req, client)?;
+        encoded.map(|e| decode(&e)).transpose()
+    }
+}
+
+#[cfg(test)]
+mod test_codec {
+
+    mod encoder {
+
+        use crate::codec::{self, Codec, Encoder, HEADER_RAW, MAGIC};
+
+        fn json() -> Vec<u8> {
+            br#"{"device":"cafef00d","temperature":42}"#.repeat(64)
+        }
+
+        #[test]
+        fn test_raw() {
+            let e: Encoder = Encoder::new(Codec::Raw, 0);
+            let encoded: Vec<u8> = e.encode(b"v");
+            assert_eq!(encoded, [&MAGIC[..], &[HEADER_RAW, b'v']].concat());
+            assert_eq!(codec::decode(&encoded).unwrap(), b"v".to_vec());
+            assert_eq!(e.encode(&json()).len(), json().len() + MAGIC.len() + 1);
+            assert!(codec::decode(&MAGIC).is_err());
+            assert!(codec::decode(&[&MAGIC[..], &[0xff]].concat()).is_err());
+        }
+
+        #[test]
+        fn test_legacy() {
+            assert_eq!(codec::decode(&json()).unwrap(), json());
+            assert_eq!(codec::decode(&[]).unwrap(), Vec::<u8>::new());
+            assert_eq!(codec::decode(&[0x01, b'v']).unwrap(), vec![0x01, b'v']);
+        }
+
+        #[cfg(feature = "zstd")]
+        #[test]
+        fn test_zstd() {
+            let e: Encoder = Encoder::new(Codec::Zstd(3), 16);
+            let encoded: Vec<u8> = e.encode(&json());
+            assert_eq!(encoded[MAGIC.len()], codec::HEADER_ZSTD);
+            assert!(encoded.len() < json().len());
+            assert_eq!(codec::decode(&encoded).unwrap(), json());
+            assert!(codec::decode_limited(&encoded, json().len()).is_ok());
+            assert!(codec::decode_limited(&encoded, json().len() - 1).is_err());
+            assert_eq!(e.encode(b"small")[MAGIC.len()], HEADER_RAW);
+        }
+
+        #[cfg(feature = "lz4")]
+        #[test]
+        fn test_lz4() {
+            let e: Encoder = Encoder::new(Codec::Lz4, 16);
+            let encoded: Vec<u8> = e.encode(&json());
+            assert_eq!(encoded[MAGIC.len()], codec::HEADER_LZ4);
+            assert_eq!(codec::decode(&encoded).unwrap(), json());
+            assert!(codec::decode_limited(&encoded, json().len() - 1).is_err());
+
+            let mut bomb: Vec<u8> = [&MAGIC[..], &[codec::HEADER_LZ4]].concat();
+            bomb.extend_from_slice(&u32::MAX.to_le_bytes());
+            assert!(codec::decode(&bomb).is_err());
+            assert_eq!(e.encode(b"small")[MAGIC.len()], HEADER_RAW);
+        }
+    }
+
+    mod select_decoded_new {
+
+        use crate::bucket::Bucket;
+        use crate::codec::{self, Codec, Encoder};
+        use crate::get::{select_bytes_new_mut, GetRequest};
+        use crate::item::Item;
+        use crate::mem::{self, MemoryKv};
+        use crate::upsert::{convert_request, upsert_bytes_all_new_mut, BulkRequest};
+
+        #[test]
+        fn test_roundtrip() {
+            let mut kv = MemoryKv::new();
+            let req = BulkRequest::new(
+                Bucket::from(String::from("devices")),
+                vec![Item::new("a", "1"), Item::new("b", "2")],
+            );
+            let v2b = codec::val2bytes_encoded_new(Encoder::new(Codec::Raw, 0), |v: &str| {
+                v.as_bytes().to_vec()
+            });
+            let raw = convert_request(req, |k: &str| k.as_bytes().to_vec(), v2b);
+            let f = upsert_bytes_all_new_mut(mem::create, mem::upsert, mem::upsert_builder());
+            f(vec![raw].into_iter(), &mut kv).unwrap();
+
+            let get = codec::select_decoded_new(select_bytes_new_mut(mem::select, mem::builder()));
+            let req = GetRequest::new(Bucket::from(String::from("devices")), b"b".to_vec());
+            assert_eq!(get(&req, &mut kv).unwrap(), Some(b"2".to_vec()));
+        }
+    }
+}
@@ -0,0 +1,414 @@
+//! A conformance test harness for backends.
+//!
+//! Backend authors build handlers(`select_bytes_new_mut`, `upsert_bytes_all_new_mut`,
+//! `delete_key_bytes_mut`, `list_keys_bytes_new_mut` and `drop_bucket_mut`) with their
+//! executor closures, wrap them using `kv_handlers_new` and run `check_all`.
+//!
+//! The bucket used by the checks is dropped before and after each check.
+//! Upsert counts are not checked because they depend on the builder(e.g, whether unchanged
+//! values are counted).
+//! A backend may either fail with `Event::BucketNotFound` or return empty results for a missing
+//! bucket; any other error fails the check.
+
+use crate::bucket::Bucket;
+use crate::evt::Event;
+use crate::get::GetRequest;
+use crate::item::Item;
+use crate::mem::{self, MemoryKv};
+use crate::upsert::BulkRequest;
+pub use crate::upsert::Requests;
+
+/// Traits for the handlers to be checked.
+pub trait KvHandlers<C> {
+    fn get(&self, req: &GetRequest<Vec<u8>>, client: &mut C) -> Result<Option<Vec<u8>>, Event>;
+    fn upsert(&self, requests: Requests, client: &mut C) -> Result<u64, Event>;
+    fn delete(&self, b: &Bucket, key: &[u8], client: &mut C) -> Result<u64, Event>;
+    fn list(&self, b: &Bucket, client: &mut C) -> Result<Vec<Vec<u8>>, Event>;
+    fn drop(&self, b: &Bucket, client: &mut C) -> Result<(), Event>;
+}
+
+struct KvHandlersF<G, U, D, L, R> {
+    get: G,
+    upsert: U,
+    delete: D,
+    list: L,
+    drop: R,
+}
+
+impl<G, U, D, L, R, C> KvHandlers<C> for KvHandlersF<G, U, D, L, R>
+where
+    G: Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>,
+    U: Fn(Requests, &mut C) -> Result<u64, Event>,
+    D: Fn(&Bucket, &[u8], &mut C) -> Result<u64, Event>,
+    L: Fn(&Bucket, &mut C) -> Result<Vec<Vec<u8>>, Event>,
+    R: Fn(&Bucket, &mut C) -> Result<(), Event>,
+{
+    fn get(&self, req: &GetRequest<Vec<u8>>, client: &mut C) -> Result<Option<Vec<u8>>, Event> {
+        (self.get)(req, client)
+    }
+    fn upsert(&self, requests: Requests, client: &mut C) -> Result<u64, Event> {
+        (self.upsert)(requests, client)
+    }
+    fn delete(&self, b: &Bucket, key: &[u8], client: &mut C) -> Result<u64, Event> {
+        (self.delete)(b, key, client)
+    }
+    fn list(&self, b: &Bucket, client: &mut C) -> Result<Vec<Vec<u8>>, Event> {
+        (self.list)(b, client)
+    }
+    fn drop(&self, b: &Bucket, client: &mut C) -> Result<(), Event> {
+        (self.drop)(b, client)
+    }
+}
+
+/// Creates new `KvHandlers` implementation which uses handlers built by this crate.
+///
+/// # Arguments
+/// - get: Gets a value(see `select_bytes_new_mut`).
+/// - upsert: Upserts requests(see `upsert_bytes_all_new_mut`); commit changes if needed.
+/// - delete: Deletes a key(see `delete_key_bytes_mut`).
+/// - list: Lists keys(see `list_keys_bytes_new_mut`).
+/// - drop: Drops a bucket(see `drop_bucket_mut`).
+pub fn kv_handlers_new<G, U, D, L, R, C>(
+    get: G,
+    upsert: U,
+    delete: D,
+    list: L,
+    drop: R,
+) -> impl KvHandlers<C>
+where
+    G: Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>,
+    U: Fn(Requests, &mut C) -> Result<u64, Event>,
+    D: Fn(&Bucket, &[u8], &mut C) -> Result<u64, Event>,
+    L: Fn(&Bucket, &mut C) -> Result<Vec<Vec<u8>>, Event>,
+    R: Fn(&Bucket, &mut C) -> Result<(), Event>,
+{
+    KvHandlersF {
+        get,
+        upsert,
+        delete,
+        list,
+        drop,
+    }
+}
+
+/// Creates new `KvHandlers` implementation for `MemoryKv`(the model).
+pub fn kv_handlers_memory() -> impl KvHandlers<MemoryKv> {
+    use crate::del::{delete_key_bytes_mut, drop_bucket_mut};
+    use crate::get::select_bytes_new_mut;
+    use crate::list::list_keys_bytes_new_mut;
+    use crate::upsert::upsert_bytes_all_new_mut;
+    kv_handlers_new(
+        select_bytes_new_mut(mem::select, mem::builder()),
+        upsert_bytes_all_new_mut(mem::create, mem::upsert, mem::upsert_builder()),
+        delete_key_bytes_mut(mem::delete, mem::builder()),
+        list_keys_bytes_new_mut(mem::list_keys, mem::builder()),
+        drop_bucket_mut(mem::drop, mem::builder()),
+    )
+}
+
+fn copy(b: &Bucket) -> Bucket {
+    Bucket::from(String::from(b.as_str()))
+}
+
+fn expect<T>(check: &str, expected: T, got: T) -> Result<(), Event>
+where
+    T: PartialEq + std::fmt::Debug,
+{
+    match expected == got {
+        true => Ok(()),
+        false => Err(Event::UnexpectedError(format!(
+            "conformance: {}: expected {:?}, got {:?}",
+            check, expected, got
+        ))),
+    }
+}
+
+fn put<H, C>(h: &H, c: &mut C, b: &Bucket, items: &[(&[u8], &[u8])]) -> Result<u64, Event>
+where
+    H: KvHandlers<C>,
+{
+    let items = items
+        .iter()
+        .map(|(k, v)| Item::new(k.to_vec(), v.to_vec()))
+        .collect();
+    h.upsert(vec![BulkRequest::new(copy(b), items)].into_iter(), c)
+}
+
+fn get<H, C>(h: &H, c: &mut C, b: &Bucket, key: &[u8]) -> Result<Option<Vec<u8>>, Event>
+where
+    H: KvHandlers<C>,
+{
+    h.get(&GetRequest::new(copy(b), key.to_vec()), c)
+}
+
+/// Checks that an upsert overwrites the value of an existing key.
+pub fn check_upsert_overwrite<H, C>(h: &H, c: &mut C, b: &Bucket) -> Result<(), Event>
+where
+    H: KvHandlers<C>,
+{
+    h.drop(b, c)?;
+    put(h, c, b, &[(b"k", b"v1")])?;
+    expect(
+        "get after insert",
+        Some(b"v1".to_vec()),
+        get(h, c, b, b"k")?,
+    )?;
+    put(h, c, b, &[(b"k", b"v2")])?;
+    expect(
+        "get after update",
+        Some(b"v2".to_vec()),
+        get(h, c, b, b"k")?,
+    )?;
+    put(h, c, b, &[(b"k", b"v3"), (b"k", b"v4")])?;
+    expect(
+        "last write in a request",
+        Some(b"v4".to_vec()),
+        get(h, c, b, b"k")?,
+    )?;
+    expect("get missing key", None, get(h, c, b, b"missing")?)?;
+    h.drop(b, c)
+}
+
+/// Checks that keys are listed in the byte order.
+pub fn check_list_order<H, C>(h: &H, c: &mut C, b: &Bucket) -> Result<(), Event>
+where
+    H: KvHandlers<C>,
+{
+    h.drop(b, c)?;
+    let keys: [&[u8]; 6] = [b"b", b"a\xff", b"", b"a", b"\x00", b"B"];
+    let items: Vec<(&[u8], &[u8])> = keys.iter().map(|k| (*k, b"v".as_slice())).collect();
+    put(h, c, b, &items)?;
+    let mut expected: Vec<Vec<u8>> = keys.iter().map(|k| k.to_vec()).collect();
+    expected.sort();
+    expect("list order", expected, h.list(b, c)?)?;
+    h.drop(b, c)
+}
+
+/// Checks that delete returns the number of deleted keys.
+pub fn check_delete_count<H, C>(h: &H, c: &mut C, b: &Bucket) -> Result<(), Event>
+where
+    H: KvHandlers<C>,
+{
+    h.drop(b, c)?;
+    put(h, c, b, &[(b"k1", b"v"), (b"k2", b"v")])?;
+    expect("delete existing key", 1, h.delete(b, b"k1", c)?)?;
+    expect("delete deleted key", 0, h.delete(b, b"k1", c)?)?;
+    expect("delete missing key", 0, h.delete(b, b"k3", c)?)?;
+    expect("get deleted key", None, get(h, c, b, b"k1")?)?;
+    expect("list after delete", vec![b"k2".to_vec()], h.list(b, c)?)?;
+    h.drop(b, c)
+}
+
+/// Checks that dropping a missing bucket is OK.
+pub fn check_drop_missing<H, C>(h: &H, c: &mut C, b: &Bucket) -> Result<(), Event>
+where
+    H: KvHandlers<C>,
+{
+    h.drop(b, c)?;
+    h.drop(b, c)?;
+    put(h, c, b, &[(b"k", b"v")])?;
+    h.drop(b, c)?;
+    let keys: Vec<Vec<u8>> = or_empty(true, h.list(b, c), vec![])?;
+    expect("list after drop", Vec::<Vec<u8>>::new(), keys)
+}
+
+/// A xorshift64 generator(deterministic for a seed).
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn bytes(&mut self, max_len: u64, alphabet: u8) -> Vec<u8> {
+        let len: u64 = self.below(max_len + 1);
+        (0..len)
+            .map(|_| self.below(alphabet as u64) as u8)
+            .collect()
+    }
+}
+
+/// Treats `BucketNotFound` for a bucket missing in the model as an empty result.
+///
+/// Any other error(and `BucketNotFound` for an existing bucket) fails the check.
+fn or_empty<T>(model_missing: bool, r: Result<T, Event>, empty: T) -> Result<T, Event> {
+    match (model_missing, r) {
+        (true, Err(Event::BucketNotFound(_))) => Ok(empty),
+        (_, r) => r,
+    }
+}
+
+/// Runs random operations on the backend and the model(`MemoryKv`) and compares results.
+///
+/// Keys are short and drawn from a small alphabet so that overwrites and deletes hit.
+///
+/// # Arguments
+/// - h: The handlers to be checked.
+/// - c: The client of the backend.
+/// - b: The bucket used by the check.
+/// - seed: The seed of the operations(a failure can be replayed using the same seed).
+/// - steps: The number of operations.
+pub fn check_model<H, C>(h: &H, c: &mut C, b: &Bucket, seed: u64, steps: u64) -> Result<(), Event>
+where
+    H: KvHandlers<C>,
+{
+    let model_h = kv_handlers_memory();
+    let mut model = MemoryKv::new();
+    let mut rng = XorShift::new(seed);
+    h.drop(b, c)?;
+    for step in 0..steps {
+        let missing: bool = model.bucket(b.as_str()).is_none();
+        let check = |name: &str| format!("seed {} step {}: {}", seed, step, name);
+        match rng.below(10) {
+            0..=3 => {
+                let n: u64 = 1 + rng.below(4);
+                let items: Vec<(Vec<u8>, Vec<u8>)> =
+                    (0..n).map(|_| (rng.bytes(2, 4), rng.bytes(3, 3))).collect();
+                let refs: Vec<(&[u8], &[u8])> = items
+                    .iter()
+                    .map(|(k, v)| (k.as_slice(), v.as_slice()))
+                    .collect();
+                put(&model_h, &mut model, b, &refs)?;
+                put(h, c, b, &refs)?;
+            }
+            4..=5 => {
+                let key: Vec<u8> = rng.bytes(2, 4);
+                let expected = or_empty(missing, get(&model_h, &mut model, b, &key), None)?;
+                let got = or_empty(missing, get(h, c, b, &key), None)?;
+                expect(&check("get"), expected, got)?;
+            }
+            6..=7 => {
+                let key: Vec<u8> = rng.bytes(2, 4);
+                let expected = or_empty(missing, model_h.delete(b, &key, &mut model), 0)?;
+                let got = or_empty(missing, h.delete(b, &key, c), 0)?;
+                expect(&check("delete"), expected, got)?;
+            }
+            8 => {
+                let expected = or_empty(missing, model_h.list(b, &mut model), vec![])?;
+                let got = or_empty(missing, h.list(b, c), vec![])?;
+                expect(&check("list"), expected, got)?;
+            }
+            _ => {
+                if rng.below(4) == 0 {
+                    model_h.drop(b, &mut model)?;
+                    h.drop(b, c)?;
+                }
+            }
+        }
+    }
+    h.drop(b, c)
+}
+
+/// Runs all checks(`check_model` runs 3 seeds of 200 operations).
+pub fn check_all<H, C>(h: &H, c: &mut C, b: &Bucket) -> Result<(), Event>
+where
+    H: KvHandlers<C>,
+{
+    check_upsert_overwrite(h, c, b)?;
+    check_list_order(h, c, b)?;
+    check_delete_count(h, c, b)?;
+    check_drop_missing(h, c, b)?;
+    [0x2545f4914f6cdd1d, 42, 0xcafef00d]
+        .iter()
+        .try_for_each(|seed| check_model(h, c, b, *seed, 200))
+}
+
+#[cfg(test)]
+mod test_conformance {
+
+    mod check_all {
+        use crate::bucket::Bucket;
+        use crate::conformance::{self, kv_handlers_memory, kv_handlers_new, Requests};
+        use crate::del::{delete_key_bytes_mut, drop_bucket_mut};
+        use crate::evt::Event;
+        use crate::get::select_bytes_new_mut;
+        use crate::list::list_keys_bytes_new_mut;
+        use crate::mem::{self, MemoryKv};
+        use crate::upsert::upsert_bytes_all_new_mut;
+
+        fn bucket() -> Bucket {
+            Bucket::from(String::from("conformance"))
+        }
+
+        #[test]
+        fn test_memory() {
+            let h = kv_handlers_memory();
+            let mut kv = MemoryKv::new();
+            conformance::check_all(&h, &mut kv, &bucket()).unwrap();
+            assert_eq!(kv.bucket_names().count(), 0);
+        }
+
+        #[test]
+        fn test_unordered_list() {
+            let list = |kv: &mut MemoryKv, q: &str| {
+                let mut keys: Vec<Vec<u8>> = mem::list_keys(kv, q)?;
+                keys.reverse();
+                Ok(keys)
+            };
+            let upsert = upsert_bytes_all_new_mut(mem::create, mem::upsert, mem::upsert_builder());
+            let h = kv_handlers_new(
+                select_bytes_new_mut(mem::select, mem::builder()),
+                move |r: Requests, kv: &mut MemoryKv| upsert(r, kv),
+                delete_key_bytes_mut(mem::delete, mem::builder()),
+                list_keys_bytes_new_mut(list, mem::builder()),
+                drop_bucket_mut(mem::drop, mem::builder()),
+            );
+            let mut kv = MemoryKv::new();
+            let r = conformance::check_list_order(&h, &mut kv, &bucket());
+            assert!(matches!(r, Err(Event::UnexpectedError(_))));
+        }
+
+        #[test]
+        fn test_first_write_wins() {
+            let upsert = |kv: &mut MemoryKv, q: &str, key: &[u8], val: &[u8]| {
+                let exists: bool = mem::select(kv, q, key)?.is_some();
+                match exists {
+                    true => Ok(0),
+                    false => mem::upsert(kv, q, key, val),
+                }
+            };
+            let upsert = upsert_bytes_all_new_mut(mem::create, upsert, mem::upsert_builder());
+            let h = kv_handlers_new(
+                select_bytes_new_mut(mem::select, mem::builder()),
+                move |r: Requests, kv: &mut MemoryKv| upsert(r, kv),
+                delete_key_bytes_mut(mem::delete, mem::builder()),
+                list_keys_bytes_new_mut(mem::list_keys, mem::builder()),
+                drop_bucket_mut(mem::drop, mem::builder()),
+            );
+            let mut kv = MemoryKv::new();
+            assert!(conformance::check_model(&h, &mut kv, &bucket(), 42, 200).is_err());
+        }
+
+        #[test]
+        fn test_missing_bucket_unexpected() {
+            let select = |kv: &mut MemoryKv, q: &str, key: &[u8]| {
+                mem::select(kv, q, key).map_err(|e| match e {
+                    Event::BucketNotFound(b) => Event::UnexpectedError(b),
+                    e => e,
+                })
+            };
+            let upsert = upsert_bytes_all_new_mut(mem::create, mem::upsert, mem::upsert_builder());
+            let h = kv_handlers_new(
+                select_bytes_new_mut(select, mem::builder()),
+                move |r: Requests, kv: &mut MemoryKv| upsert(r, kv),
+                delete_key_bytes_mut(mem::delete, mem::builder()),
+                list_keys_bytes_new_mut(mem::list_keys, mem::builder()),
+                drop_bucket_mut(mem::drop, mem::builder()),
+            );
+            let mut kv = MemoryKv::new();
+            let r = conformance::check_model(&h, &mut kv, &bucket(), 42, 200);
+            assert!(matches!(r, Err(Event::UnexpectedError(_))));
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! CRC32C(Castagnoli) checksums(table driven, no dependencies).
+//!
+//! Used by value checksums(`checksum`) and binary dumps(`dump`).
+
+const CASTAGNOLI: u32 = 0x82f6_3b78;
+
+const fn table_new() -> [u32; 256] {
+    let mut t: [u32; 256] = [0; 256];
+    let mut i: usize = 0;
+    while i < 256 {
+        let mut c: u32 = i as u32;
+        let mut k: usize = 0;
+        while k < 8 {
+            c = match c & 1 {
+                1 => (c >> 1) ^ CASTAGNOLI,
+                _ => c >> 1,
+            };
+            k += 1;
+        }
+        t[i] = c;
+        i += 1;
+    }
+    t
+}
+
+const TABLE: [u32; 256] = table_new();
+
+/// An incremental CRC32C(Castagnoli) checksum.
+#[derive(Debug, Clone)]
+pub struct Crc32c {
+    state: u32,
+}
+
+impl Default for Crc32c {
+    fn default() -> Self {
+        Self { state: !0 }
+    }
+}
+
+impl Crc32c {
+    /// Creates new checksum state.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds bytes to the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        self.state = data.iter().fold(self.state, |c, b| {
+            TABLE[((c ^ u32::from(*b)) & 0xff) as usize] ^ (c >> 8)
+        });
+    }
+
+    /// Gets the checksum of bytes added so far.
+    pub fn value(&self) -> u32 {
+        !self.state
+    }
+}
+
+/// Computes CRC32C(Castagnoli) checksum of the bytes.
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut c = Crc32c::new();
+    c.update(data);
+    c.value()
+}
+
+#[cfg(test)]
+mod test_crc {
+
+    mod crc32c {
+
+        use crate::crc::{self, Crc32c};
+
+        #[test]
+        fn test_check_value() {
+            assert_eq!(crc::crc32c(b"123456789"), 0xe306_9283);
+            assert_eq!(crc::crc32c(b""), 0);
+        }
+
+        #[test]
+        fn test_incremental() {
+            let mut c = Crc32c::new();
+            c.update(b"1234");
+            c.update(b"56789");
+            assert_eq!(c.value(), crc::crc32c(b"123456789"));
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! Authenticated encryption of values(ChaCha20-Poly1305).
+//!
+//! An encrypted value(envelope) looks like:
+//!
+//! | Offset | Length | Content                          |
+//! |--------|--------|----------------------------------|
+//! | 0      | 1      | version(0x02)                    |
+//! | 1      | 4      | key id(big endian)               |
+//! | 5      | 12     | nonce(random)                    |
+//! | 17     | -      | ciphertext + tag(16 bytes)       |
+//!
+//! The ciphertext is bound to the header(version and key id), the bucket name and the key
+//! (associated data), so a value copied to another key or bucket, or whose header was altered,
+//! can not be decrypted.
+//! Keep old keys in the `Keyring` after rotation until `reencrypt_bucket_new_mut` rewrote
+//! all values under the new key.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use crate::bucket::Bucket;
+use crate::evt::Event;
+use crate::get::GetRequest;
+use crate::item::{Item, RawItem};
+use crate::list::PageRequest;
+use crate::upsert::{BulkRequest, Requests};
+
+/// The version byte of the envelope.
+pub const ENVELOPE_VERSION: u8 = 0x02;
+
+const NONCE_SIZE: usize = 12;
+const HEADER_SIZE: usize = 1 + 4 + NONCE_SIZE;
+
+fn aad(id: u32, bucket: &Bucket, key: &[u8]) -> Vec<u8> {
+    let b: &[u8] = bucket.as_str().as_bytes();
+    let mut v: Vec<u8> = Vec::with_capacity(5 + 4 + b.len() + key.len());
+    v.push(ENVELOPE_VERSION);
+    v.extend_from_slice(&id.to_be_bytes());
+    v.extend_from_slice(&(b.len() as u32).to_be_bytes());
+    v.extend_from_slice(b);
+    v.extend_from_slice(key);
+    v
+}
+
+/// Gets the key id from the envelope.
+pub fn key_id(envelope: &[u8]) -> Option<u32> {
+    match envelope.first() {
+        Some(&ENVELOPE_VERSION) => envelope
+            .get(1..5)
+            .and_then(|s| <[u8; 4]>::try_from(s).ok())
+            .map(u32::from_be_bytes),
+        _ => None,
+    }
+}
+
+/// Encryption keys identified by ids; new values are encrypted using the current key.
+pub struct Keyring {
+    keys: BTreeMap<u32, ChaCha20Poly1305>,
+    current: u32,
+}
+
+impl Keyring {
+    /// Creates new keyring which uses the key as the current key.
+    pub fn new(id: u32, key: [u8; 32]) -> Self {
+        let mut keys = BTreeMap::new();
+        keys.insert(id, ChaCha20Poly1305::new(Key::from_slice(&key)));
+        Self { keys, current: id }
+    }
+
+    /// Adds the key(e.g, an old key to decrypt values not re-encrypted yet).
+    pub fn with_key(mut self, id: u32, key: [u8; 32]) -> Self {
+        self.keys
+            .insert(id, ChaCha20Poly1305::new(Key::from_slice(&key)));
+        self
+    }
+
+    /// Adds the key and uses it as the current key.
+    pub fn rotate(self, id: u32, key: [u8; 32]) -> Self {
+        let mut k: Self = self.with_key(id, key);
+        k.current = id;
+        k
+    }
+
+    /// Gets the id of the current key.
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    /// Encrypts the value of the key in the bucket using the current key.
+    pub fn encrypt(&self, bucket: &Bucket, key: &[u8], val: &[u8]) -> Result<Vec<u8>, Event> {
+        let cipher: &ChaCha20Poly1305 = self
+            .keys
+            .get(&self.current)
+            .ok_or_else(|| Event::UnexpectedError(String::from("No current key")))?;
+        let nonce: Nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad: Vec<u8> = aad(self.current, bucket, key);
+        let sealed: Vec<u8> = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: val,
+                    aad: &aad,
+                },
+            )
+            .map_err(|e| Event::UnexpectedError(format!("Unable to encrypt: {}", e)))?;
+        let mut envelope: Vec<u8> = Vec::with_capacity(HEADER_SIZE + sealed.len());
+        envelope.push(ENVELOPE_VERSION);
+        envelope.extend_from_slice(&self.current.to_be_bytes());
+        envelope.extend_from_slice(&nonce);
+        envelope.extend_from_slice(&sealed);
+        Ok(envelope)
+    }
+
+    /// Decrypts the envelope of the key in the bucket.
+    pub fn decrypt(&self, bucket: &Bucket, key: &[u8], envelope: &[u8]) -> Result<Vec<u8>, Event> {
+        let id: u32 = key_id(envelope)
+            .filter(|_| HEADER_SIZE <= envelope.len())
+            .ok_or_else(|| Event::UnexpectedError(String::from("Invalid envelope")))?;
+        let cipher: &ChaCha20Poly1305 = self
+            .keys
+            .get(&id)
+            .ok_or_else(|| Event::UnexpectedError(format!("Unknown key id: {}", id)))?;
+        let nonce: &Nonce = Nonce::from_slice(&envelope[5..HEADER_SIZE]);
+        let aad: Vec<u8> = aad(id, bucket, key);
+        let msg: &[u8] = &envelope[HEADER_SIZE..];
+        cipher
+            .decrypt(nonce, Payload { msg, aad: &aad })
+            .map_err(|e| Event::UnexpectedError(format!("Unable to decrypt: {}", e)))
+    }
+
+    fn encrypt_request(
+        &self,
+        req: BulkRequest<Vec<u8>, Vec<u8>>,
+    ) -> Result<BulkRequest<Vec<u8>, Vec<u8>>, Event> {
+        let b: Bucket = Bucket::from(String::from(req.as_bucket().as_str()));
+        let items: Vec<RawItem> = req
+            .as_items()
+            .iter()
+            .map(|i| {
+                let sealed: Vec<u8> = self.encrypt(&b, i.as_key(), i.as_val())?;
+                Ok(Item::new(i.as_key().clone(), sealed))
+            })
+            .collect::<Result<_, Event>>()?;
+        Ok(BulkRequest::new(b, items))
+    }
+}
+
+/// Creates upsert requests handler which encrypts values.
+///
+/// # Arguments
+/// - keyring: Encrypts values using the current key.
+/// - upsert: The handler(e.g, `upsert_bytes_all_new_mut`).
+pub fn upsert_encrypted_new<U, I, T>(
+    keyring: Arc<Keyring>,
+    upsert: U,
+) -> impl Fn(I, &mut T) -> Result<u64, Event>
+where
+    U: Fn(Requests, &mut T) -> Result<u64, Event>,
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    move |requests: I, transaction: &mut T| {
+        let encrypted: Vec<BulkRequest<Vec<u8>, Vec<u8>>> = requests
+            .map(|req| keyring.encrypt_request(req))
+            .collect::<Result<_, Event>>()?;
+        upsert(encrypted.into_iter(), transaction)
+    }
+}
+
+/// Creates select request handler which decrypts the selected value.
+///
+/// # Arguments
+/// - keyring: Decrypts values using the key in the envelope.
+/// - select: The handler(e.g, `select_bytes_new_mut`).
+pub fn select_decrypted_new<S, C>(
+    keyring: Arc<Keyring>,
+    select: S,
+) -> impl Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>
+where
+    S: Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>,
+{
+    move |req: &GetRequest<Vec<u8>>, client: &mut C| {
+        let sealed: Option<Vec<u8>> = select(req, client)?;
+        sealed
+            .map(|s| keyring.decrypt(req.as_bucket(), req.as_key(), &s))
+            .transpose()
+    }
+}
+
+/// Creates new re-encrypter which rewrites values not encrypted by the current key
+/// (or in an older envelope version).
+///
+/// Each value is replaced by compare-and-set on the envelope read: a value written
+/// concurrently(after the page was read) is kept as is and is not counted, so no transaction or
+/// lock is required.
+///
+/// Returns the number of rewritten values.
+///
+/// # Arguments
+/// - keyring: Decrypts values and encrypts them using the current key.
+/// - page: Gets a page(see `list_items_page_new_mut`).
+/// - swap: Replaces a value if it still equals the envelope read(see `swap_bytes_new_mut`).
+/// - page_size: The number of items per page.
+pub fn reencrypt_bucket_new_mut<P, S, C>(
+    keyring: Arc<Keyring>,
+    page: P,
+    swap: S,
+    page_size: u64,
+) -> impl Fn(&Bucket, &mut C) -> Result<u64, Event>
+where
+    P: Fn(&PageRequest, &mut C) -> Result<Vec<RawItem>, Event>,
+    S: Fn(&Bucket, &RawItem, &[u8], &mut C) -> Result<u64, Event>,
+{
+    let sz: u64 = page_size.max(1);
+    move |b: &Bucket, client: &mut C| {
+        let mut after: Option<Vec<u8>> = None;
+        let mut cnt: u64 = 0;
+        loop {
+            let req = PageRequest::new(Bucket::from(String::from(b.as_str())), after, sz);
+            let items: Vec<RawItem> = page(&req, client)?;
+            let full: bool = items.len() as u64 == sz;
+            after = items.last().map(|i| i.as_key().clone());
+            let current = |v: &[u8]| key_id(v) == Some(keyring.current());
+            for i in items.iter().filter(|i| !current(i.as_val())) {
+                let plain: Vec<u8> = keyring.decrypt(b, i.as_key(), i.as_val())?;
+                let sealed: Vec<u8> = keyring.encrypt(b, i.as_key(), &plain)?;
+                let item: RawItem = Item::new(i.as_key().clone(), sealed);
+                cnt += swap(b, &item, i.as_val(), client)?;
+            }
+            if !full || after.is_none() {
+                return Ok(cnt);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_crypt {
+
+    mod keyring {
+
+        use crate::bucket::Bucket;
+        use crate::crypt::{self, Keyring};
+
+        #[test]
+        fn test_bound() {
+            let k: Keyring = Keyring::new(1, [7; 32]);
+            let b: Bucket = Bucket::from(String::from("users"));
+            let sealed: Vec<u8> = k.encrypt(&b, b"alice", b"pii").unwrap();
+            assert_eq!(crypt::key_id(&sealed), Some(1));
+            assert_eq!(k.decrypt(&b, b"alice", &sealed).unwrap(), b"pii".to_vec());
+            assert!(k.decrypt(&b, b"bob", &sealed).is_err());
+            let other: Bucket = Bucket::from(String::from("users2"));
+            assert!(k.decrypt(&other, b"alice", &sealed).is_err());
+            assert!(k.decrypt(&b, b"alice", &sealed[..10]).is_err());
+        }
+
+        #[test]
+        fn test_header_bound() {
+            let k: Keyring = Keyring::new(1, [7; 32]).with_key(2, [7; 32]);
+            let b: Bucket = Bucket::from(String::from("users"));
+            let sealed: Vec<u8> = k.encrypt(&b, b"alice", b"pii").unwrap();
+
+            let mut other_id: Vec<u8> = sealed.clone();
+            other_id[1..5].copy_from_slice(&2u32.to_be_bytes());
+            assert!(k.decrypt(&b, b"alice", &other_id).is_err());
+        }
+    }
+
+    mod reencrypt_bucket_new_mut {
+
+        use std::sync::Arc;
+
+        use crate::bucket::Bucket;
+        use crate::crypt::{self, Keyring};
+        use crate::get::{select_bytes_new_mut, GetRequest};
+        use crate::item::{Item, RawItem};
+        use crate::list::list_items_page_new_mut;
+        use crate::mem::{self, MemoryKv};
+        use crate::upsert::{swap_bytes_new_mut, upsert_bytes_all_new_mut, BulkRequest};
+
+        fn bucket() -> Bucket {
+            Bucket::from(String::from("users"))
+        }
+
+        fn seed(kv: &mut MemoryKv, keyring: &Arc<Keyring>) {
+            let put = crypt::upsert_encrypted_new(
+                keyring.clone(),
+                upsert_bytes_all_new_mut(mem::create, mem::upsert, mem::upsert_builder()),
+            );
+            let items = (0..5)
+                .map(|i| Item::new(vec![i], format!("v{}", i).into_bytes()))
+                .collect();
+            put(vec![BulkRequest::new(bucket(), items)].into_iter(), kv).unwrap();
+        }
+
+        #[test]
+        fn test_rotate() {
+            let mut kv = MemoryKv::new();
+            seed(&mut kv, &Arc::new(Keyring::new(1, [1; 32])));
+
+            let new: Arc<Keyring> = Arc::new(Keyring::new(1, [1; 32]).rotate(2, [2; 32]));
+            let f = crypt::reencrypt_bucket_new_mut(
+                new.clone(),
+                list_items_page_new_mut(mem::page, mem::page_builder()),
+                swap_bytes_new_mut(mem::swap, mem::builder()),
+                2,
+            );
+            assert_eq!(f(&bucket(), &mut kv).unwrap(), 5);
+            assert_eq!(f(&bucket(), &mut kv).unwrap(), 0);
+
+            let get = crypt::select_decrypted_new(
+                Arc::new(Keyring::new(2, [2; 32])),
+                select_bytes_new_mut(mem::select, mem::builder()),
+            );
+            let req = GetRequest::new(bucket(), vec![3]);
+            assert_eq!(get(&req, &mut kv).unwrap(), Some(b"v3".to_vec()));
+        }
+
+        #[test]
+        fn test_concurrent_write() {
+            let mut kv = MemoryKv::new();
+            seed(&mut kv, &Arc::new(Keyring::new(1, [1; 32])));
+
+            let new: Arc<Keyring> = Arc::new(Keyring::new(1, [1; 32]).rotate(2, [2; 32]));
+            let writer = new.clone();
+            let swap = swap_bytes_new_mut(mem::swap, mem::builder());
+            let f = crypt::reencrypt_bucket_new_mut(
+                new.clone(),
+                list_items_page_new_mut(mem::page, mem::page_builder()),
+                |b: &Bucket, item: &RawItem, old: &[u8], kv: &mut MemoryKv| {
+                    if item.as_key() == &[3] {
+                        let written: Vec<u8> = writer.encrypt(b, item.as_key(), b"written")?;
+                        mem::upsert(kv, b.as_str(), item.as_key(), &written)?;
+                    }
+                    swap(b, item, old, kv)
+                },
+                10,
+            );
+            assert_eq!(f(&bucket(), &mut kv).unwrap(), 4);
+
+            let get =
+                crypt::select_decrypted_new(new, select_bytes_new_mut(mem::select, mem::builder()));
+            let req = GetRequest::new(bucket(), vec![3]);
+            assert_eq!(get(&req, &mut kv).unwrap(), Some(b"written".to_vec()));
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::bucket::{bucket_checker_new_unchecked, Bucket};
 use crate::evt::Event;
+use crate::trace::{self, Op};
 
 /// Creates new remover which uses closures to delete rows and build delete query string.
 ///
@@ -15,8 +16,15 @@ where
     B: Fn(&Bucket) -> Result<String, Event>,
 {
     move |b: &Bucket, key: &[u8], client: &mut C| {
-        let query: String = builder(b)?;
-        delete(client, query.as_str(), key)
+        let op: Op = Op::new("delete", b).with_key(key);
+        trace::instrumented(
+            op,
+            || {
+                let query: String = builder(b)?;
+                delete(client, query.as_str(), key)
+            },
+            |cnt| *cnt,
+        )
     }
 }
 
@@ -34,8 +42,15 @@ where
     B: Fn(&Bucket) -> Result<String, Event>,
 {
     move |b: &Bucket, client: &mut C| {
-        let query: String = builder(b)?;
-        remove(client, query.as_str())
+        let op: Op = Op::new("drop", b);
+        trace::instrumented(
+            op,
+            || {
+                let query: String = builder(b)?;
+                remove(client, query.as_str())
+            },
+            |_| 0,
+        )
     }
 }
 
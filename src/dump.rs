@@ -0,0 +1,792 @@
+use std::io::{BufRead, BufReader, Read, Write};
+
+use crate::bucket::Bucket;
+use crate::crc::Crc32c;
+use crate::evt::Event;
+use crate::item::{Item, RawItem};
+use crate::list::{visit_items_mut, PageRequest};
+use crate::upsert::BulkRequest;
+
+/// Portable formats of a bucket dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// One JSON object per line.
+    ///
+    /// - header: `{"format":"rdb2kv","version":1,"bucket":"<name>"}`
+    /// - item: `{"key":"<base64>","val":"<base64>"}`
+    /// - trailer: `{"count":<number of items>}`
+    ///
+    /// Keys/values use the standard base64 alphabet with padding.
+    /// A dump without the trailer(e.g, cut off at a line boundary) is rejected as truncated.
+    Ndjson,
+
+    /// Length-prefixed binary format(integers are big endian).
+    ///
+    /// - header: magic `RDB2KV`, version(u8 = 1), reserved(u8 = 0), name length(u32), name
+    /// - item: tag(u8 = 1), key length(u32), key, val length(u32), val
+    /// - trailer: tag(u8 = 0), item count(u64), CRC32C(u32) of all preceding bytes
+    Binary,
+}
+
+const MAGIC: &[u8; 6] = b"RDB2KV";
+const VERSION: u8 = 1;
+const TAG_ITEM: u8 = 1;
+const TAG_END: u8 = 0;
+
+/// Initial buffer capacity of a sized field(lengths in a dump are not trusted).
+const DUMP_READ_CHUNK: usize = 64 * 1024;
+
+/// The maximum length of a line of an NDJSON dump(in bytes).
+const NDJSON_LINE_MAX: u64 = 64 * 1024 * 1024;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes using the standard base64 alphabet with padding.
+pub fn base64_encode(data: &[u8]) -> String {
+    let mapd = data.chunks(3).flat_map(|c: &[u8]| {
+        let n: u32 = c
+            .iter()
+            .enumerate()
+            .fold(0, |n, (i, b)| n | (u32::from(*b) << (16 - 8 * i)));
+        (0..4).map(move |i: usize| match i <= c.len() {
+            true => char::from(BASE64[((n >> (18 - 6 * i)) & 0x3f) as usize]),
+            false => '=',
+        })
+    });
+    mapd.collect()
+}
+
+fn base64_value(c: u8) -> Result<u32, Event> {
+    match c {
+        b'A'..=b'Z' => Ok(u32::from(c - b'A')),
+        b'a'..=b'z' => Ok(u32::from(c - b'a') + 26),
+        b'0'..=b'9' => Ok(u32::from(c - b'0') + 52),
+        b'+' => Ok(62),
+        b'/' => Ok(63),
+        _ => Err(Event::UnexpectedError(format!(
+            "Invalid base64 character: {}",
+            char::from(c)
+        ))),
+    }
+}
+
+/// Decodes base64 string(standard alphabet with padding).
+pub fn base64_decode(s: &str) -> Result<Vec<u8>, Event> {
+    let b: &[u8] = s.as_bytes();
+    if !b.len().is_multiple_of(4) {
+        return Err(Event::UnexpectedError(format!(
+            "Invalid base64 length: {}",
+            b.len()
+        )));
+    }
+    let pad: usize = b.iter().rev().take(2).filter(|c| **c == b'=').count();
+    let mut out: Vec<u8> = Vec::with_capacity(b.len() / 4 * 3);
+    for (i, chunk) in b.chunks(4).enumerate() {
+        let last: bool = (i + 1) * 4 == b.len();
+        let valid: usize = match last {
+            true => 4 - pad,
+            false => 4,
+        };
+        let n: u32 = chunk[..valid].iter().enumerate().try_fold(0, |n, (j, c)| {
+            base64_value(*c).map(|v| n | (v << (18 - 6 * j)))
+        })?;
+        let bytes: [u8; 4] = n.to_be_bytes();
+        out.extend_from_slice(&bytes[1..valid]);
+    }
+    Ok(out)
+}
+
+fn json_string(s: &str) -> String {
+    let escaped = s.chars().map(|c: char| match c {
+        '"' => String::from("\\\""),
+        '\\' => String::from("\\\\"),
+        c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32),
+        c => String::from(c),
+    });
+    format!("\"{}\"", escaped.collect::<String>())
+}
+
+fn json_invalid(line: &str) -> Event {
+    Event::UnexpectedError(format!("Invalid dump line: {}", line))
+}
+
+fn json_parse_string<I>(chars: &mut std::iter::Peekable<I>, line: &str) -> Result<String, Event>
+where
+    I: Iterator<Item = char>,
+{
+    let mut s = String::new();
+    loop {
+        match chars.next().ok_or_else(|| json_invalid(line))? {
+            '"' => return Ok(s),
+            '\\' => match chars.next().ok_or_else(|| json_invalid(line))? {
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let c: char = u32::from_str_radix(hex.as_str(), 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| json_invalid(line))?;
+                    s.push(c);
+                }
+                'n' => s.push('\n'),
+                't' => s.push('\t'),
+                'r' => s.push('\r'),
+                'b' => s.push('\u{8}'),
+                'f' => s.push('\u{c}'),
+                c @ ('"' | '\\' | '/') => s.push(c),
+                _ => return Err(json_invalid(line)),
+            },
+            c => s.push(c),
+        }
+    }
+}
+
+/// Gets the next char outside of strings, skipping whitespace.
+fn json_next_token<I>(chars: &mut std::iter::Peekable<I>) -> Option<char>
+where
+    I: Iterator<Item = char>,
+{
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    chars.next()
+}
+
+/// Parses a flat JSON object whose values are strings or numbers.
+///
+/// Whitespace is skipped between tokens only; string values are kept as is.
+fn json_fields(line: &str) -> Result<Vec<(String, String)>, Event> {
+    let mut chars = line.chars().peekable();
+    let mut fields: Vec<(String, String)> = vec![];
+    if json_next_token(&mut chars) != Some('{') {
+        return Err(json_invalid(line));
+    }
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    if chars.next_if_eq(&'}').is_some() {
+        return Ok(fields);
+    }
+    loop {
+        if json_next_token(&mut chars) != Some('"') {
+            return Err(json_invalid(line));
+        }
+        let name: String = json_parse_string(&mut chars, line)?;
+        if json_next_token(&mut chars) != Some(':') {
+            return Err(json_invalid(line));
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let val: String = match chars.peek() {
+            Some('"') => {
+                chars.next();
+                json_parse_string(&mut chars, line)?
+            }
+            _ => {
+                let mut v = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '-') {
+                    v.push(c);
+                }
+                v
+            }
+        };
+        fields.push((name, val));
+        match json_next_token(&mut chars) {
+            Some(',') => continue,
+            Some('}') => return Ok(fields),
+            _ => return Err(json_invalid(line)),
+        }
+    }
+}
+
+fn json_field<'a>(
+    fields: &'a [(String, String)],
+    name: &str,
+    line: &str,
+) -> Result<&'a str, Event> {
+    fields
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.as_str())
+        .ok_or_else(|| json_invalid(line))
+}
+
+fn io_error(e: std::io::Error) -> Event {
+    Event::UnexpectedError(format!("Unable to read/write a dump: {}", e))
+}
+
+/// Writes key/value pairs of a bucket in a dump format.
+pub struct DumpWriter<W> {
+    format: DumpFormat,
+    w: W,
+    crc: Crc32c,
+    count: u64,
+}
+
+impl<W> DumpWriter<W>
+where
+    W: Write,
+{
+    /// Creates new writer and writes the header.
+    ///
+    /// # Arguments
+    /// - format: The dump format.
+    /// - w: The destination.
+    /// - b: The bucket which is recorded in the header.
+    pub fn new(format: DumpFormat, w: W, b: &Bucket) -> Result<Self, Event> {
+        let mut dw = Self {
+            format,
+            w,
+            crc: Crc32c::new(),
+            count: 0,
+        };
+        match format {
+            DumpFormat::Ndjson => {
+                let header: String = format!(
+                    "{{\"format\":\"rdb2kv\",\"version\":{},\"bucket\":{}}}\n",
+                    VERSION,
+                    json_string(b.as_str()),
+                );
+                dw.put(header.as_bytes())?;
+            }
+            DumpFormat::Binary => {
+                dw.put(MAGIC)?;
+                dw.put(&[VERSION, 0])?;
+                dw.put_sized(b.as_str().as_bytes())?;
+            }
+        }
+        Ok(dw)
+    }
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Event> {
+        self.crc.update(data);
+        self.w.write_all(data).map_err(io_error)
+    }
+
+    fn put_sized(&mut self, data: &[u8]) -> Result<(), Event> {
+        let sz: u32 = u32::try_from(data.len())
+            .map_err(|_| Event::UnexpectedError(format!("Too large to dump: {}", data.len())))?;
+        self.put(&sz.to_be_bytes())?;
+        self.put(data)
+    }
+
+    /// Writes a key/value pair.
+    pub fn write_item(&mut self, key: &[u8], val: &[u8]) -> Result<(), Event> {
+        match self.format {
+            DumpFormat::Ndjson => {
+                let line: String = format!(
+                    "{{\"key\":\"{}\",\"val\":\"{}\"}}\n",
+                    base64_encode(key),
+                    base64_encode(val),
+                );
+                self.put(line.as_bytes())?;
+            }
+            DumpFormat::Binary => {
+                self.put(&[TAG_ITEM])?;
+                self.put_sized(key)?;
+                self.put_sized(val)?;
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Gets the number of items written so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Writes the trailer, flushes and returns the destination.
+    pub fn finish(mut self) -> Result<W, Event> {
+        match self.format {
+            DumpFormat::Ndjson => {
+                let trailer: String = format!("{{\"count\":{}}}\n", self.count);
+                self.put(trailer.as_bytes())?;
+            }
+            DumpFormat::Binary => {
+                self.put(&[TAG_END])?;
+                self.put(&self.count.to_be_bytes())?;
+                let sum: u32 = self.crc.value();
+                self.w.write_all(&sum.to_be_bytes()).map_err(io_error)?;
+            }
+        }
+        self.w.flush().map_err(io_error)?;
+        Ok(self.w)
+    }
+}
+
+/// Reads key/value pairs from a dump.
+pub struct DumpReader<R> {
+    format: DumpFormat,
+    r: BufReader<R>,
+    bucket: String,
+    crc: Crc32c,
+    count: u64,
+    done: bool,
+}
+
+impl<R> DumpReader<R>
+where
+    R: Read,
+{
+    /// Creates new reader and reads the header.
+    pub fn new(format: DumpFormat, r: R) -> Result<Self, Event> {
+        let mut dr = Self {
+            format,
+            r: BufReader::new(r),
+            bucket: String::new(),
+            crc: Crc32c::new(),
+            count: 0,
+            done: false,
+        };
+        dr.bucket = match format {
+            DumpFormat::Ndjson => {
+                let line: String = dr
+                    .next_line()?
+                    .ok_or_else(|| Event::UnexpectedError(String::from("Empty dump")))?;
+                let fields = json_fields(line.as_str())?;
+                let version: &str = json_field(&fields, "version", line.as_str())?;
+                match (json_field(&fields, "format", line.as_str())?, version) {
+                    ("rdb2kv", "1") => {}
+                    _ => return Err(json_invalid(line.as_str())),
+                }
+                String::from(json_field(&fields, "bucket", line.as_str())?)
+            }
+            DumpFormat::Binary => {
+                let head: Vec<u8> = dr.take_bytes(8)?;
+                match (&head[..6], head[6]) {
+                    (m, VERSION) if m == MAGIC => {}
+                    _ => return Err(Event::UnexpectedError(String::from("Not a binary dump"))),
+                }
+                let name: Vec<u8> = dr.take_sized()?;
+                String::from_utf8(name).map_err(|e| {
+                    Event::UnexpectedError(format!("Invalid bucket name in a dump: {}", e))
+                })?
+            }
+        };
+        Ok(dr)
+    }
+
+    /// Gets the bucket name recorded in the header.
+    pub fn bucket(&self) -> &str {
+        self.bucket.as_str()
+    }
+
+    /// Reads the next non-blank line(up to `NDJSON_LINE_MAX` bytes).
+    fn next_line(&mut self) -> Result<Option<String>, Event> {
+        loop {
+            let mut line = String::new();
+            let sz: usize = self
+                .r
+                .by_ref()
+                .take(NDJSON_LINE_MAX + 1)
+                .read_line(&mut line)
+                .map_err(io_error)?;
+            if NDJSON_LINE_MAX < sz as u64 {
+                return Err(Event::UnexpectedError(format!(
+                    "Too long line in a dump: more than {} bytes",
+                    NDJSON_LINE_MAX
+                )));
+            }
+            match (sz, line.trim()) {
+                (0, _) => return Ok(None),
+                (_, "") => continue,
+                (_, l) => return Ok(Some(String::from(l))),
+            }
+        }
+    }
+
+    /// Reads exactly `sz` bytes; the buffer grows as bytes arrive(`sz` is untrusted).
+    fn take_bytes(&mut self, sz: usize) -> Result<Vec<u8>, Event> {
+        let mut buf: Vec<u8> = Vec::with_capacity(sz.min(DUMP_READ_CHUNK));
+        let got: usize = self
+            .r
+            .by_ref()
+            .take(sz as u64)
+            .read_to_end(&mut buf)
+            .map_err(io_error)?;
+        if got != sz {
+            return Err(Event::UnexpectedError(format!(
+                "Truncated dump: expected {} bytes, got {}",
+                sz, got
+            )));
+        }
+        self.crc.update(&buf);
+        Ok(buf)
+    }
+
+    fn take_u32(&mut self) -> Result<u32, Event> {
+        let b: Vec<u8> = self.take_bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn take_sized(&mut self) -> Result<Vec<u8>, Event> {
+        let sz: u32 = self.take_u32()?;
+        self.take_bytes(sz as usize)
+    }
+
+    fn read_binary(&mut self) -> Result<Option<RawItem>, Event> {
+        let tag: Vec<u8> = self.take_bytes(1)?;
+        match tag[0] {
+            TAG_ITEM => {
+                let key: Vec<u8> = self.take_sized()?;
+                let val: Vec<u8> = self.take_sized()?;
+                Ok(Some(Item::new(key, val)))
+            }
+            TAG_END => {
+                let cnt: Vec<u8> = self.take_bytes(8)?;
+                let expected: u32 = self.crc.value();
+                let actual: u32 = self.take_u32()?;
+                let cnt: u64 = u64::from_be_bytes(cnt.try_into().unwrap_or_default());
+                match (cnt == self.count, expected == actual) {
+                    (true, true) => Ok(None),
+                    (false, _) => Err(Event::UnexpectedError(format!(
+                        "Item count mismatch: expected {}, got {}",
+                        cnt, self.count
+                    ))),
+                    (_, false) => Err(Event::UnexpectedError(String::from(
+                        "Dump checksum mismatch",
+                    ))),
+                }
+            }
+            t => Err(Event::UnexpectedError(format!("Unknown dump tag: {}", t))),
+        }
+    }
+
+    fn read_ndjson(&mut self) -> Result<Option<RawItem>, Event> {
+        let line: String = self.next_line()?.ok_or_else(|| {
+            Event::UnexpectedError(String::from("Truncated dump: missing trailer"))
+        })?;
+        let fields = json_fields(line.as_str())?;
+        let cnt: &str = match json_field(&fields, "count", line.as_str()) {
+            Ok(cnt) => cnt,
+            Err(_) => {
+                let key: Vec<u8> = base64_decode(json_field(&fields, "key", line.as_str())?)?;
+                let val: Vec<u8> = base64_decode(json_field(&fields, "val", line.as_str())?)?;
+                return Ok(Some(Item::new(key, val)));
+            }
+        };
+        let cnt: u64 = cnt.parse().map_err(|_| json_invalid(line.as_str()))?;
+        if cnt != self.count {
+            return Err(Event::UnexpectedError(format!(
+                "Item count mismatch: expected {}, got {}",
+                cnt, self.count
+            )));
+        }
+        match self.next_line()? {
+            None => Ok(None),
+            Some(_) => Err(Event::UnexpectedError(String::from(
+                "Unexpected data after the dump trailer",
+            ))),
+        }
+    }
+
+    /// Reads the next key/value pair(`None` at the end of the dump).
+    pub fn read_item(&mut self) -> Result<Option<RawItem>, Event> {
+        if self.done {
+            return Ok(None);
+        }
+        let o: Option<RawItem> = match self.format {
+            DumpFormat::Ndjson => self.read_ndjson(),
+            DumpFormat::Binary => self.read_binary(),
+        }?;
+        match o.is_some() {
+            true => self.count += 1,
+            false => self.done = true,
+        }
+        Ok(o)
+    }
+}
+
+impl<R> Iterator for DumpReader<R>
+where
+    R: Read,
+{
+    type Item = Result<RawItem, Event>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_item() {
+            Ok(o) => o.map(Ok),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Creates new bucket exporter which writes all items of a bucket in the key order.
+///
+/// Returns the number of exported items.
+///
+/// # Arguments
+/// - page: Gets a page of items(see `list_items_page_new_mut`).
+/// - page_size: The number of items per page.
+pub fn export_bucket_new_mut<P, C, W>(
+    page: P,
+    page_size: u64,
+) -> impl Fn(&Bucket, &mut C, &mut DumpWriter<W>) -> Result<u64, Event>
+where
+    P: Fn(&PageRequest, &mut C) -> Result<Vec<RawItem>, Event>,
+    W: Write,
+{
+    move |b: &Bucket, client: &mut C, w: &mut DumpWriter<W>| {
+        visit_items_mut(&page, b, None, page_size, client, |item: RawItem| {
+            w.write_item(item.as_key(), item.as_val())
+        })
+    }
+}
+
+/// Creates new bucket importer which upserts items read from a dump in batches.
+///
+/// Returns the sum of counts returned by the upsert handler.
+///
+/// # Arguments
+/// - upsert: Upserts requests(see `upsert_bytes_all_new_mut`).
+/// - batch_size: The number of items per request.
+pub fn import_bucket_new_mut<U, T, R>(
+    upsert: U,
+    batch_size: usize,
+) -> impl Fn(&Bucket, &mut DumpReader<R>, &mut T) -> Result<u64, Event>
+where
+    U: Fn(std::vec::IntoIter<BulkRequest<Vec<u8>, Vec<u8>>>, &mut T) -> Result<u64, Event>,
+    R: Read,
+{
+    let sz: usize = batch_size.max(1);
+    move |b: &Bucket, r: &mut DumpReader<R>, transaction: &mut T| {
+        let mut tot: u64 = 0;
+        loop {
+            let items: Vec<RawItem> = r.by_ref().take(sz).collect::<Result<_, _>>()?;
+            if items.is_empty() {
+                return Ok(tot);
+            }
+            let req = BulkRequest::new(Bucket::from(String::from(b.as_str())), items);
+            tot += upsert(vec![req].into_iter(), transaction)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_dump {
+
+    mod base64 {
+
+        use crate::dump::{base64_decode, base64_encode};
+
+        #[test]
+        fn test_vectors() {
+            let vectors = [
+                ("", ""),
+                ("f", "Zg=="),
+                ("fo", "Zm8="),
+                ("foo", "Zm9v"),
+                ("foob", "Zm9vYg=="),
+                ("fooba", "Zm9vYmE="),
+                ("foobar", "Zm9vYmFy"),
+            ];
+            for (raw, encoded) in vectors {
+                assert_eq!(base64_encode(raw.as_bytes()), encoded);
+                assert_eq!(base64_decode(encoded).unwrap(), raw.as_bytes());
+            }
+        }
+
+        #[test]
+        fn test_invalid() {
+            assert!(base64_decode("Zm9").is_err());
+            assert!(base64_decode("Zm9*").is_err());
+        }
+    }
+
+    mod json_fields {
+
+        use crate::dump::json_fields;
+
+        #[test]
+        fn test_whitespace() {
+            let got = json_fields(r#" { "bucket" : "a b\tc" , "version" : 1 } "#).unwrap();
+            assert_eq!(
+                got,
+                vec![
+                    (String::from("bucket"), String::from("a b\tc")),
+                    (String::from("version"), String::from("1")),
+                ]
+            );
+            assert_eq!(json_fields("{ }").unwrap(), vec![]);
+        }
+
+        #[test]
+        fn test_escapes() {
+            let got = json_fields(r#"{"s":"\b\f\n\r\t\"\\\/\u0041"}"#).unwrap();
+            assert_eq!(got[0].1, "\u{8}\u{c}\n\r\t\"\\/A");
+            assert!(json_fields(r#"{"s":"\x"}"#).is_err());
+        }
+    }
+
+    mod dump_writer {
+
+        use crate::bucket::Bucket;
+        use crate::dump::{DumpFormat, DumpReader, DumpWriter, NDJSON_LINE_MAX};
+        use crate::item::RawItem;
+
+        fn roundtrip(format: DumpFormat) -> Vec<u8> {
+            let b = Bucket::from(String::from("devices"));
+            let mut w = DumpWriter::new(format, vec![], &b).unwrap();
+            w.write_item(b"k1", b"").unwrap();
+            w.write_item(b"k2", &[0x00, 0xff]).unwrap();
+            let dumped: Vec<u8> = w.finish().unwrap();
+
+            let r = DumpReader::new(format, dumped.as_slice()).unwrap();
+            assert_eq!(r.bucket(), "devices");
+            let items: Vec<RawItem> = r.collect::<Result<_, _>>().unwrap();
+            let pairs: Vec<(Vec<u8>, Vec<u8>)> = items.into_iter().map(|i| i.into_pair()).collect();
+            assert_eq!(
+                pairs,
+                vec![(b"k1".to_vec(), vec![]), (b"k2".to_vec(), vec![0x00, 0xff]),]
+            );
+            dumped
+        }
+
+        #[test]
+        fn test_ndjson() {
+            let dumped: Vec<u8> = roundtrip(DumpFormat::Ndjson);
+            let s: String = String::from_utf8(dumped).unwrap();
+            assert_eq!(
+                s,
+                concat!(
+                    "{\"format\":\"rdb2kv\",\"version\":1,\"bucket\":\"devices\"}\n",
+                    "{\"key\":\"azE=\",\"val\":\"\"}\n",
+                    "{\"key\":\"azI=\",\"val\":\"AP8=\"}\n",
+                    "{\"count\":2}\n",
+                ),
+            );
+        }
+
+        #[test]
+        fn test_ndjson_truncated() {
+            let dumped: Vec<u8> = roundtrip(DumpFormat::Ndjson);
+            let s: &str = std::str::from_utf8(&dumped).unwrap();
+            let cut: &str = s.trim_end_matches("{\"count\":2}\n");
+            let r = DumpReader::new(DumpFormat::Ndjson, cut.as_bytes()).unwrap();
+            assert!(r.collect::<Result<Vec<_>, _>>().is_err());
+
+            let miscounted: String = s.replace("\"count\":2", "\"count\":3");
+            let r = DumpReader::new(DumpFormat::Ndjson, miscounted.as_bytes()).unwrap();
+            assert!(r.collect::<Result<Vec<_>, _>>().is_err());
+        }
+
+        #[test]
+        fn test_ndjson_blank_lines() {
+            let b = Bucket::from(String::from("devices"));
+            let mut dumped: Vec<u8> = DumpWriter::new(DumpFormat::Ndjson, vec![], &b)
+                .unwrap()
+                .finish()
+                .unwrap();
+            dumped.splice(0..0, std::iter::repeat_n(b'\n', 1_000_000));
+            let r = DumpReader::new(DumpFormat::Ndjson, dumped.as_slice()).unwrap();
+            assert_eq!(r.collect::<Result<Vec<_>, _>>().unwrap().len(), 0);
+        }
+
+        #[test]
+        fn test_ndjson_long_line() {
+            let line: Vec<u8> = vec![b'{'; NDJSON_LINE_MAX as usize + 1];
+            assert!(DumpReader::new(DumpFormat::Ndjson, line.as_slice()).is_err());
+        }
+
+        #[test]
+        fn test_binary() {
+            let mut dumped: Vec<u8> = roundtrip(DumpFormat::Binary);
+            let i: usize = dumped.len() - 20;
+            dumped[i] ^= 0x01;
+            let r = DumpReader::new(DumpFormat::Binary, dumped.as_slice()).unwrap();
+            assert!(r.collect::<Result<Vec<_>, _>>().is_err());
+        }
+
+        #[test]
+        fn test_binary_oversized() {
+            let mut dumped: Vec<u8> = b"RDB2KV\x01\x00".to_vec();
+            dumped.extend_from_slice(&u32::MAX.to_be_bytes());
+            dumped.extend_from_slice(b"devices");
+            assert!(DumpReader::new(DumpFormat::Binary, dumped.as_slice()).is_err());
+        }
+
+        #[test]
+        fn test_ndjson_bucket_whitespace() {
+            let b = Bucket::from(String::from("dev ices"));
+            let w = DumpWriter::new(DumpFormat::Ndjson, vec![], &b).unwrap();
+            let dumped: Vec<u8> = w.finish().unwrap();
+            let r = DumpReader::new(DumpFormat::Ndjson, dumped.as_slice()).unwrap();
+            assert_eq!(r.bucket(), "dev ices");
+        }
+
+        #[test]
+        fn test_binary_truncated() {
+            let dumped: Vec<u8> = roundtrip(DumpFormat::Binary);
+            let truncated: &[u8] = &dumped[..dumped.len() - 4];
+            let r = DumpReader::new(DumpFormat::Binary, truncated).unwrap();
+            assert!(r.collect::<Result<Vec<_>, _>>().is_err());
+        }
+    }
+
+    mod import_bucket_new_mut {
+
+        use std::collections::BTreeMap;
+
+        use crate::bucket::Bucket;
+        use crate::dump::{self, DumpFormat, DumpReader, DumpWriter};
+        use crate::evt::Event;
+        use crate::item::{Item, RawItem};
+        use crate::list::{list_items_page_new_mut, page_builder_new, PageRequest};
+        use crate::upsert::BulkRequest;
+
+        struct DummyClient {
+            rows: BTreeMap<Vec<u8>, Vec<u8>>,
+            requests: u64,
+        }
+
+        fn page() -> impl Fn(&PageRequest, &mut DummyClient) -> Result<Vec<RawItem>, Event> {
+            let list_getter = |c: &mut DummyClient, _q: &str, after: Option<&[u8]>, limit: u64| {
+                let rows = c.rows.iter().filter(|(k, _)| after < Some(k.as_slice()));
+                let items = rows.map(|(k, v)| Item::new(k.clone(), v.clone()));
+                Ok(items.take(limit as usize).collect())
+            };
+            let builder = page_builder_new(
+                |_: &Bucket| Ok(String::from("")),
+                |_: &Bucket| Ok(String::from("")),
+            );
+            list_items_page_new_mut(list_getter, builder)
+        }
+
+        #[test]
+        fn test_export_import() {
+            let b = Bucket::from(String::from("devices"));
+            let mut src = DummyClient {
+                rows: (0..5u8).map(|i| (vec![i], vec![i, i])).collect(),
+                requests: 0,
+            };
+            let export = dump::export_bucket_new_mut(page(), 2);
+            let mut w = DumpWriter::new(DumpFormat::Binary, vec![], &b).unwrap();
+            assert_eq!(export(&b, &mut src, &mut w).unwrap(), 5);
+            let dumped: Vec<u8> = w.finish().unwrap();
+
+            let upsert = |reqs: std::vec::IntoIter<BulkRequest<Vec<u8>, Vec<u8>>>,
+                          c: &mut DummyClient| {
+                c.requests += 1;
+                let items: Vec<RawItem> = reqs
+                    .flat_map(|r| {
+                        let v: Vec<RawItem> = r
+                            .as_items()
+                            .iter()
+                            .map(|i| Item::new(i.as_key().clone(), i.as_val().clone()))
+                            .collect();
+                        v
+                    })
+                    .collect();
+                let cnt: u64 = items.len() as u64;
+                c.rows.extend(items.into_iter().map(|i| i.into_pair()));
+                Ok::<_, Event>(cnt)
+            };
+            let import = dump::import_bucket_new_mut(upsert, 2);
+            let mut dst = DummyClient {
+                rows: BTreeMap::new(),
+                requests: 0,
+            };
+            let mut r = DumpReader::new(DumpFormat::Binary, dumped.as_slice()).unwrap();
+            assert_eq!(import(&b, &mut r, &mut dst).unwrap(), 5);
+            assert_eq!(dst.rows, src.rows);
+            assert_eq!(dst.requests, 3);
+        }
+    }
+}
@@ -1,7 +1,17 @@
 /// A list of request handle results.
+///
+/// New variants may be added in minor releases; matches need a wildcard arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Event {
     ConnectionError(String),
     UnexpectedError(String),
     InvalidBucket(String),
+    BucketNotFound(String),
+    /// A transaction conflict(e.g, serialization failure or deadlock) which may succeed if replayed.
+    Conflict(String),
+    /// No pooled connection became available in time.
+    PoolTimeout(String),
+    /// A stored value does not match its checksum.
+    ChecksumMismatch(String),
 }
@@ -0,0 +1,347 @@
+use crate::bucket::Bucket;
+use crate::evt::Event;
+
+/// A kind of write recorded in a changelog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOp {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl ChangeOp {
+    /// Gets the op code stored in a changelog: "I", "U" or "D".
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeOp::Insert => "I",
+            ChangeOp::Update => "U",
+            ChangeOp::Delete => "D",
+        }
+    }
+}
+
+impl TryFrom<&str> for ChangeOp {
+    type Error = Event;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "I" => Ok(ChangeOp::Insert),
+            "U" => Ok(ChangeOp::Update),
+            "D" => Ok(ChangeOp::Delete),
+            _ => Err(Event::UnexpectedError(format!("Unknown change op: {}", s))),
+        }
+    }
+}
+
+/// A write recorded in a changelog.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    key: Vec<u8>,
+    op: ChangeOp,
+    revision: u64,
+    timestamp: i64,
+}
+
+impl Change {
+    /// Creates new change.
+    ///
+    /// # Arguments
+    /// - key: The changed key.
+    /// - op: The kind of the write.
+    /// - revision: The changelog revision(increases monotonically).
+    /// - timestamp: The unix time(seconds) of the write.
+    pub fn new(key: Vec<u8>, op: ChangeOp, revision: u64, timestamp: i64) -> Self {
+        Self {
+            key,
+            op,
+            revision,
+            timestamp,
+        }
+    }
+
+    /// Gets the key reference.
+    pub fn as_key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Gets the kind of the write.
+    pub fn op(&self) -> ChangeOp {
+        self.op
+    }
+
+    /// Gets the revision; pass it to `changes_since` to resume after this change.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Gets the unix time(seconds) of the write.
+    pub fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Traits for building changelog query strings from `Bucket`.
+pub trait ChangelogBuilder {
+    /// Builds queries which create the changelog table and its triggers.
+    fn build_enable(&self, b: &Bucket) -> Result<String, Event>;
+
+    /// Builds queries which drop the triggers and the changelog table.
+    fn build_disable(&self, b: &Bucket) -> Result<String, Event>;
+
+    /// Builds select query which gets changes after a revision(up to a limit).
+    fn build_since(&self, b: &Bucket) -> Result<String, Event>;
+}
+
+struct ChangelogBuilderF<E, D, S> {
+    enable: E,
+    disable: D,
+    since: S,
+}
+
+impl<E, D, S> ChangelogBuilder for ChangelogBuilderF<E, D, S>
+where
+    E: Fn(&Bucket) -> Result<String, Event>,
+    D: Fn(&Bucket) -> Result<String, Event>,
+    S: Fn(&Bucket) -> Result<String, Event>,
+{
+    fn build_enable(&self, b: &Bucket) -> Result<String, Event> {
+        (self.enable)(b)
+    }
+    fn build_disable(&self, b: &Bucket) -> Result<String, Event> {
+        (self.disable)(b)
+    }
+    fn build_since(&self, b: &Bucket) -> Result<String, Event> {
+        (self.since)(b)
+    }
+}
+
+/// Creates new `ChangelogBuilder` implementation which uses closures to build query strings.
+///
+/// # Arguments
+/// - enable: Builds create queries for the changelog table and triggers.
+/// - disable: Builds drop queries for the changelog table and triggers.
+/// - since: Builds select query for changes after a revision.
+pub fn changelog_builder_new<E, D, S>(enable: E, disable: D, since: S) -> impl ChangelogBuilder
+where
+    E: Fn(&Bucket) -> Result<String, Event>,
+    D: Fn(&Bucket) -> Result<String, Event>,
+    S: Fn(&Bucket) -> Result<String, Event>,
+{
+    ChangelogBuilderF {
+        enable,
+        disable,
+        since,
+    }
+}
+
+/// Creates new changelog enabler which uses closures to create the changelog and build queries.
+///
+/// # Arguments
+/// - exec: Executes (possibly multiple) queries.
+/// - builder: Builds changelog query strings.
+pub fn changelog_enable_mut<E, B, C>(
+    exec: E,
+    builder: B,
+) -> impl Fn(&Bucket, &mut C) -> Result<(), Event>
+where
+    E: Fn(&mut C, &str) -> Result<(), Event>,
+    B: ChangelogBuilder,
+{
+    move |b: &Bucket, client: &mut C| {
+        let query: String = builder.build_enable(b)?;
+        exec(client, query.as_str())
+    }
+}
+
+/// Creates new changelog disabler which uses closures to drop the changelog and build queries.
+///
+/// # Arguments
+/// - exec: Executes (possibly multiple) queries.
+/// - builder: Builds changelog query strings.
+pub fn changelog_disable_mut<E, B, C>(
+    exec: E,
+    builder: B,
+) -> impl Fn(&Bucket, &mut C) -> Result<(), Event>
+where
+    E: Fn(&mut C, &str) -> Result<(), Event>,
+    B: ChangelogBuilder,
+{
+    move |b: &Bucket, client: &mut C| {
+        let query: String = builder.build_disable(b)?;
+        exec(client, query.as_str())
+    }
+}
+
+/// Creates new changelog reader which gets changes after a revision, ordered by the revision.
+///
+/// Start from revision 0 and resume from the revision of the last change received.
+///
+/// Revisions must follow the commit order: a change must never become visible with a revision
+/// lower than a revision already read. A sequence assigned on write does not guarantee this when
+/// writers run concurrently(see examples/postgres for revisions assigned after commit).
+///
+/// # Arguments
+/// - select: Selects up to `limit` changes after the revision.
+/// - builder: Builds changelog query strings.
+pub fn changes_since_new_mut<S, B, C>(
+    select: S,
+    builder: B,
+) -> impl Fn(&Bucket, u64, u64, &mut C) -> Result<Vec<Change>, Event>
+where
+    S: Fn(&mut C, &str, u64, u64) -> Result<Vec<Change>, Event>,
+    B: ChangelogBuilder,
+{
+    move |b: &Bucket, revision: u64, limit: u64, client: &mut C| {
+        let query: String = builder.build_since(b)?;
+        select(client, query.as_str(), revision, limit)
+    }
+}
+
+/// Creates new change follower which waits for a live notification when no change is available.
+///
+/// Changes are read before waiting, so writes done before the subscription are not missed
+/// (the subscription, e.g, `LISTEN`, must be issued before the first call).
+///
+/// # Arguments
+/// - wait: Waits for a notification of the bucket; returns false on timeout.
+/// - since: Gets changes after a revision(see `changes_since_new_mut`).
+pub fn changes_follow_new_mut<W, S, C>(
+    wait: W,
+    since: S,
+) -> impl Fn(&Bucket, u64, u64, &mut C) -> Result<Vec<Change>, Event>
+where
+    W: Fn(&mut C, &Bucket) -> Result<bool, Event>,
+    S: Fn(&Bucket, u64, u64, &mut C) -> Result<Vec<Change>, Event>,
+{
+    move |b: &Bucket, revision: u64, limit: u64, client: &mut C| {
+        let changes: Vec<Change> = since(b, revision, limit, client)?;
+        if !changes.is_empty() || !wait(client, b)? {
+            return Ok(changes);
+        }
+        since(b, revision, limit, client)
+    }
+}
+
+#[cfg(test)]
+mod test_feed {
+
+    mod change_op {
+
+        use crate::feed::ChangeOp;
+
+        #[test]
+        fn test_roundtrip() {
+            for op in [ChangeOp::Insert, ChangeOp::Update, ChangeOp::Delete] {
+                assert_eq!(ChangeOp::try_from(op.as_str()).unwrap(), op);
+            }
+            assert!(ChangeOp::try_from("X").is_err());
+        }
+    }
+
+    mod changes_since_new_mut {
+
+        use crate::bucket::Bucket;
+        use crate::evt::Event;
+        use crate::feed::{self, changelog_builder_new, Change, ChangeOp};
+
+        struct DummyClient {
+            committed: Vec<Change>,
+        }
+
+        #[test]
+        fn test_resume() {
+            let builder = changelog_builder_new(
+                |_: &Bucket| Ok(String::from("")),
+                |_: &Bucket| Ok(String::from("")),
+                |_: &Bucket| Ok(String::from("")),
+            );
+            let select = |c: &mut DummyClient, _q: &str, rev: u64, limit: u64| {
+                let after = c.committed.iter().filter(|ch| rev < ch.revision());
+                Ok::<_, Event>(after.take(limit as usize).cloned().collect())
+            };
+            let since = feed::changes_since_new_mut(select, builder);
+            let b = Bucket::from(String::from("devices"));
+            let change = |k: &[u8], rev: u64| Change::new(k.to_vec(), ChangeOp::Insert, rev, 0);
+            let mut c = DummyClient {
+                committed: vec![change(b"k1", 1), change(b"k2", 2), change(b"k3", 3)],
+            };
+
+            let mut revision: u64 = 0;
+            let mut keys: Vec<Vec<u8>> = vec![];
+            loop {
+                let page: Vec<Change> = since(&b, revision, 2, &mut c).unwrap();
+                match page.last() {
+                    None => break,
+                    Some(last) => revision = last.revision(),
+                }
+                keys.extend(page.iter().map(|ch| ch.as_key().to_vec()));
+            }
+            assert_eq!(keys, vec![b"k1".to_vec(), b"k2".to_vec(), b"k3".to_vec()]);
+
+            c.committed.push(change(b"k4", 4));
+            let got: Vec<Change> = since(&b, revision, 2, &mut c).unwrap();
+            assert_eq!(got, vec![change(b"k4", 4)]);
+        }
+    }
+
+    mod changes_follow_new_mut {
+
+        use crate::bucket::Bucket;
+        use crate::feed::{self, changelog_builder_new, Change, ChangeOp};
+
+        struct DummyClient {
+            log: Vec<Change>,
+            pending: Option<Change>,
+            waited: u64,
+        }
+
+        fn follower(
+        ) -> impl Fn(&Bucket, u64, u64, &mut DummyClient) -> Result<Vec<Change>, crate::evt::Event>
+        {
+            let builder = changelog_builder_new(
+                |_: &Bucket| Ok(String::from("")),
+                |_: &Bucket| Ok(String::from("")),
+                |_: &Bucket| Ok(String::from("")),
+            );
+            let select = |c: &mut DummyClient, _q: &str, rev: u64, limit: u64| {
+                let after = c.log.iter().filter(|ch| rev < ch.revision());
+                Ok(after.take(limit as usize).cloned().collect())
+            };
+            let since = feed::changes_since_new_mut(select, builder);
+            let wait = |c: &mut DummyClient, _b: &Bucket| {
+                c.waited += 1;
+                match c.pending.take() {
+                    None => Ok(false),
+                    Some(ch) => {
+                        c.log.push(ch);
+                        Ok(true)
+                    }
+                }
+            };
+            feed::changes_follow_new_mut(wait, since)
+        }
+
+        #[test]
+        fn test_follow() {
+            let f = follower();
+            let b = Bucket::from(String::from("devices"));
+            let mut c = DummyClient {
+                log: vec![Change::new(b"k1".to_vec(), ChangeOp::Insert, 1, 0)],
+                pending: Some(Change::new(b"k1".to_vec(), ChangeOp::Delete, 2, 0)),
+                waited: 0,
+            };
+
+            let got: Vec<Change> = f(&b, 0, 10, &mut c).unwrap();
+            assert_eq!(got.len(), 1);
+            assert_eq!(c.waited, 0);
+
+            let got: Vec<Change> = f(&b, got[0].revision(), 10, &mut c).unwrap();
+            assert_eq!(got.len(), 1);
+            assert_eq!(got[0].op(), ChangeOp::Delete);
+            assert_eq!(c.waited, 1);
+
+            let got: Vec<Change> = f(&b, got[0].revision(), 10, &mut c).unwrap();
+            assert!(got.is_empty());
+            assert_eq!(c.waited, 2);
+        }
+    }
+}
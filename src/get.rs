@@ -1,3 +1,4 @@
+use crate::trace::{self, Op};
 use crate::{bucket::Bucket, evt::Event};
 
 /// A get request to get up to single value.
@@ -39,8 +40,15 @@ where
     move |req: &GetRequest<Vec<u8>>, client: &mut C| {
         let b: &Bucket = req.as_bucket();
         let k: &[u8] = req.as_key();
-        let query: String = builder(b)?;
-        select(client, query.as_str(), k)
+        let op: Op = Op::new("select", b).with_key(k);
+        trace::instrumented(
+            op,
+            || {
+                let query: String = builder(b)?;
+                select(client, query.as_str(), k)
+            },
+            |ov| ov.iter().count() as u64,
+        )
     }
 }
 
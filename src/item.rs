@@ -25,3 +25,6 @@ impl<K, V> Item<K, V> {
         (self.key, self.val)
     }
 }
+
+/// A key/value pair as bytes.
+pub type RawItem = Item<Vec<u8>, Vec<u8>>;
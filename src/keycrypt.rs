@@ -0,0 +1,400 @@
+//! Deterministic key encryption which keeps point lookups working.
+//!
+//! The same (bucket, key) is always encoded to the same bytes, so `GetRequest`,
+//! `delete_key_bytes_mut` and upserts match exactly after the key is encoded.
+//!
+//! | Scheme              | Stored key                            | List decoding |
+//! |---------------------|---------------------------------------|---------------|
+//! | `DeterministicKeys` | nonce(24) + XChaCha20-Poly1305(key)   | yes           |
+//! | `HmacKeys`          | HMAC-SHA256(bucket, key)(32)          | no            |
+//!
+//! `DeterministicKeys` derives the nonce from HMAC-SHA256 of the bucket and the key(SIV-like),
+//! and authenticates the key bound to the bucket.
+//!
+//! Trade-offs:
+//! - Equal keys produce equal stored keys; an observer learns which rows share a key.
+//! - Stored keys are not ordered like plain keys: listings and pages are ordered by the
+//!   encoded bytes, and range/prefix scans over plain keys are not possible.
+//!   `page_items_decoded_new` pages in the encoded order and decodes each page; `after` is a
+//!   plain key(encoded again before paging), so it works with `visit_items_mut`.
+//! - Stored keys are longer than plain keys(40 bytes more for `DeterministicKeys`).
+
+use std::sync::Arc;
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::bucket::Bucket;
+use crate::evt::Event;
+use crate::get::GetRequest;
+use crate::item::{Item, RawItem};
+use crate::list::PageRequest;
+use crate::upsert::{BulkRequest, Requests};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const NONCE_SIZE: usize = 24;
+
+/// Traits for encoding keys.
+pub trait KeyCodec {
+    /// Encodes the key in the bucket deterministically.
+    fn encode(&self, bucket: &Bucket, key: &[u8]) -> Result<Vec<u8>, Event>;
+
+    /// Decodes the stored key; `None` if the scheme is irreversible.
+    fn decode(&self, bucket: &Bucket, stored: &[u8]) -> Result<Option<Vec<u8>>, Event>;
+}
+
+fn mac(secret: &[u8; 32], bucket: &Bucket, key: &[u8]) -> Result<[u8; 32], Event> {
+    let b: &[u8] = bucket.as_str().as_bytes();
+    let mut m: HmacSha256 = <HmacSha256 as Mac>::new_from_slice(secret)
+        .map_err(|e| Event::UnexpectedError(format!("Invalid HMAC key: {}", e)))?;
+    m.update(&(b.len() as u32).to_be_bytes());
+    m.update(b);
+    m.update(key);
+    Ok(m.finalize().into_bytes().into())
+}
+
+fn decode_required<K>(codec: &K, bucket: &Bucket, stored: &[u8]) -> Result<Vec<u8>, Event>
+where
+    K: KeyCodec,
+{
+    codec
+        .decode(bucket, stored)?
+        .ok_or_else(|| Event::UnexpectedError(String::from("Keys can not be decoded")))
+}
+
+/// Reversible deterministic encryption of keys.
+pub struct DeterministicKeys {
+    mac_key: [u8; 32],
+    cipher: XChaCha20Poly1305,
+}
+
+impl DeterministicKeys {
+    /// Creates new codec.
+    ///
+    /// # Arguments
+    /// - mac_key: Derives nonces.
+    /// - enc_key: Encrypts keys.
+    pub fn new(mac_key: [u8; 32], enc_key: [u8; 32]) -> Self {
+        Self {
+            mac_key,
+            cipher: XChaCha20Poly1305::new(Key::from_slice(&enc_key)),
+        }
+    }
+}
+
+impl KeyCodec for DeterministicKeys {
+    fn encode(&self, bucket: &Bucket, key: &[u8]) -> Result<Vec<u8>, Event> {
+        let tag: [u8; 32] = mac(&self.mac_key, bucket, key)?;
+        let nonce: &XNonce = XNonce::from_slice(&tag[..NONCE_SIZE]);
+        let aad: &[u8] = bucket.as_str().as_bytes();
+        let sealed: Vec<u8> = self
+            .cipher
+            .encrypt(nonce, Payload { msg: key, aad })
+            .map_err(|e| Event::UnexpectedError(format!("Unable to encrypt a key: {}", e)))?;
+        let mut stored: Vec<u8> = Vec::with_capacity(NONCE_SIZE + sealed.len());
+        stored.extend_from_slice(nonce);
+        stored.extend_from_slice(&sealed);
+        Ok(stored)
+    }
+
+    fn decode(&self, bucket: &Bucket, stored: &[u8]) -> Result<Option<Vec<u8>>, Event> {
+        let invalid = || Event::UnexpectedError(String::from("Unable to decrypt a key"));
+        let (nonce, sealed) = match NONCE_SIZE <= stored.len() {
+            true => Ok(stored.split_at(NONCE_SIZE)),
+            false => Err(invalid()),
+        }?;
+        let aad: &[u8] = bucket.as_str().as_bytes();
+        let key: Vec<u8> = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: sealed, aad })
+            .map_err(|_| invalid())?;
+        match mac(&self.mac_key, bucket, &key)?[..NONCE_SIZE] == *nonce {
+            true => Ok(Some(key)),
+            false => Err(invalid()),
+        }
+    }
+}
+
+/// Irreversible keyed tokens(HMAC-SHA256) of keys.
+pub struct HmacKeys {
+    secret: [u8; 32],
+}
+
+impl HmacKeys {
+    /// Creates new codec which uses the secret key.
+    pub fn new(secret: [u8; 32]) -> Self {
+        Self { secret }
+    }
+}
+
+impl KeyCodec for HmacKeys {
+    fn encode(&self, bucket: &Bucket, key: &[u8]) -> Result<Vec<u8>, Event> {
+        mac(&self.secret, bucket, key).map(|m| m.to_vec())
+    }
+
+    fn decode(&self, _: &Bucket, _: &[u8]) -> Result<Option<Vec<u8>>, Event> {
+        Ok(None)
+    }
+}
+
+/// Creates select request handler which encodes the key.
+///
+/// # Arguments
+/// - codec: Encodes keys.
+/// - select: The handler(e.g, `select_bytes_new_mut`).
+pub fn select_key_encoded_new<K, S, C>(
+    codec: Arc<K>,
+    select: S,
+) -> impl Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>
+where
+    K: KeyCodec,
+    S: Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>,
+{
+    move |req: &GetRequest<Vec<u8>>, client: &mut C| {
+        let b: &Bucket = req.as_bucket();
+        let key: Vec<u8> = codec.encode(b, req.as_key())?;
+        let encoded = GetRequest::new(Bucket::from(String::from(b.as_str())), key);
+        select(&encoded, client)
+    }
+}
+
+/// Creates remover which encodes the key.
+///
+/// # Arguments
+/// - codec: Encodes keys.
+/// - delete: The handler(e.g, `delete_key_bytes_mut`).
+pub fn delete_key_encoded_new<K, D, C>(
+    codec: Arc<K>,
+    delete: D,
+) -> impl Fn(&Bucket, &[u8], &mut C) -> Result<u64, Event>
+where
+    K: KeyCodec,
+    D: Fn(&Bucket, &[u8], &mut C) -> Result<u64, Event>,
+{
+    move |b: &Bucket, key: &[u8], client: &mut C| {
+        let encoded: Vec<u8> = codec.encode(b, key)?;
+        delete(b, &encoded, client)
+    }
+}
+
+/// Creates upsert requests handler which encodes keys.
+///
+/// # Arguments
+/// - codec: Encodes keys.
+/// - upsert: The handler(e.g, `upsert_bytes_all_new_mut`).
+pub fn upsert_key_encoded_new<K, U, I, T>(
+    codec: Arc<K>,
+    upsert: U,
+) -> impl Fn(I, &mut T) -> Result<u64, Event>
+where
+    K: KeyCodec,
+    U: Fn(Requests, &mut T) -> Result<u64, Event>,
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    move |requests: I, transaction: &mut T| {
+        let encoded: Vec<BulkRequest<Vec<u8>, Vec<u8>>> = requests
+            .map(|req| {
+                let b: &Bucket = req.as_bucket();
+                let items: Vec<RawItem> = req
+                    .as_items()
+                    .iter()
+                    .map(|i| Ok(Item::new(codec.encode(b, i.as_key())?, i.as_val().clone())))
+                    .collect::<Result<_, Event>>()?;
+                Ok(BulkRequest::new(
+                    Bucket::from(String::from(b.as_str())),
+                    items,
+                ))
+            })
+            .collect::<Result<_, Event>>()?;
+        upsert(encoded.into_iter(), transaction)
+    }
+}
+
+/// Creates keys getter which decodes listed keys.
+///
+/// Fails if the scheme is irreversible(e.g, `HmacKeys`).
+/// Keys are ordered by the stored(encoded) bytes, not by the plain keys.
+///
+/// # Arguments
+/// - codec: Decodes keys.
+/// - list: The handler(e.g, `list_keys_bytes_new_mut`).
+pub fn list_keys_decoded_new<K, L, C>(
+    codec: Arc<K>,
+    list: L,
+) -> impl Fn(&Bucket, &mut C) -> Result<Vec<Vec<u8>>, Event>
+where
+    K: KeyCodec,
+    L: Fn(&Bucket, &mut C) -> Result<Vec<Vec<u8>>, Event>,
+{
+    move |b: &Bucket, client: &mut C| {
+        let stored: Vec<Vec<u8>> = list(b, client)?;
+        stored
+            .iter()
+            .map(|s| decode_required(codec.as_ref(), b, s))
+            .collect()
+    }
+}
+
+/// Creates page getter which decodes keys of the page.
+///
+/// Fails if the scheme is irreversible(e.g, `HmacKeys`).
+/// `after` of the request is a plain key(e.g, the last key of the previous page); it is encoded
+/// before paging. Pages are ordered by the stored(encoded) bytes, not by the plain keys.
+///
+/// # Arguments
+/// - codec: Encodes `after` and decodes keys.
+/// - page: Gets a page of stored items(e.g, `list_items_page_new_mut`).
+pub fn page_items_decoded_new<K, P, C>(
+    codec: Arc<K>,
+    page: P,
+) -> impl Fn(&PageRequest, &mut C) -> Result<Vec<RawItem>, Event>
+where
+    K: KeyCodec,
+    P: Fn(&PageRequest, &mut C) -> Result<Vec<RawItem>, Event>,
+{
+    move |req: &PageRequest, client: &mut C| {
+        let b: &Bucket = req.as_bucket();
+        let after: Option<Vec<u8>> = req.as_after().map(|a| codec.encode(b, a)).transpose()?;
+        let encoded = PageRequest::new(Bucket::from(String::from(b.as_str())), after, req.limit());
+        page(&encoded, client)?
+            .into_iter()
+            .map(|i| {
+                let (stored, val) = i.into_pair();
+                Ok(Item::new(decode_required(codec.as_ref(), b, &stored)?, val))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test_keycrypt {
+
+    mod deterministic_keys {
+
+        use crate::bucket::Bucket;
+        use crate::keycrypt::{DeterministicKeys, HmacKeys, KeyCodec};
+
+        #[test]
+        fn test_deterministic() {
+            let c = DeterministicKeys::new([1; 32], [2; 32]);
+            let b: Bucket = Bucket::from(String::from("users"));
+            let e1: Vec<u8> = c.encode(&b, b"alice@example.com").unwrap();
+            let e2: Vec<u8> = c.encode(&b, b"alice@example.com").unwrap();
+            assert_eq!(e1, e2);
+            assert_ne!(e1, c.encode(&b, b"bob@example.com").unwrap());
+            let other: Bucket = Bucket::from(String::from("admins"));
+            assert_ne!(e1, c.encode(&other, b"alice@example.com").unwrap());
+            assert_eq!(
+                c.decode(&b, &e1).unwrap(),
+                Some(b"alice@example.com".to_vec())
+            );
+            assert!(c.decode(&other, &e1).is_err());
+
+            let h = HmacKeys::new([3; 32]);
+            assert_eq!(h.encode(&b, b"k").unwrap(), h.encode(&b, b"k").unwrap());
+            assert_eq!(h.decode(&b, &h.encode(&b, b"k").unwrap()).unwrap(), None);
+        }
+    }
+
+    mod handlers {
+
+        use std::sync::Arc;
+
+        use crate::bucket::Bucket;
+        use crate::del::delete_key_bytes_mut;
+        use crate::get::{select_bytes_new_mut, GetRequest};
+        use crate::item::Item;
+        use crate::keycrypt::{self, DeterministicKeys, HmacKeys};
+        use crate::list::{list_items_page_new_mut, list_keys_bytes_new_mut, visit_items_mut};
+        use crate::mem::{self, MemoryKv};
+        use crate::upsert::{upsert_bytes_all_new_mut, BulkRequest};
+
+        fn bucket() -> Bucket {
+            Bucket::from(String::from("users"))
+        }
+
+        #[test]
+        fn test_point_lookup() {
+            let codec: Arc<DeterministicKeys> = Arc::new(DeterministicKeys::new([1; 32], [2; 32]));
+            let mut kv = MemoryKv::new();
+            let put = keycrypt::upsert_key_encoded_new(
+                codec.clone(),
+                upsert_bytes_all_new_mut(mem::create, mem::upsert, mem::upsert_builder()),
+            );
+            let items = vec![
+                Item::new(b"alice".to_vec(), b"1".to_vec()),
+                Item::new(b"bob".to_vec(), b"2".to_vec()),
+            ];
+            put(vec![BulkRequest::new(bucket(), items)].into_iter(), &mut kv).unwrap();
+            assert!(kv
+                .bucket("users")
+                .unwrap()
+                .get(&b"alice".to_vec())
+                .is_none());
+
+            let get = keycrypt::select_key_encoded_new(
+                codec.clone(),
+                select_bytes_new_mut(mem::select, mem::builder()),
+            );
+            let req = GetRequest::new(bucket(), b"bob".to_vec());
+            assert_eq!(get(&req, &mut kv).unwrap(), Some(b"2".to_vec()));
+
+            let del = keycrypt::delete_key_encoded_new(
+                codec.clone(),
+                delete_key_bytes_mut(mem::delete, mem::builder()),
+            );
+            assert_eq!(del(&bucket(), b"bob", &mut kv).unwrap(), 1);
+
+            let list = keycrypt::list_keys_decoded_new(
+                codec,
+                list_keys_bytes_new_mut(mem::list_keys, mem::builder()),
+            );
+            assert_eq!(list(&bucket(), &mut kv).unwrap(), vec![b"alice".to_vec()]);
+
+            let list = keycrypt::list_keys_decoded_new(
+                Arc::new(HmacKeys::new([3; 32])),
+                list_keys_bytes_new_mut(mem::list_keys, mem::builder()),
+            );
+            assert!(list(&bucket(), &mut kv).is_err());
+        }
+
+        #[test]
+        fn test_pages() {
+            let codec: Arc<DeterministicKeys> = Arc::new(DeterministicKeys::new([1; 32], [2; 32]));
+            let mut kv = MemoryKv::new();
+            let put = keycrypt::upsert_key_encoded_new(
+                codec.clone(),
+                upsert_bytes_all_new_mut(mem::create, mem::upsert, mem::upsert_builder()),
+            );
+            let items = (0..5u8)
+                .map(|i| Item::new(vec![b'k', i], vec![i]))
+                .collect();
+            put(vec![BulkRequest::new(bucket(), items)].into_iter(), &mut kv).unwrap();
+
+            let page = keycrypt::page_items_decoded_new(
+                codec,
+                list_items_page_new_mut(mem::page, mem::page_builder()),
+            );
+            let mut visited: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+            let cnt: u64 = visit_items_mut(&page, &bucket(), None, 2, &mut kv, |i| {
+                visited.push(i.into_pair());
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(cnt, 5);
+            visited.sort();
+            let expected: Vec<(Vec<u8>, Vec<u8>)> =
+                (0..5u8).map(|i| (vec![b'k', i], vec![i])).collect();
+            assert_eq!(visited, expected);
+
+            let page = keycrypt::page_items_decoded_new(
+                Arc::new(HmacKeys::new([3; 32])),
+                list_items_page_new_mut(mem::page, mem::page_builder()),
+            );
+            assert!(visit_items_mut(&page, &bucket(), None, 2, &mut kv, |_| Ok(())).is_err());
+        }
+    }
+}
@@ -1,7 +1,32 @@
+#![cfg_attr(test, allow(clippy::bool_assert_comparison))]
+
+pub mod blob;
 pub mod bucket;
+pub mod buffer;
+pub mod cache;
+pub mod checksum;
+pub mod chunk;
+pub mod codec;
+pub mod conformance;
+pub mod crc;
+#[cfg(feature = "encrypt")]
+pub mod crypt;
 pub mod del;
+pub mod dump;
 pub mod evt;
+pub mod feed;
 pub mod get;
 pub mod item;
+#[cfg(feature = "key-encrypt")]
+pub mod keycrypt;
 pub mod list;
+pub mod load;
+pub mod mem;
+pub mod memo;
+pub mod migrate;
+#[cfg(any(feature = "r2d2", feature = "deadpool"))]
+pub mod pool;
+pub mod record;
+pub mod retry;
+mod trace;
 pub mod upsert;
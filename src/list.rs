@@ -1,5 +1,7 @@
 use crate::bucket::{bucket_checker_new_unchecked, Bucket};
 use crate::evt::Event;
+use crate::item::RawItem;
+use crate::trace::{self, Op};
 
 /// Creates new keys getter which uses closures to list and build select query string.
 ///
@@ -15,8 +17,15 @@ where
     B: Fn(&Bucket) -> Result<String, Event>,
 {
     move |b: &Bucket, client: &mut C| {
-        let query: String = builder(b)?;
-        list(client, query.as_str())
+        let op: Op = Op::new("list", b);
+        trace::instrumented(
+            op,
+            || {
+                let query: String = builder(b)?;
+                list(client, query.as_str())
+            },
+            |keys| keys.len() as u64,
+        )
     }
 }
 
@@ -43,6 +52,176 @@ pub fn list_query_builder_unchecked() -> impl Fn(&Bucket) -> Result<String, Even
     list_query_builder_checked(checker)
 }
 
+/// Creates new buckets getter which uses a closure to list bucket names.
+///
+/// # Arguments
+/// - list: Selects bucket names(e.g, table names from the catalog).
+/// - query: The query string which selects bucket names.
+pub fn list_buckets_new_mut<L, C>(
+    list: L,
+    query: String,
+) -> impl Fn(&mut C) -> Result<Vec<Bucket>, Event>
+where
+    L: Fn(&mut C, &str) -> Result<Vec<String>, Event>,
+{
+    move |client: &mut C| {
+        let names: Vec<String> = list(client, query.as_str())?;
+        Ok(names.into_iter().map(Bucket::from).collect())
+    }
+}
+
+/// A request to get a page of key/value pairs ordered by the key.
+pub struct PageRequest {
+    bucket: Bucket,
+    after: Option<Vec<u8>>,
+    limit: u64,
+}
+
+impl PageRequest {
+    /// Creates new page request for the bucket.
+    ///
+    /// # Arguments
+    /// - bucket: The bucket to be listed.
+    /// - after: Lists keys greater than this key(from the first key if `None`).
+    /// - limit: The maximum number of items in the page.
+    pub fn new(bucket: Bucket, after: Option<Vec<u8>>, limit: u64) -> Self {
+        Self {
+            bucket,
+            after,
+            limit,
+        }
+    }
+
+    /// Gets the bucket reference.
+    pub fn as_bucket(&self) -> &Bucket {
+        &self.bucket
+    }
+
+    /// Gets the key after which items are listed.
+    pub fn as_after(&self) -> Option<&[u8]> {
+        self.after.as_deref()
+    }
+
+    /// Gets the maximum number of items.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+}
+
+/// Traits for building page query strings from `Bucket`.
+pub trait PageBuilder {
+    /// Builds select query for the first page(bound parameter: limit).
+    fn build_first(&self, b: &Bucket) -> Result<String, Event>;
+
+    /// Builds select query for pages after a key(bound parameters: after, limit).
+    fn build_after(&self, b: &Bucket) -> Result<String, Event>;
+}
+
+struct PageBuilderF<F, A> {
+    first: F,
+    after: A,
+}
+
+impl<F, A> PageBuilder for PageBuilderF<F, A>
+where
+    F: Fn(&Bucket) -> Result<String, Event>,
+    A: Fn(&Bucket) -> Result<String, Event>,
+{
+    fn build_first(&self, b: &Bucket) -> Result<String, Event> {
+        (self.first)(b)
+    }
+    fn build_after(&self, b: &Bucket) -> Result<String, Event> {
+        (self.after)(b)
+    }
+}
+
+/// Creates new `PageBuilder` implementation which uses closures to build query strings.
+///
+/// # Arguments
+/// - first: Builds select query string for the first page.
+/// - after: Builds select query string for pages after a key.
+pub fn page_builder_new<F, A>(first: F, after: A) -> impl PageBuilder
+where
+    F: Fn(&Bucket) -> Result<String, Event>,
+    A: Fn(&Bucket) -> Result<String, Event>,
+{
+    PageBuilderF { first, after }
+}
+
+/// Creates new page getter which uses closures to select key/value pairs and build query strings.
+///
+/// # Arguments
+/// - list: Selects up to `limit` items after the key(if any) ordered by the key.
+/// - builder: Builds page query strings.
+pub fn list_items_page_new_mut<L, B, C>(
+    list: L,
+    builder: B,
+) -> impl Fn(&PageRequest, &mut C) -> Result<Vec<RawItem>, Event>
+where
+    L: Fn(&mut C, &str, Option<&[u8]>, u64) -> Result<Vec<RawItem>, Event>,
+    B: PageBuilder,
+{
+    move |req: &PageRequest, client: &mut C| {
+        let b: &Bucket = req.as_bucket();
+        let op: Op = match req.as_after() {
+            None => Op::new("page", b),
+            Some(after) => Op::new("page", b).with_key(after),
+        };
+        trace::instrumented(
+            op,
+            || {
+                let query: String = match req.as_after() {
+                    None => builder.build_first(b)?,
+                    Some(_) => builder.build_after(b)?,
+                };
+                list(client, query.as_str(), req.as_after(), req.limit())
+            },
+            |items| items.len() as u64,
+        )
+    }
+}
+
+/// Visits all items of the bucket page by page.
+///
+/// Returns the number of visited items.
+///
+/// # Arguments
+/// - page: Gets a page(see `list_items_page_new_mut`).
+/// - b: The bucket to be visited.
+/// - after: Visits keys greater than this key(from the first key if `None`).
+/// - page_size: The number of items per page.
+/// - client: The client used to get pages.
+/// - visitor: Receives items in the key order.
+pub fn visit_items_mut<P, C, V>(
+    page: &P,
+    b: &Bucket,
+    after: Option<Vec<u8>>,
+    page_size: u64,
+    client: &mut C,
+    mut visitor: V,
+) -> Result<u64, Event>
+where
+    P: Fn(&PageRequest, &mut C) -> Result<Vec<RawItem>, Event>,
+    V: FnMut(RawItem) -> Result<(), Event>,
+{
+    let sz: u64 = page_size.max(1);
+    let mut req = PageRequest::new(Bucket::from(String::from(b.as_str())), after, sz);
+    let mut cnt: u64 = 0;
+    loop {
+        let items: Vec<RawItem> = page(&req, client)?;
+        let full: bool = items.len() as u64 == sz;
+        let last: Option<Vec<u8>> = items.last().map(|i| i.as_key().clone());
+        for item in items {
+            visitor(item)?;
+            cnt += 1;
+        }
+        match (full, last) {
+            (true, Some(key)) => req.after = Some(key),
+            _ => return Ok(cnt),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test_list {
 
@@ -64,4 +243,55 @@ mod test_list {
             assert_eq!(v.len(), 0);
         }
     }
+
+    mod visit_items_mut {
+
+        use std::collections::BTreeMap;
+
+        use crate::bucket::Bucket;
+        use crate::item::Item;
+        use crate::list::{self, page_builder_new};
+
+        struct DummyClient {
+            rows: BTreeMap<Vec<u8>, Vec<u8>>,
+            queries: Vec<String>,
+        }
+
+        #[test]
+        fn test_pages() {
+            let list_getter = |c: &mut DummyClient, q: &str, after: Option<&[u8]>, limit: u64| {
+                c.queries.push(String::from(q));
+                let rows = c.rows.iter().filter(|(k, _)| match after {
+                    None => true,
+                    Some(a) => a < k.as_slice(),
+                });
+                let items = rows.map(|(k, v)| Item::new(k.clone(), v.clone()));
+                Ok(items.take(limit as usize).collect())
+            };
+            let builder = page_builder_new(
+                |_: &Bucket| Ok(String::from("first")),
+                |_: &Bucket| Ok(String::from("after")),
+            );
+            let page = list::list_items_page_new_mut(list_getter, builder);
+            let mut c = DummyClient {
+                rows: (0..5u8).map(|i| (vec![i], vec![i])).collect(),
+                queries: vec![],
+            };
+            let b = Bucket::from(String::from("devices"));
+
+            let mut keys: Vec<Vec<u8>> = vec![];
+            let cnt: u64 = list::visit_items_mut(&page, &b, None, 2, &mut c, |i| {
+                keys.push(i.into_pair().0);
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(cnt, 5);
+            assert_eq!(keys, (0..5u8).map(|i| vec![i]).collect::<Vec<_>>());
+            assert_eq!(c.queries, vec!["first", "after", "after"]);
+
+            let cnt: u64 =
+                list::visit_items_mut(&page, &b, Some(vec![3]), 2, &mut c, |_| Ok(())).unwrap();
+            assert_eq!(cnt, 1);
+        }
+    }
 }
@@ -0,0 +1,192 @@
+use crate::bucket::Bucket;
+use crate::evt::Event;
+use crate::item::Item;
+use crate::upsert::BulkRequest;
+
+/// Traits for building bulk load query strings from `Bucket`.
+///
+/// A bulk load copies items into a staging table, merges them into the bucket
+/// and clears the staging table.
+pub trait LoadBuilder {
+    /// Builds create query for the bucket.
+    fn build_create(&self, b: &Bucket) -> Result<String, Event>;
+
+    /// Builds create query for the staging table.
+    fn build_stage(&self, b: &Bucket) -> Result<String, Event>;
+
+    /// Builds copy query which loads items into the staging table.
+    fn build_copy(&self, b: &Bucket) -> Result<String, Event>;
+
+    /// Builds merge query which upserts staged items into the bucket.
+    fn build_merge(&self, b: &Bucket) -> Result<String, Event>;
+
+    /// Builds clear query which removes staged items.
+    fn build_clear(&self, b: &Bucket) -> Result<String, Event>;
+}
+
+struct LoadBuilderF<C, S, P, M, R> {
+    create: C,
+    stage: S,
+    copy: P,
+    merge: M,
+    clear: R,
+}
+
+impl<C, S, P, M, R> LoadBuilder for LoadBuilderF<C, S, P, M, R>
+where
+    C: Fn(&Bucket) -> Result<String, Event>,
+    S: Fn(&Bucket) -> Result<String, Event>,
+    P: Fn(&Bucket) -> Result<String, Event>,
+    M: Fn(&Bucket) -> Result<String, Event>,
+    R: Fn(&Bucket) -> Result<String, Event>,
+{
+    fn build_create(&self, b: &Bucket) -> Result<String, Event> {
+        (self.create)(b)
+    }
+    fn build_stage(&self, b: &Bucket) -> Result<String, Event> {
+        (self.stage)(b)
+    }
+    fn build_copy(&self, b: &Bucket) -> Result<String, Event> {
+        (self.copy)(b)
+    }
+    fn build_merge(&self, b: &Bucket) -> Result<String, Event> {
+        (self.merge)(b)
+    }
+    fn build_clear(&self, b: &Bucket) -> Result<String, Event> {
+        (self.clear)(b)
+    }
+}
+
+/// Creates new `LoadBuilder` implementation which uses closures to build query strings.
+///
+/// # Arguments
+/// - create: Builds create query string for the bucket.
+/// - stage: Builds create query string for the staging table.
+/// - copy: Builds copy query string.
+/// - merge: Builds merge query string.
+/// - clear: Builds clear query string for the staging table.
+pub fn load_builder_new<C, S, P, M, R>(
+    create: C,
+    stage: S,
+    copy: P,
+    merge: M,
+    clear: R,
+) -> impl LoadBuilder
+where
+    C: Fn(&Bucket) -> Result<String, Event>,
+    S: Fn(&Bucket) -> Result<String, Event>,
+    P: Fn(&Bucket) -> Result<String, Event>,
+    M: Fn(&Bucket) -> Result<String, Event>,
+    R: Fn(&Bucket) -> Result<String, Event>,
+{
+    LoadBuilderF {
+        create,
+        stage,
+        copy,
+        merge,
+        clear,
+    }
+}
+
+fn load_bytes_mut<E, P, B, T>(
+    q: &BulkRequest<Vec<u8>, Vec<u8>>,
+    exec: &E,
+    copy: &P,
+    builder: &B,
+    transaction: &mut T,
+) -> Result<u64, Event>
+where
+    E: Fn(&mut T, &str) -> Result<u64, Event>,
+    P: Fn(&mut T, &str, &[Item<Vec<u8>, Vec<u8>>]) -> Result<u64, Event>,
+    B: LoadBuilder,
+{
+    let b: &Bucket = q.as_bucket();
+    let cnt_c: u64 = exec(transaction, builder.build_create(b)?.as_str())?;
+    exec(transaction, builder.build_stage(b)?.as_str())?;
+    copy(transaction, builder.build_copy(b)?.as_str(), q.as_items())?;
+    let cnt_m: u64 = exec(transaction, builder.build_merge(b)?.as_str())?;
+    exec(transaction, builder.build_clear(b)?.as_str())?;
+    Ok(cnt_c + cnt_m)
+}
+
+/// Creates bulk load requests handler which copies items into a staging table and merges them.
+///
+/// The count has the same meaning as the one returned by `upsert_bytes_all_new_mut`:
+/// the sum of rows affected by the create queries and the merge queries.
+///
+/// # Arguments
+/// - exec: Executes create/stage/merge/clear queries which uses mutable transaction object.
+/// - copy: Copies items into the staging table using the copy query string.
+/// - builder: Builds bulk load query strings.
+pub fn load_bytes_all_new_mut<E, P, B, I, T>(
+    exec: E,
+    copy: P,
+    builder: B,
+) -> impl Fn(I, &mut T) -> Result<u64, Event>
+where
+    E: Fn(&mut T, &str) -> Result<u64, Event>,
+    P: Fn(&mut T, &str, &[Item<Vec<u8>, Vec<u8>>]) -> Result<u64, Event>,
+    B: LoadBuilder,
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    move |mut requests: I, transaction: &mut T| {
+        requests.try_fold(0, |tot, req| {
+            load_bytes_mut(&req, &exec, &copy, &builder, transaction).map(|cnt| cnt + tot)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_load {
+
+    mod load_bytes_all_new_mut {
+
+        use crate::bucket::Bucket;
+        use crate::item::Item;
+        use crate::load::{self, load_builder_new};
+        use crate::upsert::BulkRequest;
+
+        struct DummyTransaction {
+            queries: Vec<String>,
+            staged: usize,
+        }
+
+        #[test]
+        fn test_single_request() {
+            let exec = |t: &mut DummyTransaction, q: &str| {
+                t.queries.push(String::from(q));
+                match q {
+                    "merge" => Ok(t.staged as u64),
+                    _ => Ok(0),
+                }
+            };
+            let copy = |t: &mut DummyTransaction, q: &str, items: &[Item<Vec<u8>, Vec<u8>>]| {
+                t.queries.push(String::from(q));
+                t.staged = items.len();
+                Ok(items.len() as u64)
+            };
+            let b = load_builder_new(
+                |_: &Bucket| Ok(String::from("create")),
+                |_: &Bucket| Ok(String::from("stage")),
+                |_: &Bucket| Ok(String::from("copy")),
+                |_: &Bucket| Ok(String::from("merge")),
+                |_: &Bucket| Ok(String::from("clear")),
+            );
+            let f = load::load_bytes_all_new_mut(exec, copy, b);
+            let req = vec![BulkRequest::new(
+                Bucket::from(String::from("devices")),
+                vec![Item::new(vec![0x01], vec![]), Item::new(vec![0x02], vec![])],
+            )];
+            let mut dt = DummyTransaction {
+                queries: vec![],
+                staged: 0,
+            };
+            let cnt: u64 = f(req.into_iter(), &mut dt).unwrap();
+            assert_eq!(cnt, 2);
+            assert_eq!(
+                dt.queries,
+                vec!["create", "stage", "copy", "merge", "clear"]
+            );
+        }
+    }
+}
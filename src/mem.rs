@@ -0,0 +1,322 @@
+//! An in-memory reference backend.
+//!
+//! The "query string" of this backend is the bucket name: use `builder`, `upsert_builder` and
+//! `page_builder` to build them, and pass the executor functions below to the handlers.
+//!
+//! Semantics follow the SQL examples:
+//! - Upserting an unchanged value is not counted(`WHERE val <> excluded.val`).
+//! - Selecting/deleting/listing a missing bucket returns `Event::BucketNotFound`.
+//! - Dropping a missing bucket is OK(`DROP TABLE IF EXISTS`).
+
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::ops::Bound;
+
+use crate::blob::BlobIo;
+use crate::bucket::Bucket;
+use crate::evt::Event;
+use crate::item::{Item, RawItem};
+use crate::list::{page_builder_new, PageBuilder};
+use crate::upsert::{upsert_builder_new, UpsertBuilder, UpsertOutcome};
+
+/// Key/value pairs ordered by the key.
+pub type MemoryBucket = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// An in-memory key/value store.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MemoryKv {
+    buckets: BTreeMap<String, MemoryBucket>,
+}
+
+impl MemoryKv {
+    /// Creates new empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the bucket if exists.
+    pub fn bucket(&self, name: &str) -> Option<&MemoryBucket> {
+        self.buckets.get(name)
+    }
+
+    /// Gets bucket names ordered by the name.
+    pub fn bucket_names(&self) -> impl Iterator<Item = &str> {
+        self.buckets.keys().map(|b| b.as_str())
+    }
+
+    /// Runs the closure atomically: changes are discarded if the closure fails.
+    pub fn atomic<F, R>(&mut self, f: F) -> Result<R, Event>
+    where
+        F: FnOnce(&mut MemoryKv) -> Result<R, Event>,
+    {
+        let snapshot: MemoryKv = self.clone();
+        let r: Result<R, Event> = f(self);
+        if r.is_err() {
+            *self = snapshot;
+        }
+        r
+    }
+
+    fn get_mut(&mut self, name: &str) -> Result<&mut MemoryBucket, Event> {
+        self.buckets
+            .get_mut(name)
+            .ok_or_else(|| Event::BucketNotFound(String::from(name)))
+    }
+
+    fn get(&self, name: &str) -> Result<&MemoryBucket, Event> {
+        self.buckets
+            .get(name)
+            .ok_or_else(|| Event::BucketNotFound(String::from(name)))
+    }
+}
+
+/// Creates new query builder which uses the bucket name as the query string.
+pub fn builder() -> impl Fn(&Bucket) -> Result<String, Event> {
+    move |b: &Bucket| Ok(String::from(b.as_str()))
+}
+
+/// Creates new `UpsertBuilder` for `MemoryKv`.
+pub fn upsert_builder() -> impl UpsertBuilder {
+    upsert_builder_new(builder(), builder())
+}
+
+/// Creates new `PageBuilder` for `MemoryKv`.
+pub fn page_builder() -> impl PageBuilder {
+    page_builder_new(builder(), builder())
+}
+
+/// Creates the bucket if not exists and returns the number of created buckets.
+pub fn create(kv: &mut MemoryKv, bucket: &str) -> Result<u64, Event> {
+    match kv.buckets.contains_key(bucket) {
+        true => Ok(0),
+        false => {
+            kv.buckets.insert(String::from(bucket), MemoryBucket::new());
+            Ok(1)
+        }
+    }
+}
+
+/// Upserts the key/value pair and returns the outcome.
+pub fn upsert_outcome(
+    kv: &mut MemoryKv,
+    bucket: &str,
+    key: &[u8],
+    val: &[u8],
+) -> Result<UpsertOutcome, Event> {
+    let m: &mut MemoryBucket = kv.get_mut(bucket)?;
+    match m.insert(key.to_vec(), val.to_vec()) {
+        None => Ok(UpsertOutcome::Inserted),
+        Some(prev) if prev == val => Ok(UpsertOutcome::Unchanged),
+        Some(_) => Ok(UpsertOutcome::Updated),
+    }
+}
+
+/// Upserts the key/value pair and returns the number of changed rows.
+pub fn upsert(kv: &mut MemoryKv, bucket: &str, key: &[u8], val: &[u8]) -> Result<u64, Event> {
+    upsert_outcome(kv, bucket, key, val).map(|o| match o {
+        UpsertOutcome::Unchanged => 0,
+        _ => 1,
+    })
+}
+
+/// Upserts the key/value pair and returns the previous value.
+pub fn upsert_returning(
+    kv: &mut MemoryKv,
+    bucket: &str,
+    key: &[u8],
+    val: &[u8],
+) -> Result<Option<Vec<u8>>, Event> {
+    let m: &mut MemoryBucket = kv.get_mut(bucket)?;
+    Ok(m.insert(key.to_vec(), val.to_vec()))
+}
+
+/// Gets the value of the key.
+pub fn select(kv: &mut MemoryKv, bucket: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Event> {
+    kv.get(bucket).map(|m| m.get(key).cloned())
+}
+
+/// Replaces the value if it equals the old value and returns the number of replaced rows.
+pub fn swap(
+    kv: &mut MemoryKv,
+    bucket: &str,
+    key: &[u8],
+    old: &[u8],
+    new: &[u8],
+) -> Result<u64, Event> {
+    let m: &mut MemoryBucket = kv.get_mut(bucket)?;
+    match m.get_mut(key) {
+        Some(v) if v.as_slice() == old => {
+            *v = new.to_vec();
+            Ok(1)
+        }
+        _ => Ok(0),
+    }
+}
+
+/// Deletes the key and returns the number of deleted rows.
+pub fn delete(kv: &mut MemoryKv, bucket: &str, key: &[u8]) -> Result<u64, Event> {
+    let m: &mut MemoryBucket = kv.get_mut(bucket)?;
+    Ok(m.remove(key).map(|_| 1).unwrap_or(0))
+}
+
+/// Gets keys ordered by the key.
+pub fn list_keys(kv: &mut MemoryKv, bucket: &str) -> Result<Vec<Vec<u8>>, Event> {
+    kv.get(bucket).map(|m| m.keys().cloned().collect())
+}
+
+/// Gets up to `limit` items after the key(if any) ordered by the key.
+pub fn page(
+    kv: &mut MemoryKv,
+    bucket: &str,
+    after: Option<&[u8]>,
+    limit: u64,
+) -> Result<Vec<RawItem>, Event> {
+    let m: &MemoryBucket = kv.get(bucket)?;
+    let lower: Bound<&[u8]> = after.map(Bound::Excluded).unwrap_or(Bound::Unbounded);
+    let items = m
+        .range::<[u8], _>((lower, Bound::Unbounded))
+        .take(limit.try_into().unwrap_or(usize::MAX))
+        .map(|(k, v)| Item::new(k.clone(), v.clone()));
+    Ok(items.collect())
+}
+
+/// Gets bucket names(the query string is ignored).
+pub fn list_buckets(kv: &mut MemoryKv, _query: &str) -> Result<Vec<String>, Event> {
+    Ok(kv.bucket_names().map(String::from).collect())
+}
+
+/// Drops the bucket if exists.
+pub fn drop(kv: &mut MemoryKv, bucket: &str) -> Result<(), Event> {
+    kv.buckets.remove(bucket);
+    Ok(())
+}
+
+impl BlobIo for MemoryKv {
+    type Reader<'a> = Cursor<&'a [u8]>;
+    type Writer<'a> = Cursor<&'a mut [u8]>;
+
+    fn open_value_reader<'a>(
+        &'a mut self,
+        b: &Bucket,
+        key: &[u8],
+    ) -> Result<Option<Self::Reader<'a>>, Event> {
+        let m: &MemoryBucket = self.get(b.as_str())?;
+        Ok(m.get(key).map(|v| Cursor::new(v.as_slice())))
+    }
+
+    fn open_value_writer<'a>(
+        &'a mut self,
+        b: &Bucket,
+        key: &[u8],
+        len: u64,
+    ) -> Result<Self::Writer<'a>, Event> {
+        let len: usize = len
+            .try_into()
+            .map_err(|_| Event::UnexpectedError(format!("Too large value: {}", len)))?;
+        let m: &mut MemoryBucket = self.get_mut(b.as_str())?;
+        let v: &mut Vec<u8> = m.entry(key.to_vec()).or_default();
+        *v = vec![0; len];
+        Ok(Cursor::new(v.as_mut_slice()))
+    }
+}
+
+#[cfg(test)]
+mod test_mem {
+
+    mod handlers {
+        use crate::bucket::Bucket;
+        use crate::del::{delete_key_bytes_mut, drop_bucket_mut};
+        use crate::evt::Event;
+        use crate::get::{select_bytes_new_mut, GetRequest};
+        use crate::item::Item;
+        use crate::list::{list_items_page_new_mut, list_keys_bytes_new_mut, PageRequest};
+        use crate::mem::{self, MemoryKv};
+        use crate::upsert::{upsert_bytes_all_new_mut, BulkRequest};
+
+        fn bucket() -> Bucket {
+            Bucket::from(String::from("devices"))
+        }
+
+        fn put(kv: &mut MemoryKv, items: Vec<(&str, &str)>) -> u64 {
+            let f = upsert_bytes_all_new_mut(mem::create, mem::upsert, mem::upsert_builder());
+            let items = items
+                .into_iter()
+                .map(|(k, v)| Item::new(k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+                .collect();
+            let req = BulkRequest::new(bucket(), items);
+            f(vec![req].into_iter(), kv).unwrap()
+        }
+
+        #[test]
+        fn test_upsert_select() {
+            let mut kv = MemoryKv::new();
+            assert_eq!(put(&mut kv, vec![("a", "1"), ("b", "2")]), 3);
+            assert_eq!(put(&mut kv, vec![("a", "1"), ("b", "3")]), 1);
+
+            let f = select_bytes_new_mut(mem::select, mem::builder());
+            let req = GetRequest::new(bucket(), b"b".to_vec());
+            assert_eq!(f(&req, &mut kv).unwrap(), Some(b"3".to_vec()));
+            let req = GetRequest::new(bucket(), b"c".to_vec());
+            assert_eq!(f(&req, &mut kv).unwrap(), None);
+        }
+
+        #[test]
+        fn test_missing_bucket() {
+            let mut kv = MemoryKv::new();
+            let f = select_bytes_new_mut(mem::select, mem::builder());
+            let req = GetRequest::new(bucket(), b"a".to_vec());
+            match f(&req, &mut kv) {
+                Err(Event::BucketNotFound(b)) => assert_eq!(b, "devices"),
+                _ => panic!("BucketNotFound expected"),
+            }
+
+            let d = drop_bucket_mut(mem::drop, mem::builder());
+            d(&bucket(), &mut kv).unwrap();
+        }
+
+        #[test]
+        fn test_delete_list() {
+            let mut kv = MemoryKv::new();
+            put(&mut kv, vec![("c", "3"), ("a", "1"), ("b", "2")]);
+
+            let d = delete_key_bytes_mut(mem::delete, mem::builder());
+            assert_eq!(d(&bucket(), b"b", &mut kv).unwrap(), 1);
+            assert_eq!(d(&bucket(), b"b", &mut kv).unwrap(), 0);
+
+            let l = list_keys_bytes_new_mut(mem::list_keys, mem::builder());
+            assert_eq!(
+                l(&bucket(), &mut kv).unwrap(),
+                vec![b"a".to_vec(), b"c".to_vec()]
+            );
+
+            let p = list_items_page_new_mut(mem::page, mem::page_builder());
+            let req = PageRequest::new(bucket(), Some(b"a".to_vec()), 10);
+            let keys: Vec<Vec<u8>> = p(&req, &mut kv)
+                .unwrap()
+                .into_iter()
+                .map(|i| i.into_pair().0)
+                .collect();
+            assert_eq!(keys, vec![b"c".to_vec()]);
+
+            let req = PageRequest::new(bucket(), Some(b"b".to_vec()), 10);
+            assert_eq!(p(&req, &mut kv).unwrap().len(), 1);
+            let req = PageRequest::new(bucket(), None, 1);
+            assert_eq!(p(&req, &mut kv).unwrap()[0].as_key(), b"a");
+        }
+
+        #[test]
+        fn test_atomic() {
+            let mut kv = MemoryKv::new();
+            put(&mut kv, vec![("a", "1")]);
+            let r: Result<(), Event> = kv.atomic(|kv: &mut MemoryKv| {
+                mem::upsert(kv, "devices", b"a", b"2")?;
+                Err(Event::UnexpectedError(String::from("rollback")))
+            });
+            assert!(r.is_err());
+            assert_eq!(
+                kv.bucket("devices").unwrap().get(b"a".as_slice()),
+                Some(&b"1".to_vec())
+            );
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::bucket::Bucket;
+use crate::evt::Event;
+use crate::upsert::{UpsertBuilder, UpsertBuilderMulti};
+
+/// The default number of query strings memoized per builder.
+pub const MEMO_CAPACITY_DEFAULT: usize = 1024;
+
+/// A map which evicts the least recently used entry when full.
+struct Lru<K, V> {
+    entries: HashMap<K, (V, u64)>,
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    capacity: usize,
+}
+
+impl<K, V> Lru<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            capacity: capacity.max(1),
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        let e: &mut (V, u64) = self.entries.get_mut(key)?;
+        self.order.remove(&e.1);
+        self.tick += 1;
+        e.1 = self.tick;
+        self.order.insert(self.tick, key.clone());
+        Some(&e.0)
+    }
+
+    fn insert(&mut self, key: K, val: V) {
+        if let Some((_, t)) = self.entries.remove(&key) {
+            self.order.remove(&t);
+        }
+        while self.capacity <= self.entries.len() {
+            match self.order.pop_first() {
+                Some((_, oldest)) => self.entries.remove(&oldest),
+                None => break,
+            };
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (val, self.tick));
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+fn memo_lock<K>(m: &Mutex<Lru<K, String>>) -> Result<MutexGuard<'_, Lru<K, String>>, Event> {
+    m.lock()
+        .map_err(|e| Event::UnexpectedError(format!("Unable to lock query memo: {}", e)))
+}
+
+/// Gets the memoized query string or builds new one(the lock is not held while building).
+fn memo_get_or_build<K, F>(m: &Mutex<Lru<K, String>>, key: K, build: F) -> Result<String, Event>
+where
+    K: Hash + Eq + Clone,
+    F: FnOnce() -> Result<String, Event>,
+{
+    let memoized: Option<String> = memo_lock(m)?.get(&key).cloned();
+    match memoized {
+        Some(query) => Ok(query),
+        None => {
+            let query: String = build()?;
+            memo_lock(m)?.insert(key, query.clone());
+            Ok(query)
+        }
+    }
+}
+
+/// Creates new query builder which memoizes query strings per bucket.
+///
+/// Up to `MEMO_CAPACITY_DEFAULT` buckets are memoized(see `builder_memo_with_capacity`).
+///
+/// # Arguments
+/// - builder: Builds query string for buckets not seen yet.
+pub fn builder_memo_new<B>(builder: B) -> impl Fn(&Bucket) -> Result<String, Event>
+where
+    B: Fn(&Bucket) -> Result<String, Event>,
+{
+    builder_memo_with_capacity(builder, MEMO_CAPACITY_DEFAULT)
+}
+
+/// Creates new query builder which memoizes query strings of recently used buckets.
+///
+/// # Arguments
+/// - builder: Builds query string for buckets not memoized.
+/// - capacity: The maximum number of memoized buckets.
+pub fn builder_memo_with_capacity<B>(
+    builder: B,
+    capacity: usize,
+) -> impl Fn(&Bucket) -> Result<String, Event>
+where
+    B: Fn(&Bucket) -> Result<String, Event>,
+{
+    let m: Mutex<Lru<String, String>> = Mutex::new(Lru::new(capacity));
+    move |b: &Bucket| memo_get_or_build(&m, String::from(b.as_str()), || builder(b))
+}
+
+struct UpsertBuilderMemo<B> {
+    builder: B,
+    create: Mutex<Lru<String, String>>,
+    upsert: Mutex<Lru<String, String>>,
+    multi: Mutex<Lru<(String, usize), String>>,
+}
+
+impl<B> UpsertBuilder for UpsertBuilderMemo<B>
+where
+    B: UpsertBuilder,
+{
+    fn build_create(&self, b: &Bucket) -> Result<String, Event> {
+        memo_get_or_build(&self.create, String::from(b.as_str()), || {
+            self.builder.build_create(b)
+        })
+    }
+    fn build_upsert(&self, b: &Bucket) -> Result<String, Event> {
+        memo_get_or_build(&self.upsert, String::from(b.as_str()), || {
+            self.builder.build_upsert(b)
+        })
+    }
+}
+
+impl<B> UpsertBuilderMulti for UpsertBuilderMemo<B>
+where
+    B: UpsertBuilderMulti,
+{
+    fn build_upsert_multi(&self, b: &Bucket, rows: usize) -> Result<String, Event> {
+        memo_get_or_build(&self.multi, (String::from(b.as_str()), rows), || {
+            self.builder.build_upsert_multi(b, rows)
+        })
+    }
+}
+
+fn upsert_builder_memo<B>(builder: B, capacity: usize) -> UpsertBuilderMemo<B> {
+    UpsertBuilderMemo {
+        builder,
+        create: Mutex::new(Lru::new(capacity)),
+        upsert: Mutex::new(Lru::new(capacity)),
+        multi: Mutex::new(Lru::new(capacity)),
+    }
+}
+
+/// Creates new `UpsertBuilder` which memoizes create/upsert query strings per bucket.
+///
+/// Up to `MEMO_CAPACITY_DEFAULT` buckets are memoized.
+pub fn upsert_builder_memo_new<B>(builder: B) -> impl UpsertBuilder
+where
+    B: UpsertBuilder,
+{
+    upsert_builder_memo(builder, MEMO_CAPACITY_DEFAULT)
+}
+
+/// Creates new `UpsertBuilder` which memoizes query strings of up to `capacity` buckets.
+pub fn upsert_builder_memo_with_capacity<B>(builder: B, capacity: usize) -> impl UpsertBuilder
+where
+    B: UpsertBuilder,
+{
+    upsert_builder_memo(builder, capacity)
+}
+
+/// Creates new `UpsertBuilderMulti` which memoizes query strings per bucket(and rows).
+///
+/// Up to `MEMO_CAPACITY_DEFAULT` entries are memoized per query kind.
+pub fn upsert_builder_multi_memo_new<B>(builder: B) -> impl UpsertBuilderMulti
+where
+    B: UpsertBuilderMulti,
+{
+    upsert_builder_memo(builder, MEMO_CAPACITY_DEFAULT)
+}
+
+/// Creates new `UpsertBuilderMulti` which memoizes up to `capacity` entries per query kind.
+pub fn upsert_builder_multi_memo_with_capacity<B>(
+    builder: B,
+    capacity: usize,
+) -> impl UpsertBuilderMulti
+where
+    B: UpsertBuilderMulti,
+{
+    upsert_builder_memo(builder, capacity)
+}
+
+/// A kind of query executed by request handlers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Create,
+    Upsert,
+    Select,
+    Delete,
+    List,
+    Drop,
+}
+
+/// A per-connection cache of prepared statements.
+///
+/// Statements are keyed by the operation and the bucket; the least recently used statement is
+/// evicted when a new statement is added to the full cache.
+pub struct StatementCache<S> {
+    statements: Lru<(Operation, String), S>,
+}
+
+impl<S> StatementCache<S> {
+    /// Creates new cache which holds up to `capacity` statements.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            statements: Lru::new(capacity),
+        }
+    }
+
+    /// Gets the cached statement or prepares new one.
+    ///
+    /// # Arguments
+    /// - op: The kind of the query.
+    /// - b: The bucket of the query.
+    /// - builder: Builds the query string(called only if the statement is not cached).
+    /// - prepare: Prepares the query string.
+    pub fn get_or_prepare<B, P>(
+        &mut self,
+        op: Operation,
+        b: &Bucket,
+        builder: B,
+        prepare: P,
+    ) -> Result<&S, Event>
+    where
+        B: FnOnce(&Bucket) -> Result<String, Event>,
+        P: FnOnce(&str) -> Result<S, Event>,
+    {
+        let key = (op, String::from(b.as_str()));
+        if self.statements.get(&key).is_none() {
+            let query: String = builder(b)?;
+            let s: S = prepare(query.as_str())?;
+            self.statements.insert(key.clone(), s);
+        }
+        self.statements
+            .get(&key)
+            .ok_or_else(|| Event::UnexpectedError(String::from("Statement not cached")))
+    }
+
+    /// Gets the number of cached statements.
+    pub fn len(&self) -> usize {
+        self.statements.len()
+    }
+
+    /// Checks if no statement is cached.
+    pub fn is_empty(&self) -> bool {
+        0 == self.statements.len()
+    }
+
+    /// Removes all cached statements.
+    pub fn clear(&mut self) {
+        self.statements.clear()
+    }
+}
+
+#[cfg(test)]
+mod test_memo {
+
+    mod builder_memo_new {
+
+        use std::cell::Cell;
+
+        use crate::bucket::Bucket;
+        use crate::memo;
+
+        #[test]
+        fn test_build_once() {
+            let built: Cell<u64> = Cell::new(0);
+            let f = memo::builder_memo_new(|b: &Bucket| {
+                built.set(built.get() + 1);
+                Ok(format!("SELECT key FROM {}", b.as_str()))
+            });
+            let d = Bucket::from(String::from("dates"));
+            let v = Bucket::from(String::from("devices"));
+            assert_eq!(f(&d).unwrap(), "SELECT key FROM dates");
+            assert_eq!(f(&d).unwrap(), "SELECT key FROM dates");
+            assert_eq!(f(&v).unwrap(), "SELECT key FROM devices");
+            assert_eq!(built.get(), 2);
+        }
+
+        #[test]
+        fn test_capacity() {
+            let built: Cell<u64> = Cell::new(0);
+            let f = memo::builder_memo_with_capacity(
+                |b: &Bucket| {
+                    built.set(built.get() + 1);
+                    Ok(format!("SELECT key FROM {}", b.as_str()))
+                },
+                1,
+            );
+            let d = Bucket::from(String::from("dates"));
+            let v = Bucket::from(String::from("devices"));
+            f(&d).unwrap();
+            f(&v).unwrap();
+            f(&v).unwrap();
+            assert_eq!(built.get(), 2);
+            f(&d).unwrap();
+            assert_eq!(built.get(), 3);
+        }
+    }
+
+    mod upsert_builder_multi_memo_new {
+
+        use std::cell::Cell;
+
+        use crate::bucket::Bucket;
+        use crate::memo;
+        use crate::upsert::{upsert_builder_multi_new, UpsertBuilder, UpsertBuilderMulti};
+
+        #[test]
+        fn test_build_once() {
+            let built: Cell<u64> = Cell::new(0);
+            let b = memo::upsert_builder_multi_memo_new(upsert_builder_multi_new(
+                |_: &Bucket| Ok(String::from("create")),
+                |_: &Bucket| Ok(String::from("upsert")),
+                |_: &Bucket, rows: usize| {
+                    built.set(built.get() + 1);
+                    Ok(format!("upsert {}", rows))
+                },
+            ));
+            let d = Bucket::from(String::from("dates"));
+            assert_eq!(b.build_create(&d).unwrap(), "create");
+            assert_eq!(b.build_upsert_multi(&d, 2).unwrap(), "upsert 2");
+            assert_eq!(b.build_upsert_multi(&d, 2).unwrap(), "upsert 2");
+            assert_eq!(b.build_upsert_multi(&d, 1).unwrap(), "upsert 1");
+            assert_eq!(built.get(), 2);
+        }
+    }
+
+    mod statement_cache {
+
+        use crate::bucket::Bucket;
+        use crate::evt::Event;
+        use crate::memo::{Operation, StatementCache};
+
+        fn prepare(
+            c: &mut StatementCache<String>,
+            op: Operation,
+            bucket: &str,
+            prepared: &mut u64,
+        ) -> Result<String, Event> {
+            let b = Bucket::from(String::from(bucket));
+            c.get_or_prepare(
+                op,
+                &b,
+                |b: &Bucket| Ok(format!("{:?} {}", op, b.as_str())),
+                |q: &str| {
+                    *prepared += 1;
+                    Ok(String::from(q))
+                },
+            )
+            .cloned()
+        }
+
+        #[test]
+        fn test_prepare_once() {
+            let mut c: StatementCache<String> = StatementCache::new(2);
+            let mut prepared: u64 = 0;
+            assert_eq!(
+                prepare(&mut c, Operation::Select, "b1", &mut prepared).unwrap(),
+                "Select b1"
+            );
+            assert_eq!(
+                prepare(&mut c, Operation::Select, "b1", &mut prepared).unwrap(),
+                "Select b1"
+            );
+            assert_eq!(
+                prepare(&mut c, Operation::Delete, "b1", &mut prepared).unwrap(),
+                "Delete b1"
+            );
+            assert_eq!(c.len(), 2);
+            assert_eq!(prepared, 2);
+        }
+
+        #[test]
+        fn test_evict_lru() {
+            let mut c: StatementCache<String> = StatementCache::new(2);
+            let mut prepared: u64 = 0;
+            prepare(&mut c, Operation::Select, "b1", &mut prepared).unwrap();
+            prepare(&mut c, Operation::Select, "b2", &mut prepared).unwrap();
+            prepare(&mut c, Operation::Select, "b1", &mut prepared).unwrap();
+            prepare(&mut c, Operation::Select, "b3", &mut prepared).unwrap();
+            assert_eq!(c.len(), 2);
+            prepare(&mut c, Operation::Select, "b1", &mut prepared).unwrap();
+            assert_eq!(prepared, 3);
+            prepare(&mut c, Operation::Select, "b2", &mut prepared).unwrap();
+            assert_eq!(prepared, 4);
+        }
+
+        #[test]
+        fn test_prepare_error() {
+            let mut c: StatementCache<String> = StatementCache::new(2);
+            let b = Bucket::from(String::from("b"));
+            let r = c.get_or_prepare(
+                Operation::Select,
+                &b,
+                |_: &Bucket| Ok(String::from("q")),
+                |_: &str| Err(Event::UnexpectedError(String::from("syntax error"))),
+            );
+            assert!(r.is_err());
+            assert!(c.is_empty());
+        }
+    }
+}
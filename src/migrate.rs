@@ -0,0 +1,324 @@
+use crate::bucket::Bucket;
+use crate::evt::Event;
+use crate::item::RawItem;
+use crate::list::{visit_items_mut, PageRequest};
+use crate::upsert::BulkRequest;
+
+/// Progress of a bucket migration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    bucket: String,
+    copied: u64,
+    last_key: Option<Vec<u8>>,
+}
+
+impl Progress {
+    /// Gets the bucket name.
+    pub fn as_bucket(&self) -> &str {
+        self.bucket.as_str()
+    }
+
+    /// Gets the number of items copied so far.
+    pub fn copied(&self) -> u64 {
+        self.copied
+    }
+
+    /// Gets the last copied key; pass it as `after` to resume the migration.
+    pub fn as_last_key(&self) -> Option<&[u8]> {
+        self.last_key.as_deref()
+    }
+}
+
+/// Creates new bucket migrator which copies items from a source to a destination page by page.
+///
+/// Each page is upserted as a single request and committed, then `progress` is called; a
+/// persisted last key never points past an uncommitted page.
+/// Returns the number of copied items.
+///
+/// # Arguments
+/// - page: Gets a page of items from the source(see `list_items_page_new_mut`).
+/// - upsert: Upserts requests into the destination(see `upsert_bytes_all_new_mut`).
+/// - commit: Commits the upserted page on the destination(a no-op for autocommit clients).
+/// - page_size: The number of items per page.
+/// - progress: Receives the progress after each committed page.
+pub fn migrate_bucket_new_mut<P, U, K, G, S, D>(
+    page: P,
+    upsert: U,
+    commit: K,
+    page_size: u64,
+    progress: G,
+) -> impl Fn(&Bucket, Option<Vec<u8>>, &mut S, &mut D) -> Result<u64, Event>
+where
+    P: Fn(&PageRequest, &mut S) -> Result<Vec<RawItem>, Event>,
+    U: Fn(std::vec::IntoIter<BulkRequest<Vec<u8>, Vec<u8>>>, &mut D) -> Result<u64, Event>,
+    K: Fn(&mut D) -> Result<(), Event>,
+    G: Fn(&Progress),
+{
+    let sz: u64 = page_size.max(1);
+    move |b: &Bucket, after: Option<Vec<u8>>, src: &mut S, dst: &mut D| {
+        let mut p = Progress {
+            bucket: String::from(b.as_str()),
+            copied: 0,
+            last_key: after,
+        };
+        loop {
+            let req = PageRequest::new(
+                Bucket::from(String::from(b.as_str())),
+                p.last_key.clone(),
+                sz,
+            );
+            let items: Vec<RawItem> = page(&req, src)?;
+            let cnt: u64 = items.len() as u64;
+            let last: Option<Vec<u8>> = items.last().map(|i| i.as_key().clone());
+            if 0 < cnt {
+                let breq = BulkRequest::new(Bucket::from(String::from(b.as_str())), items);
+                upsert(vec![breq].into_iter(), dst)?;
+                commit(dst)?;
+                p.copied += cnt;
+                p.last_key = last;
+                progress(&p);
+            }
+            if cnt < sz {
+                return Ok(p.copied);
+            }
+        }
+    }
+}
+
+/// Creates new migrator which copies all buckets listed on the source.
+///
+/// Returns the number of copied items.
+///
+/// # Arguments
+/// - buckets: Lists buckets on the source(see `list_buckets_new_mut`).
+/// - migrate: Copies a bucket(see `migrate_bucket_new_mut`).
+pub fn migrate_all_new_mut<L, M, S, D>(
+    buckets: L,
+    migrate: M,
+) -> impl Fn(&mut S, &mut D) -> Result<u64, Event>
+where
+    L: Fn(&mut S) -> Result<Vec<Bucket>, Event>,
+    M: Fn(&Bucket, Option<Vec<u8>>, &mut S, &mut D) -> Result<u64, Event>,
+{
+    move |src: &mut S, dst: &mut D| {
+        let all: Vec<Bucket> = buckets(src)?;
+        all.iter()
+            .try_fold(0, |tot, b| migrate(b, None, src, dst).map(|cnt| cnt + tot))
+    }
+}
+
+/// The number of items and the content hash of a bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BucketDigest {
+    count: u64,
+    hash: u64,
+}
+
+impl BucketDigest {
+    /// Gets the number of items.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Gets the FNV-1a hash of length-prefixed keys and values in the key order.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv1a(h: u64, data: &[u8]) -> u64 {
+    data.iter()
+        .fold(h, |h, b| (h ^ u64::from(*b)).wrapping_mul(FNV_PRIME))
+}
+
+fn fnv1a_sized(h: u64, data: &[u8]) -> u64 {
+    let sz: [u8; 8] = (data.len() as u64).to_be_bytes();
+    fnv1a(fnv1a(h, &sz), data)
+}
+
+/// Creates new digest getter which hashes all items of a bucket.
+///
+/// # Arguments
+/// - page: Gets a page of items(see `list_items_page_new_mut`).
+/// - page_size: The number of items per page.
+pub fn digest_bucket_new_mut<P, C>(
+    page: P,
+    page_size: u64,
+) -> impl Fn(&Bucket, &mut C) -> Result<BucketDigest, Event>
+where
+    P: Fn(&PageRequest, &mut C) -> Result<Vec<RawItem>, Event>,
+{
+    move |b: &Bucket, client: &mut C| {
+        let mut hash: u64 = FNV_OFFSET;
+        let count: u64 = visit_items_mut(&page, b, None, page_size, client, |item: RawItem| {
+            hash = fnv1a_sized(fnv1a_sized(hash, item.as_key()), item.as_val());
+            Ok(())
+        })?;
+        Ok(BucketDigest { count, hash })
+    }
+}
+
+/// Creates new verifier which compares digests of a bucket on the source and the destination.
+///
+/// Returns `Event::UnexpectedError` if the counts or the hashes differ.
+///
+/// # Arguments
+/// - src_digest: Gets the digest from the source(see `digest_bucket_new_mut`).
+/// - dst_digest: Gets the digest from the destination.
+pub fn verify_bucket_new_mut<X, Y, S, D>(
+    src_digest: X,
+    dst_digest: Y,
+) -> impl Fn(&Bucket, &mut S, &mut D) -> Result<BucketDigest, Event>
+where
+    X: Fn(&Bucket, &mut S) -> Result<BucketDigest, Event>,
+    Y: Fn(&Bucket, &mut D) -> Result<BucketDigest, Event>,
+{
+    move |b: &Bucket, src: &mut S, dst: &mut D| {
+        let s: BucketDigest = src_digest(b, src)?;
+        let d: BucketDigest = dst_digest(b, dst)?;
+        match s == d {
+            true => Ok(s),
+            false => Err(Event::UnexpectedError(format!(
+                "Bucket {} differs: source {:?}, destination {:?}",
+                b.as_str(),
+                s,
+                d
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_migrate {
+
+    mod migrate_bucket_new_mut {
+
+        use std::cell::RefCell;
+        use std::collections::BTreeMap;
+
+        use crate::bucket::Bucket;
+        use crate::evt::Event;
+        use crate::item::{Item, RawItem};
+        use crate::list::{list_items_page_new_mut, page_builder_new, PageRequest};
+        use crate::migrate::{self, Progress};
+        use crate::upsert::BulkRequest;
+
+        struct DummyClient {
+            rows: BTreeMap<Vec<u8>, Vec<u8>>,
+            committed: u64,
+        }
+
+        fn page() -> impl Fn(&PageRequest, &mut DummyClient) -> Result<Vec<RawItem>, Event> {
+            let list_getter = |c: &mut DummyClient, _q: &str, after: Option<&[u8]>, limit: u64| {
+                let rows = c.rows.iter().filter(|(k, _)| after < Some(k.as_slice()));
+                let items = rows.map(|(k, v)| Item::new(k.clone(), v.clone()));
+                Ok(items.take(limit as usize).collect())
+            };
+            let builder = page_builder_new(
+                |_: &Bucket| Ok(String::from("")),
+                |_: &Bucket| Ok(String::from("")),
+            );
+            list_items_page_new_mut(list_getter, builder)
+        }
+
+        fn upsert(
+            reqs: std::vec::IntoIter<BulkRequest<Vec<u8>, Vec<u8>>>,
+            c: &mut DummyClient,
+        ) -> Result<u64, Event> {
+            let mut cnt: u64 = 0;
+            for req in reqs {
+                for i in req.as_items() {
+                    c.rows.insert(i.as_key().clone(), i.as_val().clone());
+                    cnt += 1;
+                }
+            }
+            Ok(cnt)
+        }
+
+        fn client(n: u8) -> DummyClient {
+            DummyClient {
+                rows: (0..n).map(|i| (vec![i], vec![i])).collect(),
+                committed: 0,
+            }
+        }
+
+        fn commit(c: &mut DummyClient) -> Result<(), Event> {
+            c.committed += 1;
+            Ok(())
+        }
+
+        #[test]
+        fn test_migrate_verify() {
+            let seen: RefCell<Vec<Progress>> = RefCell::new(vec![]);
+            let f = migrate::migrate_bucket_new_mut(page(), upsert, commit, 2, |p: &Progress| {
+                seen.borrow_mut().push(p.clone())
+            });
+            let b = Bucket::from(String::from("devices"));
+            let mut src = client(5);
+            let mut dst = client(0);
+
+            let cnt: u64 = f(&b, None, &mut src, &mut dst).unwrap();
+            assert_eq!(cnt, 5);
+            assert_eq!(seen.borrow().len(), 3);
+            assert_eq!(seen.borrow()[2].as_last_key(), Some([4u8].as_slice()));
+            assert_eq!(dst.committed, 3);
+
+            let verify = migrate::verify_bucket_new_mut(
+                migrate::digest_bucket_new_mut(page(), 2),
+                migrate::digest_bucket_new_mut(page(), 3),
+            );
+            let d = verify(&b, &mut src, &mut dst).unwrap();
+            assert_eq!(d.count(), 5);
+
+            dst.rows.insert(vec![2], vec![0xff]);
+            assert!(verify(&b, &mut src, &mut dst).is_err());
+        }
+
+        #[test]
+        fn test_resume() {
+            let f = migrate::migrate_bucket_new_mut(page(), upsert, commit, 2, |_: &Progress| {});
+            let b = Bucket::from(String::from("devices"));
+            let mut src = client(5);
+            let mut dst = client(0);
+            let cnt: u64 = f(&b, Some(vec![2]), &mut src, &mut dst).unwrap();
+            assert_eq!(cnt, 2);
+            assert_eq!(
+                dst.rows.keys().cloned().collect::<Vec<_>>(),
+                vec![vec![3], vec![4]]
+            );
+        }
+
+        #[test]
+        fn test_commit_error() {
+            let seen: RefCell<Vec<Progress>> = RefCell::new(vec![]);
+            let failing = |c: &mut DummyClient| match c.committed {
+                0 => commit(c),
+                _ => Err(Event::ConnectionError(String::from("lost"))),
+            };
+            let f = migrate::migrate_bucket_new_mut(page(), upsert, failing, 2, |p: &Progress| {
+                seen.borrow_mut().push(p.clone())
+            });
+            let b = Bucket::from(String::from("devices"));
+            assert!(f(&b, None, &mut client(5), &mut client(0)).is_err());
+            assert_eq!(seen.borrow().len(), 1);
+            assert_eq!(seen.borrow()[0].as_last_key(), Some([1u8].as_slice()));
+        }
+
+        #[test]
+        fn test_all() {
+            let buckets = |_: &mut DummyClient| {
+                Ok(vec![
+                    Bucket::from(String::from("b1")),
+                    Bucket::from(String::from("b2")),
+                ])
+            };
+            let m = migrate::migrate_bucket_new_mut(page(), upsert, commit, 10, |_: &Progress| {});
+            let f = migrate::migrate_all_new_mut(buckets, m);
+            let cnt: u64 = f(&mut client(3), &mut client(0)).unwrap();
+            assert_eq!(cnt, 6);
+        }
+    }
+}
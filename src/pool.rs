@@ -0,0 +1,642 @@
+//! Connection pool integration(`r2d2` for sync clients, `deadpool` for async runtimes).
+//!
+//! - `PingManager` wraps a pool manager and pings the KV store to check pooled connections
+//!   (a sync ping for `r2d2`, an async ping returning `PingFuture` for `deadpool`).
+//! - Pool exhaustion(a checkout timeout) is reported as `Event::PoolTimeout`.
+//! - With `LastError` as the r2d2 error handler, a checkout which failed because connections
+//!   could not be made or pinged reports the last error instead(see `with_r2d2_last_error`).
+
+use std::fmt;
+#[cfg(feature = "r2d2")]
+use std::sync::{Arc, Mutex};
+
+use crate::bucket::Bucket;
+use crate::evt::Event;
+use crate::get::GetRequest;
+#[cfg(feature = "r2d2")]
+use crate::retry::transaction_mut;
+#[cfg(feature = "r2d2")]
+use crate::upsert::BulkRequest;
+
+type RawGetRequest = GetRequest<Vec<u8>>;
+
+/// A boxed future of an async ping(`deadpool`).
+#[cfg(feature = "deadpool")]
+pub type PingFuture<'a> =
+    std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), Event>> + Send + 'a>>;
+
+/// An error from the wrapped manager or from the ping.
+#[derive(Debug)]
+pub enum PingError<E> {
+    Manager(E),
+    Ping(Event),
+}
+
+impl<E> fmt::Display for PingError<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PingError::Manager(e) => write!(f, "{}", e),
+            PingError::Ping(e) => write!(f, "Unable to ping: {:?}", e),
+        }
+    }
+}
+
+impl<E> std::error::Error for PingError<E> where E: fmt::Debug + fmt::Display {}
+
+/// A pool manager which pings the KV store when a pooled connection is checked.
+pub struct PingManager<M, P> {
+    manager: M,
+    ping: P,
+}
+
+impl<M, P> PingManager<M, P> {
+    /// Creates new manager.
+    ///
+    /// # Arguments
+    /// - manager: Creates connections(e.g, `r2d2_postgres::PostgresConnectionManager`).
+    /// - ping: Checks the connection(e.g, `ping_select_new` for r2d2; a function returning
+    ///   `PingFuture` for deadpool).
+    pub fn new(manager: M, ping: P) -> Self {
+        Self { manager, ping }
+    }
+}
+
+/// Creates new ping which selects a key from the bucket.
+///
+/// A missing bucket(`Event::BucketNotFound`) is healthy; the server answered.
+///
+/// # Arguments
+/// - select: The select handler(e.g, `select_bytes_new_mut`).
+/// - bucket: The bucket name to be selected.
+pub fn ping_select_new<S, C>(select: S, bucket: String) -> impl Fn(&mut C) -> Result<(), Event>
+where
+    S: Fn(&RawGetRequest, &mut C) -> Result<Option<Vec<u8>>, Event>,
+{
+    let req: RawGetRequest = GetRequest::new(Bucket::from(bucket), vec![]);
+    move |client: &mut C| match select(&req, client) {
+        Ok(_) | Err(Event::BucketNotFound(_)) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(feature = "r2d2")]
+impl<M, P> r2d2::ManageConnection for PingManager<M, P>
+where
+    M: r2d2::ManageConnection,
+    M::Error: fmt::Display,
+    P: Fn(&mut M::Connection) -> Result<(), Event> + Send + Sync + 'static,
+{
+    type Connection = M::Connection;
+    type Error = PingError<M::Error>;
+
+    fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        self.manager.connect().map_err(PingError::Manager)
+    }
+
+    fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        self.manager.is_valid(conn).map_err(PingError::Manager)?;
+        (self.ping)(conn).map_err(PingError::Ping)
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        self.manager.has_broken(conn)
+    }
+}
+
+/// Checks out a connection from the pool and uses it.
+///
+/// # Arguments
+/// - pool: The pool; `Event::PoolTimeout` is returned if no connection is available in time.
+/// - f: Uses the connection(e.g, starts a transaction and upserts).
+#[cfg(feature = "r2d2")]
+pub fn with_r2d2<M, T, F>(pool: &r2d2::Pool<M>, f: F) -> Result<T, Event>
+where
+    M: r2d2::ManageConnection,
+    F: FnOnce(&mut M::Connection) -> Result<T, Event>,
+{
+    let mut conn: r2d2::PooledConnection<M> = pool
+        .get()
+        .map_err(|e| Event::PoolTimeout(format!("Unable to get a connection: {}", e)))?;
+    f(&mut conn)
+}
+
+/// An r2d2 error handler which keeps the last connect/ping error.
+///
+/// Set a clone as the error handler(`r2d2::Builder::error_handler`) and pass it to
+/// `with_r2d2_last_error`.
+#[cfg(feature = "r2d2")]
+#[derive(Debug, Default, Clone)]
+pub struct LastError {
+    last: Arc<Mutex<Option<Event>>>,
+}
+
+#[cfg(feature = "r2d2")]
+impl LastError {
+    /// Creates new handler without an error.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes the last error(if any).
+    pub fn take(&self) -> Option<Event> {
+        self.last.lock().ok().and_then(|mut l| l.take())
+    }
+}
+
+#[cfg(feature = "r2d2")]
+impl<E> r2d2::HandleError<PingError<E>> for LastError
+where
+    E: fmt::Display,
+{
+    fn handle_error(&self, error: PingError<E>) {
+        let e: Event = match error {
+            PingError::Manager(e) => Event::ConnectionError(format!("Unable to connect: {}", e)),
+            PingError::Ping(e) => e,
+        };
+        if let Ok(mut l) = self.last.lock() {
+            *l = Some(e);
+        }
+    }
+}
+
+/// Checks out a connection from the pool and uses it, reporting why the checkout failed.
+///
+/// Returns the last connect/ping error kept by the handler if any; `Event::PoolTimeout`
+/// otherwise(all connections were in use).
+///
+/// # Arguments
+/// - pool: The pool built with the handler.
+/// - last: The error handler of the pool.
+/// - f: Uses the connection.
+#[cfg(feature = "r2d2")]
+pub fn with_r2d2_last_error<M, T, F>(
+    pool: &r2d2::Pool<M>,
+    last: &LastError,
+    f: F,
+) -> Result<T, Event>
+where
+    M: r2d2::ManageConnection,
+    F: FnOnce(&mut M::Connection) -> Result<T, Event>,
+{
+    let mut conn: r2d2::PooledConnection<M> = pool.get().map_err(|e| {
+        last.take()
+            .unwrap_or_else(|| Event::PoolTimeout(format!("Unable to get a connection: {}", e)))
+    })?;
+    f(&mut conn)
+}
+
+/// Creates upsert requests handler which upserts in a transaction on a pooled connection.
+///
+/// The transaction is rolled back if the upsert or the commit fails.
+///
+/// # Arguments
+/// - upsert: The handler(e.g, `upsert_bytes_all_new_mut`).
+/// - begin: Begins a transaction(e.g, executes `BEGIN`).
+/// - commit: Commits the transaction.
+/// - rollback: Rolls back the transaction.
+#[cfg(feature = "r2d2")]
+pub fn upsert_r2d2_new<U, B, K, L, M, I>(
+    upsert: U,
+    begin: B,
+    commit: K,
+    rollback: L,
+) -> impl Fn(I, &r2d2::Pool<M>) -> Result<u64, Event>
+where
+    U: Fn(I, &mut M::Connection) -> Result<u64, Event>,
+    B: Fn(&mut M::Connection) -> Result<(), Event>,
+    K: Fn(&mut M::Connection) -> Result<(), Event>,
+    L: Fn(&mut M::Connection) -> Result<(), Event>,
+    M: r2d2::ManageConnection,
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    move |requests: I, pool: &r2d2::Pool<M>| {
+        with_r2d2(pool, |conn| {
+            transaction_mut(conn, &begin, &commit, &rollback, |c| upsert(requests, c))
+        })
+    }
+}
+
+/// Creates select request handler which uses a pooled connection.
+///
+/// # Arguments
+/// - select: The handler(e.g, `select_bytes_new_mut`).
+#[cfg(feature = "r2d2")]
+pub fn select_r2d2_new<S, M>(
+    select: S,
+) -> impl Fn(&RawGetRequest, &r2d2::Pool<M>) -> Result<Option<Vec<u8>>, Event>
+where
+    S: Fn(&RawGetRequest, &mut M::Connection) -> Result<Option<Vec<u8>>, Event>,
+    M: r2d2::ManageConnection,
+{
+    move |req: &RawGetRequest, pool: &r2d2::Pool<M>| with_r2d2(pool, |conn| select(req, conn))
+}
+
+/// Creates remover which uses a pooled connection.
+///
+/// # Arguments
+/// - delete: The handler(e.g, `delete_key_bytes_mut`).
+#[cfg(feature = "r2d2")]
+pub fn delete_r2d2_new<D, M>(
+    delete: D,
+) -> impl Fn(&Bucket, &[u8], &r2d2::Pool<M>) -> Result<u64, Event>
+where
+    D: Fn(&Bucket, &[u8], &mut M::Connection) -> Result<u64, Event>,
+    M: r2d2::ManageConnection,
+{
+    move |b: &Bucket, key: &[u8], pool: &r2d2::Pool<M>| with_r2d2(pool, |conn| delete(b, key, conn))
+}
+
+/// Creates keys getter which uses a pooled connection.
+///
+/// # Arguments
+/// - list: The handler(e.g, `list_keys_bytes_new_mut`).
+#[cfg(feature = "r2d2")]
+pub fn list_keys_r2d2_new<L, M>(
+    list: L,
+) -> impl Fn(&Bucket, &r2d2::Pool<M>) -> Result<Vec<Vec<u8>>, Event>
+where
+    L: Fn(&Bucket, &mut M::Connection) -> Result<Vec<Vec<u8>>, Event>,
+    M: r2d2::ManageConnection,
+{
+    move |b: &Bucket, pool: &r2d2::Pool<M>| with_r2d2(pool, |conn| list(b, conn))
+}
+
+/// Creates bucket dropper which uses a pooled connection.
+///
+/// # Arguments
+/// - remove: The handler(e.g, `drop_bucket_mut`).
+#[cfg(feature = "r2d2")]
+pub fn drop_r2d2_new<D, M>(remove: D) -> impl Fn(&Bucket, &r2d2::Pool<M>) -> Result<(), Event>
+where
+    D: Fn(&Bucket, &mut M::Connection) -> Result<(), Event>,
+    M: r2d2::ManageConnection,
+{
+    move |b: &Bucket, pool: &r2d2::Pool<M>| with_r2d2(pool, |conn| remove(b, conn))
+}
+
+#[cfg(feature = "deadpool")]
+impl<M, P> deadpool::managed::Manager for PingManager<M, P>
+where
+    M: deadpool::managed::Manager,
+    P: for<'a> Fn(&'a mut M::Type) -> PingFuture<'a> + Send + Sync,
+{
+    type Type = M::Type;
+    type Error = PingError<M::Error>;
+
+    async fn create(&self) -> Result<Self::Type, Self::Error> {
+        self.manager.create().await.map_err(PingError::Manager)
+    }
+
+    async fn recycle(
+        &self,
+        obj: &mut Self::Type,
+        metrics: &deadpool::managed::Metrics,
+    ) -> deadpool::managed::RecycleResult<Self::Error> {
+        use deadpool::managed::RecycleError;
+        self.manager
+            .recycle(obj, metrics)
+            .await
+            .map_err(|e| match e {
+                RecycleError::Message(m) => RecycleError::Message(m),
+                RecycleError::Backend(e) => RecycleError::Backend(PingError::Manager(e)),
+            })?;
+        (self.ping)(obj)
+            .await
+            .map_err(|e| RecycleError::Backend(PingError::Ping(e)))
+    }
+
+    fn detach(&self, obj: &mut Self::Type) {
+        self.manager.detach(obj)
+    }
+}
+
+/// Checks out an object from the pool and uses it.
+///
+/// # Arguments
+/// - pool: The pool; `Event::PoolTimeout` is returned if no object is available in time.
+/// - f: Uses the object(e.g, a sync client wrapped by the manager).
+#[cfg(feature = "deadpool")]
+pub async fn with_deadpool<M, T, F>(pool: &deadpool::managed::Pool<M>, f: F) -> Result<T, Event>
+where
+    M: deadpool::managed::Manager,
+    M::Error: fmt::Display,
+    F: FnOnce(&mut M::Type) -> Result<T, Event>,
+{
+    use deadpool::managed::PoolError;
+    let mut obj: deadpool::managed::Object<M> = pool.get().await.map_err(|e| match e {
+        PoolError::Timeout(_) => Event::PoolTimeout(format!("Unable to get a connection: {}", e)),
+        _ => Event::ConnectionError(format!("Unable to get a connection: {}", e)),
+    })?;
+    f(&mut obj)
+}
+
+#[cfg(test)]
+mod test_pool {
+
+    mod ping_select_new {
+
+        use crate::evt::Event;
+        use crate::get::GetRequest;
+        use crate::pool;
+
+        #[test]
+        fn test_missing_bucket() {
+            let ping = pool::ping_select_new(
+                |req: &GetRequest<Vec<u8>>, up: &mut bool| match *up {
+                    true => Err(Event::BucketNotFound(String::from(
+                        req.as_bucket().as_str(),
+                    ))),
+                    false => Err(Event::ConnectionError(String::from("down"))),
+                },
+                String::from("ping"),
+            );
+            assert!(ping(&mut true).is_ok());
+            assert!(matches!(ping(&mut false), Err(Event::ConnectionError(_))));
+        }
+    }
+
+    #[cfg(feature = "r2d2")]
+    mod with_r2d2 {
+
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        use crate::evt::Event;
+        use crate::pool::{self, LastError, PingManager};
+
+        #[derive(Debug)]
+        struct Never;
+
+        impl std::fmt::Display for Never {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "never")
+            }
+        }
+
+        impl std::error::Error for Never {}
+
+        struct Counter;
+
+        impl r2d2::ManageConnection for Counter {
+            type Connection = u64;
+            type Error = Never;
+            fn connect(&self) -> Result<u64, Never> {
+                Ok(0)
+            }
+            fn is_valid(&self, _: &mut u64) -> Result<(), Never> {
+                Ok(())
+            }
+            fn has_broken(&self, _: &mut u64) -> bool {
+                false
+            }
+        }
+
+        #[test]
+        fn test_checkout() {
+            let healthy: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
+            let h: Arc<AtomicBool> = healthy.clone();
+            let m = PingManager::new(Counter, move |_: &mut u64| match h.load(Ordering::SeqCst) {
+                true => Ok(()),
+                false => Err(Event::ConnectionError(String::from("down"))),
+            });
+            let p: r2d2::Pool<_> = r2d2::Pool::builder()
+                .max_size(1)
+                .connection_timeout(Duration::from_millis(100))
+                .build(m)
+                .unwrap();
+
+            let cnt: u64 = pool::with_r2d2(&p, |c: &mut u64| {
+                *c += 1;
+                Ok(*c)
+            })
+            .unwrap();
+            assert_eq!(cnt, 1);
+
+            let r: Result<(), Event> = pool::with_r2d2(&p, |_| {
+                let nested = pool::with_r2d2(&p, |_| Ok(()));
+                assert!(matches!(nested, Err(Event::PoolTimeout(_))));
+                Ok(())
+            });
+            assert!(r.is_ok());
+
+            healthy.store(false, Ordering::SeqCst);
+            let r: Result<(), Event> = pool::with_r2d2(&p, |_| Ok(()));
+            assert!(matches!(r, Err(Event::PoolTimeout(_))));
+        }
+
+        #[test]
+        fn test_last_error() {
+            let healthy: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
+            let h: Arc<AtomicBool> = healthy.clone();
+            let m = PingManager::new(Counter, move |_: &mut u64| match h.load(Ordering::SeqCst) {
+                true => Ok(()),
+                false => Err(Event::ConnectionError(String::from("down"))),
+            });
+            let last = LastError::new();
+            let p: r2d2::Pool<_> = r2d2::Pool::builder()
+                .max_size(1)
+                .connection_timeout(Duration::from_millis(100))
+                .error_handler(Box::new(last.clone()))
+                .build(m)
+                .unwrap();
+
+            let r: Result<(), Event> = pool::with_r2d2_last_error(&p, &last, |_| {
+                let nested = pool::with_r2d2_last_error(&p, &last, |_| Ok(()));
+                assert!(matches!(nested, Err(Event::PoolTimeout(_))));
+                Ok(())
+            });
+            assert!(r.is_ok());
+
+            healthy.store(false, Ordering::SeqCst);
+            let r: Result<(), Event> = pool::with_r2d2_last_error(&p, &last, |_| Ok(()));
+            match r {
+                Err(Event::ConnectionError(m)) => assert_eq!(m, "down"),
+                r => panic!("ConnectionError expected: {:?}", r),
+            }
+        }
+    }
+
+    #[cfg(feature = "r2d2")]
+    mod upsert_r2d2_new {
+
+        use std::time::Duration;
+
+        use crate::bucket::Bucket;
+        use crate::evt::Event;
+        use crate::item::Item;
+        use crate::pool;
+        use crate::record::{self, RecordingClient};
+        use crate::upsert::{upsert_builder_new, upsert_bytes_all_new_mut, BulkRequest};
+
+        #[derive(Debug)]
+        struct Never;
+
+        impl std::fmt::Display for Never {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "never")
+            }
+        }
+
+        impl std::error::Error for Never {}
+
+        /// Fails the upsert of the second connection only.
+        struct Recorders;
+
+        impl r2d2::ManageConnection for Recorders {
+            type Connection = RecordingClient;
+            type Error = Never;
+            fn connect(&self) -> Result<RecordingClient, Never> {
+                let mut c = RecordingClient::new();
+                c.fail_at(2, Event::Conflict(String::from("40001")));
+                Ok(c)
+            }
+            fn is_valid(&self, _: &mut RecordingClient) -> Result<(), Never> {
+                Ok(())
+            }
+            fn has_broken(&self, _: &mut RecordingClient) -> bool {
+                false
+            }
+        }
+
+        #[test]
+        fn test_transaction() {
+            let p: r2d2::Pool<_> = r2d2::Pool::builder()
+                .max_size(1)
+                .connection_timeout(Duration::from_millis(100))
+                .build(Recorders)
+                .unwrap();
+            let f = pool::upsert_r2d2_new(
+                upsert_bytes_all_new_mut(
+                    record::execute,
+                    record::upsert,
+                    upsert_builder_new(
+                        |b: &Bucket| Ok(format!("CREATE {}", b.as_str())),
+                        |b: &Bucket| Ok(format!("UPSERT {}", b.as_str())),
+                    ),
+                ),
+                |c: &mut RecordingClient| record::execute_unit(c, "BEGIN"),
+                |c: &mut RecordingClient| record::execute_unit(c, "COMMIT"),
+                |c: &mut RecordingClient| record::execute_unit(c, "ROLLBACK"),
+            );
+            let req = |k: &[u8]| {
+                let item = Item::new(k.to_vec(), b"v".to_vec());
+                vec![BulkRequest::new(
+                    Bucket::from(String::from("b1")),
+                    vec![item],
+                )]
+                .into_iter()
+            };
+
+            let r = f(req(b"k1"), &p);
+            assert!(matches!(r, Err(Event::Conflict(_))));
+            f(req(b"k2"), &p).unwrap();
+
+            let c = p.get().unwrap();
+            let q: Vec<&str> = c.queries().collect();
+            assert_eq!(
+                q,
+                vec![
+                    "BEGIN",
+                    "CREATE b1",
+                    "UPSERT b1",
+                    "ROLLBACK",
+                    "BEGIN",
+                    "CREATE b1",
+                    "UPSERT b1",
+                    "COMMIT",
+                ]
+            );
+        }
+    }
+
+    #[cfg(feature = "deadpool")]
+    mod with_deadpool {
+
+        use std::future::Future;
+        use std::pin::pin;
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::task::{Context, Poll, Waker};
+
+        use crate::evt::Event;
+        use crate::pool::{self, PingFuture, PingManager};
+
+        /// Polls a future which never waits(an uncontended pool without timeouts).
+        fn block_on<F>(f: F) -> F::Output
+        where
+            F: Future,
+        {
+            let mut f = pin!(f);
+            match f.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+                Poll::Ready(o) => o,
+                Poll::Pending => panic!("The future must not wait"),
+            }
+        }
+
+        #[derive(Debug)]
+        struct Never;
+
+        impl std::fmt::Display for Never {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "never")
+            }
+        }
+
+        #[derive(Default)]
+        struct Counter {
+            created: AtomicU64,
+        }
+
+        impl deadpool::managed::Manager for Counter {
+            type Type = u64;
+            type Error = Never;
+
+            async fn create(&self) -> Result<u64, Never> {
+                self.created.fetch_add(1, Ordering::SeqCst);
+                Ok(0)
+            }
+
+            async fn recycle(
+                &self,
+                _: &mut u64,
+                _: &deadpool::managed::Metrics,
+            ) -> deadpool::managed::RecycleResult<Never> {
+                Ok(())
+            }
+        }
+
+        /// A connection is broken once it is marked(non-zero).
+        fn ping(c: &mut u64) -> PingFuture<'_> {
+            Box::pin(async move {
+                match *c {
+                    0 => Ok(()),
+                    _ => Err(Event::ConnectionError(String::from("broken"))),
+                }
+            })
+        }
+
+        #[test]
+        fn test_async_ping() {
+            let p = deadpool::managed::Pool::builder(PingManager::new(Counter::default(), ping))
+                .max_size(1)
+                .build()
+                .unwrap();
+
+            let got: u64 = block_on(pool::with_deadpool(&p, |c: &mut u64| Ok(*c))).unwrap();
+            assert_eq!(got, 0);
+            block_on(pool::with_deadpool(&p, |c: &mut u64| {
+                *c = 1;
+                Ok(())
+            }))
+            .unwrap();
+
+            let got: u64 = block_on(pool::with_deadpool(&p, |c: &mut u64| Ok(*c))).unwrap();
+            assert_eq!(got, 0);
+            let created: u64 = p.manager().manager.created.load(Ordering::SeqCst);
+            assert_eq!(created, 2);
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! A mock client which records queries and bound parameters.
+//!
+//! Pass the executor functions below to the handlers together with a `RecordingClient`,
+//! then assert `calls()`.
+//! Each call consumes the next scripted reply(if any); otherwise it returns an empty result
+//! (0, `None` or an empty list).
+//! A scripted failure replaces the call at the index(0-based) with the event.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::evt::Event;
+use crate::item::RawItem;
+use crate::upsert::UpsertOutcome;
+
+/// A bound parameter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Param {
+    Bytes(Vec<u8>),
+    Int(u64),
+    Null,
+}
+
+/// A recorded call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    query: String,
+    params: Vec<Param>,
+}
+
+impl Call {
+    /// Gets the query string.
+    pub fn as_query(&self) -> &str {
+        self.query.as_str()
+    }
+
+    /// Gets the bound parameters.
+    pub fn as_params(&self) -> &[Param] {
+        &self.params
+    }
+}
+
+/// A scripted reply.
+pub enum Reply {
+    Count(u64),
+    Value(Option<Vec<u8>>),
+    Outcome(UpsertOutcome),
+    Keys(Vec<Vec<u8>>),
+    Items(Vec<RawItem>),
+    Names(Vec<String>),
+}
+
+/// A client which records calls and returns scripted replies.
+#[derive(Default)]
+pub struct RecordingClient {
+    calls: Vec<Call>,
+    replies: VecDeque<Reply>,
+    failures: BTreeMap<usize, Event>,
+}
+
+impl RecordingClient {
+    /// Creates new client without scripted replies.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a scripted reply.
+    pub fn push_reply(&mut self, r: Reply) -> &mut Self {
+        self.replies.push_back(r);
+        self
+    }
+
+    /// Fails the call at the index(0-based) with the event.
+    pub fn fail_at(&mut self, index: usize, e: Event) -> &mut Self {
+        self.failures.insert(index, e);
+        self
+    }
+
+    /// Gets recorded calls(including failed calls).
+    pub fn calls(&self) -> &[Call] {
+        &self.calls
+    }
+
+    /// Gets recorded query strings.
+    pub fn queries(&self) -> impl Iterator<Item = &str> {
+        self.calls.iter().map(|c| c.as_query())
+    }
+
+    /// Removes recorded calls.
+    pub fn clear(&mut self) {
+        self.calls.clear()
+    }
+
+    fn record(&mut self, query: &str, params: Vec<Param>) -> Result<Option<Reply>, Event> {
+        let index: usize = self.calls.len();
+        self.calls.push(Call {
+            query: String::from(query),
+            params,
+        });
+        match self.failures.remove(&index) {
+            Some(e) => Err(e),
+            None => Ok(self.replies.pop_front()),
+        }
+    }
+}
+
+fn bytes(b: &[u8]) -> Param {
+    Param::Bytes(b.to_vec())
+}
+
+fn mismatch(expected: &str) -> Event {
+    Event::UnexpectedError(format!("Scripted reply mismatch: expected {}", expected))
+}
+
+/// Executes a query without parameters(e.g, create) and returns the count.
+pub fn execute(c: &mut RecordingClient, query: &str) -> Result<u64, Event> {
+    match c.record(query, vec![])? {
+        None => Ok(0),
+        Some(Reply::Count(n)) => Ok(n),
+        Some(_) => Err(mismatch("Count")),
+    }
+}
+
+/// Executes a query without parameters(e.g, drop).
+pub fn execute_unit(c: &mut RecordingClient, query: &str) -> Result<(), Event> {
+    execute(c, query).map(|_| ())
+}
+
+/// Upserts a key/value pair and returns the count.
+pub fn upsert(c: &mut RecordingClient, query: &str, key: &[u8], val: &[u8]) -> Result<u64, Event> {
+    match c.record(query, vec![bytes(key), bytes(val)])? {
+        None => Ok(0),
+        Some(Reply::Count(n)) => Ok(n),
+        Some(_) => Err(mismatch("Count")),
+    }
+}
+
+/// Upserts a key/value pair and returns the outcome(`Inserted` if not scripted).
+pub fn upsert_outcome(
+    c: &mut RecordingClient,
+    query: &str,
+    key: &[u8],
+    val: &[u8],
+) -> Result<UpsertOutcome, Event> {
+    match c.record(query, vec![bytes(key), bytes(val)])? {
+        None => Ok(UpsertOutcome::Inserted),
+        Some(Reply::Outcome(o)) => Ok(o),
+        Some(_) => Err(mismatch("Outcome")),
+    }
+}
+
+/// Upserts a key/value pair and returns the previous value.
+pub fn upsert_returning(
+    c: &mut RecordingClient,
+    query: &str,
+    key: &[u8],
+    val: &[u8],
+) -> Result<Option<Vec<u8>>, Event> {
+    match c.record(query, vec![bytes(key), bytes(val)])? {
+        None => Ok(None),
+        Some(Reply::Value(v)) => Ok(v),
+        Some(_) => Err(mismatch("Value")),
+    }
+}
+
+/// Selects the value of a key.
+pub fn select(c: &mut RecordingClient, query: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Event> {
+    match c.record(query, vec![bytes(key)])? {
+        None => Ok(None),
+        Some(Reply::Value(v)) => Ok(v),
+        Some(_) => Err(mismatch("Value")),
+    }
+}
+
+/// Deletes a key and returns the count.
+pub fn delete(c: &mut RecordingClient, query: &str, key: &[u8]) -> Result<u64, Event> {
+    match c.record(query, vec![bytes(key)])? {
+        None => Ok(0),
+        Some(Reply::Count(n)) => Ok(n),
+        Some(_) => Err(mismatch("Count")),
+    }
+}
+
+/// Lists keys.
+pub fn list_keys(c: &mut RecordingClient, query: &str) -> Result<Vec<Vec<u8>>, Event> {
+    match c.record(query, vec![])? {
+        None => Ok(vec![]),
+        Some(Reply::Keys(k)) => Ok(k),
+        Some(_) => Err(mismatch("Keys")),
+    }
+}
+
+/// Gets a page(parameters: after(if any) and limit).
+pub fn page(
+    c: &mut RecordingClient,
+    query: &str,
+    after: Option<&[u8]>,
+    limit: u64,
+) -> Result<Vec<RawItem>, Event> {
+    let params: Vec<Param> = match after {
+        None => vec![Param::Int(limit)],
+        Some(a) => vec![bytes(a), Param::Int(limit)],
+    };
+    match c.record(query, params)? {
+        None => Ok(vec![]),
+        Some(Reply::Items(i)) => Ok(i),
+        Some(_) => Err(mismatch("Items")),
+    }
+}
+
+/// Lists bucket names.
+pub fn list_buckets(c: &mut RecordingClient, query: &str) -> Result<Vec<String>, Event> {
+    match c.record(query, vec![])? {
+        None => Ok(vec![]),
+        Some(Reply::Names(n)) => Ok(n),
+        Some(_) => Err(mismatch("Names")),
+    }
+}
+
+#[cfg(test)]
+mod test_record {
+
+    mod recording_client {
+        use crate::bucket::Bucket;
+        use crate::evt::Event;
+        use crate::get::{select_bytes_new_mut, GetRequest};
+        use crate::item::Item;
+        use crate::list::{list_items_page_new_mut, page_builder_new, PageRequest};
+        use crate::record::{self, Param, RecordingClient, Reply};
+        use crate::upsert::{upsert_builder_new, upsert_bytes_all_new_mut, BulkRequest};
+
+        fn req(bucket: &str, keys: &[&str]) -> BulkRequest<Vec<u8>, Vec<u8>> {
+            let items = keys
+                .iter()
+                .map(|k| Item::new(k.as_bytes().to_vec(), b"v".to_vec()))
+                .collect();
+            BulkRequest::new(Bucket::from(String::from(bucket)), items)
+        }
+
+        #[test]
+        fn test_upsert_sql() {
+            let f = upsert_bytes_all_new_mut(
+                record::execute,
+                record::upsert,
+                upsert_builder_new(
+                    |b: &Bucket| Ok(format!("CREATE {}", b.as_str())),
+                    |b: &Bucket| Ok(format!("UPSERT {}", b.as_str())),
+                ),
+            );
+            let mut c = RecordingClient::new();
+            c.push_reply(Reply::Count(0))
+                .push_reply(Reply::Count(1))
+                .push_reply(Reply::Count(1));
+            let cnt: u64 = f(vec![req("b1", &["k1", "k2"])].into_iter(), &mut c).unwrap();
+            assert_eq!(cnt, 2);
+            let q: Vec<&str> = c.queries().collect();
+            assert_eq!(q, vec!["CREATE b1", "UPSERT b1", "UPSERT b1"]);
+            assert_eq!(
+                c.calls()[2].as_params(),
+                &[Param::Bytes(b"k2".to_vec()), Param::Bytes(b"v".to_vec())]
+            );
+        }
+
+        #[test]
+        fn test_create_failure_mid_batch() {
+            let f = upsert_bytes_all_new_mut(
+                record::execute,
+                record::upsert,
+                upsert_builder_new(
+                    |b: &Bucket| Ok(format!("CREATE {}", b.as_str())),
+                    |b: &Bucket| Ok(format!("UPSERT {}", b.as_str())),
+                ),
+            );
+            let mut c = RecordingClient::new();
+            c.fail_at(2, Event::ConnectionError(String::from("gone")));
+            let reqs = vec![req("b1", &["k1"]), req("b2", &["k2"]), req("b3", &["k3"])];
+            let r = f(reqs.into_iter(), &mut c);
+            assert!(matches!(r, Err(Event::ConnectionError(_))));
+            let q: Vec<&str> = c.queries().collect();
+            assert_eq!(q, vec!["CREATE b1", "UPSERT b1", "CREATE b2"]);
+        }
+
+        #[test]
+        fn test_select_page() {
+            let mut c = RecordingClient::new();
+            c.push_reply(Reply::Value(Some(b"42".to_vec())));
+            let f = select_bytes_new_mut(record::select, |b: &Bucket| {
+                Ok(format!("SELECT {}", b.as_str()))
+            });
+            let q = GetRequest::new(Bucket::from(String::from("b1")), b"k".to_vec());
+            assert_eq!(f(&q, &mut c).unwrap(), Some(b"42".to_vec()));
+
+            let p = list_items_page_new_mut(
+                record::page,
+                page_builder_new(
+                    |_: &Bucket| Ok(String::from("FIRST")),
+                    |_: &Bucket| Ok(String::from("AFTER")),
+                ),
+            );
+            let r = PageRequest::new(Bucket::from(String::from("b1")), Some(b"k".to_vec()), 10);
+            assert_eq!(p(&r, &mut c).unwrap().len(), 0);
+            assert_eq!(c.calls()[1].as_query(), "AFTER");
+            assert_eq!(
+                c.calls()[1].as_params(),
+                &[Param::Bytes(b"k".to_vec()), Param::Int(10)]
+            );
+        }
+
+        #[test]
+        fn test_mismatch() {
+            let mut c = RecordingClient::new();
+            c.push_reply(Reply::Keys(vec![]));
+            assert!(record::select(&mut c, "SELECT", b"k").is_err());
+        }
+    }
+}
@@ -0,0 +1,492 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use crate::bucket::Bucket;
+use crate::evt::Event;
+use crate::get::GetRequest;
+
+/// Limits and delays of retries.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base: Duration,
+    cap: Duration,
+    deadline: Option<Duration>,
+}
+
+impl RetryPolicy {
+    /// Creates new retry policy without a deadline.
+    ///
+    /// # Arguments
+    /// - max_attempts: The maximum number of attempts(including the first one).
+    /// - base: The delay before the first retry(doubled on each retry).
+    /// - cap: The maximum delay between attempts.
+    pub fn new(max_attempts: u32, base: Duration, cap: Duration) -> Self {
+        Self {
+            max_attempts,
+            base,
+            cap,
+            deadline: None,
+        }
+    }
+
+    /// Sets the time budget; no retry is made if it would end after the deadline.
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Gets the maximum number of attempts.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Gets the upper bound of the delay after the failed attempt(1-origin).
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp: u32 = attempt.saturating_sub(1).min(31);
+        self.base.saturating_mul(1 << exp).min(self.cap)
+    }
+}
+
+impl Default for RetryPolicy {
+    /// 5 attempts, 50ms base delay, 2s maximum delay.
+    fn default() -> Self {
+        Self::new(5, Duration::from_millis(50), Duration::from_secs(2))
+    }
+}
+
+/// Checks if the event is transient(`ConnectionError`, `Conflict` or `PoolTimeout`).
+///
+/// A `ConnectionError` is retried on the same client: it can succeed only if the client
+/// reconnects by itself(e.g, the operation checks out a pooled connection or reopens the
+/// connection). Exclude it with a custom predicate when the client cannot reconnect.
+pub fn retryable_default(e: &Event) -> bool {
+    matches!(
+        e,
+        Event::ConnectionError(_) | Event::Conflict(_) | Event::PoolTimeout(_)
+    )
+}
+
+/// Picks a delay in `[d/2, d]`.
+fn jitter(d: Duration) -> Duration {
+    let r: u64 = RandomState::new().build_hasher().finish();
+    let ratio: f64 = (r as f64) / (u64::MAX as f64);
+    d / 2 + (d / 2).mul_f64(ratio)
+}
+
+/// Calls the closure until it succeeds, fails with a non-retryable event or exhausts the policy.
+///
+/// The closure is called again from scratch; use it to replay a whole transaction
+/// (begin, upsert and commit) on `Event::Conflict`.
+///
+/// # Arguments
+/// - policy: Limits attempts and delays.
+/// - retryable: Checks if the failed attempt can be retried.
+/// - sleep: Waits between attempts(e.g, `std::thread::sleep`).
+/// - f: The operation to be retried.
+pub fn retry_mut<T, F, R, S>(
+    policy: &RetryPolicy,
+    retryable: &R,
+    sleep: &S,
+    mut f: F,
+) -> Result<T, Event>
+where
+    F: FnMut() -> Result<T, Event>,
+    R: Fn(&Event) -> bool,
+    S: Fn(Duration),
+{
+    let started: Instant = Instant::now();
+    let mut attempt: u32 = 1;
+    loop {
+        let e: Event = match f() {
+            Ok(t) => return Ok(t),
+            Err(e) => e,
+        };
+        let delay: Duration = jitter(policy.backoff(attempt));
+        let expired: bool = policy
+            .deadline
+            .map(|d| d < started.elapsed() + delay)
+            .unwrap_or(false);
+        match attempt < policy.max_attempts && !expired && retryable(&e) {
+            true => {
+                sleep(delay);
+                attempt += 1;
+            }
+            false => return Err(e),
+        }
+    }
+}
+
+/// Calls the closure inside a transaction until it succeeds, retrying as `retry_mut`.
+///
+/// Each attempt begins a new transaction, runs the closure and commits; a failed attempt is
+/// rolled back before the next one(errors of the rollback itself are ignored), so the whole
+/// transaction is replayed on `Event::Conflict`.
+///
+/// # Arguments
+/// - policy: Limits attempts and delays.
+/// - retryable: Checks if the failed attempt can be retried.
+/// - sleep: Waits between attempts.
+/// - client: The client which runs the transaction.
+/// - begin: Begins a transaction(e.g, executes `BEGIN`).
+/// - commit: Commits the transaction.
+/// - rollback: Rolls back the transaction.
+/// - f: The operation run in the transaction.
+#[allow(clippy::too_many_arguments)]
+pub fn retry_transaction_mut<T, C, B, K, L, F, R, S>(
+    policy: &RetryPolicy,
+    retryable: &R,
+    sleep: &S,
+    client: &mut C,
+    begin: &B,
+    commit: &K,
+    rollback: &L,
+    mut f: F,
+) -> Result<T, Event>
+where
+    B: Fn(&mut C) -> Result<(), Event>,
+    K: Fn(&mut C) -> Result<(), Event>,
+    L: Fn(&mut C) -> Result<(), Event>,
+    F: FnMut(&mut C) -> Result<T, Event>,
+    R: Fn(&Event) -> bool,
+    S: Fn(Duration),
+{
+    retry_mut(policy, retryable, sleep, || {
+        transaction_mut(client, begin, commit, rollback, &mut f)
+    })
+}
+
+/// Runs the closure in a transaction; rolls back(ignoring errors of the rollback) on failure.
+pub(crate) fn transaction_mut<T, C, B, K, L, F>(
+    client: &mut C,
+    begin: &B,
+    commit: &K,
+    rollback: &L,
+    f: F,
+) -> Result<T, Event>
+where
+    B: Fn(&mut C) -> Result<(), Event>,
+    K: Fn(&mut C) -> Result<(), Event>,
+    L: Fn(&mut C) -> Result<(), Event>,
+    F: FnOnce(&mut C) -> Result<T, Event>,
+{
+    begin(client)?;
+    let r: Result<T, Event> = f(client).and_then(|t| commit(client).map(|_| t));
+    if r.is_err() {
+        let _ = rollback(client);
+    }
+    r
+}
+
+/// Creates select request handler which retries the handler.
+///
+/// # Arguments
+/// - policy: Limits attempts and delays.
+/// - retryable: Checks if the failed attempt can be retried.
+/// - sleep: Waits between attempts.
+/// - select: The handler(e.g, `select_bytes_new_mut`).
+pub fn select_retry_new_mut<H, R, S, C>(
+    policy: RetryPolicy,
+    retryable: R,
+    sleep: S,
+    select: H,
+) -> impl Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>
+where
+    H: Fn(&GetRequest<Vec<u8>>, &mut C) -> Result<Option<Vec<u8>>, Event>,
+    R: Fn(&Event) -> bool,
+    S: Fn(Duration),
+{
+    move |req: &GetRequest<Vec<u8>>, client: &mut C| {
+        retry_mut(&policy, &retryable, &sleep, || select(req, client))
+    }
+}
+
+/// Creates remover which retries the handler.
+///
+/// # Arguments
+/// - policy: Limits attempts and delays.
+/// - retryable: Checks if the failed attempt can be retried.
+/// - sleep: Waits between attempts.
+/// - delete: The handler(e.g, `delete_key_bytes_mut`).
+pub fn delete_retry_new_mut<H, R, S, C>(
+    policy: RetryPolicy,
+    retryable: R,
+    sleep: S,
+    delete: H,
+) -> impl Fn(&Bucket, &[u8], &mut C) -> Result<u64, Event>
+where
+    H: Fn(&Bucket, &[u8], &mut C) -> Result<u64, Event>,
+    R: Fn(&Event) -> bool,
+    S: Fn(Duration),
+{
+    move |b: &Bucket, key: &[u8], client: &mut C| {
+        retry_mut(&policy, &retryable, &sleep, || delete(b, key, client))
+    }
+}
+
+fn bucket_retry_new_mut<H, R, S, C, T>(
+    policy: RetryPolicy,
+    retryable: R,
+    sleep: S,
+    handler: H,
+) -> impl Fn(&Bucket, &mut C) -> Result<T, Event>
+where
+    H: Fn(&Bucket, &mut C) -> Result<T, Event>,
+    R: Fn(&Event) -> bool,
+    S: Fn(Duration),
+{
+    move |b: &Bucket, client: &mut C| retry_mut(&policy, &retryable, &sleep, || handler(b, client))
+}
+
+/// Creates keys getter which retries the handler.
+///
+/// # Arguments
+/// - policy: Limits attempts and delays.
+/// - retryable: Checks if the failed attempt can be retried.
+/// - sleep: Waits between attempts.
+/// - list: The handler(e.g, `list_keys_bytes_new_mut`).
+pub fn list_keys_retry_new_mut<H, R, S, C>(
+    policy: RetryPolicy,
+    retryable: R,
+    sleep: S,
+    list: H,
+) -> impl Fn(&Bucket, &mut C) -> Result<Vec<Vec<u8>>, Event>
+where
+    H: Fn(&Bucket, &mut C) -> Result<Vec<Vec<u8>>, Event>,
+    R: Fn(&Event) -> bool,
+    S: Fn(Duration),
+{
+    bucket_retry_new_mut(policy, retryable, sleep, list)
+}
+
+/// Creates bucket dropper which retries the handler.
+///
+/// # Arguments
+/// - policy: Limits attempts and delays.
+/// - retryable: Checks if the failed attempt can be retried.
+/// - sleep: Waits between attempts.
+/// - remove: The handler(e.g, `drop_bucket_mut`).
+pub fn drop_retry_new_mut<H, R, S, C>(
+    policy: RetryPolicy,
+    retryable: R,
+    sleep: S,
+    remove: H,
+) -> impl Fn(&Bucket, &mut C) -> Result<(), Event>
+where
+    H: Fn(&Bucket, &mut C) -> Result<(), Event>,
+    R: Fn(&Event) -> bool,
+    S: Fn(Duration),
+{
+    bucket_retry_new_mut(policy, retryable, sleep, remove)
+}
+
+#[cfg(test)]
+mod test_retry {
+
+    mod retry_mut {
+
+        use std::cell::{Cell, RefCell};
+        use std::time::Duration;
+
+        use crate::evt::Event;
+        use crate::retry::{self, RetryPolicy};
+
+        fn policy() -> RetryPolicy {
+            RetryPolicy::new(3, Duration::from_millis(10), Duration::from_millis(15))
+        }
+
+        #[test]
+        fn test_transient() {
+            let calls: Cell<u32> = Cell::new(0);
+            let slept: RefCell<Vec<Duration>> = RefCell::new(vec![]);
+            let r: Result<u32, Event> = retry::retry_mut(
+                &policy(),
+                &retry::retryable_default,
+                &|d: Duration| slept.borrow_mut().push(d),
+                || {
+                    calls.set(calls.get() + 1);
+                    match calls.get() {
+                        1 => Err(Event::ConnectionError(String::from("reset"))),
+                        2 => Err(Event::Conflict(String::from("40001"))),
+                        n => Ok(n),
+                    }
+                },
+            );
+            assert_eq!(r.unwrap(), 3);
+            let slept: Vec<Duration> = slept.into_inner();
+            assert_eq!(slept.len(), 2);
+            assert!(Duration::from_millis(5) <= slept[0] && slept[0] <= Duration::from_millis(10));
+            assert!(Duration::from_millis(7) <= slept[1] && slept[1] <= Duration::from_millis(15));
+        }
+
+        #[test]
+        fn test_fatal() {
+            let calls: Cell<u32> = Cell::new(0);
+            let r: Result<(), Event> = retry::retry_mut(
+                &policy(),
+                &retry::retryable_default,
+                &|_: Duration| {},
+                || {
+                    calls.set(calls.get() + 1);
+                    Err(Event::InvalidBucket(String::from("x;")))
+                },
+            );
+            assert!(matches!(r, Err(Event::InvalidBucket(_))));
+            assert_eq!(calls.get(), 1);
+        }
+
+        #[test]
+        fn test_exhausted() {
+            let calls: Cell<u32> = Cell::new(0);
+            let r: Result<(), Event> = retry::retry_mut(
+                &policy(),
+                &retry::retryable_default,
+                &|_: Duration| {},
+                || {
+                    calls.set(calls.get() + 1);
+                    Err(Event::ConnectionError(String::from("down")))
+                },
+            );
+            assert!(matches!(r, Err(Event::ConnectionError(_))));
+            assert_eq!(calls.get(), 3);
+        }
+
+        #[test]
+        fn test_deadline() {
+            let calls: Cell<u32> = Cell::new(0);
+            let p: RetryPolicy = policy().with_deadline(Duration::from_millis(1));
+            let r: Result<(), Event> =
+                retry::retry_mut(&p, &retry::retryable_default, &|_: Duration| {}, || {
+                    calls.set(calls.get() + 1);
+                    Err(Event::ConnectionError(String::from("down")))
+                });
+            assert!(r.is_err());
+            assert_eq!(calls.get(), 1);
+        }
+    }
+
+    mod retry_transaction_mut {
+
+        use std::time::Duration;
+
+        use crate::bucket::Bucket;
+        use crate::evt::Event;
+        use crate::item::Item;
+        use crate::record::{self, RecordingClient, Reply};
+        use crate::retry::{self, RetryPolicy};
+        use crate::upsert::{upsert_builder_new, upsert_bytes_all_new_mut, BulkRequest};
+
+        fn begin(c: &mut RecordingClient) -> Result<(), Event> {
+            record::execute_unit(c, "BEGIN")
+        }
+
+        fn commit(c: &mut RecordingClient) -> Result<(), Event> {
+            record::execute_unit(c, "COMMIT")
+        }
+
+        fn rollback(c: &mut RecordingClient) -> Result<(), Event> {
+            record::execute_unit(c, "ROLLBACK")
+        }
+
+        #[test]
+        fn test_replay() {
+            let upsert = upsert_bytes_all_new_mut(
+                record::execute,
+                record::upsert,
+                upsert_builder_new(
+                    |b: &Bucket| Ok(format!("CREATE {}", b.as_str())),
+                    |b: &Bucket| Ok(format!("UPSERT {}", b.as_str())),
+                ),
+            );
+            let mut c = RecordingClient::new();
+            c.fail_at(2, Event::Conflict(String::from("40001")))
+                .fail_at(7, Event::Conflict(String::from("40001")));
+            for r in [0, 0, 0, 0, 0, 1, 0, 0, 0, 1] {
+                c.push_reply(Reply::Count(r));
+            }
+            let cnt: u64 = retry::retry_transaction_mut(
+                &RetryPolicy::default(),
+                &retry::retryable_default,
+                &|_: Duration| {},
+                &mut c,
+                &begin,
+                &commit,
+                &rollback,
+                |c: &mut RecordingClient| {
+                    let item = Item::new(b"k".to_vec(), b"v".to_vec());
+                    let req = BulkRequest::new(Bucket::from(String::from("b1")), vec![item]);
+                    upsert(vec![req].into_iter(), c)
+                },
+            )
+            .unwrap();
+            assert_eq!(cnt, 1);
+            let q: Vec<&str> = c.queries().collect();
+            assert_eq!(
+                q,
+                vec![
+                    "BEGIN",
+                    "CREATE b1",
+                    "UPSERT b1",
+                    "ROLLBACK", // conflict on upsert
+                    "BEGIN",
+                    "CREATE b1",
+                    "UPSERT b1",
+                    "COMMIT",
+                    "ROLLBACK", // on commit
+                    "BEGIN",
+                    "CREATE b1",
+                    "UPSERT b1",
+                    "COMMIT",
+                ]
+            );
+        }
+
+        #[test]
+        fn test_fatal() {
+            let mut c = RecordingClient::new();
+            let r: Result<(), Event> = retry::retry_transaction_mut(
+                &RetryPolicy::default(),
+                &retry::retryable_default,
+                &|_: Duration| {},
+                &mut c,
+                &begin,
+                &commit,
+                &rollback,
+                |_: &mut RecordingClient| Err(Event::InvalidBucket(String::from("x;"))),
+            );
+            assert!(matches!(r, Err(Event::InvalidBucket(_))));
+            let q: Vec<&str> = c.queries().collect();
+            assert_eq!(q, vec!["BEGIN", "ROLLBACK"]);
+        }
+    }
+
+    mod select_retry_new_mut {
+
+        use std::time::Duration;
+
+        use crate::bucket::Bucket;
+        use crate::evt::Event;
+        use crate::get::GetRequest;
+        use crate::retry::{self, RetryPolicy};
+
+        #[test]
+        fn test_reconnect() {
+            let f = retry::select_retry_new_mut(
+                RetryPolicy::default(),
+                retry::retryable_default,
+                |_: Duration| {},
+                |_: &GetRequest<Vec<u8>>, up: &mut bool| match *up {
+                    true => Ok(Some(b"2022/11/01".to_vec())),
+                    false => {
+                        *up = true;
+                        Err(Event::ConnectionError(String::from("reset")))
+                    }
+                },
+            );
+            let req = GetRequest::new(Bucket::from(String::from("dates")), b"k".to_vec());
+            let mut up: bool = false;
+            assert_eq!(f(&req, &mut up).unwrap(), Some(b"2022/11/01".to_vec()));
+        }
+    }
+}
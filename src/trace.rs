@@ -0,0 +1,282 @@
+//! Spans of KV operations(`tracing` feature).
+//!
+//! Instrumented handlers: select, upsert(row by row, chunked, report and previous values),
+//! swap, delete, drop, list and page. Composite helpers(e.g, dump, migrate, buffer) are traced
+//! through the handlers they are given; load and changelog handlers are not instrumented.
+
+use crate::bucket::Bucket;
+use crate::evt::Event;
+
+/// Describes a single KV operation to be traced.
+#[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+pub(crate) struct Op<'a> {
+    name: &'static str,
+    bucket: &'a Bucket,
+    key: Option<&'a [u8]>,
+    items: Option<usize>,
+}
+
+impl<'a> Op<'a> {
+    /// Creates new operation description for the bucket.
+    pub(crate) fn new(name: &'static str, bucket: &'a Bucket) -> Self {
+        Self {
+            name,
+            bucket,
+            key: None,
+            items: None,
+        }
+    }
+
+    /// Sets the key of the operation(only the length is logged unless `trace-keys` is enabled).
+    pub(crate) fn with_key(mut self, key: &'a [u8]) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Sets the number of input items of the operation.
+    pub(crate) fn with_items(mut self, items: usize) -> Self {
+        self.items = Some(items);
+        self
+    }
+}
+
+/// Runs the operation inside a span which records the outcome and the result count.
+///
+/// # Arguments
+/// - op: Describes the operation.
+/// - f: Executes the operation.
+/// - count: Gets the number of affected/returned items from the result.
+#[cfg(feature = "tracing")]
+pub(crate) fn instrumented<T, F, N>(op: Op, f: F, count: N) -> Result<T, Event>
+where
+    F: FnOnce() -> Result<T, Event>,
+    N: Fn(&T) -> u64,
+{
+    let span = tracing::info_span!(
+        "kv",
+        op = op.name,
+        bucket = op.bucket.as_str(),
+        key_len = tracing::field::Empty,
+        key = tracing::field::Empty,
+        items = tracing::field::Empty,
+        count = tracing::field::Empty,
+        outcome = tracing::field::Empty,
+    );
+    if let Some(key) = op.key {
+        span.record("key_len", key.len());
+        #[cfg(feature = "trace-keys")]
+        span.record("key", tracing::field::display(key.escape_ascii()));
+    }
+    if let Some(items) = op.items {
+        span.record("items", items);
+    }
+    let result: Result<T, Event> = span.in_scope(f);
+    match &result {
+        Ok(t) => {
+            span.record("count", count(t));
+            span.record("outcome", "ok");
+        }
+        Err(e) => {
+            span.record("outcome", "error");
+            tracing::error!(parent: &span, event = ?e, "kv operation failed");
+        }
+    }
+    result
+}
+
+/// Runs the operation(tracing disabled).
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn instrumented<T, F, N>(_op: Op, f: F, _count: N) -> Result<T, Event>
+where
+    F: FnOnce() -> Result<T, Event>,
+    N: Fn(&T) -> u64,
+{
+    f()
+}
+
+#[cfg(test)]
+mod test_trace {
+
+    mod instrumented {
+
+        use crate::bucket::Bucket;
+        use crate::evt::Event;
+        use crate::trace::{self, Op};
+
+        #[test]
+        fn test_passthrough() {
+            let b: Bucket = Bucket::from(String::from("dates"));
+            let op = Op::new("select", &b).with_key(b"2022/11/01").with_items(1);
+            let ok: Result<u64, Event> = trace::instrumented(op, || Ok(42), |n| *n);
+            assert_eq!(ok.unwrap(), 42);
+
+            let op = Op::new("drop", &b);
+            let ng: Result<(), Event> = trace::instrumented(
+                op,
+                || Err(Event::UnexpectedError(String::from("broken"))),
+                |_| 0,
+            );
+            assert!(matches!(ng, Err(Event::UnexpectedError(_))));
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    mod capture {
+
+        use std::fmt::Debug;
+        use std::sync::{Arc, Mutex};
+
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Metadata, Subscriber};
+
+        use crate::bucket::Bucket;
+        use crate::evt::Event;
+        use crate::item::{Item, RawItem};
+        use crate::list::{list_items_page_new_mut, page_builder_new, PageRequest};
+        use crate::trace::{self, Op};
+
+        type Fields = Vec<(String, String)>;
+
+        #[derive(Default)]
+        struct Captured {
+            spans: Vec<(&'static str, Fields)>,
+            events: Vec<(Option<u64>, Fields)>,
+        }
+
+        impl Captured {
+            fn field(&self, span: usize, name: &str) -> Option<&str> {
+                let fields: &Fields = &self.spans[span].1;
+                fields
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| v.as_str())
+            }
+        }
+
+        struct Visitor<'a>(&'a mut Fields);
+
+        impl Visit for Visitor<'_> {
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                self.0
+                    .push((String::from(field.name()), format!("{:?}", value)));
+            }
+
+            fn record_str(&mut self, field: &Field, value: &str) {
+                self.0
+                    .push((String::from(field.name()), String::from(value)));
+            }
+        }
+
+        struct Capturing(Arc<Mutex<Captured>>);
+
+        impl Subscriber for Capturing {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+                let mut c = self.0.lock().unwrap();
+                let mut fields: Fields = vec![];
+                attrs.record(&mut Visitor(&mut fields));
+                c.spans.push((attrs.metadata().name(), fields));
+                Id::from_u64(c.spans.len() as u64)
+            }
+
+            fn record(&self, span: &Id, values: &Record<'_>) {
+                let mut c = self.0.lock().unwrap();
+                let i: usize = span.into_u64() as usize - 1;
+                values.record(&mut Visitor(&mut c.spans[i].1));
+            }
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+            fn event(&self, event: &tracing::Event<'_>) {
+                let mut fields: Fields = vec![];
+                event.record(&mut Visitor(&mut fields));
+                let parent: Option<u64> = event.parent().map(|p| p.into_u64());
+                self.0.lock().unwrap().events.push((parent, fields));
+            }
+
+            fn enter(&self, _: &Id) {}
+
+            fn exit(&self, _: &Id) {}
+        }
+
+        fn captured<F>(f: F) -> Arc<Mutex<Captured>>
+        where
+            F: FnOnce(),
+        {
+            let c: Arc<Mutex<Captured>> = Arc::default();
+            tracing::subscriber::with_default(Capturing(c.clone()), f);
+            c
+        }
+
+        #[test]
+        fn test_span_fields() {
+            let b: Bucket = Bucket::from(String::from("dates"));
+            let c = captured(|| {
+                let op = Op::new("select", &b).with_key(b"2022/11/01").with_items(1);
+                let _ = trace::instrumented(op, || Ok::<u64, Event>(42), |n| *n);
+            });
+            let c = c.lock().unwrap();
+            assert_eq!(c.spans.len(), 1);
+            assert_eq!(c.spans[0].0, "kv");
+            assert_eq!(c.field(0, "op"), Some("select"));
+            assert_eq!(c.field(0, "bucket"), Some("dates"));
+            assert_eq!(c.field(0, "key_len"), Some("10"));
+            assert_eq!(c.field(0, "items"), Some("1"));
+            assert_eq!(c.field(0, "count"), Some("42"));
+            assert_eq!(c.field(0, "outcome"), Some("ok"));
+            #[cfg(feature = "trace-keys")]
+            assert_eq!(c.field(0, "key"), Some("2022/11/01"));
+            #[cfg(not(feature = "trace-keys"))]
+            assert_eq!(c.field(0, "key"), None);
+            assert!(c.events.is_empty());
+        }
+
+        #[test]
+        fn test_error() {
+            let b: Bucket = Bucket::from(String::from("dates"));
+            let c = captured(|| {
+                let _ = trace::instrumented(
+                    Op::new("drop", &b),
+                    || Err::<(), _>(Event::UnexpectedError(String::from("broken"))),
+                    |_| 0,
+                );
+            });
+            let c = c.lock().unwrap();
+            assert_eq!(c.field(0, "outcome"), Some("error"));
+            assert_eq!(c.field(0, "count"), None);
+            assert_eq!(c.events.len(), 1);
+            let (parent, fields) = &c.events[0];
+            assert_eq!(*parent, Some(1));
+            let message = fields.iter().find(|(n, _)| n == "message").unwrap();
+            assert_eq!(message.1, "kv operation failed");
+            let event = fields.iter().find(|(n, _)| n == "event").unwrap();
+            assert!(event.1.contains("broken"));
+        }
+
+        #[test]
+        fn test_page() {
+            let b: Bucket = Bucket::from(String::from("dates"));
+            let list = |_: &mut (), _q: &str, _after: Option<&[u8]>, _limit: u64| {
+                Ok(vec![Item::new(b"k".to_vec(), b"v".to_vec())])
+            };
+            let builder = page_builder_new(
+                |_: &Bucket| Ok(String::from("")),
+                |_: &Bucket| Ok(String::from("")),
+            );
+            let page = list_items_page_new_mut(list, builder);
+            let c = captured(|| {
+                let req = PageRequest::new(b, Some(b"a".to_vec()), 10);
+                let items: Vec<RawItem> = page(&req, &mut ()).unwrap();
+                assert_eq!(items.len(), 1);
+            });
+            let c = c.lock().unwrap();
+            assert_eq!(c.field(0, "op"), Some("page"));
+            assert_eq!(c.field(0, "key_len"), Some("1"));
+            assert_eq!(c.field(0, "count"), Some("1"));
+        }
+    }
+}
@@ -1,6 +1,10 @@
+use std::collections::BTreeMap;
+use std::vec;
+
 use crate::bucket::Bucket;
 use crate::evt::Event;
-use crate::item::Item;
+use crate::item::{Item, RawItem};
+use crate::trace::{self, Op};
 
 /// An upsert requests in a single bucket.
 pub struct BulkRequest<K, V> {
@@ -25,6 +29,9 @@ impl<K, V> BulkRequest<K, V> {
     }
 }
 
+/// Raw upsert requests collected by wrapping handlers and passed to the wrapped handler.
+pub type Requests = vec::IntoIter<BulkRequest<Vec<u8>, Vec<u8>>>;
+
 /// Converts generic request to raw request(key/val = bytes).
 ///
 /// # Arguments
@@ -125,15 +132,22 @@ where
 {
     move |req: &BulkRequest<_, _>, tx: &mut T| {
         let b: &Bucket = req.as_bucket();
-        let query_c: String = builder.build_create(b)?;
-        let query_u: String = builder.build_upsert(b)?;
-        upsert_bytes_mut(
-            req,
-            &create,
-            &upsert,
-            tx,
-            query_c.as_str(),
-            query_u.as_str(),
+        let op: Op = Op::new("upsert", b).with_items(req.as_items().len());
+        trace::instrumented(
+            op,
+            || {
+                let query_c: String = builder.build_create(b)?;
+                let query_u: String = builder.build_upsert(b)?;
+                upsert_bytes_mut(
+                    req,
+                    &create,
+                    &upsert,
+                    tx,
+                    query_c.as_str(),
+                    query_u.as_str(),
+                )
+            },
+            |cnt| *cnt,
         )
     }
 }
@@ -189,6 +203,471 @@ where
     upsert_bytes_all_new_mut(c, u, builder)
 }
 
+/// Maximum number of host parameters in a single SQLite statement(since 3.32.0).
+pub const SQLITE_MAX_PARAMS: usize = 32766;
+
+/// Maximum number of bind parameters in a single PostgreSQL statement.
+pub const POSTGRES_MAX_PARAMS: usize = 65535;
+
+/// Traits for building multi-row upsert query strings from `Bucket`.
+pub trait UpsertBuilderMulti: UpsertBuilder {
+    /// Builds upsert query which upserts `rows` items at once.
+    fn build_upsert_multi(&self, b: &Bucket, rows: usize) -> Result<String, Event>;
+}
+
+struct UpsertBuilderMultiF<C, U, M> {
+    create: C,
+    upsert: U,
+    multi: M,
+}
+impl<C, U, M> UpsertBuilder for UpsertBuilderMultiF<C, U, M>
+where
+    C: Fn(&Bucket) -> Result<String, Event>,
+    U: Fn(&Bucket) -> Result<String, Event>,
+{
+    fn build_create(&self, b: &Bucket) -> Result<String, Event> {
+        (self.create)(b)
+    }
+    fn build_upsert(&self, b: &Bucket) -> Result<String, Event> {
+        (self.upsert)(b)
+    }
+}
+impl<C, U, M> UpsertBuilderMulti for UpsertBuilderMultiF<C, U, M>
+where
+    C: Fn(&Bucket) -> Result<String, Event>,
+    U: Fn(&Bucket) -> Result<String, Event>,
+    M: Fn(&Bucket, usize) -> Result<String, Event>,
+{
+    fn build_upsert_multi(&self, b: &Bucket, rows: usize) -> Result<String, Event> {
+        (self.multi)(b, rows)
+    }
+}
+
+/// Creates new `UpsertBuilderMulti` implementation which uses closures to build query strings.
+///
+/// # Arguments
+/// - create: Builds create query string.
+/// - upsert: Builds single-row upsert query string.
+/// - multi: Builds multi-row upsert query string for the number of rows.
+pub fn upsert_builder_multi_new<C, U, M>(create: C, upsert: U, multi: M) -> impl UpsertBuilderMulti
+where
+    C: Fn(&Bucket) -> Result<String, Event>,
+    U: Fn(&Bucket) -> Result<String, Event>,
+    M: Fn(&Bucket, usize) -> Result<String, Event>,
+{
+    UpsertBuilderMultiF {
+        create,
+        upsert,
+        multi,
+    }
+}
+
+fn values_placeholders<F>(rows: usize, param: F) -> String
+where
+    F: Fn(usize) -> String,
+{
+    let mapd = (0..rows).map(|row: usize| {
+        let k: String = param(2 * row + 1);
+        let v: String = param(2 * row + 2);
+        format!("({}, {})", k, v)
+    });
+    mapd.collect::<Vec<_>>().join(", ")
+}
+
+/// Creates key/val placeholders for SQLite multi-row `VALUES`: `(?1, ?2), (?3, ?4), ...`
+pub fn values_placeholders_sqlite(rows: usize) -> String {
+    values_placeholders(rows, |i: usize| format!("?{}", i))
+}
+
+/// Creates key/val placeholders for PostgreSQL multi-row `VALUES`: `($1::BYTEA, $2::BYTEA), ...`
+pub fn values_placeholders_postgres(rows: usize) -> String {
+    values_placeholders(rows, |i: usize| format!("${}::BYTEA", i))
+}
+
+/// Computes the number of rows per statement which does not exceed the parameter limit.
+///
+/// # Arguments
+/// - max_params: The maximum number of parameters in a single statement.
+/// - params_per_row: The number of parameters used by a single row.
+pub fn chunk_size_from_params(max_params: usize, params_per_row: usize) -> usize {
+    max_params.checked_div(params_per_row).unwrap_or(1).max(1)
+}
+
+/// Keeps the last item of each key(same as row-by-row upserts); `None` if keys are distinct.
+fn dedup_chunk(chunk: &[Item<Vec<u8>, Vec<u8>>]) -> Option<Vec<Item<Vec<u8>, Vec<u8>>>> {
+    let last: BTreeMap<&[u8], usize> = chunk
+        .iter()
+        .enumerate()
+        .map(|(i, item)| (item.as_key().as_slice(), i))
+        .collect();
+    match last.len() == chunk.len() {
+        true => None,
+        false => Some(
+            chunk
+                .iter()
+                .enumerate()
+                .filter(|(i, item)| last.get(item.as_key().as_slice()) == Some(i))
+                .map(|(_, item)| Item::new(item.as_key().clone(), item.as_val().clone()))
+                .collect(),
+        ),
+    }
+}
+
+fn upsert_bytes_chunked_mut<C, U, B, T>(
+    q: &BulkRequest<Vec<u8>, Vec<u8>>,
+    create: &C,
+    upsert: &U,
+    builder: &B,
+    transaction: &mut T,
+    chunk_size: usize,
+) -> Result<u64, Event>
+where
+    C: Fn(&mut T, &str) -> Result<u64, Event>,
+    U: Fn(&mut T, &str, &[Item<Vec<u8>, Vec<u8>>]) -> Result<u64, Event>,
+    B: UpsertBuilderMulti,
+{
+    let b: &Bucket = q.as_bucket();
+    let query_c: String = builder.build_create(b)?;
+    let cnt_c: u64 = create(transaction, query_c.as_str())?;
+
+    let items: &[Item<Vec<u8>, Vec<u8>>] = q.as_items();
+    let cnt_u: u64 = items.chunks(chunk_size).try_fold(0, |tot, chunk| {
+        let deduped: Option<Vec<Item<Vec<u8>, Vec<u8>>>> = dedup_chunk(chunk);
+        let rows: &[Item<Vec<u8>, Vec<u8>>] = deduped.as_deref().unwrap_or(chunk);
+        let query_u: String = builder.build_upsert_multi(b, rows.len())?;
+        upsert(transaction, query_u.as_str(), rows).map(|cnt| cnt + tot)
+    })?;
+    Ok(cnt_c + cnt_u)
+}
+
+/// Creates upsert requests handler which upserts items in chunks using multi-row statements.
+///
+/// Duplicate keys in a chunk are resolved before building the statement(the last item wins,
+/// same as row-by-row upserts); PostgreSQL rejects a statement which updates the same row twice.
+///
+/// # Arguments
+/// - create: Creates bucket which uses mutable transaction object.
+/// - upsert: Upserts a chunk of items using the multi-row query string.
+/// - builder: Builds create/multi-row upsert query strings.
+/// - chunk_size: The maximum number of items per statement(see `chunk_size_from_params`).
+pub fn upsert_bytes_all_new_chunked_mut<C, U, B, I, T>(
+    create: C,
+    upsert: U,
+    builder: B,
+    chunk_size: usize,
+) -> impl Fn(I, &mut T) -> Result<u64, Event>
+where
+    C: Fn(&mut T, &str) -> Result<u64, Event>,
+    U: Fn(&mut T, &str, &[Item<Vec<u8>, Vec<u8>>]) -> Result<u64, Event>,
+    B: UpsertBuilderMulti,
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    let sz: usize = chunk_size.max(1);
+    let f = move |req: &BulkRequest<_, _>, tx: &mut T| {
+        let op: Op = Op::new("upsert", req.as_bucket()).with_items(req.as_items().len());
+        trace::instrumented(
+            op,
+            || upsert_bytes_chunked_mut(req, &create, &upsert, &builder, tx, sz),
+            |cnt| *cnt,
+        )
+    };
+    move |requests: I, transaction: &mut T| upsert_bytes_all_mut(requests, transaction, &f)
+}
+
+/// The result of upserting a single item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpsertOutcome {
+    Inserted,
+    Updated,
+    Unchanged,
+}
+
+/// Upsert counts of a single bucket.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BucketReport {
+    created: u64,
+    inserted: u64,
+    updated: u64,
+    unchanged: u64,
+}
+
+impl BucketReport {
+    /// Gets the number of created buckets.
+    pub fn created(&self) -> u64 {
+        self.created
+    }
+
+    /// Gets the number of inserted rows.
+    pub fn inserted(&self) -> u64 {
+        self.inserted
+    }
+
+    /// Gets the number of updated rows.
+    pub fn updated(&self) -> u64 {
+        self.updated
+    }
+
+    /// Gets the number of rows skipped because the value was not changed.
+    pub fn unchanged(&self) -> u64 {
+        self.unchanged
+    }
+
+    fn add_outcome(&mut self, o: UpsertOutcome) {
+        match o {
+            UpsertOutcome::Inserted => self.inserted += 1,
+            UpsertOutcome::Updated => self.updated += 1,
+            UpsertOutcome::Unchanged => self.unchanged += 1,
+        }
+    }
+
+    fn merge(&mut self, other: &BucketReport) {
+        self.created += other.created;
+        self.inserted += other.inserted;
+        self.updated += other.updated;
+        self.unchanged += other.unchanged;
+    }
+}
+
+/// Upsert counts per bucket.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UpsertReport {
+    buckets: BTreeMap<String, BucketReport>,
+}
+
+impl UpsertReport {
+    /// Gets the counts of the bucket.
+    pub fn get(&self, bucket: &str) -> Option<&BucketReport> {
+        self.buckets.get(bucket)
+    }
+
+    /// Gets bucket names and counts ordered by the bucket name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &BucketReport)> {
+        self.buckets.iter().map(|(b, r)| (b.as_str(), r))
+    }
+
+    /// Gets the counts of all buckets.
+    pub fn total(&self) -> BucketReport {
+        self.buckets
+            .values()
+            .fold(BucketReport::default(), |mut tot, r| {
+                tot.merge(r);
+                tot
+            })
+    }
+
+    fn merge(&mut self, bucket: &Bucket, r: BucketReport) {
+        self.buckets
+            .entry(String::from(bucket.as_str()))
+            .or_default()
+            .merge(&r);
+    }
+}
+
+fn upsert_bytes_report_mut<C, U, T>(
+    q: &BulkRequest<Vec<u8>, Vec<u8>>,
+    create: &C,
+    upsert: &U,
+    transaction: &mut T,
+    query_c: &str,
+    query_u: &str,
+) -> Result<BucketReport, Event>
+where
+    C: Fn(&mut T, &str) -> Result<u64, Event>,
+    U: Fn(&mut T, &str, &[u8], &[u8]) -> Result<UpsertOutcome, Event>,
+{
+    let created: u64 = create(transaction, query_c)?;
+    let init = BucketReport {
+        created,
+        ..Default::default()
+    };
+
+    let items: &[Item<Vec<u8>, Vec<u8>>] = q.as_items();
+    items.iter().try_fold(init, |mut r, item| {
+        let key: &[u8] = item.as_key();
+        let val: &[u8] = item.as_val();
+        let o: UpsertOutcome = upsert(transaction, query_u, key, val)?;
+        r.add_outcome(o);
+        Ok(r)
+    })
+}
+
+/// Creates upsert requests handler which reports inserted/updated/unchanged rows per bucket.
+///
+/// PostgreSQL can derive the outcome using `RETURNING (xmax = 0)`:
+/// no row means unchanged, `true` means inserted and `false` means updated.
+///
+/// # Arguments
+/// - create: Creates bucket and returns the number of created buckets.
+/// - upsert: Upserts into the bucket and returns the outcome.
+/// - builder: Builds create/upsert query strings.
+pub fn upsert_bytes_all_new_report_mut<C, U, B, I, T>(
+    create: C,
+    upsert: U,
+    builder: B,
+) -> impl Fn(I, &mut T) -> Result<UpsertReport, Event>
+where
+    C: Fn(&mut T, &str) -> Result<u64, Event>,
+    U: Fn(&mut T, &str, &[u8], &[u8]) -> Result<UpsertOutcome, Event>,
+    B: UpsertBuilder,
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    move |mut requests: I, transaction: &mut T| {
+        requests.try_fold(UpsertReport::default(), |mut report, req| {
+            let b: &Bucket = req.as_bucket();
+            let op: Op = Op::new("upsert", b).with_items(req.as_items().len());
+            let r: BucketReport = trace::instrumented(
+                op,
+                || {
+                    let query_c: String = builder.build_create(b)?;
+                    let query_u: String = builder.build_upsert(b)?;
+                    upsert_bytes_report_mut(
+                        &req,
+                        &create,
+                        &upsert,
+                        transaction,
+                        query_c.as_str(),
+                        query_u.as_str(),
+                    )
+                },
+                |r| r.created + r.inserted + r.updated,
+            )?;
+            report.merge(b, r);
+            Ok(report)
+        })
+    }
+}
+
+/// Previous values of items in a request(`None` if the key did not exist).
+pub type PrevValues = Vec<Option<Vec<u8>>>;
+
+fn upsert_prev_all_mut<I, T, F>(
+    mut requests: I,
+    transaction: &mut T,
+    f: &F,
+) -> Result<Vec<PrevValues>, Event>
+where
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+    F: Fn(&BulkRequest<Vec<u8>, Vec<u8>>, &mut T) -> Result<PrevValues, Event>,
+{
+    requests.try_fold(vec![], |mut all, req| {
+        let op: Op = Op::new("upsert", req.as_bucket()).with_items(req.as_items().len());
+        let prev: PrevValues = trace::instrumented(
+            op,
+            || f(&req, transaction),
+            |prev| prev.iter().filter(|p| p.is_some()).count() as u64,
+        )?;
+        all.push(prev);
+        Ok(all)
+    })
+}
+
+/// Creates upsert requests handler which returns previous values using a single query per item.
+///
+/// The upsert query must return the replaced value(e.g, a CTE with `RETURNING` on PostgreSQL).
+/// Previous values are returned per request, in the order of items.
+///
+/// # Arguments
+/// - create: Creates bucket which uses mutable transaction object.
+/// - upsert: Upserts into the bucket and returns the previous value if any.
+/// - builder: Builds create/upsert query strings.
+pub fn upsert_bytes_all_new_returning_mut<C, U, B, I, T>(
+    create: C,
+    upsert: U,
+    builder: B,
+) -> impl Fn(I, &mut T) -> Result<Vec<PrevValues>, Event>
+where
+    C: Fn(&mut T, &str) -> Result<u64, Event>,
+    U: Fn(&mut T, &str, &[u8], &[u8]) -> Result<Option<Vec<u8>>, Event>,
+    B: UpsertBuilder,
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    let f = move |req: &BulkRequest<Vec<u8>, Vec<u8>>, tx: &mut T| {
+        let b: &Bucket = req.as_bucket();
+        let query_c: String = builder.build_create(b)?;
+        let query_u: String = builder.build_upsert(b)?;
+        create(tx, query_c.as_str())?;
+        req.as_items()
+            .iter()
+            .map(|item| upsert(tx, query_u.as_str(), item.as_key(), item.as_val()))
+            .collect()
+    };
+    move |requests: I, transaction: &mut T| upsert_prev_all_mut(requests, transaction, &f)
+}
+
+/// Creates upsert requests handler which selects previous values before upserting.
+///
+/// The select and the upsert must run in the same transaction to be atomic
+/// (e.g, an immediate transaction on SQLite).
+///
+/// # Arguments
+/// - create: Creates bucket which uses mutable transaction object.
+/// - select: Selects the current value of the key.
+/// - upsert: Upserts into the bucket.
+/// - builder: Builds create/upsert query strings.
+/// - select_builder: Builds select query string.
+pub fn upsert_bytes_all_new_select_first_mut<C, S, U, B, Q, I, T>(
+    create: C,
+    select: S,
+    upsert: U,
+    builder: B,
+    select_builder: Q,
+) -> impl Fn(I, &mut T) -> Result<Vec<PrevValues>, Event>
+where
+    C: Fn(&mut T, &str) -> Result<u64, Event>,
+    S: Fn(&mut T, &str, &[u8]) -> Result<Option<Vec<u8>>, Event>,
+    U: Fn(&mut T, &str, &[u8], &[u8]) -> Result<u64, Event>,
+    B: UpsertBuilder,
+    Q: Fn(&Bucket) -> Result<String, Event>,
+    I: Iterator<Item = BulkRequest<Vec<u8>, Vec<u8>>>,
+{
+    let f = move |req: &BulkRequest<Vec<u8>, Vec<u8>>, tx: &mut T| {
+        let b: &Bucket = req.as_bucket();
+        let query_c: String = builder.build_create(b)?;
+        let query_s: String = select_builder(b)?;
+        let query_u: String = builder.build_upsert(b)?;
+        create(tx, query_c.as_str())?;
+        req.as_items()
+            .iter()
+            .map(|item| {
+                let prev: Option<Vec<u8>> = select(tx, query_s.as_str(), item.as_key())?;
+                upsert(tx, query_u.as_str(), item.as_key(), item.as_val())?;
+                Ok(prev)
+            })
+            .collect()
+    };
+    move |requests: I, transaction: &mut T| upsert_prev_all_mut(requests, transaction, &f)
+}
+
+/// Creates new compare-and-set handler which replaces the value of the item's key with the
+/// item's value only if the current value equals the old value.
+///
+/// Returns the number of replaced rows(0 if the value was changed or deleted since it was read).
+///
+/// # Arguments
+/// - swap: Replaces the value of the key if it equals the old value
+///   (e.g, `UPDATE ... SET val = $3 WHERE key = $1 AND val = $2`).
+/// - builder: Builds compare-and-set query string.
+pub fn swap_bytes_new_mut<S, B, C>(
+    swap: S,
+    builder: B,
+) -> impl Fn(&Bucket, &RawItem, &[u8], &mut C) -> Result<u64, Event>
+where
+    S: Fn(&mut C, &str, &[u8], &[u8], &[u8]) -> Result<u64, Event>,
+    B: Fn(&Bucket) -> Result<String, Event>,
+{
+    move |b: &Bucket, item: &RawItem, old: &[u8], client: &mut C| {
+        let op: Op = Op::new("swap", b).with_key(item.as_key());
+        trace::instrumented(
+            op,
+            || {
+                let query: String = builder(b)?;
+                swap(client, query.as_str(), item.as_key(), old, item.as_val())
+            },
+            |cnt| *cnt,
+        )
+    }
+}
+
 #[cfg(test)]
 mod test_upsert {
 
@@ -267,4 +746,228 @@ mod test_upsert {
             assert_eq!(i.as_val(), b"");
         }
     }
+
+    mod upsert_bytes_all_new_chunked_mut {
+
+        use crate::upsert::{upsert_builder_multi_new, Bucket, BulkRequest, Item};
+
+        struct DummyTransaction {
+            queries: Vec<String>,
+        }
+
+        fn items(n: usize) -> Vec<Item<Vec<u8>, Vec<u8>>> {
+            (0..n)
+                .map(|i: usize| Item::new(format!("{}", i).into_bytes(), vec![]))
+                .collect()
+        }
+
+        #[test]
+        fn test_chunks() {
+            let c = |_t: &mut DummyTransaction, _q: &str| Ok(0);
+            let u = |t: &mut DummyTransaction, q: &str, chunk: &[Item<Vec<u8>, Vec<u8>>]| {
+                t.queries.push(String::from(q));
+                Ok(chunk.len() as u64)
+            };
+            let b = upsert_builder_multi_new(
+                |_: &Bucket| Ok(String::from("")),
+                |_: &Bucket| Ok(String::from("")),
+                |bkt: &Bucket, rows: usize| {
+                    Ok(format!(
+                        "INSERT INTO {} VALUES {}",
+                        bkt.as_str(),
+                        crate::upsert::values_placeholders_sqlite(rows),
+                    ))
+                },
+            );
+            let f = crate::upsert::upsert_bytes_all_new_chunked_mut(c, u, b, 2);
+            let req = vec![BulkRequest::new(
+                Bucket::from(String::from("devices")),
+                items(5),
+            )];
+            let mut dt = DummyTransaction { queries: vec![] };
+            let cnt: u64 = f(req.into_iter(), &mut dt).unwrap();
+            assert_eq!(cnt, 5);
+            assert_eq!(dt.queries.len(), 3);
+            assert_eq!(
+                dt.queries[0],
+                "INSERT INTO devices VALUES (?1, ?2), (?3, ?4)"
+            );
+            assert_eq!(dt.queries[2], "INSERT INTO devices VALUES (?1, ?2)");
+        }
+
+        #[test]
+        fn test_duplicates() {
+            let c = |_t: &mut DummyTransaction, _q: &str| Ok(0);
+            let u = |t: &mut DummyTransaction, q: &str, chunk: &[Item<Vec<u8>, Vec<u8>>]| {
+                t.queries.push(String::from(q));
+                let pairs: Vec<String> = chunk
+                    .iter()
+                    .map(|i| format!("{:?}={:?}", i.as_key(), i.as_val()))
+                    .collect();
+                t.queries.push(pairs.join(","));
+                Ok(chunk.len() as u64)
+            };
+            let b = upsert_builder_multi_new(
+                |_: &Bucket| Ok(String::from("")),
+                |_: &Bucket| Ok(String::from("")),
+                |_: &Bucket, rows: usize| Ok(crate::upsert::values_placeholders_sqlite(rows)),
+            );
+            let f = crate::upsert::upsert_bytes_all_new_chunked_mut(c, u, b, 3);
+            let dup = vec![
+                Item::new(b"a".to_vec(), b"1".to_vec()),
+                Item::new(b"b".to_vec(), b"2".to_vec()),
+                Item::new(b"a".to_vec(), b"3".to_vec()),
+            ];
+            let req = vec![BulkRequest::new(Bucket::from(String::from("devices")), dup)];
+            let mut dt = DummyTransaction { queries: vec![] };
+            assert_eq!(f(req.into_iter(), &mut dt).unwrap(), 2);
+            assert_eq!(dt.queries[0], "(?1, ?2), (?3, ?4)");
+            assert_eq!(dt.queries[1], "[98]=[50],[97]=[51]");
+        }
+    }
+
+    mod values_placeholders_postgres {
+
+        #[test]
+        fn test_two_rows() {
+            let s: String = crate::upsert::values_placeholders_postgres(2);
+            assert_eq!(s, "($1::BYTEA, $2::BYTEA), ($3::BYTEA, $4::BYTEA)");
+        }
+    }
+
+    mod chunk_size_from_params {
+
+        use crate::upsert::{chunk_size_from_params, SQLITE_MAX_PARAMS};
+
+        #[test]
+        fn test_sqlite() {
+            assert_eq!(chunk_size_from_params(SQLITE_MAX_PARAMS, 2), 16383);
+        }
+
+        #[test]
+        fn test_zero() {
+            assert_eq!(chunk_size_from_params(0, 2), 1);
+            assert_eq!(chunk_size_from_params(10, 0), 1);
+        }
+    }
+
+    mod upsert_bytes_all_new_report_mut {
+
+        use crate::upsert::{upsert_builder_new, Bucket, BulkRequest, Item, UpsertOutcome};
+
+        struct DummyTransaction {}
+
+        #[test]
+        fn test_outcomes() {
+            let c = |_t: &mut DummyTransaction, _q: &str| Ok(1);
+            let u = |_t: &mut DummyTransaction, _q: &str, key: &[u8], _val: &[u8]| match key {
+                b"new" => Ok(UpsertOutcome::Inserted),
+                b"old" => Ok(UpsertOutcome::Updated),
+                _ => Ok(UpsertOutcome::Unchanged),
+            };
+            let b = upsert_builder_new(
+                |_: &Bucket| Ok(String::from("")),
+                |_: &Bucket| Ok(String::from("")),
+            );
+            let f = crate::upsert::upsert_bytes_all_new_report_mut(c, u, b);
+            let item = |k: &[u8]| Item::new(k.to_vec(), vec![]);
+            let req = vec![
+                BulkRequest::new(
+                    Bucket::from(String::from("devices")),
+                    vec![item(b"new"), item(b"old"), item(b"same")],
+                ),
+                BulkRequest::new(Bucket::from(String::from("dates")), vec![item(b"new")]),
+                BulkRequest::new(Bucket::from(String::from("devices")), vec![item(b"new")]),
+            ];
+            let mut dt = DummyTransaction {};
+            let report = f(req.into_iter(), &mut dt).unwrap();
+
+            let devices = report.get("devices").unwrap();
+            assert_eq!(devices.created(), 2);
+            assert_eq!(devices.inserted(), 2);
+            assert_eq!(devices.updated(), 1);
+            assert_eq!(devices.unchanged(), 1);
+
+            let names: Vec<&str> = report.iter().map(|(b, _)| b).collect();
+            assert_eq!(names, vec!["dates", "devices"]);
+
+            let total = report.total();
+            assert_eq!(total.created(), 3);
+            assert_eq!(total.inserted(), 3);
+        }
+    }
+
+    mod upsert_bytes_all_new_select_first_mut {
+
+        use std::collections::BTreeMap;
+
+        use crate::upsert::{upsert_builder_new, Bucket, BulkRequest, Item};
+
+        struct DummyTransaction {
+            rows: BTreeMap<Vec<u8>, Vec<u8>>,
+        }
+
+        #[test]
+        fn test_previous_values() {
+            let c = |_t: &mut DummyTransaction, _q: &str| Ok(0);
+            let s = |t: &mut DummyTransaction, _q: &str, key: &[u8]| Ok(t.rows.get(key).cloned());
+            let u = |t: &mut DummyTransaction, _q: &str, key: &[u8], val: &[u8]| {
+                t.rows.insert(key.to_vec(), val.to_vec());
+                Ok(1)
+            };
+            let b = upsert_builder_new(
+                |_: &Bucket| Ok(String::from("")),
+                |_: &Bucket| Ok(String::from("")),
+            );
+            let f =
+                crate::upsert::upsert_bytes_all_new_select_first_mut(c, s, u, b, |_: &Bucket| {
+                    Ok(String::from(""))
+                });
+            let req = vec![BulkRequest::new(
+                Bucket::from(String::from("devices")),
+                vec![
+                    Item::new(b"k1".to_vec(), b"v1".to_vec()),
+                    Item::new(b"k2".to_vec(), b"v2".to_vec()),
+                    Item::new(b"k1".to_vec(), b"v3".to_vec()),
+                ],
+            )];
+            let mut dt = DummyTransaction {
+                rows: BTreeMap::new(),
+            };
+            let prev = f(req.into_iter(), &mut dt).unwrap();
+            assert_eq!(prev, vec![vec![None, None, Some(b"v1".to_vec())]]);
+            assert_eq!(dt.rows.get(b"k1".as_slice()), Some(&b"v3".to_vec()));
+        }
+    }
+
+    mod upsert_bytes_all_new_returning_mut {
+
+        use crate::evt::Event;
+        use crate::upsert::{upsert_builder_new, Bucket, BulkRequest, Item};
+
+        struct DummyTransaction {}
+
+        #[test]
+        fn test_error() {
+            let c = |_t: &mut DummyTransaction, _q: &str| Ok(0);
+            let u = |_t: &mut DummyTransaction, _q: &str, key: &[u8], _val: &[u8]| match key {
+                b"bad" => Err(Event::UnexpectedError(String::from("bad key"))),
+                _ => Ok(Some(b"old".to_vec())),
+            };
+            let b = upsert_builder_new(
+                |_: &Bucket| Ok(String::from("")),
+                |_: &Bucket| Ok(String::from("")),
+            );
+            let f = crate::upsert::upsert_bytes_all_new_returning_mut(c, u, b);
+            let item = |k: &[u8]| Item::new(k.to_vec(), vec![]);
+            let bkt = || Bucket::from(String::from("devices"));
+
+            let ok = vec![BulkRequest::new(bkt(), vec![item(b"good")])];
+            let prev = f(ok.into_iter(), &mut DummyTransaction {}).unwrap();
+            assert_eq!(prev, vec![vec![Some(b"old".to_vec())]]);
+
+            let ng = vec![BulkRequest::new(bkt(), vec![item(b"good"), item(b"bad")])];
+            assert!(f(ng.into_iter(), &mut DummyTransaction {}).is_err());
+        }
+    }
 }